@@ -0,0 +1,81 @@
+// parsing benchmarks
+// - the documents of test_files
+// - a large generated document (many long strokes), for each trace encoding
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use writer_inkml::{
+    parse_bytes, parse_formatted_bytes, writer_with_config, BrushBuilder, FormattedStroke,
+    TraceEncoding, WriterConfig,
+};
+
+const TEST_FILES: [&str; 4] = [
+    "test_files/journal_output.xml",
+    "test_files/onenote_web.xml",
+    "test_files/word_output.xml",
+    "test_files/10065.inkml",
+];
+
+/// document with `num_strokes` strokes of `num_points` points
+fn generated_document(num_strokes: usize, num_points: usize, encoding: TraceEncoding) -> Vec<u8> {
+    let brush = BrushBuilder::default().build();
+    let strokes = (0..num_strokes)
+        .map(|stroke_index| {
+            let offset = stroke_index as f64 * 0.1;
+            (
+                FormattedStroke::new(
+                    (0..num_points)
+                        .map(|i| offset + (i as f64 * 0.05).cos())
+                        .collect(),
+                    (0..num_points)
+                        .map(|i| offset + (i as f64 * 0.07).sin())
+                        .collect(),
+                    (0..num_points)
+                        .map(|i| (i as f64 / num_points as f64).min(1.0))
+                        .collect(),
+                )
+                .unwrap(),
+                brush.clone(),
+            )
+        })
+        .collect();
+    writer_with_config(strokes, &WriterConfig::default().encoding(encoding)).unwrap()
+}
+
+fn bench_test_files(c: &mut Criterion) {
+    let mut group = c.benchmark_group("test_files");
+    for path in TEST_FILES {
+        let content = std::fs::read(path).unwrap();
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(path), &content, |b, content| {
+            b.iter(|| parse_bytes(content).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_generated(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generated");
+    group.sample_size(20);
+    for encoding in [
+        TraceEncoding::Explicit,
+        TraceEncoding::SingleDifference,
+        TraceEncoding::DoubleDifference,
+    ] {
+        let content = generated_document(2000, 200, encoding);
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("parser", format!("{encoding:?}")),
+            &content,
+            |b, content| b.iter(|| parse_bytes(content).unwrap()),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("parse_formatted", format!("{encoding:?}")),
+            &content,
+            |b, content| b.iter(|| parse_formatted_bytes(content).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_test_files, bench_generated);
+criterion_main!(benches);
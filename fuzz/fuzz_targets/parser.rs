@@ -0,0 +1,18 @@
+// any document : the parser should give an error, never panic
+// (and what it accepts can be written back)
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use writer_inkml::{
+    parse_bytes, parse_formatted_bytes, parse_formatted_with_options, write_parser_result,
+    ParserOptions,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_formatted_bytes(data);
+    // what was parsed can be written back
+    if let Ok(result) = parse_bytes(data) {
+        let _ = write_parser_result(&result);
+    }
+    let _ = parse_formatted_with_options(data, &ParserOptions::default().strict(false));
+});
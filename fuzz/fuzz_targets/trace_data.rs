@@ -0,0 +1,17 @@
+// text of a trace for some channel types : the decoder should give an
+// error, never panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use writer_inkml::fuzzing::decode_trace;
+
+fuzz_target!(|data: &[u8]| {
+    // the first bytes give the types of the channels, the rest is the text
+    if let Some((&num_channels, rest)) = data.split_first() {
+        let num_channels = usize::from(num_channels % 8).min(rest.len());
+        let (channel_types, text) = rest.split_at(num_channels);
+        if let Ok(text) = std::str::from_utf8(text) {
+            let _ = decode_trace(channel_types, text);
+        }
+    }
+});
@@ -0,0 +1,104 @@
+// async parsing api (tokio), for services receiving documents over the network
+//
+// The reader is read by chunks on the runtime and the chunks are fed to the
+// (synchronous) parser running on the blocking thread pool, so that the
+// runtime is never blocked and the document is never fully in memory
+
+use crate::brushes::Brush;
+use crate::parser::{parse_formatted, parser, ParserResult};
+use crate::trace_data::FormattedStroke;
+use anyhow::anyhow;
+use std::io::Read;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+
+/// size of the chunks read from the async reader
+const CHUNK_SIZE: usize = 64 * 1024;
+/// number of chunks waiting for the parser at most
+const MAX_PENDING_CHUNKS: usize = 4;
+
+/// Same as `parser` for an async reader
+pub async fn parser_async<R: AsyncRead + Unpin>(reader: R) -> anyhow::Result<ParserResult> {
+    parse_chunks(reader, parser).await
+}
+
+/// Same as `parse_formatted` for an async reader
+pub async fn parse_formatted_async<R: AsyncRead + Unpin>(
+    reader: R,
+) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
+    parse_chunks(reader, parse_formatted).await
+}
+
+/// runs `parse` on the blocking thread pool, feeding it the chunks read from `reader`
+async fn parse_chunks<R, T>(
+    mut reader: R,
+    parse: fn(ChunkReader) -> anyhow::Result<T>,
+) -> anyhow::Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(MAX_PENDING_CHUNKS);
+    let parse_task = tokio::task::spawn_blocking(move || parse(ChunkReader::new(receiver)));
+
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        match reader.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(length) => {
+                chunk.truncate(length);
+                if sender.send(Ok(chunk)).await.is_err() {
+                    // the parser stopped early (malformed document)
+                    break;
+                }
+            }
+            Err(e) => {
+                let _ = sender.send(Err(e)).await;
+                break;
+            }
+        }
+    }
+    // end of the document for the parser
+    drop(sender);
+
+    parse_task
+        .await
+        .map_err(|e| anyhow!("The parsing task failed : {e}"))?
+}
+
+/// synchronous reader over the chunks sent by the async side
+struct ChunkReader {
+    receiver: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    /// position in the current chunk
+    offset: usize,
+}
+
+impl ChunkReader {
+    fn new(receiver: mpsc::Receiver<std::io::Result<Vec<u8>>>) -> ChunkReader {
+        ChunkReader {
+            receiver,
+            chunk: vec![],
+            offset: 0,
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.offset = 0;
+                }
+                // all chunks were read
+                None => return Ok(0),
+            }
+        }
+        let length = buf.len().min(self.chunk.len() - self.offset);
+        buf[..length].copy_from_slice(&self.chunk[self.offset..self.offset + length]);
+        self.offset += length;
+        Ok(length)
+    }
+}
@@ -0,0 +1,126 @@
+// conversion of many documents at once (e.g. a folder of exports), in
+// parallel with the `parallel` feature
+//
+// A failing document doesn't stop the batch : its error is logged and
+// returned in its place
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use tracing::warn;
+
+use crate::brushes::Brush;
+use crate::options::{FormatOptions, ParserLimits, ParserOptions, WriterConfig};
+use crate::parser::parse_formatted_with_options;
+use crate::trace_data::FormattedStroke;
+use crate::writer::writer_with_config;
+
+/// Options for `convert_batch`
+/// (the parser options can't be shared between threads, they are built for
+/// each document from these ones)
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    pub(crate) strict: bool,
+    pub(crate) limits: ParserLimits,
+    pub(crate) format: FormatOptions,
+    /// the strokes are written back to inkml with this config
+    pub(crate) writer: Option<WriterConfig>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            strict: true,
+            limits: ParserLimits::default(),
+            format: FormatOptions::default(),
+            writer: None,
+        }
+    }
+}
+
+impl BatchOptions {
+    /// see `ParserOptions::strict`
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// limits applied to each document (see `ParserLimits`)
+    pub fn limits(mut self, limits: ParserLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn format(mut self, format: FormatOptions) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// writes the strokes of each document back to inkml with `config`
+    /// (e.g. to normalise documents from different applications)
+    pub fn write_with(mut self, config: WriterConfig) -> Self {
+        self.writer = Some(config);
+        self
+    }
+
+    fn parser_options(&self) -> ParserOptions {
+        ParserOptions::default()
+            .strict(self.strict)
+            .limits(self.limits)
+            .format(self.format)
+    }
+}
+
+/// a document converted by `convert_batch`
+#[derive(Debug, Clone)]
+pub struct BatchOutput {
+    pub path: PathBuf,
+    pub strokes: Vec<(FormattedStroke, Brush)>,
+    /// the document written back, if `BatchOptions::write_with` was set
+    pub inkml: Option<Vec<u8>>,
+}
+
+fn convert(path: PathBuf, options: &BatchOptions) -> anyhow::Result<BatchOutput> {
+    let file = File::open(&path).map_err(|e| anyhow!("Could not open {} : {e}", path.display()))?;
+    let strokes = parse_formatted_with_options(BufReader::new(file), &options.parser_options())
+        // (the context keeps the typed errors, e.g. `LimitExceeded`, downcastable)
+        .map_err(|e| e.context(format!("Could not parse {}", path.display())))?;
+    let inkml = match &options.writer {
+        Some(config) => Some(
+            writer_with_config(strokes.clone(), config)
+                .map_err(|e| e.context(format!("Could not write {}", path.display())))?,
+        ),
+        None => None,
+    };
+    Ok(BatchOutput {
+        path,
+        strokes,
+        inkml,
+    })
+}
+
+/// Parses (and writes back if asked) the documents at `inputs`, one result per
+/// input in the same order. The failures are logged and don't stop the batch
+/// With the `parallel` feature, the documents are converted on the rayon thread pool
+pub fn convert_batch(
+    inputs: impl IntoIterator<Item = PathBuf>,
+    options: &BatchOptions,
+) -> Vec<anyhow::Result<BatchOutput>> {
+    let inputs = inputs.into_iter().collect::<Vec<PathBuf>>();
+    let convert_logged = |path: PathBuf| {
+        let result = convert(path, options);
+        if let Err(e) = &result {
+            warn!("batch conversion : {e:#}");
+        }
+        result
+    };
+    #[cfg(feature = "parallel")]
+    let results = inputs.into_par_iter().map(convert_logged).collect();
+    #[cfg(not(feature = "parallel"))]
+    let results = inputs.into_iter().map(convert_logged).collect();
+    results
+}
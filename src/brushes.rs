@@ -1,69 +1,236 @@
 use std::io::Write;
+use std::sync::Arc;
 use std::{collections::HashMap, hash::Hash};
 use xml::writer::{Error, EventWriter, XmlEvent};
 
+use crate::colors::Color;
+use crate::options::{BlackTransparency, IdAttribute};
 use crate::traits::Writable;
+use anyhow::anyhow;
+use tracing::warn;
 
-#[derive(Debug, Clone)]
+/// shape of the pen tip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BrushTip {
+    #[default]
+    Ellipse,
+    Rectangle,
+}
+
+impl BrushTip {
+    pub(crate) fn parse(name: &Option<String>) -> anyhow::Result<BrushTip> {
+        match name {
+            Some(value) => match value.as_str() {
+                "ellipse" => Ok(BrushTip::Ellipse),
+                "rectangle" => Ok(BrushTip::Rectangle),
+                _ => Err(anyhow!(
+                    "the brush tip {value} is not part of the accepted tips : ellipse or rectangle"
+                )),
+            },
+            None => Err(anyhow!("BrushTip::parse was given a None")),
+        }
+    }
+}
+
+impl From<BrushTip> for String {
+    fn from(value: BrushTip) -> Self {
+        match value {
+            BrushTip::Ellipse => String::from("ellipse"),
+            BrushTip::Rectangle => String::from("rectangle"),
+        }
+    }
+}
+
+/// how the color of the brush is combined with what's below
+/// (binary raster operations of the inkml spec)
+/// `MaskPen` is used by highlighters, `CopyPen` is the usual opaque pen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RasterOp {
+    Black,
+    NotMergePen,
+    MaskNotPen,
+    NotCopyPen,
+    MaskPenNot,
+    Not,
+    XorPen,
+    NotMaskPen,
+    MaskPen,
+    NotXorPen,
+    NoOperation,
+    MergeNotPen,
+    #[default]
+    CopyPen,
+    MergePenNot,
+    MergePen,
+    White,
+}
+
+impl RasterOp {
+    pub(crate) fn parse(name: &Option<String>) -> anyhow::Result<RasterOp> {
+        match name {
+            Some(value) => match value.as_str() {
+                "black" => Ok(RasterOp::Black),
+                "notMergePen" => Ok(RasterOp::NotMergePen),
+                "maskNotPen" => Ok(RasterOp::MaskNotPen),
+                "notCopyPen" => Ok(RasterOp::NotCopyPen),
+                "maskPenNot" => Ok(RasterOp::MaskPenNot),
+                "not" => Ok(RasterOp::Not),
+                "xorPen" => Ok(RasterOp::XorPen),
+                "notMaskPen" => Ok(RasterOp::NotMaskPen),
+                "maskPen" => Ok(RasterOp::MaskPen),
+                "notXorPen" => Ok(RasterOp::NotXorPen),
+                "noOperation" => Ok(RasterOp::NoOperation),
+                "mergeNotPen" => Ok(RasterOp::MergeNotPen),
+                "copyPen" => Ok(RasterOp::CopyPen),
+                "mergePenNot" => Ok(RasterOp::MergePenNot),
+                "mergePen" => Ok(RasterOp::MergePen),
+                "white" => Ok(RasterOp::White),
+                _ => Err(anyhow!("the raster operation {value} was not found")),
+            },
+            None => Err(anyhow!("RasterOp::parse was given a None")),
+        }
+    }
+}
+
+impl From<RasterOp> for String {
+    fn from(value: RasterOp) -> Self {
+        match value {
+            RasterOp::Black => String::from("black"),
+            RasterOp::NotMergePen => String::from("notMergePen"),
+            RasterOp::MaskNotPen => String::from("maskNotPen"),
+            RasterOp::NotCopyPen => String::from("notCopyPen"),
+            RasterOp::MaskPenNot => String::from("maskPenNot"),
+            RasterOp::Not => String::from("not"),
+            RasterOp::XorPen => String::from("xorPen"),
+            RasterOp::NotMaskPen => String::from("notMaskPen"),
+            RasterOp::MaskPen => String::from("maskPen"),
+            RasterOp::NotXorPen => String::from("notXorPen"),
+            RasterOp::NoOperation => String::from("noOperation"),
+            RasterOp::MergeNotPen => String::from("mergeNotPen"),
+            RasterOp::CopyPen => String::from("copyPen"),
+            RasterOp::MergePenNot => String::from("mergePenNot"),
+            RasterOp::MergePen => String::from("mergePen"),
+            RasterOp::White => String::from("white"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Brush {
     /// name for the brush
     /// ```html
     /// <brush xml:id="name">
     /// ```
-    name: String,
+    /// (shared, so that cloning a brush for each of its strokes is cheap)
+    pub(crate) name: Arc<str>,
+    /// color, its alpha is the opposite of the inkml `transparency`
+    pub color: Color,
+    /// width of the tip in cm
+    pub width_cm: f64,
+    /// height of the tip in cm
+    /// equal to the width for round nibs, different for calligraphy
+    /// or highlighter tips
+    pub height_cm: f64,
+    pub ignorepressure: bool,
+    /// shape of the tip, `Rectangle` for highlighters
+    pub tip: BrushTip,
+    /// blending with the ink below, `MaskPen` for highlighters
+    pub raster_op: RasterOp,
+    /// `fitToCurve` property (smoothing of the stroke), written only if set
+    pub fit_to_curve: Option<bool>,
+    /// `antiAliased` property, written only if set
+    pub anti_aliased: Option<bool>,
+}
+
+/// Style of a stroke as it should be rendered, computed from its `Brush`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectiveStyle {
     /// RGB triplet
     pub color: (u8, u8, u8),
-    // simplified version, the stroke width is
-    // given as a positive float corresponding to the width in
-    // cm
-    pub stroke_width_cm: f64,
-    pub ignorepressure: bool,
-    pub transparency: u8,
+    /// opacity, from 0.0 (invisible) to 1.0 (opaque)
+    pub alpha: f32,
+    /// stroke width in cm
+    pub width_cm: f64,
 }
 
 impl Brush {
+    /// the style to render strokes drawn with this brush
+    ///
+    /// The alpha of the color (0 - 255) is converted here to an opacity
+    pub fn effective_style(&self) -> EffectiveStyle {
+        EffectiveStyle {
+            color: self.color.to_rgb(),
+            alpha: f32::from(self.color.a) / f32::from(u8::MAX),
+            width_cm: self.stroke_width_cm(),
+        }
+    }
+
+    /// simplified stroke width (in cm) for renderers without
+    /// support for anisotropic tips : the max of the width and height
+    pub fn stroke_width_cm(&self) -> f64 {
+        self.width_cm.max(self.height_cm)
+    }
+
     pub(crate) fn init_brush_with_id(id: &str) -> Brush {
         Brush {
-            name: id.to_owned(),
-            color: (0, 0, 0),
-            stroke_width_cm: 0.0,
-            transparency: 0,
+            name: id.into(),
+            color: Color::BLACK,
+            width_cm: 0.0,
+            height_cm: 0.0,
             ignorepressure: false,
+            tip: BrushTip::default(),
+            raster_op: RasterOp::default(),
+            fit_to_curve: None,
+            anti_aliased: None,
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
-pub struct PositiveFiniteFloat {
-    stroke_width: f64,
+/// size (width or height) of a brush as compared for duplicates : rounded to
+/// the micrometre, so that widths differing by float noise (e.g. after a unit
+/// conversion) give the same brush
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct QuantizedSize {
+    micrometres: i64,
 }
 
-impl PositiveFiniteFloat {
-    fn new(stroke_width: f64) -> PositiveFiniteFloat {
-        PositiveFiniteFloat {
-            stroke_width: if stroke_width.is_finite() {
-                stroke_width
+/// micrometres in a cm
+const MICROMETRES_PER_CM: f64 = 10_000.0;
+
+impl QuantizedSize {
+    /// the size in cm, rounded to the micrometre
+    pub(crate) fn cm(&self) -> f64 {
+        self.micrometres as f64 / MICROMETRES_PER_CM
+    }
+
+    pub(crate) fn new(size_cm: f64) -> QuantizedSize {
+        QuantizedSize {
+            // non finite values are all mapped to 0
+            micrometres: if size_cm.is_finite() {
+                (size_cm * MICROMETRES_PER_CM).round() as i64
             } else {
-                0.0
+                0
             },
         }
     }
 }
 
-impl Eq for PositiveFiniteFloat {} // works because we guarantee the value is finite
-
-impl Hash for PositiveFiniteFloat {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.stroke_width.to_bits().hash(state);
-    }
-}
-
 /// Type alias that's used to check brush duplicates using a hashmap
-/// - The first element is the (r,g,b) value
-/// - The second element is the stroke width
-/// - The third is whether or not pressure is ignored
-/// - The last one is transparency
-type BrushIndex = ((u8, u8, u8), PositiveFiniteFloat, bool, u8);
+/// - The first element is the color (with its alpha)
+/// - The second and third elements are the width and height (in micrometres)
+/// - The fourth is whether or not pressure is ignored
+/// - The fifth and sixth are the tip shape and the raster operation
+/// - The last ones are the fitToCurve and antiAliased properties
+pub(crate) type BrushIndex = (
+    Color,
+    QuantizedSize,
+    QuantizedSize,
+    bool,
+    BrushTip,
+    RasterOp,
+    Option<bool>,
+    Option<bool>,
+);
 
 /// We iterate over the strokes and construct a collection of brushes
 /// so that we have the lowest number of brushes used
@@ -73,110 +240,297 @@ type BrushIndex = ((u8, u8, u8), PositiveFiniteFloat, bool, u8);
 /// twice
 #[derive(Default, Debug)]
 pub(crate) struct BrushCollection {
-    /// Brush collection (dictionnary on brush indexed by the brush id)
-    brushes: HashMap<String, Brush>,
-    /// Called with color, stroke width, ignorepressure and transparency, gives
+    /// Brush collection, in the order the brushes were first added
+    /// (so that ids and the written output are deterministic)
+    brushes: Vec<Brush>,
+    /// Called with color, stroke width, ignorepressure and the other properties, gives
     /// the id corresponding to this value
     duplicate_search: HashMap<BrushIndex, String>,
-    /// Memorizes the brush id given for each call wanting to add a brush
-    mapping: Vec<String>,
+    /// keep the names of the brushes instead of numbering them
+    preserve_ids: bool,
+    /// (name asked for, properties) -> id, when the ids are preserved
+    named_search: HashMap<(Arc<str>, BrushIndex), String>,
+}
+
+impl Brush {
+    /// key identifying brushes with the same properties (regardless of their name)
+    pub(crate) fn duplicate_key(&self) -> BrushIndex {
+        (
+            self.color,
+            QuantizedSize::new(self.width_cm),
+            QuantizedSize::new(self.height_cm),
+            self.ignorepressure,
+            self.tip,
+            self.raster_op,
+            self.fit_to_curve,
+            self.anti_aliased,
+        )
+    }
 }
 
 impl BrushCollection {
-    pub(crate) fn add_brush(&mut self, brush: &Brush) {
-        let duplicate_key = (
-            brush.color,
-            PositiveFiniteFloat::new(brush.stroke_width_cm),
-            brush.ignorepressure,
-            brush.transparency,
-        );
+    /// collection keeping the names of the brushes if `preserve_ids` is set
+    /// (see `WriterConfig::preserve_brush_ids`)
+    pub(crate) fn new(preserve_ids: bool) -> BrushCollection {
+        BrushCollection {
+            preserve_ids,
+            ..Default::default()
+        }
+    }
+
+    /// adds the brush (if no identical brush exists) and returns
+    /// the id of the brush to use
+    pub(crate) fn add_brush(&mut self, brush: &Brush) -> String {
+        let duplicate_key = brush.duplicate_key();
+        if self.preserve_ids && !brush.name.is_empty() {
+            return self.add_named_brush(brush, duplicate_key);
+        }
 
         match self.duplicate_search.get(&duplicate_key) {
             None => {
-                // get the id
-                let id = format!("br{}", self.brushes.len() + 1);
-                self.mapping.push(id.clone());
+                // get the id (skipping the names kept from other brushes)
+                let id = (self.brushes.len() + 1..)
+                    .map(|index| format!("br{index}"))
+                    .find(|id| !self.is_taken(id))
+                    .unwrap();
 
                 // push to duplicate search
                 self.duplicate_search.insert(duplicate_key, id.clone());
 
                 // push to brushes
                 // edit the brush to take the new unique id
-                let mut new_brush = brush.clone();
-                new_brush.name = id.clone();
-                self.brushes.insert(id, new_brush);
-            }
-            Some(id) => {
-                self.mapping.push(id.clone());
+                self.push(brush, &id);
+                id
             }
+            Some(id) => id.clone(),
+        }
+    }
+
+    /// adds a brush keeping its name, unless another brush already has it
+    fn add_named_brush(&mut self, brush: &Brush, duplicate_key: BrushIndex) -> String {
+        let named_key = (brush.name.clone(), duplicate_key);
+        if let Some(id) = self.named_search.get(&named_key) {
+            return id.clone();
+        }
+        let mut id = brush.name.to_string();
+        if self.is_taken(&id) {
+            id = (1..)
+                .map(|index| format!("{}_{index}", brush.name))
+                .find(|candidate| !self.is_taken(candidate))
+                .unwrap();
+            warn!(
+                "the brush id {} is used by brushes with different properties, renamed to {id}",
+                brush.name
+            );
         }
+        self.duplicate_search
+            .entry(brush.duplicate_key())
+            .or_insert_with(|| id.clone());
+        self.named_search.insert(named_key, id.clone());
+        self.push(brush, &id);
+        id
     }
 
-    pub(crate) fn brushes(&self) -> HashMap<String, Brush> {
-        self.brushes.clone()
+    fn is_taken(&self, id: &str) -> bool {
+        self.brushes.iter().any(|brush| &*brush.name == id)
     }
 
-    pub(crate) fn mapping(&self) -> Vec<String> {
-        self.mapping.clone()
+    fn push(&mut self, brush: &Brush, id: &str) {
+        let mut new_brush = brush.clone();
+        new_brush.name = id.into();
+        self.brushes.push(new_brush);
+    }
+
+    pub(crate) fn brushes(&self) -> &[Brush] {
+        &self.brushes
     }
 }
 
 impl Brush {
+    /// `color` : RGB triplet or `Color`
+    /// `transparency` : inkml transparency (0 : opaque, 255 : fully transparent),
+    /// applied on top of the alpha of the color
     pub fn init(
         name: String,
-        color: (u8, u8, u8),
+        color: impl Into<Color>,
         ignorepressure: bool,
         transparency: u8,
         stroke_width: f64,
     ) -> Brush {
+        let color = color.into();
+        let alpha = u16::from(color.a) * u16::from(u8::MAX - transparency) / u16::from(u8::MAX);
         Brush {
-            name,
-            color,
-            stroke_width_cm: stroke_width,
-            transparency,
+            name: name.into(),
+            color: Color {
+                a: alpha as u8,
+                ..color
+            },
+            width_cm: stroke_width,
+            height_cm: stroke_width,
             ignorepressure,
+            tip: BrushTip::default(),
+            raster_op: RasterOp::default(),
+            fit_to_curve: None,
+            anti_aliased: None,
         }
     }
+
+    /// name (id) of the brush
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the same brush with another name
+    pub fn with_name(mut self, name: impl Into<String>) -> Brush {
+        self.name = name.into().into();
+        self
+    }
+}
+
+/// Builder for a `Brush` where only some properties are customised
+///
+/// Defaults : named `br0`, black, 1 mm wide round tip, opaque, pressure sensitive
+#[derive(Debug, Clone)]
+pub struct BrushBuilder {
+    brush: Brush,
+}
+
+impl Default for BrushBuilder {
+    fn default() -> Self {
+        BrushBuilder {
+            brush: Brush::init(String::from("br0"), (0, 0, 0), false, 0, 0.1),
+        }
+    }
+}
+
+impl BrushBuilder {
+    pub fn new() -> BrushBuilder {
+        BrushBuilder::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.brush.name = name.into().into();
+        self
+    }
+
+    /// RGB triplet (opaque) or `Color`
+    pub fn color(mut self, color: impl Into<Color>) -> Self {
+        self.brush.color = color.into();
+        self
+    }
+
+    /// sets both the width and height of the tip, in cm
+    pub fn width_cm(mut self, width: f64) -> Self {
+        self.brush.width_cm = width;
+        self.brush.height_cm = width;
+        self
+    }
+
+    /// sets both the width and height of the tip, in mm
+    pub fn width_mm(self, width: f64) -> Self {
+        self.width_cm(width / 10.0)
+    }
+
+    /// sets the height of the tip only, in cm (for rectangular tips)
+    pub fn height_cm(mut self, height: f64) -> Self {
+        self.brush.height_cm = height;
+        self
+    }
+
+    /// 0 is opaque, 255 is fully transparent (sets the alpha of the color)
+    pub fn transparency(mut self, transparency: u8) -> Self {
+        self.brush.color = self.brush.color.with_transparency(transparency);
+        self
+    }
+
+    pub fn ignore_pressure(mut self, ignorepressure: bool) -> Self {
+        self.brush.ignorepressure = ignorepressure;
+        self
+    }
+
+    pub fn tip(mut self, tip: BrushTip) -> Self {
+        self.brush.tip = tip;
+        self
+    }
+
+    pub fn raster_op(mut self, raster_op: RasterOp) -> Self {
+        self.brush.raster_op = raster_op;
+        self
+    }
+
+    pub fn fit_to_curve(mut self, fit_to_curve: bool) -> Self {
+        self.brush.fit_to_curve = Some(fit_to_curve);
+        self
+    }
+
+    pub fn anti_aliased(mut self, anti_aliased: bool) -> Self {
+        self.brush.anti_aliased = Some(anti_aliased);
+        self
+    }
+
+    pub fn build(self) -> Brush {
+        self.brush
+    }
 }
 
 impl Writable for Brush {
     /// function to write the brush to the xml file
-    fn write<W: Write>(&self, writer: &mut EventWriter<W>) -> Result<(), Error> {
+    fn write<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+    ) -> Result<(), Error> {
+        self.write_with_transparency(writer, id_attribute, BlackTransparency::default())
+    }
+}
+
+impl Brush {
+    /// writes the brush, the transparency of a black color being written
+    /// following `black_transparency`
+    pub(crate) fn write_with_transparency<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+        black_transparency: BlackTransparency,
+    ) -> Result<(), Error> {
+        let transparency = self.color.transparency();
+        let (color, transparency) = match black_transparency {
+            _ if transparency == 0 || self.color.to_rgb() != (0, 0, 0) => {
+                (self.color.to_hex(), transparency)
+            }
+            BlackTransparency::Nudge => (Color::rgb(1, 1, 1).to_hex(), transparency),
+            BlackTransparency::Emit => (self.color.to_hex(), transparency),
+            BlackTransparency::AlphaInColor => (self.color.to_hex_alpha(), 0),
+        };
+
         // add brush
-        writer.write(XmlEvent::start_element("brush").attr("xml:id", &self.name))?;
+        writer.write(XmlEvent::start_element("brush").attr(id_attribute.name(), &self.name))?;
 
         writer.write(
             XmlEvent::start_element("brushProperty")
                 .attr("name", "width")
-                .attr("value", &format!("{}", self.stroke_width_cm))
+                .attr("value", &format!("{}", self.width_cm))
                 .attr("units", "cm"),
         )?;
         writer.write(XmlEvent::end_element())?;
         writer.write(
             XmlEvent::start_element("brushProperty")
                 .attr("name", "height")
-                .attr("value", &format!("{}", self.stroke_width_cm))
+                .attr("value", &format!("{}", self.height_cm))
                 .attr("units", "cm"),
         )?;
         writer.write(XmlEvent::end_element())?;
         writer.write(
             XmlEvent::start_element("brushProperty")
                 .attr("name", "color")
-                .attr(
-                    "value",
-                    &format!(
-                        "#{:02X}{:02X}{:02X}",
-                        self.color.0, self.color.1, self.color.2
-                    ),
-                ),
+                .attr("value", &color),
         )?;
         writer.write(XmlEvent::end_element())?;
-        // transparency work but only with colors != 0,0,0
-        if self.transparency > 0 && self.color != (0, 0, 0) {
+        if transparency > 0 {
             writer.write(
                 XmlEvent::start_element("brushProperty")
                     .attr("name", "transparency")
-                    .attr("value", &format!("{:?}", self.transparency)),
+                    .attr("value", &format!("{:?}", transparency)),
             )?;
             writer.write(XmlEvent::end_element())?;
         }
@@ -190,8 +544,160 @@ impl Writable for Brush {
             writer.write(XmlEvent::end_element())?;
         }
 
+        // tip and rasterOp are only written when they differ from the defaults
+        if self.tip != BrushTip::default() {
+            writer.write(
+                XmlEvent::start_element("brushProperty")
+                    .attr("name", "tip")
+                    .attr("value", &String::from(self.tip)),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+        }
+
+        if self.raster_op != RasterOp::default() {
+            writer.write(
+                XmlEvent::start_element("brushProperty")
+                    .attr("name", "rasterOp")
+                    .attr("value", &String::from(self.raster_op)),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+        }
+
+        for (property_name, property) in [
+            ("fitToCurve", self.fit_to_curve),
+            ("antiAliased", self.anti_aliased),
+        ] {
+            if let Some(value) = property {
+                writer.write(
+                    XmlEvent::start_element("brushProperty")
+                        .attr("name", property_name)
+                        .attr("value", if value { "1" } else { "0" }),
+                )?;
+                writer.write(XmlEvent::end_element())?;
+            }
+        }
+
         writer.write(XmlEvent::end_element())?; //close brush
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+    use xml::writer::EmitterConfig;
+
+    /// the brush read back from a document holding it
+    fn written_and_read(brush: &Brush) -> Brush {
+        let mut writer = EmitterConfig::new()
+            .write_document_declaration(false)
+            .create_writer(vec![]);
+        brush.write(&mut writer, IdAttribute::XmlId).unwrap();
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        let document = format!(
+            r#"<ink xmlns="http://www.w3.org/2003/InkML"><definitions>{written}</definitions></ink>"#
+        );
+        parse_str(&document).unwrap().brushes()[brush.name()].clone()
+    }
+
+    #[test]
+    fn raster_ops_are_read_from_their_written_name() {
+        use RasterOp::*;
+        for raster_op in [
+            Black,
+            NotMergePen,
+            MaskNotPen,
+            NotCopyPen,
+            MaskPenNot,
+            Not,
+            XorPen,
+            NotMaskPen,
+            MaskPen,
+            NotXorPen,
+            NoOperation,
+            MergeNotPen,
+            CopyPen,
+            MergePenNot,
+            MergePen,
+            White,
+        ] {
+            let name = Some(String::from(raster_op));
+            assert_eq!(RasterOp::parse(&name).unwrap(), raster_op);
+        }
+        assert!(RasterOp::parse(&Some(String::from("blend"))).is_err());
+    }
+
+    #[test]
+    fn tips_are_read_from_their_written_name() {
+        for tip in [BrushTip::Ellipse, BrushTip::Rectangle] {
+            assert_eq!(BrushTip::parse(&Some(String::from(tip))).unwrap(), tip);
+        }
+        assert!(BrushTip::parse(&Some(String::from("triangle"))).is_err());
+    }
+
+    #[test]
+    fn highlighter_brushes_survive_a_round_trip() {
+        let highlighter = Brush {
+            tip: BrushTip::Rectangle,
+            raster_op: RasterOp::MaskPen,
+            ..Brush::init(String::from("br0"), (255, 255, 0), false, 0, 0.5)
+        };
+        let read = written_and_read(&highlighter);
+        assert_eq!(read.tip, BrushTip::Rectangle);
+        assert_eq!(read.raster_op, RasterOp::MaskPen);
+
+        let pen = Brush::init(String::from("br1"), (0, 0, 0), false, 0, 0.1);
+        let read = written_and_read(&pen);
+        assert_eq!(read.tip, BrushTip::Ellipse);
+        assert_eq!(read.raster_op, RasterOp::CopyPen);
+    }
+
+    #[test]
+    fn onenote_highlighters_are_read() {
+        let document = std::fs::read_to_string("test_files/highlighter_onenote.xml").unwrap();
+        let brush = parse_str(&document).unwrap().brushes()["br0"].clone();
+        assert_eq!(brush.tip, BrushTip::Rectangle);
+        assert_eq!((brush.width_cm, brush.height_cm), (0.07, 0.5));
+    }
+
+    #[test]
+    fn smoothing_properties_survive_a_round_trip() {
+        let pen = Brush::init(String::from("br0"), (0, 0, 0), false, 0, 0.1);
+        for (fit_to_curve, anti_aliased) in [
+            (None, None),
+            (Some(true), Some(false)),
+            (Some(false), Some(true)),
+        ] {
+            let brush = Brush {
+                fit_to_curve,
+                anti_aliased,
+                ..pen.clone()
+            };
+            let read = written_and_read(&brush);
+            assert_eq!(
+                (read.fit_to_curve, read.anti_aliased),
+                (fit_to_curve, anti_aliased)
+            );
+        }
+    }
+
+    #[test]
+    fn smoothing_properties_are_read_as_booleans() {
+        let document = r#"<ink xmlns="http://www.w3.org/2003/InkML"><definitions>
+            <brush xml:id="br0">
+                <brushProperty name="fitToCurve" value="true" />
+                <brushProperty name="antiAliased" value="0" />
+            </brush>
+        </definitions></ink>"#;
+        let brush = parse_str(document).unwrap().brushes()["br0"].clone();
+        assert_eq!(
+            (brush.fit_to_curve, brush.anti_aliased),
+            (Some(true), Some(false))
+        );
+
+        let invalid = document.replace(r#"value="true""#, r#"value="yes""#);
+        assert!(parse_str(&invalid).is_err());
+    }
+}
@@ -0,0 +1,690 @@
+// encoding and decoding of the text of traces : values of the channels,
+// with their difference modifiers (`'` and `"`)
+//
+// Only needs `core` and `alloc` so that it can be used without std
+// (`default-features = false`), e.g. by the firmware of a pen capture device
+// writing traces the parser reads back
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use anyhow::anyhow;
+
+/// type used for the encoding
+#[derive(Clone, Debug, PartialEq)]
+#[allow(unused)]
+#[derive(Default)]
+pub enum ChannelType {
+    Integer,
+    #[default]
+    Decimal,
+    Double,
+    Bool,
+}
+
+impl ChannelType {
+    pub fn get_null_value(self: &ChannelType) -> ChannelDataEl {
+        match self {
+            ChannelType::Integer => ChannelDataEl::Integer(0),
+            ChannelType::Decimal => ChannelDataEl::Double(0.0),
+            ChannelType::Bool => ChannelDataEl::Bool,
+            ChannelType::Double => ChannelDataEl::Double(0.0),
+        }
+    }
+}
+
+/// How the values of a trace are written
+/// (the parser reads all of them)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceEncoding {
+    /// absolute values : `10 10,11 12,13 15`
+    #[default]
+    Explicit,
+    /// first point absolute, then the difference with the previous point
+    /// `10 10,'1'2,'2'3`
+    SingleDifference,
+    /// first point absolute, second one as a difference, then the difference
+    /// between successive differences : `10 10,'1'2,"1"1`
+    /// (smallest output for smooth strokes)
+    DoubleDifference,
+}
+
+/// polymorphic enum to hold the data from a trace before a resolution conversion
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelData {
+    Integer(Vec<i64>),
+    Bool(Vec<bool>),
+    Double(Vec<f64>),
+}
+
+impl ChannelData {
+    /// number of values of the channel
+    pub fn len(&self) -> usize {
+        match self {
+            ChannelData::Integer(int_vec) => int_vec.len(),
+            ChannelData::Bool(bool_vec) => bool_vec.len(),
+            ChannelData::Double(double_vec) => double_vec.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the value at `index`, formatted as in a trace with the given encoding
+    /// (booleans are always explicit)
+    /// Doubles are written with `precision` decimals if given
+    fn format_value(
+        &self,
+        index: usize,
+        encoding: TraceEncoding,
+        precision: Option<usize>,
+    ) -> Option<String> {
+        // the encoding actually used for this point : the first point is explicit
+        // and a double difference needs two previous points
+        let encoding = match (encoding, index) {
+            (_, 0) => TraceEncoding::Explicit,
+            (TraceEncoding::DoubleDifference, 1) => TraceEncoding::SingleDifference,
+            (encoding, _) => encoding,
+        };
+        match self {
+            ChannelData::Integer(int_vec) => int_vec.get(index).map(|x| match encoding {
+                TraceEncoding::Explicit => format!("{x}"),
+                TraceEncoding::SingleDifference => format!("'{}", x - int_vec[index - 1]),
+                TraceEncoding::DoubleDifference => format!(
+                    "\"{}",
+                    (x - int_vec[index - 1]) - (int_vec[index - 1] - int_vec[index - 2])
+                ),
+            }),
+            ChannelData::Bool(bool_vec) => bool_vec
+                .get(index)
+                .map(|x| String::from(if *x { "T" } else { "F" })),
+            ChannelData::Double(double_vec) => {
+                // with a precision, differences are taken between rounded values
+                // so that rounding errors don't accumulate when they are summed back
+                let value = |index: usize| match precision {
+                    Some(precision) => round_to(double_vec[index], precision),
+                    None => double_vec[index],
+                };
+                let format = |x: f64| match precision {
+                    Some(precision) => format_decimal(x, precision),
+                    None => format!("{x}"),
+                };
+                double_vec.get(index).map(|_| match encoding {
+                    TraceEncoding::Explicit => format(value(index)),
+                    TraceEncoding::SingleDifference => {
+                        format!("'{}", format(value(index) - value(index - 1)))
+                    }
+                    TraceEncoding::DoubleDifference => format!(
+                        "\"{}",
+                        format(
+                            (value(index) - value(index - 1))
+                                - (value(index - 1) - value(index - 2))
+                        )
+                    ),
+                })
+            }
+        }
+    }
+}
+
+/// rounds `x` to `precision` decimals
+#[cfg(feature = "std")]
+fn round_to(x: f64, precision: usize) -> f64 {
+    let factor = 10f64.powi(precision as i32);
+    (x * factor).round() / factor
+}
+
+/// rounds `x` to `precision` decimals (half away from zero, as `f64::round`,
+/// which needs std)
+#[cfg(not(feature = "std"))]
+fn round_to(x: f64, precision: usize) -> f64 {
+    let factor = (0..precision).fold(1.0, |factor, _| factor * 10.0);
+    let scaled = x * factor;
+    // from 2^52 on, floats have no decimals
+    if scaled.is_nan() || scaled.abs() >= 4503599627370496.0 {
+        return scaled / factor;
+    }
+    let truncated = scaled as i64 as f64;
+    let rounded = match scaled - truncated {
+        fraction if fraction >= 0.5 => truncated + 1.0,
+        fraction if fraction <= -0.5 => truncated - 1.0,
+        // keeps the sign of -0
+        _ if truncated == 0.0 => 0.0 * scaled,
+        _ => truncated,
+    };
+    rounded / factor
+}
+
+/// formats `x` with at most `precision` decimals, without trailing zeros
+fn format_decimal(x: f64, precision: usize) -> String {
+    let formatted = format!("{:.*}", precision, x);
+    let formatted = if formatted.contains('.') {
+        formatted.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &formatted
+    };
+    if formatted == "-0" {
+        String::from("0")
+    } else {
+        String::from(formatted)
+    }
+}
+
+/// formats raw channel data as the text of a trace
+/// (doubles with `precision` decimals if given)
+pub fn format_trace(
+    data: &[ChannelData],
+    encoding: TraceEncoding,
+    precision: Option<usize>,
+) -> String {
+    let num_points = data.iter().map(|x| x.len()).min().unwrap_or(0);
+    (0..num_points)
+        .map(|index| {
+            let values = data
+                .iter()
+                .filter_map(|channel| channel.format_value(index, encoding, precision))
+                .collect::<Vec<String>>();
+            // values starting with a difference modifier don't need a separator
+            if values.iter().all(|value| value.starts_with(['\'', '"'])) {
+                values.concat()
+            } else {
+                values.join(" ")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// polymorhpic enum to hold the data from a point of the trace
+/// Only used for holding the last element or difference (in order to calculate
+/// 'x or "y)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelDataEl {
+    Integer(i64),
+    Double(f64),
+    Bool,
+}
+
+impl From<ChannelDataEl> for String {
+    fn from(value: ChannelDataEl) -> Self {
+        match value {
+            ChannelDataEl::Bool => String::from("1"),
+            ChannelDataEl::Double(value) => format!("{value}"),
+            ChannelDataEl::Integer(value) => format!("{value}"),
+        }
+    }
+}
+
+/// Type of modifier
+/// Used as a token before the corresponding value is parsed
+#[derive(Debug, Clone, Copy, Default)]
+enum ValueModifier {
+    #[default]
+    Explicit,
+    SingleDifference,
+    DoubleDifference,
+}
+
+impl ChannelData {
+    fn map_from_channel_type(ch_type: ChannelType) -> ChannelData {
+        match ch_type {
+            ChannelType::Integer => ChannelData::Integer(vec![]),
+            ChannelType::Decimal => ChannelData::Double(vec![]),
+            ChannelType::Bool => ChannelData::Bool(vec![]),
+            ChannelType::Double => ChannelData::Double(vec![]),
+        }
+    }
+}
+
+/// Decoder for the text of traces
+///
+/// The same decoder can be reused for all the traces of a document (see `reset`)
+/// so that its buffers are only allocated once
+#[derive(Default)]
+pub struct TraceData {
+    data: Vec<ChannelData>,
+    last_value_modifiers: Vec<ValueModifier>,
+    last_value_difference: Vec<ChannelDataEl>,
+    /// the index of the channel we are currently parsing the data for
+    index_channel: usize,
+    /// to switch to the new modifier if it's found before the numerical value
+    /// Hence we are yet to have the value info to create the nextr LastValueModifier
+    new_modifier: ValueModifier,
+    /// points missing their last channels repeat the previous values
+    fill_missing_channels: bool,
+}
+
+impl TraceData {
+    /// decoder filling the missing channels of points or not
+    /// (see `ParserOptions::fill_missing_channels`)
+    pub fn new(fill_missing_channels: bool) -> TraceData {
+        TraceData {
+            fill_missing_channels,
+            ..Default::default()
+        }
+    }
+
+    /// gives back the parsed data, leaving empty channels of the same types
+    pub fn take_data(&mut self) -> Vec<ChannelData> {
+        let empty = self
+            .data
+            .iter()
+            .map(|channel| match channel {
+                ChannelData::Integer(_) => ChannelData::Integer(vec![]),
+                ChannelData::Bool(_) => ChannelData::Bool(vec![]),
+                ChannelData::Double(_) => ChannelData::Double(vec![]),
+            })
+            .collect();
+        core::mem::replace(&mut self.data, empty)
+    }
+
+    /// prepares the decoder for a new trace with channels of types `types`
+    /// (the buffers of the previous trace are reused)
+    pub fn reset(&mut self, types: impl IntoIterator<Item = ChannelType>) {
+        self.data.clear();
+        self.last_value_difference.clear();
+        for channel_type in types {
+            self.last_value_difference
+                .push(channel_type.get_null_value());
+            self.data
+                .push(ChannelData::map_from_channel_type(channel_type));
+        }
+        self.last_value_modifiers.clear();
+        self.last_value_modifiers
+            .resize(self.data.len(), ValueModifier::Explicit);
+        self.index_channel = 0;
+        self.new_modifier = ValueModifier::Explicit;
+    }
+
+    pub fn parse_raw_data(&mut self, line_str: &str) -> anyhow::Result<()> {
+        //line_str : ex '37'-40'1680'0'0
+        // one element from the trace string after
+        // splitting per ,
+        let num_channels = self.last_value_modifiers.len();
+        for line in line_str.split(',') {
+            // blank points (trailing comma, `10 10,,20 20`) are skipped
+            if line.trim().is_empty() {
+                continue;
+            }
+            // reset the variables
+            self.index_channel = 0;
+            // values are parsed directly from slices of the line :
+            // start of the value we are currently reading (if any)
+            let mut value_start: Option<usize> = None;
+            let bytes = line.as_bytes();
+            let mut position = 0;
+
+            // will store the modifier : updated if needed
+            self.new_modifier = *self
+                .last_value_modifiers
+                .first()
+                .ok_or_else(|| anyhow!("The trace has no channel"))?;
+            while self.index_channel < num_channels {
+                match bytes.get(position) {
+                    Some(next_byte) => {
+                        match next_byte {
+                            b' ' | b'\r' | b'\n' | b'\t' => {
+                                if let Some(start) = value_start.take() {
+                                    self.push_found_value(&line[start..position])?;
+                                }
+                            }
+                            b'!' | b'\'' | b'"' => {
+                                self.new_modifier = match next_byte {
+                                    b'!' => ValueModifier::Explicit,
+                                    b'\'' => ValueModifier::SingleDifference,
+                                    _ => ValueModifier::DoubleDifference,
+                                };
+                                if let Some(start) = value_start.take() {
+                                    self.push_found_value(&line[start..position])?;
+                                }
+                            }
+                            b'0'..=b'9' | b'.' => {
+                                value_start.get_or_insert(position);
+                            }
+                            // exponent of a decimal value (1.2e3)
+                            b'e' | b'E' if value_start.is_some() => {}
+                            // sign of the exponent (1.2e-3)
+                            b'-' | b'+'
+                                if value_start.is_some()
+                                    && matches!(bytes[position - 1], b'e' | b'E') => {}
+                            b'-' | b'+' => {
+                                // 0-12 is valid syntax !!
+                                if let Some(start) = value_start {
+                                    // if two values are concatenated with no space in between
+                                    // parse the value up till now
+                                    self.push_found_value(&line[start..position])?;
+
+                                    // then restart
+                                    self.new_modifier = *self
+                                        .last_value_modifiers
+                                        .get(self.index_channel)
+                                        .ok_or_else(|| anyhow!("Could not find the last value modified for the current channel"))?;
+                                }
+                                value_start = Some(position);
+                            }
+                            b'T' | b'F' => {
+                                // for boolean traces
+                                let start = value_start.take().unwrap_or(position);
+                                self.push_found_value(&line[start..=position])?;
+                            }
+                            _ => {
+                                // all bytes before are ascii so this is a char boundary
+                                let next_char = line[position..].chars().next().unwrap_or('?');
+                                return Err(anyhow!("Unexpected char {next_char} found"));
+                            }
+                        }
+                        position += 1;
+                    }
+                    None => {
+                        // we expect to have situation like 0,
+                        // hence we have None but we have parsed correctly
+                        if let Some(start) = value_start.take() {
+                            self.push_found_value(&line[start..])?;
+                        } else if self.fill_missing_channels && self.index_channel > 0 {
+                            self.repeat_previous_value()?;
+                        } else {
+                            return Err(anyhow!("Unexpected end. Expected more data before the end of the current trace"));
+                            // we have exhausted the whole line before
+                            // parsing all channel data ...
+                            // Remark : needed so that we never loop forever
+                        }
+                    }
+                }
+            }
+
+            // verify that the end of the line is all spaces
+            // check that we have not more ignored data further down
+            if let Some(next_char) = line
+                .get(position..)
+                .unwrap_or("")
+                .chars()
+                .find(|next_char| !matches!(next_char, ' ' | '\r' | '\n' | '\t'))
+            {
+                return Err(anyhow!(
+                    "char not expected {:?}, we only expected space-like elements",
+                    next_char
+                )); //there was something left ...
+            }
+        }
+
+        Ok(())
+    }
+
+    /// gives the current channel the value it had on the previous point
+    fn repeat_previous_value(&mut self) -> anyhow::Result<()> {
+        let index_channel = self.index_channel;
+        let missing =
+            || anyhow!("A point is missing values and there's no previous point to take them from");
+        let difference = match self
+            .data
+            .get_mut(index_channel)
+            .ok_or_else(|| anyhow!("Could not find the current channel"))?
+        {
+            ChannelData::Integer(current) => {
+                current.push(*current.last().ok_or_else(missing)?);
+                ChannelDataEl::Integer(0)
+            }
+            ChannelData::Double(current) => {
+                current.push(*current.last().ok_or_else(missing)?);
+                ChannelDataEl::Double(0.0)
+            }
+            ChannelData::Bool(current) => {
+                current.push(*current.last().ok_or_else(missing)?);
+                ChannelDataEl::Bool
+            }
+        };
+        // as if the value was given explicitly
+        self.last_value_difference[index_channel] = difference;
+        self.last_value_modifiers[index_channel] = ValueModifier::Explicit;
+        self.index_channel += 1;
+        Ok(())
+    }
+
+    fn push_found_value(&mut self, value_str: &str) -> anyhow::Result<()> {
+        let index_channel = self.index_channel;
+        // push to the corresponding channel
+        match self
+            .data
+            .get_mut(index_channel)
+            .ok_or_else(|| anyhow!("Could not find the current channel"))?
+        {
+            ChannelData::Integer(current) => {
+                let value = value_str
+                    .parse::<i64>()
+                    .map_err(|e| anyhow!("{e} : Could not parse the value {value_str} as int"))?;
+                let last_difference = match self.last_value_difference[index_channel] {
+                    ChannelDataEl::Integer(last_difference) => last_difference,
+                    _ => {
+                        return Err(anyhow!(
+                            "The saved previous element for the channel is incorrect"
+                        ))
+                    }
+                };
+                let previous = current.last().copied();
+                // malformed traces can overflow
+                let overflow = || anyhow!("Integer overflow while decoding the value {value_str}");
+                let (new_value, new_difference) = match (self.new_modifier, previous) {
+                    // keep the difference with the previous value for
+                    // a double difference that would follow
+                    (ValueModifier::Explicit, Some(previous)) => {
+                        (value, value.checked_sub(previous).ok_or_else(overflow)?)
+                    }
+                    (ValueModifier::Explicit, None) => (value, last_difference),
+                    // the value is the new difference
+                    (ValueModifier::SingleDifference, Some(previous)) => {
+                        (value.checked_add(previous).ok_or_else(overflow)?, value)
+                    }
+                    (ValueModifier::DoubleDifference, Some(previous)) => {
+                        let difference = last_difference.checked_add(value).ok_or_else(overflow)?;
+                        (
+                            previous.checked_add(difference).ok_or_else(overflow)?,
+                            difference,
+                        )
+                    }
+                    (_, None) => return Err(missing_previous_value(self.new_modifier)),
+                };
+                self.last_value_difference[index_channel] = ChannelDataEl::Integer(new_difference);
+                current.push(new_value);
+            }
+            ChannelData::Double(current) => {
+                let value = value_str
+                    .parse::<f64>()
+                    .map_err(|e| anyhow!("{e} : Could not parse the value {value_str} to float"))?;
+                let last_difference = match self.last_value_difference[index_channel] {
+                    ChannelDataEl::Double(last_difference) => last_difference,
+                    _ => {
+                        return Err(anyhow!(
+                            "The saved previous element for the channel is incorrect"
+                        ))
+                    }
+                };
+                let previous = current.last().copied();
+                let (new_value, new_difference) = match (self.new_modifier, previous) {
+                    (ValueModifier::Explicit, Some(previous)) => (value, value - previous),
+                    (ValueModifier::Explicit, None) => (value, last_difference),
+                    (ValueModifier::SingleDifference, Some(previous)) => (value + previous, value),
+                    (ValueModifier::DoubleDifference, Some(previous)) => {
+                        (value + previous + last_difference, last_difference + value)
+                    }
+                    (_, None) => return Err(missing_previous_value(self.new_modifier)),
+                };
+                self.last_value_difference[index_channel] = ChannelDataEl::Double(new_difference);
+                current.push(new_value);
+            }
+            ChannelData::Bool(current) => {
+                // boolean : will be true or false, not changing anything there
+                // so effectively the corresponding index in the last_value_difference
+                // element is unused
+                match value_str {
+                    "T" => current.push(true),
+                    "F" => current.push(false),
+                    _ => return Err(anyhow!("Could not parse to bool the value {:?}", value_str)),
+                }
+            }
+        }
+
+        self.last_value_modifiers[index_channel] = self.new_modifier;
+        self.index_channel += 1;
+        Ok(())
+    }
+}
+
+/// error for a difference found on the first value of a channel
+fn missing_previous_value(modifier: ValueModifier) -> anyhow::Error {
+    anyhow!(
+        "Could not find the previous value for the channel.
+        This is unexpected as we found a {:?} modifier, so the value is
+        calculated relative to the previous one",
+        modifier
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the channels read back from the text of a trace
+    fn parsed(text: &str, types: &[ChannelType]) -> Vec<ChannelData> {
+        let mut trace_data = TraceData::new(false);
+        trace_data.reset(types.iter().cloned());
+        trace_data.parse_raw_data(text).unwrap();
+        trace_data.take_data()
+    }
+
+    #[test]
+    fn differences_are_written_after_the_first_points() {
+        let data = [
+            ChannelData::Integer(vec![10, 11, 13, 16]),
+            ChannelData::Integer(vec![10, 12, 15, 19]),
+        ];
+        assert_eq!(
+            format_trace(&data, TraceEncoding::Explicit, None),
+            "10 10,11 12,13 15,16 19"
+        );
+        assert_eq!(
+            format_trace(&data, TraceEncoding::SingleDifference, None),
+            "10 10,'1'2,'2'3,'3'4"
+        );
+        assert_eq!(
+            format_trace(&data, TraceEncoding::DoubleDifference, None),
+            "10 10,'1'2,\"1\"1,\"1\"1"
+        );
+    }
+
+    #[test]
+    fn encoded_traces_are_read_back() {
+        let types = [
+            ChannelType::Integer,
+            ChannelType::Integer,
+            ChannelType::Bool,
+        ];
+        let data = vec![
+            ChannelData::Integer(vec![10, 11, 13, 16, 16]),
+            ChannelData::Integer(vec![10, -2, 15, 19, 0]),
+            ChannelData::Bool(vec![true, false, false, true, true]),
+        ];
+        for encoding in [
+            TraceEncoding::Explicit,
+            TraceEncoding::SingleDifference,
+            TraceEncoding::DoubleDifference,
+        ] {
+            let text = format_trace(&data, encoding, None);
+            assert_eq!(parsed(&text, &types), data, "{encoding:?} : {text}");
+        }
+    }
+
+    #[test]
+    fn decimals_are_written_without_trailing_zeros() {
+        assert_eq!(format_decimal(1.5, 3), "1.5");
+        assert_eq!(format_decimal(2.0, 3), "2");
+        assert_eq!(format_decimal(1.23456, 3), "1.235");
+        assert_eq!(format_decimal(-0.0001, 3), "0");
+        assert_eq!(round_to(1.23456, 2), 1.23);
+        assert_eq!(round_to(-1.235, 0), -1.0);
+    }
+
+    #[test]
+    fn decimal_differences_dont_accumulate_rounding_errors() {
+        let x = vec![0.1234, 0.2468, 0.3702, 0.4936, 0.617];
+        let data = [ChannelData::Double(x.clone())];
+        for encoding in [
+            TraceEncoding::Explicit,
+            TraceEncoding::SingleDifference,
+            TraceEncoding::DoubleDifference,
+        ] {
+            let text = format_trace(&data, encoding, Some(2));
+            let ChannelData::Double(read) = &parsed(&text, &[ChannelType::Double])[0] else {
+                panic!("{text} was not read as doubles");
+            };
+            for (read, x) in read.iter().zip(&x) {
+                assert!(
+                    (read - round_to(*x, 2)).abs() < 1e-9,
+                    "{encoding:?} : {text}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scientific_notation_and_plus_signs_are_read() {
+        let types = [ChannelType::Double, ChannelType::Double];
+        assert_eq!(
+            parsed("1.2e3 +4.5,-1.5E-2 2e+1", &types),
+            [
+                ChannelData::Double(vec![1200.0, -0.015]),
+                ChannelData::Double(vec![4.5, 20.0])
+            ]
+        );
+        let types = [ChannelType::Integer, ChannelType::Integer];
+        assert_eq!(
+            parsed("+5 -3,0-12", &types),
+            [
+                ChannelData::Integer(vec![5, 0]),
+                ChannelData::Integer(vec![-3, -12])
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_points_are_skipped() {
+        let types = [ChannelType::Integer, ChannelType::Integer];
+        assert_eq!(
+            parsed("10 10,,20 20, ,30 30,", &types),
+            [
+                ChannelData::Integer(vec![10, 20, 30]),
+                ChannelData::Integer(vec![10, 20, 30])
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_channels_are_filled_only_when_asked() {
+        let types = [
+            ChannelType::Integer,
+            ChannelType::Integer,
+            ChannelType::Integer,
+        ];
+        let text = "10 10 5,11 11,20 20";
+
+        let mut strict = TraceData::new(false);
+        strict.reset(types.iter().cloned());
+        assert!(strict.parse_raw_data(text).is_err());
+
+        let mut filling = TraceData::new(true);
+        filling.reset(types.iter().cloned());
+        filling.parse_raw_data(text).unwrap();
+        assert_eq!(
+            filling.take_data(),
+            [
+                ChannelData::Integer(vec![10, 11, 20]),
+                ChannelData::Integer(vec![10, 11, 20]),
+                ChannelData::Integer(vec![5, 5, 5])
+            ]
+        );
+
+        // the first point has no previous values to take
+        let mut filling = TraceData::new(true);
+        filling.reset(types.iter().cloned());
+        assert!(filling.parse_raw_data("10 10").is_err());
+    }
+}
@@ -0,0 +1,395 @@
+// color values for the `color` brush property
+// Besides the `#RRGGBB` format of the spec, some producers write
+// css colors : names (`blue`), `rgb(0,0,255)` or other hex lengths
+
+use anyhow::anyhow;
+use std::fmt;
+use std::str::FromStr;
+
+/// RGBA color, `a` going from 0 (invisible) to 255 (opaque)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+
+    /// opaque color
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color {
+            r,
+            g,
+            b,
+            a: u8::MAX,
+        }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+
+    /// RGB triplet
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        (self.r, self.g, self.b)
+    }
+
+    /// the inkml `transparency` : 0 for opaque, 255 for fully transparent
+    pub fn transparency(self) -> u8 {
+        u8::MAX - self.a
+    }
+
+    /// the same color with the inkml `transparency` (0 : opaque, 255 : fully transparent)
+    pub fn with_transparency(self, transparency: u8) -> Color {
+        Color {
+            a: u8::MAX - transparency,
+            ..self
+        }
+    }
+
+    /// `#RRGGBB`, the format of the spec (the alpha is left out)
+    pub fn to_hex(self) -> String {
+        format!("#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+
+    /// `#RRGGBBAA`
+    pub fn to_hex_alpha(self) -> String {
+        format!("{}{:02X}", self.to_hex(), self.a)
+    }
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Color::BLACK
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Color::rgb(r, g, b)
+    }
+}
+
+impl From<(u8, u8, u8, u8)> for Color {
+    fn from((r, g, b, a): (u8, u8, u8, u8)) -> Self {
+        Color::rgba(r, g, b, a)
+    }
+}
+
+impl From<Color> for (u8, u8, u8) {
+    fn from(color: Color) -> Self {
+        color.to_rgb()
+    }
+}
+
+/// same formats as the `color` brush property (see `parse_color`),
+/// colors without alpha are opaque
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(color_string: &str) -> Result<Self, Self::Err> {
+        let ((r, g, b), alpha) = parse_color(color_string)?;
+        Ok(Color::rgba(r, g, b, alpha.unwrap_or(u8::MAX)))
+    }
+}
+
+/// `#RRGGBB` for opaque colors, `#RRGGBBAA` otherwise
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.a == u8::MAX {
+            write!(f, "{}", self.to_hex())
+        } else {
+            write!(f, "{}", self.to_hex_alpha())
+        }
+    }
+}
+
+/// Parses a color value
+/// Returns the RGB triplet and the alpha value if the color has one
+///
+/// Accepted formats :
+/// - `#RGB`, `#RGBA`, `#RRGGBB` and `#RRGGBBAA`
+/// - `rgb(r, g, b)` and `rgba(r, g, b, a)` with `a` between 0 and 1
+/// - css color names (case insensitive)
+pub(crate) fn parse_color(color_string: &str) -> anyhow::Result<((u8, u8, u8), Option<u8>)> {
+    let color_string = color_string.trim();
+    if let Some(hex) = color_string.strip_prefix('#') {
+        parse_hex(hex).ok_or(anyhow!("Failed to parse {color_string}"))
+    } else if let Some(components) = color_string
+        .strip_prefix("rgba(")
+        .or(color_string.strip_prefix("rgb("))
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        parse_rgb_function(components).ok_or(anyhow!("Failed to parse {color_string}"))
+    } else {
+        CSS_COLORS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(color_string))
+            .map(|(_, color)| (*color, None))
+            .ok_or(anyhow!(
+                "Unexpected color {color_string}, expected an hex color, rgb() or a color name"
+            ))
+    }
+}
+
+/// parses the hex digits of a color, without the `#`
+fn parse_hex(hex: &str) -> Option<((u8, u8, u8), Option<u8>)> {
+    if !hex.is_ascii() {
+        return None;
+    }
+    // short forms : each digit is doubled (#F0A is #FF00AA)
+    let short = |index: usize| {
+        u8::from_str_radix(&hex[index..=index], 16)
+            .ok()
+            .map(|v| v * 17)
+    };
+    let long = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+    match hex.len() {
+        3 => Some(((short(0)?, short(1)?, short(2)?), None)),
+        4 => Some(((short(0)?, short(1)?, short(2)?), Some(short(3)?))),
+        6 => Some(((long(0)?, long(2)?, long(4)?), None)),
+        8 => Some(((long(0)?, long(2)?, long(4)?), Some(long(6)?))),
+        _ => None,
+    }
+}
+
+/// parses the inside of `rgb(r, g, b)` or `rgba(r, g, b, a)`
+fn parse_rgb_function(components: &str) -> Option<((u8, u8, u8), Option<u8>)> {
+    let values = components.split(',').map(str::trim).collect::<Vec<&str>>();
+    let channel = |index: usize| values[index].parse::<u8>().ok();
+    match values.len() {
+        3 => Some(((channel(0)?, channel(1)?, channel(2)?), None)),
+        4 => {
+            let alpha = values[3].parse::<f64>().ok()?;
+            if !(0.0..=1.0).contains(&alpha) {
+                return None;
+            }
+            Some((
+                (channel(0)?, channel(1)?, channel(2)?),
+                Some((alpha * 255.0).round() as u8),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// css named colors
+const CSS_COLORS: [(&str, (u8, u8, u8)); 148] = [
+    ("aliceblue", (0xF0, 0xF8, 0xFF)),
+    ("antiquewhite", (0xFA, 0xEB, 0xD7)),
+    ("aqua", (0x00, 0xFF, 0xFF)),
+    ("aquamarine", (0x7F, 0xFF, 0xD4)),
+    ("azure", (0xF0, 0xFF, 0xFF)),
+    ("beige", (0xF5, 0xF5, 0xDC)),
+    ("bisque", (0xFF, 0xE4, 0xC4)),
+    ("black", (0x00, 0x00, 0x00)),
+    ("blanchedalmond", (0xFF, 0xEB, 0xCD)),
+    ("blue", (0x00, 0x00, 0xFF)),
+    ("blueviolet", (0x8A, 0x2B, 0xE2)),
+    ("brown", (0xA5, 0x2A, 0x2A)),
+    ("burlywood", (0xDE, 0xB8, 0x87)),
+    ("cadetblue", (0x5F, 0x9E, 0xA0)),
+    ("chartreuse", (0x7F, 0xFF, 0x00)),
+    ("chocolate", (0xD2, 0x69, 0x1E)),
+    ("coral", (0xFF, 0x7F, 0x50)),
+    ("cornflowerblue", (0x64, 0x95, 0xED)),
+    ("cornsilk", (0xFF, 0xF8, 0xDC)),
+    ("crimson", (0xDC, 0x14, 0x3C)),
+    ("cyan", (0x00, 0xFF, 0xFF)),
+    ("darkblue", (0x00, 0x00, 0x8B)),
+    ("darkcyan", (0x00, 0x8B, 0x8B)),
+    ("darkgoldenrod", (0xB8, 0x86, 0x0B)),
+    ("darkgray", (0xA9, 0xA9, 0xA9)),
+    ("darkgreen", (0x00, 0x64, 0x00)),
+    ("darkgrey", (0xA9, 0xA9, 0xA9)),
+    ("darkkhaki", (0xBD, 0xB7, 0x6B)),
+    ("darkmagenta", (0x8B, 0x00, 0x8B)),
+    ("darkolivegreen", (0x55, 0x6B, 0x2F)),
+    ("darkorange", (0xFF, 0x8C, 0x00)),
+    ("darkorchid", (0x99, 0x32, 0xCC)),
+    ("darkred", (0x8B, 0x00, 0x00)),
+    ("darksalmon", (0xE9, 0x96, 0x7A)),
+    ("darkseagreen", (0x8F, 0xBC, 0x8F)),
+    ("darkslateblue", (0x48, 0x3D, 0x8B)),
+    ("darkslategray", (0x2F, 0x4F, 0x4F)),
+    ("darkslategrey", (0x2F, 0x4F, 0x4F)),
+    ("darkturquoise", (0x00, 0xCE, 0xD1)),
+    ("darkviolet", (0x94, 0x00, 0xD3)),
+    ("deeppink", (0xFF, 0x14, 0x93)),
+    ("deepskyblue", (0x00, 0xBF, 0xFF)),
+    ("dimgray", (0x69, 0x69, 0x69)),
+    ("dimgrey", (0x69, 0x69, 0x69)),
+    ("dodgerblue", (0x1E, 0x90, 0xFF)),
+    ("firebrick", (0xB2, 0x22, 0x22)),
+    ("floralwhite", (0xFF, 0xFA, 0xF0)),
+    ("forestgreen", (0x22, 0x8B, 0x22)),
+    ("fuchsia", (0xFF, 0x00, 0xFF)),
+    ("gainsboro", (0xDC, 0xDC, 0xDC)),
+    ("ghostwhite", (0xF8, 0xF8, 0xFF)),
+    ("gold", (0xFF, 0xD7, 0x00)),
+    ("goldenrod", (0xDA, 0xA5, 0x20)),
+    ("gray", (0x80, 0x80, 0x80)),
+    ("green", (0x00, 0x80, 0x00)),
+    ("greenyellow", (0xAD, 0xFF, 0x2F)),
+    ("grey", (0x80, 0x80, 0x80)),
+    ("honeydew", (0xF0, 0xFF, 0xF0)),
+    ("hotpink", (0xFF, 0x69, 0xB4)),
+    ("indianred", (0xCD, 0x5C, 0x5C)),
+    ("indigo", (0x4B, 0x00, 0x82)),
+    ("ivory", (0xFF, 0xFF, 0xF0)),
+    ("khaki", (0xF0, 0xE6, 0x8C)),
+    ("lavender", (0xE6, 0xE6, 0xFA)),
+    ("lavenderblush", (0xFF, 0xF0, 0xF5)),
+    ("lawngreen", (0x7C, 0xFC, 0x00)),
+    ("lemonchiffon", (0xFF, 0xFA, 0xCD)),
+    ("lightblue", (0xAD, 0xD8, 0xE6)),
+    ("lightcoral", (0xF0, 0x80, 0x80)),
+    ("lightcyan", (0xE0, 0xFF, 0xFF)),
+    ("lightgoldenrodyellow", (0xFA, 0xFA, 0xD2)),
+    ("lightgray", (0xD3, 0xD3, 0xD3)),
+    ("lightgreen", (0x90, 0xEE, 0x90)),
+    ("lightgrey", (0xD3, 0xD3, 0xD3)),
+    ("lightpink", (0xFF, 0xB6, 0xC1)),
+    ("lightsalmon", (0xFF, 0xA0, 0x7A)),
+    ("lightseagreen", (0x20, 0xB2, 0xAA)),
+    ("lightskyblue", (0x87, 0xCE, 0xFA)),
+    ("lightslategray", (0x77, 0x88, 0x99)),
+    ("lightslategrey", (0x77, 0x88, 0x99)),
+    ("lightsteelblue", (0xB0, 0xC4, 0xDE)),
+    ("lightyellow", (0xFF, 0xFF, 0xE0)),
+    ("lime", (0x00, 0xFF, 0x00)),
+    ("limegreen", (0x32, 0xCD, 0x32)),
+    ("linen", (0xFA, 0xF0, 0xE6)),
+    ("magenta", (0xFF, 0x00, 0xFF)),
+    ("maroon", (0x80, 0x00, 0x00)),
+    ("mediumaquamarine", (0x66, 0xCD, 0xAA)),
+    ("mediumblue", (0x00, 0x00, 0xCD)),
+    ("mediumorchid", (0xBA, 0x55, 0xD3)),
+    ("mediumpurple", (0x93, 0x70, 0xDB)),
+    ("mediumseagreen", (0x3C, 0xB3, 0x71)),
+    ("mediumslateblue", (0x7B, 0x68, 0xEE)),
+    ("mediumspringgreen", (0x00, 0xFA, 0x9A)),
+    ("mediumturquoise", (0x48, 0xD1, 0xCC)),
+    ("mediumvioletred", (0xC7, 0x15, 0x85)),
+    ("midnightblue", (0x19, 0x19, 0x70)),
+    ("mintcream", (0xF5, 0xFF, 0xFA)),
+    ("mistyrose", (0xFF, 0xE4, 0xE1)),
+    ("moccasin", (0xFF, 0xE4, 0xB5)),
+    ("navajowhite", (0xFF, 0xDE, 0xAD)),
+    ("navy", (0x00, 0x00, 0x80)),
+    ("oldlace", (0xFD, 0xF5, 0xE6)),
+    ("olive", (0x80, 0x80, 0x00)),
+    ("olivedrab", (0x6B, 0x8E, 0x23)),
+    ("orange", (0xFF, 0xA5, 0x00)),
+    ("orangered", (0xFF, 0x45, 0x00)),
+    ("orchid", (0xDA, 0x70, 0xD6)),
+    ("palegoldenrod", (0xEE, 0xE8, 0xAA)),
+    ("palegreen", (0x98, 0xFB, 0x98)),
+    ("paleturquoise", (0xAF, 0xEE, 0xEE)),
+    ("palevioletred", (0xDB, 0x70, 0x93)),
+    ("papayawhip", (0xFF, 0xEF, 0xD5)),
+    ("peachpuff", (0xFF, 0xDA, 0xB9)),
+    ("peru", (0xCD, 0x85, 0x3F)),
+    ("pink", (0xFF, 0xC0, 0xCB)),
+    ("plum", (0xDD, 0xA0, 0xDD)),
+    ("powderblue", (0xB0, 0xE0, 0xE6)),
+    ("purple", (0x80, 0x00, 0x80)),
+    ("rebeccapurple", (0x66, 0x33, 0x99)),
+    ("red", (0xFF, 0x00, 0x00)),
+    ("rosybrown", (0xBC, 0x8F, 0x8F)),
+    ("royalblue", (0x41, 0x69, 0xE1)),
+    ("saddlebrown", (0x8B, 0x45, 0x13)),
+    ("salmon", (0xFA, 0x80, 0x72)),
+    ("sandybrown", (0xF4, 0xA4, 0x60)),
+    ("seagreen", (0x2E, 0x8B, 0x57)),
+    ("seashell", (0xFF, 0xF5, 0xEE)),
+    ("sienna", (0xA0, 0x52, 0x2D)),
+    ("silver", (0xC0, 0xC0, 0xC0)),
+    ("skyblue", (0x87, 0xCE, 0xEB)),
+    ("slateblue", (0x6A, 0x5A, 0xCD)),
+    ("slategray", (0x70, 0x80, 0x90)),
+    ("slategrey", (0x70, 0x80, 0x90)),
+    ("snow", (0xFF, 0xFA, 0xFA)),
+    ("springgreen", (0x00, 0xFF, 0x7F)),
+    ("steelblue", (0x46, 0x82, 0xB4)),
+    ("tan", (0xD2, 0xB4, 0x8C)),
+    ("teal", (0x00, 0x80, 0x80)),
+    ("thistle", (0xD8, 0xBF, 0xD8)),
+    ("tomato", (0xFF, 0x63, 0x47)),
+    ("turquoise", (0x40, 0xE0, 0xD0)),
+    ("violet", (0xEE, 0x82, 0xEE)),
+    ("wheat", (0xF5, 0xDE, 0xB3)),
+    ("white", (0xFF, 0xFF, 0xFF)),
+    ("whitesmoke", (0xF5, 0xF5, 0xF5)),
+    ("yellow", (0xFF, 0xFF, 0x00)),
+    ("yellowgreen", (0x9A, 0xCD, 0x32)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_color("#0000FF").unwrap(), ((0, 0, 255), None));
+        assert_eq!(parse_color("#F0A").unwrap(), ((255, 0, 170), None));
+        assert_eq!(parse_color("#F0A8").unwrap(), ((255, 0, 170), Some(136)));
+        assert_eq!(
+            parse_color("#FF00AA80").unwrap(),
+            ((255, 0, 170), Some(128))
+        );
+        assert!(parse_color("#FF00A").is_err());
+        assert!(parse_color("#GG0000").is_err());
+    }
+
+    #[test]
+    fn rgb_functions() {
+        assert_eq!(parse_color("rgb(0,0,255)").unwrap(), ((0, 0, 255), None));
+        assert_eq!(
+            parse_color("rgba(0, 128, 255, 0.5)").unwrap(),
+            ((0, 128, 255), Some(128))
+        );
+        assert!(parse_color("rgb(0, 0, 256)").is_err());
+        assert!(parse_color("rgba(0, 0, 0, 2)").is_err());
+        assert!(parse_color("rgb(0, 0)").is_err());
+    }
+
+    #[test]
+    fn color_names() {
+        assert_eq!(parse_color("blue").unwrap(), ((0, 0, 255), None));
+        assert_eq!(parse_color(" Blue ").unwrap(), ((0, 0, 255), None));
+        assert_eq!(
+            parse_color("cornflowerblue").unwrap(),
+            ((0x64, 0x95, 0xED), None)
+        );
+        assert!(parse_color("notacolor").is_err());
+    }
+
+    #[test]
+    fn colors_without_alpha_are_opaque() {
+        assert_eq!("blue".parse::<Color>().unwrap(), Color::rgb(0, 0, 255));
+        assert_eq!(
+            "#0000FF80".parse::<Color>().unwrap(),
+            Color::rgba(0, 0, 255, 128)
+        );
+    }
+
+    #[test]
+    fn brush_colors_keep_their_alpha() {
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML"><definitions>
+            <brush xml:id="br0"><brushProperty name="color" value="#0000FF80" /></brush>
+            <brush xml:id="br1">
+                <brushProperty name="transparency" value="128" />
+                <brushProperty name="color" value="rgb(0, 0, 255)" />
+            </brush>
+        </definitions></ink>"##;
+        let result = crate::parser::parse_str(document).unwrap();
+        assert_eq!(result.brushes()["br0"].color, Color::rgba(0, 0, 255, 128));
+        // without alpha, the color keeps the transparency read before
+        assert_eq!(result.brushes()["br1"].color.transparency(), 128);
+    }
+}
@@ -0,0 +1,81 @@
+// InkML documents inside of containers : gzip compressed files and
+// zip packages (notebook exports from Journal, OneNote, ...)
+
+use anyhow::anyhow;
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::options::ParserOptions;
+use crate::parser::{parser_with_options, ParserResult};
+
+/// first bytes of gzip data
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// data read as is or decompressed, see `decompressed`
+pub enum MaybeCompressed<T: Read> {
+    Plain(BufReader<T>),
+    Gzip(MultiGzDecoder<BufReader<T>>),
+}
+
+impl<T: Read> Read for MaybeCompressed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            MaybeCompressed::Plain(reader) => reader.read(buf),
+            MaybeCompressed::Gzip(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Decompresses the data if it starts with the gzip magic number,
+/// reads it as is otherwise
+pub fn decompressed<T: Read>(reader: T) -> std::io::Result<MaybeCompressed<T>> {
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(MaybeCompressed::Gzip(MultiGzDecoder::new(reader)))
+    } else {
+        Ok(MaybeCompressed::Plain(reader))
+    }
+}
+
+/// Same as `parser_with_options` for possibly gzip compressed data
+pub fn parse<T: Read>(reader: T, options: &ParserOptions) -> anyhow::Result<ParserResult> {
+    parser_with_options(decompressed(reader)?, options)
+}
+
+/// Parses a (possibly gzip compressed) InkML file
+pub fn parse_file<P: AsRef<Path>>(path: P) -> anyhow::Result<ParserResult> {
+    parse(File::open(path)?, &ParserOptions::default())
+}
+
+/// Parses the entry `entry` (its path inside of the package) of the zip file `path`
+pub fn parse_zip_entry<P: AsRef<Path>>(path: P, entry: &str) -> anyhow::Result<ParserResult> {
+    parse_zip_entry_with_options(path, entry, &ParserOptions::default())
+}
+
+/// Same as `parse_zip_entry` with options
+pub fn parse_zip_entry_with_options<P: AsRef<Path>>(
+    path: P,
+    entry: &str,
+    options: &ParserOptions,
+) -> anyhow::Result<ParserResult> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let file = archive
+        .by_name(entry)
+        .map_err(|e| anyhow!("Could not open the entry {entry} of the zip file : {e}"))?;
+    parse(file, options)
+}
+
+/// paths of the InkML documents (`.inkml` entries, compressed or not) of the zip file `path`
+pub fn zip_inkml_entries<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<String>> {
+    let archive = zip::ZipArchive::new(File::open(path)?)?;
+    Ok(archive
+        .file_names()
+        .filter(|name| {
+            let name = name.to_ascii_lowercase();
+            name.ends_with(".inkml") || name.ends_with(".inkml.gz")
+        })
+        .map(String::from)
+        .collect())
+}
@@ -2,10 +2,16 @@
 // for writing we assume we'll use only 1 context
 // but we use as many brushes as needed
 
-use crate::trace_data::ChannelDataEl;
+pub use crate::codec::ChannelType;
+use crate::codec::{ChannelData, ChannelDataEl};
+use crate::mapping::TableMapping;
+use crate::options::IdAttribute;
+use crate::trace_data::BoundingBox;
 use crate::traits::Writable;
+use crate::xml_helpers::split_reference;
 use anyhow::anyhow;
 use std::io::Write;
+use std::str::FromStr;
 use xml::writer::{Error, EventWriter, XmlEvent};
 
 /// types of channel
@@ -26,6 +32,12 @@ pub enum ChannelKind {
     OE,
     OTx,
     OTy,
+    /// S : tip switch state (touching the surface or not)
+    S,
+    /// state of the n-th side button (B1, B2, ...)
+    B(u32),
+    /// T : time of the point
+    T,
 }
 
 impl ChannelKind {
@@ -39,7 +51,12 @@ impl ChannelKind {
                 "OE" => Ok(ChannelKind::OE),
                 "OTx" => Ok(ChannelKind::OTx),
                 "OTy" => Ok(ChannelKind::OTy),
-                _ => Err(anyhow!("the channel kind {:?} was not found. It is either not implemented or incorrect", value)),
+                "S" => Ok(ChannelKind::S),
+                "T" => Ok(ChannelKind::T),
+                _ => match value.strip_prefix('B').map(str::parse) {
+                    Some(Ok(button)) => Ok(ChannelKind::B(button)),
+                    _ => Err(anyhow!("the channel kind {:?} was not found. It is either not implemented or incorrect", value)),
+                },
             },
             None => Err(anyhow!("an empty string was given")),
         }
@@ -52,9 +69,16 @@ impl ChannelKind {
             ChannelKind::OA | ChannelKind::OE | ChannelKind::OTx | ChannelKind::OTy => {
                 ResolutionUnits::OneOverDegree
             }
+            ChannelKind::S | ChannelKind::B(_) => ResolutionUnits::OneOverDev,
+            ChannelKind::T => ResolutionUnits::OneOverMs,
         }
     }
 
+    /// state channels (tip switch and buttons)
+    pub(crate) fn is_state(&self) -> bool {
+        matches!(self, ChannelKind::S | ChannelKind::B(_))
+    }
+
     fn get_default_unit(&self) -> ChannelUnit {
         match self {
             ChannelKind::X | ChannelKind::Y => ChannelUnit::cm,
@@ -62,6 +86,8 @@ impl ChannelKind {
             ChannelKind::OA | ChannelKind::OE | ChannelKind::OTx | ChannelKind::OTy => {
                 ChannelUnit::deg
             }
+            ChannelKind::S | ChannelKind::B(_) => ChannelUnit::dev,
+            ChannelKind::T => ChannelUnit::ms,
         }
     }
 }
@@ -73,25 +99,16 @@ impl From<ChannelKind> for String {
             ChannelKind::Y => String::from("Y"),
             ChannelKind::F => String::from("F"),
             ChannelKind::OA => String::from("OA"),
-            ChannelKind::OE => String::from("OF"),
+            ChannelKind::OE => String::from("OE"),
             ChannelKind::OTx => String::from("OTx"),
             ChannelKind::OTy => String::from("OTy"),
+            ChannelKind::S => String::from("S"),
+            ChannelKind::B(button) => format!("B{button}"),
+            ChannelKind::T => String::from("T"),
         }
     }
 }
 
-/// type used for the encoding
-#[derive(Clone, Debug)]
-#[allow(unused)]
-#[derive(Default)]
-pub enum ChannelType {
-    Integer,
-    #[default]
-    Decimal,
-    Double,
-    Bool,
-}
-
 impl ChannelType {
     pub(crate) fn parse(name: &Option<String>) -> anyhow::Result<ChannelType> {
         match name {
@@ -106,8 +123,9 @@ impl ChannelType {
         }
     }
 
-    fn get_max_value(&self, max_val: &Option<String>) -> Option<ChannelDataEl> {
-        match max_val {
+    /// parses a bound of the channel (`min` or `max`)
+    fn parse_bound(&self, bound: &Option<String>) -> Option<ChannelDataEl> {
+        match bound {
             None => None,
             Some(max_parsed_str) => {
                 // match depending on the type
@@ -133,24 +151,14 @@ impl From<ChannelType> for String {
             ChannelType::Integer => String::from("integer"),
             ChannelType::Decimal => String::from("decimal"),
             ChannelType::Double => String::from("double"),
-            ChannelType::Bool => String::from("bool"),
-        }
-    }
-}
-
-impl ChannelType {
-    pub fn get_null_value(self: &ChannelType) -> ChannelDataEl {
-        match self {
-            ChannelType::Integer => ChannelDataEl::Integer(0),
-            ChannelType::Decimal => ChannelDataEl::Double(0.0),
-            ChannelType::Bool => ChannelDataEl::Bool,
-            ChannelType::Double => ChannelDataEl::Double(0.0),
+            ChannelType::Bool => String::from("boolean"),
         }
     }
 }
 
-#[derive(Clone, Debug)]
-#[allow(unused)]
+/// unit of the resolution of a channel (values per unit)
+#[derive(Clone, Debug, PartialEq)]
+#[allow(unused, clippy::enum_variant_names)]
 #[derive(Default)]
 pub enum ResolutionUnits {
     // 1/cm
@@ -164,6 +172,10 @@ pub enum ResolutionUnits {
     OneOverDegree,
     // 1/himetric
     OneOverHimetric,
+    // 1/s, second
+    OneOverS,
+    // 1/ms, millisecond
+    OneOverMs,
 }
 
 impl From<ResolutionUnits> for String {
@@ -174,6 +186,8 @@ impl From<ResolutionUnits> for String {
             ResolutionUnits::OneOverDev => String::from("1/dev"),
             ResolutionUnits::OneOverDegree => String::from("1/deg"),
             ResolutionUnits::OneOverHimetric => String::from("1/himetric"),
+            ResolutionUnits::OneOverS => String::from("1/s"),
+            ResolutionUnits::OneOverMs => String::from("1/ms"),
         }
     }
 }
@@ -187,22 +201,57 @@ impl ResolutionUnits {
                 "1/dev" => Ok(ResolutionUnits::OneOverDev),
                 "1/deg" => Ok(ResolutionUnits::OneOverDegree),
                 "1/himetric" => Ok(ResolutionUnits::OneOverHimetric),
+                "1/s" => Ok(ResolutionUnits::OneOverS),
+                "1/ms" => Ok(ResolutionUnits::OneOverMs),
                 _ => Err(
                     anyhow!("Could not find a `ResolutionUnits` matching {value}. 
                             It either is incorrect of this unit is not implemented 
-                            (1/cm, 1/mm, 1/dev, 1/deg, and 1/himetric are the ones currently implemented)"),
+                            (1/cm, 1/mm, 1/dev, 1/deg, 1/himetric, 1/s and 1/ms are the ones currently implemented)"),
                 ),
             },
             None => Err(anyhow!("ResolutionUnits::parse was given a None, aborting")),
         }
     }
+
+    /// the unit the resolution is given per (cm for 1/cm)
+    pub fn unit(&self) -> ChannelUnit {
+        match self {
+            ResolutionUnits::OneOverCm => ChannelUnit::cm,
+            ResolutionUnits::OneOverMm => ChannelUnit::mm,
+            ResolutionUnits::OneOverDev => ChannelUnit::dev,
+            ResolutionUnits::OneOverDegree => ChannelUnit::deg,
+            ResolutionUnits::OneOverHimetric => ChannelUnit::himetric,
+            ResolutionUnits::OneOverS => ChannelUnit::s,
+            ResolutionUnits::OneOverMs => ChannelUnit::ms,
+        }
+    }
+
+    /// Converts a resolution to another unit (1000 1/cm is 100 1/mm)
+    /// Fails for units of different dimensions (1/deg to 1/cm)
+    pub fn convert_to(&self, output_unit: ResolutionUnits, resolution: f64) -> anyhow::Result<f64> {
+        // values per unit : divided by the size of the output unit in the input one
+        Ok(resolution / self.unit().convert_to(output_unit.unit(), 1.0)?)
+    }
 }
 
+impl FromStr for ResolutionUnits {
+    type Err = anyhow::Error;
+
+    /// parses a unit as written in the `units` attribute (`1/cm`)
+    fn from_str(value: &str) -> anyhow::Result<ResolutionUnits> {
+        ResolutionUnits::parse(&Some(value.to_owned()))
+    }
+}
+
+/// number of cm in an inch
+pub(crate) const CM_PER_INCH: f64 = 2.54;
+
+/// unit of the values of a channel or of a brush size
 // TODO : use the full unit list from the inkml spec
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[allow(unused, non_camel_case_types)]
 #[derive(Default)]
-pub(crate) enum ChannelUnit {
+pub enum ChannelUnit {
     /// distance unit, `mm`
     mm,
     /// distance unit, `cm`
@@ -210,12 +259,42 @@ pub(crate) enum ChannelUnit {
     cm,
     /// distance unit, `m`
     m,
+    /// distance unit, `in` (2.54 cm)
+    inch,
+    /// distance unit, `pt` (1/72 in)
+    pt,
+    /// distance unit, `px` (1/96 in, as in CSS)
+    px,
     /// device ind unit
     dev,
     /// degree
     deg,
     /// himetric
     himetric,
+    /// time unit, `s`
+    s,
+    /// time unit, `ms`
+    ms,
+}
+
+impl FromStr for ChannelUnit {
+    type Err = anyhow::Error;
+
+    /// parses a unit as written in the `units` attribute (`cm`, `in`, `deg`)
+    fn from_str(value: &str) -> anyhow::Result<ChannelUnit> {
+        ChannelUnit::parse(&Some(value.to_owned()))
+            .ok_or_else(|| anyhow!("Could not find a ChannelUnit matching {value}"))
+    }
+}
+
+/// quantity measured by a unit, conversions are only possible
+/// between units of the same dimension
+#[derive(Debug, PartialEq)]
+enum Dimension {
+    Length,
+    Angle,
+    Device,
+    Time,
 }
 
 impl From<ChannelUnit> for String {
@@ -224,9 +303,14 @@ impl From<ChannelUnit> for String {
             ChannelUnit::mm => String::from("mm"),
             ChannelUnit::cm => String::from("cm"),
             ChannelUnit::m => String::from("m"),
+            ChannelUnit::inch => String::from("in"),
+            ChannelUnit::pt => String::from("pt"),
+            ChannelUnit::px => String::from("px"),
             ChannelUnit::dev => String::from("dev"),
             ChannelUnit::deg => String::from("deg"),
             ChannelUnit::himetric => String::from("himetric"),
+            ChannelUnit::s => String::from("s"),
+            ChannelUnit::ms => String::from("ms"),
         }
     }
 }
@@ -238,57 +322,228 @@ impl ChannelUnit {
                 "mm" => Some(ChannelUnit::mm),
                 "cm" => Some(ChannelUnit::cm),
                 "m" => Some(ChannelUnit::m),
+                "in" => Some(ChannelUnit::inch),
+                "pt" => Some(ChannelUnit::pt),
+                "px" => Some(ChannelUnit::px),
                 "dev" => Some(ChannelUnit::dev),
                 "deg" => Some(ChannelUnit::deg),
                 "himetric" => Some(ChannelUnit::himetric),
+                "s" => Some(ChannelUnit::s),
+                "ms" => Some(ChannelUnit::ms),
                 _ => None,
             },
             None => None,
         }
     }
 
-    pub(crate) fn convert_to(
-        &self,
-        output_unit: ChannelUnit,
-        input_value: f64,
-    ) -> anyhow::Result<f64> {
-        // pretty horrible, better to use a table/matrix with conversion values ?
-        match (self, output_unit) {
-            (ChannelUnit::mm, ChannelUnit::mm) => Ok(input_value),
-            (ChannelUnit::mm, ChannelUnit::cm) => Ok(input_value * 1e-1),
-            (ChannelUnit::mm, ChannelUnit::m) => Ok(input_value * 1e-3),
-            (ChannelUnit::cm, ChannelUnit::mm) => Ok(input_value * 1e1),
-            (ChannelUnit::cm, ChannelUnit::cm) => Ok(input_value),
-            (ChannelUnit::cm, ChannelUnit::m) => Ok(input_value * 1e-2),
-            (ChannelUnit::m, ChannelUnit::mm) => Ok(input_value * 1e3),
-            (ChannelUnit::m, ChannelUnit::cm) => Ok(input_value * 1e2),
-            (ChannelUnit::m, ChannelUnit::m) => Ok(input_value),
-            (ChannelUnit::deg, ChannelUnit::deg) => Ok(input_value),
-            (ChannelUnit::dev, ChannelUnit::dev) => Ok(input_value),
-            (ChannelUnit::himetric, ChannelUnit::cm) => Ok(input_value * 1e-3),
-            (ChannelUnit::himetric, ChannelUnit::mm) => Ok(input_value * 1e-2),
-            (ChannelUnit::himetric, ChannelUnit::m) => Ok(input_value * 1e-5),
-            (input, output) => Err(anyhow!(
+    /// dimension of the unit and the value of one unit in the base unit
+    /// of that dimension (cm for lengths, degree for angles, ms for times)
+    fn base_factor(&self) -> (Dimension, f64) {
+        match self {
+            ChannelUnit::mm => (Dimension::Length, 0.1),
+            ChannelUnit::cm => (Dimension::Length, 1.0),
+            ChannelUnit::m => (Dimension::Length, 100.0),
+            ChannelUnit::inch => (Dimension::Length, CM_PER_INCH),
+            ChannelUnit::pt => (Dimension::Length, CM_PER_INCH / 72.0),
+            ChannelUnit::px => (Dimension::Length, CM_PER_INCH / 96.0),
+            ChannelUnit::himetric => (Dimension::Length, 1e-3),
+            ChannelUnit::deg => (Dimension::Angle, 1.0),
+            ChannelUnit::dev => (Dimension::Device, 1.0),
+            ChannelUnit::s => (Dimension::Time, 1000.0),
+            ChannelUnit::ms => (Dimension::Time, 1.0),
+        }
+    }
+
+    /// Converts a value to another unit (2.54 cm is 1 in)
+    /// Fails for units of different dimensions (deg to cm, dev to anything else)
+    pub fn convert_to(&self, output_unit: ChannelUnit, input_value: f64) -> anyhow::Result<f64> {
+        // converted through the base unit of the dimension
+        let (input_dimension, input_factor) = self.base_factor();
+        let (output_dimension, output_factor) = output_unit.base_factor();
+        if input_dimension != output_dimension {
+            return Err(anyhow!(
                 "Could not convert from {:?} to {:?}. Is the conversion valid ? 
                 (For example, converting deg to meters, or dev to another unit)",
-                input,
-                output
-            )),
+                self,
+                output_unit
+            ));
+        }
+        if input_factor == output_factor {
+            return Ok(input_value);
         }
+        Ok(input_value * input_factor / output_factor)
     }
 }
 
-#[derive(Clone, Debug)]
+/// direction of the values of a channel (`orientation` attribute)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Orientation {
+    /// `+ve` (default) : left to right for X, top to bottom for Y
+    #[default]
+    Positive,
+    /// `-ve` : right to left for X, bottom to top for Y
+    Negative,
+}
+
+impl Orientation {
+    pub(crate) fn parse(name: &Option<String>) -> anyhow::Result<Orientation> {
+        match name.as_deref() {
+            None | Some("+ve") => Ok(Orientation::Positive),
+            Some("-ve") => Ok(Orientation::Negative),
+            Some(value) => Err(anyhow!("the orientation {value} is neither +ve nor -ve")),
+        }
+    }
+}
+
+/// a channel of a context : what a value of the traces stands for, its type
+/// and how it's scaled (see `Context`)
+#[derive(Clone, Debug, PartialEq)]
 pub struct Channel {
     pub kind: ChannelKind,
+    /// type of the values in the traces
     pub types: ChannelType,
+    /// number of values per `unit_resolution` (1000 per cm : values in 1/1000 cm)
     pub resolution_value: f64,
-    max_value: Option<ChannelDataEl>,
+    pub(crate) min_value: Option<ChannelDataEl>,
+    pub(crate) max_value: Option<ChannelDataEl>,
+    pub orientation: Orientation,
+    /// unit of `resolution_value`
     pub unit_resolution: ResolutionUnits,
-    unit_channel: ChannelUnit,
+    pub(crate) unit_channel: ChannelUnit,
+    /// `table` mapping of the raw values
+    mapping: Option<TableMapping>,
+    /// id of the `timestamp` the values are relative to (`respectTo`)
+    respect_to: Option<String>,
+}
+
+/// resolution of a channel : number of values per unit
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub(crate) value: f64,
+    pub(crate) units: ResolutionUnits,
+}
+
+impl Resolution {
+    pub fn new(value: f64, units: ResolutionUnits) -> Resolution {
+        Resolution { value, units }
+    }
+
+    /// values per cm
+    pub fn per_cm(value: f64) -> Resolution {
+        Resolution::new(value, ResolutionUnits::OneOverCm)
+    }
+
+    /// values per mm
+    pub fn per_mm(value: f64) -> Resolution {
+        Resolution::new(value, ResolutionUnits::OneOverMm)
+    }
+
+    /// values per himetric unit (1/100 mm)
+    pub fn per_himetric(value: f64) -> Resolution {
+        Resolution::new(value, ResolutionUnits::OneOverHimetric)
+    }
+
+    /// values per degree, for the angles
+    pub fn per_degree(value: f64) -> Resolution {
+        Resolution::new(value, ResolutionUnits::OneOverDegree)
+    }
+
+    /// values per device unit
+    pub fn per_dev(value: f64) -> Resolution {
+        Resolution::new(value, ResolutionUnits::OneOverDev)
+    }
+
+    /// values per ms, for the timestamps
+    pub fn per_ms(value: f64) -> Resolution {
+        Resolution::new(value, ResolutionUnits::OneOverMs)
+    }
 }
 
 impl Channel {
+    /// channel in the default unit of its kind (cm for X and Y, dev for F, deg for angles)
+    /// without bounds
+    pub fn new(kind: ChannelKind, types: ChannelType, resolution: Resolution) -> Channel {
+        Channel {
+            unit_channel: kind.get_default_unit(),
+            kind,
+            types,
+            resolution_value: resolution.value,
+            min_value: None,
+            max_value: None,
+            orientation: Orientation::Positive,
+            unit_resolution: resolution.units,
+            mapping: None,
+            respect_to: None,
+        }
+    }
+
+    /// lowest value of the channel (before scaling)
+    pub fn with_min(mut self, min: f64) -> Channel {
+        self.min_value = Some(self.bound(min));
+        self
+    }
+
+    /// highest value of the channel (before scaling)
+    /// For F, the pressure is normalised from min (or 0) - max to 0 - 1
+    pub fn with_max(mut self, max: f64) -> Channel {
+        self.max_value = Some(self.bound(max));
+        self
+    }
+
+    /// sets the `max` of the channel if it doesn't have one
+    pub(crate) fn default_max(&mut self, max: f64) {
+        if self.max_value.is_none() {
+            self.max_value = Some(self.bound(max));
+        }
+    }
+
+    pub fn with_unit(mut self, unit: ChannelUnit) -> Channel {
+        self.unit_channel = unit;
+        self
+    }
+
+    pub fn with_orientation(mut self, orientation: Orientation) -> Channel {
+        self.orientation = orientation;
+        self
+    }
+
+    /// raw values (and the `min` and `max`) are mapped through `mapping`
+    /// before the scaling, e.g. to linearise the pressure of a device
+    pub fn with_mapping(mut self, mapping: TableMapping) -> Channel {
+        self.mapping = Some(mapping);
+        self
+    }
+
+    pub(crate) fn set_mapping(&mut self, mapping: TableMapping) {
+        self.mapping = Some(mapping);
+    }
+
+    pub fn mapping(&self) -> Option<&TableMapping> {
+        self.mapping.as_ref()
+    }
+
+    /// the values are relative to the `timestamp` with the id `timestamp`
+    /// (for a T channel giving offsets from the start of a session)
+    pub fn with_respect_to(mut self, timestamp: impl Into<String>) -> Channel {
+        self.respect_to = Some(timestamp.into());
+        self
+    }
+
+    /// id of the `timestamp` (or of another element) the values are relative to
+    pub fn respect_to(&self) -> Option<&str> {
+        self.respect_to.as_deref()
+    }
+
+    /// a bound in the type of the channel
+    fn bound(&self, value: f64) -> ChannelDataEl {
+        match self.types {
+            ChannelType::Integer | ChannelType::Bool => {
+                ChannelDataEl::Integer(value.round() as i64)
+            }
+            ChannelType::Decimal | ChannelType::Double => ChannelDataEl::Double(value),
+        }
+    }
+
     pub fn initialise_channel_from_name(
         kind_type_unit_v: Vec<Option<String>>,
     ) -> anyhow::Result<Channel> {
@@ -298,23 +553,45 @@ impl Channel {
         let channel_kind = ChannelKind::parse(&kind_type_unit_v[0])?;
         let types = ChannelType::parse(channel_type)?;
 
-        // we are parsing the max value
-        // useful for the F channel (where the mapping in 0-1 is done through the max value)
+        let unit_channel = ChannelUnit::parse(unit).unwrap_or(channel_kind.get_default_unit());
+        // times are counted in their unit unless a resolution is given
+        let (resolution_value, unit_resolution) = match (&channel_kind, &unit_channel) {
+            (ChannelKind::T, ChannelUnit::s) => (1.0, ResolutionUnits::OneOverS),
+            (ChannelKind::T, _) => (1.0, ResolutionUnits::OneOverMs),
+            _ => (1000.0, channel_kind.get_default_resolution_unit()),
+        };
+
+        // we are parsing the min and max values
+        // useful for the F channel (where the mapping in 0-1 is done through them)
         // For the F channel, if we have a dev unit, the max value will be used for the mapping instead
         Ok(Channel {
             kind: channel_kind.clone(),
             types: types.clone(),
-            resolution_value: 1000.0,
-            max_value: types.get_max_value(&kind_type_unit_v[3]),
-            unit_resolution: channel_kind.get_default_resolution_unit(),
-            unit_channel: ChannelUnit::parse(unit).unwrap_or(channel_kind.get_default_unit()),
+            resolution_value,
+            min_value: types.parse_bound(&kind_type_unit_v[4]),
+            max_value: types.parse_bound(&kind_type_unit_v[3]),
+            orientation: Orientation::parse(&kind_type_unit_v[5])?,
+            unit_resolution,
+            unit_channel,
+            mapping: None,
+            respect_to: kind_type_unit_v
+                .get(6)
+                .and_then(Option::as_deref)
+                .map(|reference| split_reference(reference).1.to_owned()),
         })
     }
 
     pub fn get_scaling(&self) -> f64 {
-        if self.max_value.is_some() && self.kind == ChannelKind::F {
-            // exception for F
-            1.0 / self.max_value.as_ref().unwrap().to_float()
+        if let (Some(max_value), ChannelKind::F) = (&self.max_value, &self.kind) {
+            // exception for F : mapped from min (0 by default) - max to 0 - 1
+            let range = self.mapped(max_value.to_float()) - self.min();
+            // a channel without a range (min == max) has a constant pressure
+            // of 1 (see `get_offset`) instead of infinite ones
+            if range.is_normal() {
+                1.0 / range
+            } else {
+                0.0
+            }
         } else {
             let ratio = match self.unit_resolution {
                 ResolutionUnits::OneOverCm => 1.0,
@@ -322,21 +599,202 @@ impl Channel {
                 ResolutionUnits::OneOverDegree => 1.0,
                 ResolutionUnits::OneOverDev => 1.0,
                 ResolutionUnits::OneOverHimetric => 1.0 / 1000.0,
+                // times in ms
+                ResolutionUnits::OneOverS => 1000.0,
+                ResolutionUnits::OneOverMs => 1.0,
+            };
+            // coordinates are given in screen orientation
+            let direction = match (&self.kind, self.orientation) {
+                (ChannelKind::X | ChannelKind::Y, Orientation::Negative) => -1.0,
+                _ => 1.0,
             };
-            ratio * (1.0 / self.resolution_value)
+            direction * ratio * (1.0 / self.resolution_value)
         }
     }
+
+    /// the declared `min` (0 if there is none) through the mapping
+    fn min(&self) -> f64 {
+        self.mapped(
+            self.min_value
+                .as_ref()
+                .map(ChannelDataEl::to_float)
+                .unwrap_or(0.0),
+        )
+    }
+
+    /// offset removed from the values after scaling
+    /// (for F, so that the `min` is mapped to 0)
+    pub(crate) fn get_offset(&self) -> f64 {
+        if let (Some(_), ChannelKind::F) = (&self.max_value, &self.kind) {
+            match self.get_scaling() {
+                0.0 => -1.0,
+                scaling => self.min() * scaling,
+            }
+        } else {
+            0.0
+        }
+    }
+
+    /// the values of `data` after mapping, scaling and offset (cm for X and Y, 0 - 1 for F)
+    pub(crate) fn formatted_values<'a>(
+        &'a self,
+        data: &'a ChannelData,
+    ) -> impl Iterator<Item = f64> + 'a {
+        let (scaling, offset) = (self.get_scaling(), self.get_offset());
+        data.cast_to_float(1.0)
+            .map(move |value| self.mapped(value) * scaling - offset)
+    }
+
+    /// the raw `value` through the mapping of the channel if it has one
+    fn mapped(&self, value: f64) -> f64 {
+        self.mapping
+            .as_ref()
+            .map_or(value, |mapping| mapping.apply(value))
+    }
+
+    /// raw value for a formatted one, the reverse of `formatted_values`
+    pub(crate) fn raw_value(&self, value: f64) -> f64 {
+        let scaling = self.get_scaling();
+        if scaling == 0.0 {
+            // constant channel
+            return self.max_value.as_ref().map_or(0.0, ChannelDataEl::to_float);
+        }
+        let unscaled = (value + self.get_offset()) / scaling;
+        self.mapping
+            .as_ref()
+            .map_or(unscaled, |mapping| mapping.unapply(unscaled))
+    }
+
+    /// range of the values (after scaling and offset) that can be written in this channel
+    /// The declared `min` and `max` are bounds, the pressure can't be negative
+    pub(crate) fn value_range(&self) -> (f64, f64) {
+        let (scaling, offset) = (self.get_scaling(), self.get_offset());
+        let scaled = |bound: &Option<ChannelDataEl>| {
+            bound
+                .as_ref()
+                .map(|bound| self.mapped(bound.to_float()) * scaling - offset)
+        };
+        // the bounds are swapped for the channels in the reverse orientation
+        let (lower, upper) = if scaling < 0.0 {
+            (scaled(&self.max_value), scaled(&self.min_value))
+        } else {
+            (scaled(&self.min_value), scaled(&self.max_value))
+        };
+        let min = match (lower, &self.kind) {
+            (Some(min), _) => min,
+            (None, ChannelKind::F) => 0.0,
+            (None, _) => f64::NEG_INFINITY,
+        };
+        (min, upper.unwrap_or(f64::INFINITY))
+    }
+}
+
+/// Builder for contexts with custom channels (tilt, timestamps, ...)
+/// to describe the device strokes are written for
+///
+/// `ContextBuilder::new().with_channel(ChannelKind::X, ChannelType::Integer, Resolution::per_cm(1000.0))`
+pub struct ContextBuilder {
+    name: String,
+    channel_list: Vec<Channel>,
+    sample_rate: Option<f64>,
 }
 
-#[derive(Debug)]
+impl Default for ContextBuilder {
+    fn default() -> Self {
+        ContextBuilder {
+            name: String::from("ctx0"),
+            channel_list: vec![],
+            sample_rate: None,
+        }
+    }
+}
+
+impl ContextBuilder {
+    pub fn new() -> ContextBuilder {
+        ContextBuilder::default()
+    }
+
+    /// id of the context (`ctx0` by default)
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// adds a channel in the default unit of its kind, without bounds
+    pub fn with_channel(
+        self,
+        kind: ChannelKind,
+        types: ChannelType,
+        resolution: Resolution,
+    ) -> Self {
+        self.channel(Channel::new(kind, types, resolution))
+    }
+
+    /// adds a channel (see `Channel::new` to set its bounds, unit and orientation)
+    /// The channels are written in the order they are added
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.channel_list.push(channel);
+        self
+    }
+
+    /// number of points per second of the device
+    pub fn sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Fails if the X or Y channel is missing or if a channel is given twice
+    pub fn build(self) -> anyhow::Result<Context> {
+        for (index, channel) in self.channel_list.iter().enumerate() {
+            if self.channel_list[..index]
+                .iter()
+                .any(|previous| previous.kind == channel.kind)
+            {
+                return Err(anyhow!(
+                    "The channel {:?} was given twice to the context {}",
+                    channel.kind,
+                    self.name
+                ));
+            }
+        }
+        let context = Context {
+            name: self.name,
+            channel_list: self.channel_list,
+            sample_rate: self.sample_rate,
+        };
+        for kind in [ChannelKind::X, ChannelKind::Y] {
+            if context.channel_exists(kind.clone()).is_none() {
+                return Err(anyhow!(
+                    "The context {} has no {:?} channel",
+                    context.name,
+                    kind
+                ));
+            }
+        }
+        Ok(context)
+    }
+}
+
+/// resolution of the X and Y channels of the default contexts (points per cm)
+pub(crate) const DEFAULT_RESOLUTION: f64 = 1000.0;
+/// `max` of the F channel of the default context written with pressure
+pub(crate) const DEFAULT_PRESSURE_MAX: i64 = 32767;
+
+/// The channels of the traces referring to a context, in the order of the
+/// values of each point
+/// Read from the documents (`ParserResult::contexts`) or built with
+/// `ContextBuilder` for the writer
+#[derive(Debug, Clone, PartialEq)]
 pub struct Context {
-    // name given to the context, name = ctx0 by default
-    // refered by `contextRef="#ctx0" in the trace attr
+    /// id of the context (`ctx0` by default), traces refer to it
+    /// with `contextRef="#ctx0"`
     pub name: String,
     /// vector of channels
     /// Remark : we NEED the order to be preserved as the order here
     /// also corresponds to the order in which traces are built
     pub channel_list: Vec<Channel>,
+    /// number of points per second (`sampleRate` of the ink source)
+    pub sample_rate: Option<f64>,
 }
 
 impl Default for Context {
@@ -347,54 +805,77 @@ impl Default for Context {
                 Channel {
                     kind: ChannelKind::X,
                     types: ChannelType::Integer,
-                    resolution_value: 1000.0,
+                    resolution_value: DEFAULT_RESOLUTION,
+                    min_value: None,
                     max_value: None,
+                    orientation: Orientation::Positive,
                     unit_resolution: ResolutionUnits::OneOverCm,
                     unit_channel: ChannelUnit::cm,
+                    mapping: None,
+                    respect_to: None,
                 },
                 Channel {
                     kind: ChannelKind::Y,
                     types: ChannelType::Integer,
-                    resolution_value: 1000.0,
+                    resolution_value: DEFAULT_RESOLUTION,
+                    min_value: None,
                     max_value: None,
+                    orientation: Orientation::Positive,
                     unit_resolution: ResolutionUnits::OneOverCm,
                     unit_channel: ChannelUnit::cm,
+                    mapping: None,
+                    respect_to: None,
                 },
             ],
+            sample_rate: None,
         }
     }
 }
 
 impl Context {
-    pub(crate) fn default_with_pressure() -> Context {
+    /// X and Y with `resolution` points per cm and F from 0 to `pressure_max`
+    pub(crate) fn default_with_pressure(resolution: f64, pressure_max: i64) -> Context {
         Context {
             name: String::from("ctx0"),
             channel_list: vec![
                 Channel {
                     kind: ChannelKind::X,
                     types: ChannelType::Integer,
-                    resolution_value: 1000.0,
+                    resolution_value: resolution,
+                    min_value: None,
                     max_value: None,
+                    orientation: Orientation::Positive,
                     unit_resolution: ResolutionUnits::OneOverCm,
                     unit_channel: ChannelUnit::cm,
+                    mapping: None,
+                    respect_to: None,
                 },
                 Channel {
                     kind: ChannelKind::Y,
                     types: ChannelType::Integer,
-                    resolution_value: 1000.0,
+                    resolution_value: resolution,
+                    min_value: None,
                     max_value: None,
+                    orientation: Orientation::Positive,
                     unit_resolution: ResolutionUnits::OneOverCm,
                     unit_channel: ChannelUnit::cm,
+                    mapping: None,
+                    respect_to: None,
                 },
                 Channel {
                     kind: ChannelKind::F,
                     types: ChannelType::Integer,
-                    resolution_value: 0.0,
-                    max_value: Some(ChannelDataEl::Integer(32767)),
+                    resolution_value: 1.0,
+                    min_value: None,
+                    max_value: Some(ChannelDataEl::Integer(pressure_max)),
+                    orientation: Orientation::Positive,
                     unit_resolution: ResolutionUnits::OneOverDev,
                     unit_channel: ChannelUnit::dev,
+                    mapping: None,
+                    respect_to: None,
                 },
             ],
+            sample_rate: None,
         }
     }
 
@@ -402,9 +883,18 @@ impl Context {
         Context {
             name,
             channel_list: vec![],
+            sample_rate: None,
         }
     }
 
+    /// true if the Y channel increases upward (`orientation="-ve"`)
+    /// Formatted strokes are flipped back to the screen orientation
+    pub fn y_upward(&self) -> bool {
+        self.channel_list.iter().any(|channel| {
+            channel.kind == ChannelKind::Y && channel.orientation == Orientation::Negative
+        })
+    }
+
     pub fn channel_exists(&self, channel_kind: ChannelKind) -> Option<usize> {
         self.channel_list
             .clone()
@@ -416,41 +906,93 @@ impl Context {
 }
 
 impl Writable for Context {
-    fn write<W: Write>(&self, writer: &mut EventWriter<W>) -> Result<(), Error> {
+    fn write<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+    ) -> Result<(), Error> {
+        self.write_with_canvas(writer, id_attribute, None)
+    }
+}
+
+impl Context {
+    /// writes the context with a `canvas` covering `page` (in cm) if given : a
+    /// canvas from 0 to the size of the page and the transform from the values
+    /// of the traces to it, the top left corner of the page being the origin
+    pub(crate) fn write_with_canvas<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+        page: Option<&BoundingBox>,
+    ) -> Result<(), Error> {
         // context block
-        writer.write(XmlEvent::start_element("context").attr("xml:id", &self.name))?;
+        writer.write(XmlEvent::start_element("context").attr(id_attribute.name(), &self.name))?;
+
+        if let Some(page) = page {
+            self.write_canvas(writer, id_attribute, page)?;
+        }
 
         // ink source
-        writer.write(XmlEvent::start_element("inkSource").attr("xml:id", "inkSrc0"))?;
+        // id derived from the context one so that it stays unique (ctx0 -> inkSrc0)
+        let ink_source_id = match self.name.strip_prefix("ctx") {
+            Some(suffix) => format!("inkSrc{suffix}"),
+            None => format!("inkSrc_{}", self.name),
+        };
+        writer.write(
+            XmlEvent::start_element("inkSource").attr(id_attribute.name(), &ink_source_id),
+        )?;
 
         // trace format
         writer.write(XmlEvent::start_element("traceFormat"))?;
 
         // iterate over channels
         for channel in &self.channel_list {
-            if channel.max_value.is_some() {
-                writer.write(
-                    XmlEvent::start_element("channel")
-                        .attr("name", &String::from(channel.kind.clone()))
-                        .attr("type", &String::from(channel.types.clone()))
-                        .attr(
-                            "max",
-                            &String::from(channel.max_value.as_ref().unwrap().clone()),
-                        )
-                        .attr("unit", &String::from(channel.unit_channel.clone())),
-                )?;
-            } else {
+            let name = String::from(channel.kind.clone());
+            let types = String::from(channel.types.clone());
+            let min = channel.min_value.clone().map(String::from);
+            let max = channel.max_value.clone().map(String::from);
+            let unit = String::from(channel.unit_channel.clone());
+            let mut start = XmlEvent::start_element("channel")
+                .attr("name", &name)
+                .attr("type", &types);
+            if let Some(min) = &min {
+                start = start.attr("min", min);
+            }
+            if let Some(max) = &max {
+                start = start.attr("max", max);
+            }
+            if channel.orientation == Orientation::Negative {
+                start = start.attr("orientation", "-ve");
+            }
+            let respect_to = channel.respect_to.as_ref().map(|id| format!("#{id}"));
+            if let Some(respect_to) = &respect_to {
+                start = start.attr("respectTo", respect_to);
+            }
+            writer.write(start.attr("units", &unit))?;
+            if let Some(mapping) = &channel.mapping {
+                writer.write(XmlEvent::start_element("mapping").attr("type", "table"))?;
                 writer.write(
-                    XmlEvent::start_element("channel")
-                        .attr("name", &String::from(channel.kind.clone()))
-                        .attr("type", &String::from(channel.types.clone()))
-                        .attr("unit", &String::from(channel.unit_channel.clone())),
+                    XmlEvent::start_element("table")
+                        .attr("apply", "absolute")
+                        .attr("interpolation", &String::from(mapping.interpolation())),
                 )?;
+                writer.write(XmlEvent::characters(&mapping.text()))?;
+                writer.write(XmlEvent::end_element())?; // end table
+                writer.write(XmlEvent::end_element())?; // end mapping
             }
             writer.write(XmlEvent::end_element())?;
         }
         writer.write(XmlEvent::end_element())?; // end trace format
 
+        if let Some(sample_rate) = self.sample_rate {
+            writer.write(
+                XmlEvent::start_element("sampleRate")
+                    .attr("uniform", "true")
+                    .attr("value", &format!("{sample_rate}")),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+        }
+
         // channelProperties :
         writer.write(XmlEvent::start_element("channelProperties"))?;
 
@@ -459,7 +1001,7 @@ impl Writable for Context {
                 XmlEvent::start_element("channelProperty")
                     .attr("channel", &String::from(channel.kind.clone()))
                     .attr("name", "resolution")
-                    .attr("value", &format!("{:?}", channel.resolution_value))
+                    .attr("value", &format!("{}", channel.resolution_value))
                     .attr("units", &String::from(channel.unit_resolution.clone())),
             )?;
             writer.write(XmlEvent::end_element())?;
@@ -469,4 +1011,184 @@ impl Writable for Context {
         writer.write(XmlEvent::end_element())?; // end context
         Ok(())
     }
+
+    fn write_canvas<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+        page: &BoundingBox,
+    ) -> Result<(), Error> {
+        // ids derived from the context one, like the ink source
+        let suffix = match self.name.strip_prefix("ctx") {
+            Some(suffix) => suffix.to_owned(),
+            None => format!("_{}", self.name),
+        };
+        writer.write(
+            XmlEvent::start_element("canvas").attr(id_attribute.name(), &format!("canvas{suffix}")),
+        )?;
+        writer.write(XmlEvent::start_element("traceFormat"))?;
+        for (name, size) in [
+            ("X", page.max_x - page.min_x),
+            ("Y", page.max_y - page.min_y),
+        ] {
+            writer.write(
+                XmlEvent::start_element("channel")
+                    .attr("name", name)
+                    .attr("type", "decimal")
+                    .attr("min", "0")
+                    .attr("max", &format!("{size}"))
+                    .attr("units", "cm"),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::end_element())?; // end traceFormat
+        writer.write(XmlEvent::end_element())?; // end canvas
+
+        // values of the traces to cm, moved to the corner of the page
+        let scaling = |kind: ChannelKind| {
+            self.channel_exists(kind)
+                .map_or(1.0, |index| self.channel_list[index].get_scaling())
+        };
+        let affine = format!(
+            "{} 0 {}, 0 {} {}, 0 0 1",
+            scaling(ChannelKind::X),
+            0.0 - page.min_x,
+            scaling(ChannelKind::Y),
+            0.0 - page.min_y
+        );
+        writer.write(
+            XmlEvent::start_element("canvasTransform")
+                .attr(id_attribute.name(), &format!("canvasTransform{suffix}")),
+        )?;
+        writer.write(XmlEvent::start_element("mapping").attr("type", "affine"))?;
+        writer.write(XmlEvent::start_element("affine"))?;
+        writer.write(XmlEvent::characters(&affine))?;
+        writer.write(XmlEvent::end_element())?; // end affine
+        writer.write(XmlEvent::end_element())?; // end mapping
+        writer.write(XmlEvent::end_element())?; // end canvasTransform
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xml::writer::EmitterConfig;
+
+    fn written(context: &Context, page: Option<&BoundingBox>) -> String {
+        let mut out_v: Vec<u8> = vec![];
+        let mut writer = EmitterConfig::new()
+            .perform_indent(true)
+            .write_document_declaration(false)
+            .create_writer(&mut out_v);
+        context
+            .write_with_canvas(&mut writer, IdAttribute::XmlId, page)
+            .unwrap();
+        String::from_utf8(out_v).unwrap()
+    }
+
+    #[test]
+    fn writes_the_default_pressure_context() {
+        let context = Context::default_with_pressure(DEFAULT_RESOLUTION, DEFAULT_PRESSURE_MAX);
+        assert_eq!(
+            written(&context, None),
+            include_str!("../tests/golden/context_pressure.xml")
+        );
+    }
+
+    #[test]
+    fn writes_the_units_and_bounds_of_the_channels() {
+        let context = ContextBuilder::new()
+            .name("ctx1")
+            .with_channel(
+                ChannelKind::X,
+                ChannelType::Decimal,
+                Resolution::per_mm(100.0),
+            )
+            .channel(
+                Channel::new(
+                    ChannelKind::Y,
+                    ChannelType::Integer,
+                    Resolution::per_cm(1000.0),
+                )
+                .with_min(-500.0)
+                .with_max(500.0)
+                .with_orientation(Orientation::Negative),
+            )
+            // a resolution of 0 (as in the OneNote documents) is written as is
+            .channel(
+                Channel::new(
+                    ChannelKind::F,
+                    ChannelType::Integer,
+                    Resolution::per_dev(0.0),
+                )
+                .with_max(1023.0),
+            )
+            .channel(Channel::new(
+                ChannelKind::T,
+                ChannelType::Integer,
+                Resolution::per_ms(1.0),
+            ))
+            .build()
+            .unwrap();
+        assert_eq!(
+            written(&context, Some(&BoundingBox::new(1.0, 2.0, 11.0, 22.0))),
+            include_str!("../tests/golden/context_channels.xml")
+        );
+    }
+
+    #[test]
+    fn lengths_are_converted_between_all_units() {
+        let close = |from: ChannelUnit, to: ChannelUnit, value: f64, expected: f64| {
+            let converted = from.clone().convert_to(to.clone(), value).unwrap();
+            assert!(
+                (converted - expected).abs() < 1e-9,
+                "{value} {from:?} is {converted} {to:?}, expected {expected}"
+            );
+        };
+        close(ChannelUnit::inch, ChannelUnit::cm, 1.0, 2.54);
+        close(ChannelUnit::pt, ChannelUnit::inch, 72.0, 1.0);
+        close(ChannelUnit::px, ChannelUnit::pt, 96.0, 72.0);
+        close(ChannelUnit::mm, ChannelUnit::m, 1500.0, 1.5);
+        close(ChannelUnit::himetric, ChannelUnit::mm, 100.0, 1.0);
+        close(ChannelUnit::s, ChannelUnit::ms, 1.5, 1500.0);
+
+        // every pair of lengths goes back to the same value
+        let lengths = [
+            ChannelUnit::mm,
+            ChannelUnit::cm,
+            ChannelUnit::m,
+            ChannelUnit::inch,
+            ChannelUnit::pt,
+            ChannelUnit::px,
+            ChannelUnit::himetric,
+        ];
+        for from in &lengths {
+            for to in &lengths {
+                let converted = from.convert_to(to.clone(), 3.0).unwrap();
+                close(to.clone(), from.clone(), converted, 3.0);
+            }
+        }
+    }
+
+    #[test]
+    fn units_of_different_dimensions_are_not_converted() {
+        assert!(ChannelUnit::deg.convert_to(ChannelUnit::cm, 1.0).is_err());
+        assert!(ChannelUnit::dev.convert_to(ChannelUnit::mm, 1.0).is_err());
+        assert!(ChannelUnit::ms.convert_to(ChannelUnit::inch, 1.0).is_err());
+        assert_eq!(
+            ChannelUnit::dev.convert_to(ChannelUnit::dev, 7.0).unwrap(),
+            7.0
+        );
+    }
+
+    #[test]
+    fn units_are_parsed_from_their_name() {
+        let parse = |name: &str| ChannelUnit::parse(&Some(String::from(name)));
+        assert_eq!(parse("in"), Some(ChannelUnit::inch));
+        assert_eq!(parse("pt"), Some(ChannelUnit::pt));
+        assert_eq!(parse("px"), Some(ChannelUnit::px));
+        assert_eq!(parse("himetric"), Some(ChannelUnit::himetric));
+        assert_eq!(parse("furlong"), None);
+    }
 }
@@ -0,0 +1,189 @@
+// loaders for handwriting datasets (CROHME, IAMonDo, ...) : the strokes with
+// the labels of the document and of the groups of strokes
+//
+// These datasets label the strokes with `annotation` elements : on the `ink`
+// element for the whole sample (`truth`, `UI`, `writer`, ...) and on
+// `traceGroup` (CROHME) or `traceView` (IAMonDo) elements for the symbols,
+// words or lines, which point to their traces with `traceView traceDataRef`
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::brushes::Brush;
+use crate::options::FormatOptions;
+use crate::parser::parser;
+use crate::trace_data::FormattedStroke;
+use crate::tree::{Annotation, GroupInfo, GroupedStroke};
+
+/// a sample of a dataset
+#[derive(Debug, Clone)]
+pub struct LabeledInk {
+    pub strokes: Vec<(FormattedStroke, Brush)>,
+    /// annotations of the document
+    pub annotations: Vec<Annotation>,
+    /// the annotated groups, in document order
+    pub segments: Vec<Segment>,
+}
+
+/// an annotated group of strokes (symbol, word, line, ...)
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub id: Option<String>,
+    pub annotations: Vec<Annotation>,
+    /// indices of the strokes of the segment in `LabeledInk::strokes`
+    /// (including the ones of the groups inside of it)
+    pub strokes: Vec<usize>,
+    /// index of the enclosing segment in `LabeledInk::segments`
+    pub parent: Option<usize>,
+}
+
+/// text of the first annotation of this type
+fn find_annotation<'a>(annotations: &'a [Annotation], kind: &str) -> Option<&'a str> {
+    annotations
+        .iter()
+        .find(|annotation| annotation.kind.as_deref() == Some(kind))
+        .map(|annotation| annotation.text.trim())
+}
+
+impl LabeledInk {
+    /// text of the first annotation of the document with this `type`
+    pub fn annotation(&self, kind: &str) -> Option<&str> {
+        find_annotation(&self.annotations, kind)
+    }
+
+    /// ground truth of the sample (the `truth` annotation, the LaTeX
+    /// expression for CROHME)
+    pub fn truth(&self) -> Option<&str> {
+        self.annotation("truth")
+    }
+
+    /// the segments without segments inside of them (symbols for CROHME)
+    pub fn leaves(&self) -> impl Iterator<Item = &Segment> {
+        let parents: HashSet<usize> = self.segments.iter().filter_map(|s| s.parent).collect();
+        self.segments
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| !parents.contains(index))
+            .map(|(_, segment)| segment)
+    }
+}
+
+impl Segment {
+    /// text of the first annotation of the segment with this `type`
+    pub fn annotation(&self, kind: &str) -> Option<&str> {
+        find_annotation(&self.annotations, kind)
+    }
+
+    /// label of the segment : its `truth` annotation (CROHME) or
+    /// its `transcription` (IAMonDo)
+    pub fn label(&self) -> Option<&str> {
+        self.annotation("truth")
+            .or_else(|| self.annotation("transcription"))
+    }
+}
+
+/// Reads a sample of a dataset, with the default `FormatOptions`
+/// (channels without units are scaled as in `parse_formatted`)
+pub fn load<T: Read>(reader: T) -> anyhow::Result<LabeledInk> {
+    load_with_options(reader, &FormatOptions::default())
+}
+
+/// Same as `load` for a file
+pub fn load_file<P: AsRef<Path>>(path: P) -> anyhow::Result<LabeledInk> {
+    load(BufReader::new(File::open(path)?))
+}
+
+/// Same as `load` with options for the conversion of the strokes
+pub fn load_with_options<T: Read>(
+    reader: T,
+    options: &FormatOptions,
+) -> anyhow::Result<LabeledInk> {
+    let result = parser(reader)?;
+    let annotations = result.annotations().to_vec();
+    let (groups, strokes) = result.formatted_with_groups(options)?;
+    let segments = segments(&groups, &strokes);
+    Ok(LabeledInk {
+        strokes: strokes
+            .into_iter()
+            .map(|(_, _, stroke, brush)| (stroke, brush))
+            .collect(),
+        annotations,
+        segments,
+    })
+}
+
+/// the annotated groups with the strokes they contain or refer to
+fn segments(groups: &[GroupInfo], strokes: &[GroupedStroke]) -> Vec<Segment> {
+    let mut children: Vec<Vec<usize>> = vec![vec![]; groups.len()];
+    for (index, group) in groups.iter().enumerate() {
+        if let Some(parent) = group.parent {
+            children[parent].push(index);
+        }
+    }
+    let mut members: Vec<Vec<usize>> = vec![vec![]; groups.len()];
+    let mut traces: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, (group, source, _, _)) in strokes.iter().enumerate() {
+        if let Some(group) = group {
+            members[*group].push(index);
+        }
+        if let Some(id) = &source.trace_id {
+            traces.entry(id).or_default().push(index);
+        }
+    }
+    let group_ids: HashMap<&str, usize> = groups
+        .iter()
+        .enumerate()
+        .filter_map(|(index, group)| Some((group.id.as_deref()?, index)))
+        .collect();
+
+    // strokes of a group, its subgroups and what they refer to
+    // (a reference can be a trace or another group)
+    let strokes_of = |root: usize| -> Vec<usize> {
+        let mut result = vec![];
+        let mut visited = HashSet::new();
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            if !visited.insert(index) {
+                continue;
+            }
+            result.extend(&members[index]);
+            stack.extend(&children[index]);
+            for reference in &groups[index].trace_refs {
+                match traces.get(reference.as_str()) {
+                    Some(found) => result.extend(found),
+                    None => stack.extend(group_ids.get(reference.as_str())),
+                }
+            }
+        }
+        result.sort_unstable();
+        result.dedup();
+        result
+    };
+
+    // index of each annotated group in the segments
+    let mut segment_index: Vec<Option<usize>> = vec![None; groups.len()];
+    let mut segments = vec![];
+    for (index, group) in groups.iter().enumerate() {
+        if group.annotations.is_empty() {
+            continue;
+        }
+        // parents come before their children
+        let mut parent = group.parent;
+        while let Some(candidate) = parent {
+            if segment_index[candidate].is_some() {
+                break;
+            }
+            parent = groups[candidate].parent;
+        }
+        segment_index[index] = Some(segments.len());
+        segments.push(Segment {
+            id: group.id.clone(),
+            annotations: group.annotations.clone(),
+            strokes: strokes_of(index),
+            parent: parent.and_then(|parent| segment_index[parent]),
+        });
+    }
+    segments
+}
@@ -0,0 +1,175 @@
+// differences between two versions of a document (versioning of notes,
+// comparison of the output of the writer with its input)
+//
+// Strokes are matched by the id of their trace first, then by their
+// geometry (`FormattedStroke::fingerprint` and brush properties). Strokes
+// matched by id with different points or brushes are modified, the other
+// ones added or removed
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::brushes::{Brush, BrushIndex};
+use crate::options::FormatOptions;
+use crate::parser::ParserResult;
+use crate::trace_data::FormattedStroke;
+use crate::tree::GroupedStroke;
+
+/// ids added, removed or changed between two documents (sorted)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl IdChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// differences between two documents
+/// The strokes are given by their index in the output of
+/// `ParserResult::formatted` with the default options
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InkDiff {
+    /// strokes of the second document missing from the first one
+    pub added: Vec<usize>,
+    /// strokes of the first document missing from the second one
+    pub removed: Vec<usize>,
+    /// strokes with the same id in both documents but other points or brush
+    /// (index in the first document, index in the second one)
+    pub modified: Vec<(usize, usize)>,
+    pub brushes: IdChanges,
+    pub contexts: IdChanges,
+}
+
+impl InkDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+            && self.brushes.is_empty()
+            && self.contexts.is_empty()
+    }
+}
+
+/// a stroke to match : the id of its trace, its points and brush
+pub(crate) type StrokeRef<'a> = (Option<&'a str>, &'a FormattedStroke, &'a Brush);
+
+/// stroke differences, with the indices of the strokes in their slice
+#[derive(Debug, Default)]
+pub(crate) struct StrokeDiff {
+    pub(crate) added: Vec<usize>,
+    pub(crate) removed: Vec<usize>,
+    pub(crate) modified: Vec<(usize, usize)>,
+}
+
+/// key matching identical strokes
+fn stroke_key((_, stroke, brush): &StrokeRef) -> (u64, BrushIndex) {
+    (stroke.fingerprint(), brush.duplicate_key())
+}
+
+/// matches the strokes of `a` and `b`, by id then by geometry
+pub(crate) fn diff_strokes(a: &[StrokeRef], b: &[StrokeRef]) -> StrokeDiff {
+    let mut diff = StrokeDiff::default();
+    let mut matched_a = vec![false; a.len()];
+    let mut matched_b = vec![false; b.len()];
+
+    let mut ids_b: HashMap<&str, usize> = HashMap::new();
+    for (index, (id, _, _)) in b.iter().enumerate() {
+        if let Some(id) = id {
+            ids_b.entry(id).or_insert(index);
+        }
+    }
+    for (index_a, stroke) in a.iter().enumerate() {
+        let Some(index_b) = stroke.0.and_then(|id| ids_b.remove(id)) else {
+            continue;
+        };
+        matched_a[index_a] = true;
+        matched_b[index_b] = true;
+        if stroke_key(stroke) != stroke_key(&b[index_b]) {
+            diff.modified.push((index_a, index_b));
+        }
+    }
+
+    // the other strokes of `a` by key, in document order
+    let mut remaining: HashMap<_, VecDeque<usize>> = HashMap::new();
+    for (index, stroke) in a.iter().enumerate() {
+        if !matched_a[index] {
+            remaining
+                .entry(stroke_key(stroke))
+                .or_default()
+                .push_back(index);
+        }
+    }
+    for (index_b, stroke) in b.iter().enumerate() {
+        if matched_b[index_b] {
+            continue;
+        }
+        match remaining
+            .get_mut(&stroke_key(stroke))
+            .and_then(|candidates| candidates.pop_front())
+        {
+            Some(index_a) => matched_a[index_a] = true,
+            None => diff.added.push(index_b),
+        }
+    }
+    diff.removed = (0..a.len()).filter(|index| !matched_a[*index]).collect();
+    diff
+}
+
+fn stroke_refs(strokes: &[GroupedStroke]) -> Vec<StrokeRef<'_>> {
+    strokes
+        .iter()
+        .map(|(_, source, stroke, brush)| (source.trace_id.as_deref(), stroke, brush))
+        .collect()
+}
+
+/// ids of `a` and `b` added, removed or with different values
+fn id_changes<T>(
+    a: &HashMap<String, T>,
+    b: &HashMap<String, T>,
+    same: impl Fn(&T, &T) -> bool,
+) -> IdChanges {
+    let mut changes = IdChanges {
+        added: b
+            .keys()
+            .filter(|id| !a.contains_key(*id))
+            .cloned()
+            .collect(),
+        removed: a
+            .keys()
+            .filter(|id| !b.contains_key(*id))
+            .cloned()
+            .collect(),
+        modified: a
+            .iter()
+            .filter(|(id, value)| b.get(*id).is_some_and(|other| !same(value, other)))
+            .map(|(id, _)| id.clone())
+            .collect(),
+    };
+    changes.added.sort();
+    changes.removed.sort();
+    changes.modified.sort();
+    changes
+}
+
+/// Compares two documents : strokes added, removed or modified and
+/// brushes and contexts changes (by id)
+pub fn diff(a: &ParserResult, b: &ParserResult) -> anyhow::Result<InkDiff> {
+    let options = FormatOptions::default();
+    let (_, strokes_a) = a.clone().formatted_with_groups(&options)?;
+    let (_, strokes_b) = b.clone().formatted_with_groups(&options)?;
+    let strokes = diff_strokes(&stroke_refs(&strokes_a), &stroke_refs(&strokes_b));
+
+    Ok(InkDiff {
+        added: strokes.added,
+        removed: strokes.removed,
+        modified: strokes.modified,
+        brushes: id_changes(&a.context_brush, &b.context_brush, |a, b| {
+            a.duplicate_key() == b.duplicate_key()
+        }),
+        contexts: id_changes(&a.context_dict, &b.context_dict, |a, b| a == b),
+    })
+}
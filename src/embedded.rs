@@ -0,0 +1,152 @@
+// document written piece by piece to a `core::fmt::Write` without any
+// allocation, for the firmware of digitizers streaming their ink : the
+// definitions of a context with integer channels, then the traces point by point
+//
+// The traces are encoded as `format_trace` does (see `codec`), so that the
+// parser reads them back the same way
+
+use core::fmt::{self, Write};
+
+use crate::codec::TraceEncoding;
+
+/// an integer channel of the context written by `write_header`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntegerChannel<'a> {
+    /// `X`, `Y`, `F`, `T`, ...
+    pub name: &'a str,
+    /// unit of the channel (`cm`, `dev`, `ms`, ...)
+    pub units: &'a str,
+    /// number of values per unit
+    pub resolution: f64,
+    /// highest value (for F, mapped to a pressure of 1)
+    pub max: Option<i64>,
+}
+
+/// writes `value` escaped for an attribute
+fn write_escaped<W: Write>(out: &mut W, value: &str) -> fmt::Result {
+    for c in value.chars() {
+        match c {
+            '&' => out.write_str("&amp;")?,
+            '<' => out.write_str("&lt;")?,
+            '>' => out.write_str("&gt;")?,
+            '"' => out.write_str("&quot;")?,
+            _ => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Opens the document and writes the definitions : a context `context_id`
+/// with the `channels` (in the order of the values of the points)
+pub fn write_header<W: Write>(
+    out: &mut W,
+    context_id: &str,
+    channels: &[IntegerChannel],
+) -> fmt::Result {
+    out.write_str(r#"<ink xmlns="http://www.w3.org/2003/InkML"><definitions><context xml:id=""#)?;
+    write_escaped(out, context_id)?;
+    out.write_str(r#""><inkSource xml:id="inkSrc_"#)?;
+    write_escaped(out, context_id)?;
+    out.write_str(r#""><traceFormat>"#)?;
+    for channel in channels {
+        out.write_str(r#"<channel name=""#)?;
+        write_escaped(out, channel.name)?;
+        out.write_str(r#"" type="integer""#)?;
+        if let Some(max) = channel.max {
+            write!(out, r#" max="{max}""#)?;
+        }
+        out.write_str(r#" units=""#)?;
+        write_escaped(out, channel.units)?;
+        out.write_str(r#"" />"#)?;
+    }
+    out.write_str("</traceFormat><channelProperties>")?;
+    for channel in channels {
+        out.write_str(r#"<channelProperty channel=""#)?;
+        write_escaped(out, channel.name)?;
+        write!(
+            out,
+            r#"" name="resolution" value="{}" units="1/"#,
+            channel.resolution
+        )?;
+        write_escaped(out, channel.units)?;
+        out.write_str(r#"" />"#)?;
+    }
+    out.write_str("</channelProperties></inkSource></context></definitions>")
+}
+
+/// Closes the document
+pub fn write_footer<W: Write>(out: &mut W) -> fmt::Result {
+    out.write_str("</ink>")
+}
+
+/// Trace written point by point, with `N` integer channels
+/// (without a `brushRef`, readers use their default brush)
+pub struct TraceWriter<'w, W: Write, const N: usize> {
+    out: &'w mut W,
+    encoding: TraceEncoding,
+    /// values of the previous point
+    previous: [i64; N],
+    /// differences between the previous point and the one before
+    difference: [i64; N],
+    /// number of points written
+    points: usize,
+}
+
+impl<'w, W: Write, const N: usize> TraceWriter<'w, W, N> {
+    /// opens a trace of the context `context_id` (written by `write_header`)
+    pub fn begin(
+        out: &'w mut W,
+        context_id: &str,
+        encoding: TraceEncoding,
+    ) -> Result<TraceWriter<'w, W, N>, fmt::Error> {
+        out.write_str(r##"<trace contextRef="#"##)?;
+        write_escaped(out, context_id)?;
+        out.write_str(r#"">"#)?;
+        Ok(TraceWriter {
+            out,
+            encoding,
+            previous: [0; N],
+            difference: [0; N],
+            points: 0,
+        })
+    }
+
+    /// writes a point (one value per channel)
+    /// Differences too large for an i64 are an error
+    pub fn push(&mut self, values: [i64; N]) -> fmt::Result {
+        // the first point is explicit and a double difference
+        // needs two previous points
+        let encoding = match (self.encoding, self.points) {
+            (_, 0) => TraceEncoding::Explicit,
+            (TraceEncoding::DoubleDifference, 1) => TraceEncoding::SingleDifference,
+            (encoding, _) => encoding,
+        };
+        if self.points > 0 {
+            self.out.write_char(',')?;
+        }
+        for (index, value) in values.iter().enumerate() {
+            let difference = value.checked_sub(self.previous[index]).ok_or(fmt::Error)?;
+            match encoding {
+                TraceEncoding::Explicit if index > 0 => write!(self.out, " {value}")?,
+                TraceEncoding::Explicit => write!(self.out, "{value}")?,
+                TraceEncoding::SingleDifference => write!(self.out, "'{difference}")?,
+                TraceEncoding::DoubleDifference => write!(
+                    self.out,
+                    "\"{}",
+                    difference
+                        .checked_sub(self.difference[index])
+                        .ok_or(fmt::Error)?
+                )?,
+            }
+            self.difference[index] = difference;
+        }
+        self.previous = values;
+        self.points += 1;
+        Ok(())
+    }
+
+    /// closes the trace
+    pub fn end(self) -> fmt::Result {
+        self.out.write_str("</trace>")
+    }
+}
@@ -0,0 +1,105 @@
+// recognition results given as EMMA (https://www.w3.org/TR/emma/) inside of
+// `annotationXML` elements (`emma` feature), as in the W3C sample files
+//
+// `<traceGroup xml:id="tg1"><annotationXML type="emma"><emma:emma ...>
+//   <emma:one-of id="r1"><emma:interpretation id="i1" emma:confidence="0.9" emma:tokens="hello"/>
+//   ...</emma:one-of></emma:emma></annotationXML>...</traceGroup>`
+// Each interpretation refers to the group the annotation is in, or to the
+// element of its `emma:signal` reference
+
+use crate::extensions::Extension;
+
+pub(crate) const EMMA_NAMESPACE: &str = "http://www.w3.org/2003/04/emma";
+
+/// an `emma:interpretation` of the document, see `ParserResult::emma`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmmaInterpretation {
+    pub id: Option<String>,
+    /// `emma:tokens` of the interpretation, or its text if it has none
+    pub text: String,
+    /// `emma:confidence`, between 0 and 1
+    pub confidence: Option<f64>,
+    /// id of the `one-of` (alternatives) or `group` element containing it
+    pub parent: Option<String>,
+    /// id of the traceGroup (or traceView) the interpretation is about
+    pub group: Option<String>,
+}
+
+fn is_emma(extension: &Extension) -> bool {
+    match &extension.namespace {
+        Some(namespace) => namespace == EMMA_NAMESPACE,
+        None => extension.prefix.as_deref() == Some("emma"),
+    }
+}
+
+/// value of the attribute `name`, with the prefix of the element if it has one
+/// (`emma:confidence` on `emma:interpretation`)
+fn attribute<'a>(extension: &'a Extension, name: &str) -> Option<&'a str> {
+    let prefixed = extension
+        .prefix
+        .as_ref()
+        .map(|prefix| format!("{prefix}:{name}"));
+    extension
+        .attributes
+        .iter()
+        .find(|(key, _)| Some(key) == prefixed.as_ref() || key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// text of the element and of its children
+fn all_text(extension: &Extension) -> String {
+    let mut text = extension.text.trim().to_owned();
+    for child in &extension.children {
+        let child_text = all_text(child);
+        if !child_text.is_empty() {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(&child_text);
+        }
+    }
+    text
+}
+
+/// interpretations found in an element of an `annotationXML` (and its children)
+/// `group` : id of the group the annotation is in
+pub(crate) fn interpretations(
+    extension: &Extension,
+    group: Option<&str>,
+) -> Vec<EmmaInterpretation> {
+    let mut found = vec![];
+    collect(extension, None, group, &mut found);
+    found
+}
+
+fn collect(
+    extension: &Extension,
+    parent: Option<&str>,
+    group: Option<&str>,
+    found: &mut Vec<EmmaInterpretation>,
+) {
+    if is_emma(extension) && extension.name == "interpretation" {
+        let group = attribute(extension, "signal")
+            // (only references to an element of the document)
+            .and_then(|signal| signal.strip_prefix('#'))
+            .or(group)
+            .map(String::from);
+        found.push(EmmaInterpretation {
+            id: attribute(extension, "id").map(String::from),
+            text: attribute(extension, "tokens")
+                .map(String::from)
+                .unwrap_or_else(|| all_text(extension)),
+            confidence: attribute(extension, "confidence").and_then(|value| value.parse().ok()),
+            parent: parent.map(String::from),
+            group,
+        });
+        return;
+    }
+    let parent = match extension.name.as_str() {
+        "one-of" | "group" | "sequence" if is_emma(extension) => attribute(extension, "id"),
+        _ => parent,
+    };
+    for child in &extension.children {
+        collect(child, parent, group, found);
+    }
+}
@@ -0,0 +1,131 @@
+// typed errors, for the cases where the caller may want to act on the details
+// (they are returned inside an `anyhow::Error` and can be recovered with `downcast_ref`)
+
+use std::fmt;
+
+/// a value of a stroke that can't be written in its channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfRangeValue {
+    /// index of the stroke in the input of the writer
+    pub stroke_index: usize,
+    pub point_index: usize,
+    /// name of the channel (X, Y, F)
+    pub channel: String,
+    pub value: f64,
+}
+
+impl fmt::Display for OutOfRangeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stroke {} point {} : {} = {}",
+            self.stroke_index, self.point_index, self.channel, self.value
+        )
+    }
+}
+
+/// Some strokes have values that don't fit the channels of the context
+/// (negative pressure, values above the declared `max`, NaN or infinite values)
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidStrokesError {
+    pub values: Vec<OutOfRangeValue>,
+}
+
+impl InvalidStrokesError {
+    /// indices of the strokes with at least one invalid value
+    pub fn stroke_indices(&self) -> Vec<usize> {
+        let mut indices = self
+            .values
+            .iter()
+            .map(|value| value.stroke_index)
+            .collect::<Vec<usize>>();
+        indices.dedup();
+        indices
+    }
+}
+
+impl fmt::Display for InvalidStrokesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} values are out of the range of their channel : ",
+            self.values.len()
+        )?;
+        let values = self
+            .values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{}", values.join(", "))
+    }
+}
+
+impl std::error::Error for InvalidStrokesError {}
+
+/// limits of `ParserLimits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Traces,
+    PointsPerTrace,
+    Contexts,
+    Depth,
+    Characters,
+}
+
+/// The document is larger than the limits of the parser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    /// value of the limit
+    pub max: usize,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self.limit {
+            Limit::Traces => "traces",
+            Limit::PointsPerTrace => "points in a trace",
+            Limit::Contexts => "contexts",
+            Limit::Depth => "levels of nested elements",
+            Limit::Characters => "characters of text",
+        };
+        write!(
+            f,
+            "LimitExceeded : the document has more than {} {description}",
+            self.max
+        )
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// The parsing was stopped by the progress callback of the `ParserOptions`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cancelled {
+    /// number of traces read before the cancellation
+    pub traces: usize,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled after {} traces", self.traces)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The stroke doesn't have a channel needed by an operation
+/// (e.g. the timestamps to compute speeds)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingChannel {
+    /// name of the channel (T)
+    pub channel: String,
+}
+
+impl fmt::Display for MissingChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The stroke has no {} channel", self.channel)
+    }
+}
+
+impl std::error::Error for MissingChannel {}
@@ -0,0 +1,233 @@
+// elements the parser doesn't know (vendor extensions like `<msink:context>`)
+// kept as is so that they can be written back
+
+use crate::options::IdAttribute;
+use crate::traits::Writable;
+use crate::validate::is_inkml_element;
+use std::io::Write;
+use xml::attribute::OwnedAttribute;
+use xml::name::OwnedName;
+use xml::writer::{Error, EventWriter, XmlEvent};
+
+pub(crate) const INKML_NAMESPACE: &str = "http://www.w3.org/2003/InkML";
+/// namespace of the extensions written by Microsoft applications (OneNote, Word)
+pub(crate) const MSINK_NAMESPACE: &str = "http://schemas.microsoft.com/ink/2010/main";
+
+/// an element that isn't part of InkML, with its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    /// local name of the element
+    pub name: String,
+    pub prefix: Option<String>,
+    /// uri of the namespace of the element (None if its prefix isn't declared)
+    pub namespace: Option<String>,
+    /// attributes, with their prefix (`prefix:name`)
+    pub attributes: Vec<(String, String)>,
+    /// text of the element (the text around the children is concatenated)
+    pub text: String,
+    pub children: Vec<Extension>,
+    /// local name of the element containing this one
+    pub parent: String,
+}
+
+impl Extension {
+    pub(crate) fn new(
+        name: &OwnedName,
+        attributes: &[OwnedAttribute],
+        parent: String,
+    ) -> Extension {
+        Extension {
+            name: name.local_name.clone(),
+            prefix: name.prefix.clone(),
+            namespace: name.namespace.clone(),
+            attributes: attributes
+                .iter()
+                .map(|attribute| {
+                    let name = match &attribute.name.prefix {
+                        Some(prefix) => format!("{prefix}:{}", attribute.name.local_name),
+                        None => attribute.name.local_name.clone(),
+                    };
+                    (name, attribute.value.clone())
+                })
+                .collect(),
+            text: String::new(),
+            children: vec![],
+            parent,
+        }
+    }
+
+    /// true if the element isn't an InkML element : its local name isn't one of
+    /// InkML or its namespace isn't the InkML one (e.g. an xhtml `<table>`)
+    pub(crate) fn is_extension(name: &OwnedName) -> bool {
+        if !is_inkml_element(&name.local_name) {
+            return true;
+        }
+        match (&name.prefix, &name.namespace) {
+            (_, Some(namespace)) => namespace != INKML_NAMESPACE,
+            // document without namespace declarations
+            (None, None) => false,
+            // namespaces aren't resolved, go by the usual prefix
+            (Some(prefix), None) => prefix != "inkml",
+        }
+    }
+
+    /// true for the `msink` elements of Microsoft applications
+    pub fn is_microsoft(&self) -> bool {
+        match &self.namespace {
+            Some(namespace) => namespace == MSINK_NAMESPACE,
+            None => self.prefix.as_deref() == Some("msink"),
+        }
+    }
+
+    /// true if the element or one of its children is a Microsoft one
+    pub(crate) fn has_microsoft(&self) -> bool {
+        self.is_microsoft() || self.children.iter().any(Extension::has_microsoft)
+    }
+
+    /// true if the element was read inside of the `definitions`
+    pub(crate) fn in_definitions(&self) -> bool {
+        self.parent == "definitions"
+    }
+
+    /// writes the element back, inside of an `annotationXML` if it was read in one
+    /// (e.g. the `emma:emma` element holding the `msink:context` of OneNote)
+    pub(crate) fn write_in_place<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+    ) -> Result<(), Error> {
+        if self.parent != "annotationXML" {
+            return self.write(writer, id_attribute);
+        }
+        writer.write(XmlEvent::start_element("annotationXML"))?;
+        self.write(writer, id_attribute)?;
+        writer.write(XmlEvent::end_element())
+    }
+}
+
+impl Writable for Extension {
+    /// writes the element back. A prefix is only kept if its namespace is known
+    /// (namespaces are declared on each element with a namespace)
+    fn write<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+    ) -> Result<(), Error> {
+        let name = match (&self.prefix, &self.namespace) {
+            (Some(prefix), Some(_)) => format!("{prefix}:{}", self.name),
+            _ => self.name.clone(),
+        };
+        let mut element = XmlEvent::start_element(name.as_str());
+        match (&self.prefix, &self.namespace) {
+            (Some(prefix), Some(namespace)) => {
+                element = element.ns(prefix.as_str(), namespace.as_str());
+            }
+            // unprefixed element of another default namespace (e.g. xhtml)
+            (None, Some(namespace)) => element = element.default_ns(namespace.as_str()),
+            _ => {}
+        }
+        for (name, value) in &self.attributes {
+            // same for the prefix of the attributes (`xml` is always declared)
+            let name = match name.split_once(':') {
+                Some((prefix, local_name))
+                    if prefix != "xml"
+                        && (self.namespace.is_none() || self.prefix.as_deref() != Some(prefix)) =>
+                {
+                    local_name
+                }
+                _ => name.as_str(),
+            };
+            element = element.attr(name, value);
+        }
+        writer.write(element)?;
+        if !self.text.is_empty() {
+            writer.write(XmlEvent::characters(&self.text))?;
+        }
+        for child in &self.children {
+            child.write(writer, id_attribute)?;
+        }
+        writer.write(XmlEvent::end_element())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    fn name(local_name: &str, prefix: Option<&str>, namespace: Option<&str>) -> OwnedName {
+        OwnedName {
+            local_name: String::from(local_name),
+            prefix: prefix.map(String::from),
+            namespace: namespace.map(String::from),
+        }
+    }
+
+    #[test]
+    fn elements_are_told_apart_by_their_namespace() {
+        assert!(!Extension::is_extension(&name(
+            "trace",
+            None,
+            Some(INKML_NAMESPACE)
+        )));
+        assert!(!Extension::is_extension(&name("trace", None, None)));
+        assert!(!Extension::is_extension(&name(
+            "trace",
+            Some("inkml"),
+            None
+        )));
+        // an InkML local name in another namespace
+        let xhtml = "http://www.w3.org/1999/xhtml";
+        assert!(Extension::is_extension(&name("table", None, Some(xhtml))));
+        assert!(Extension::is_extension(&name(
+            "context",
+            Some("msink"),
+            None
+        )));
+        assert!(Extension::is_extension(&name(
+            "context",
+            Some("msink"),
+            Some(MSINK_NAMESPACE)
+        )));
+        assert!(Extension::is_extension(&name(
+            "unknown",
+            None,
+            Some(INKML_NAMESPACE)
+        )));
+    }
+
+    #[test]
+    fn foreign_elements_are_not_read_as_inkml_ones() {
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML" xmlns:msink="http://schemas.microsoft.com/ink/2010/main">
+    <definitions>
+        <context xml:id="ctx0">
+            <traceFormat>
+                <channel name="X" type="integer" />
+                <channel name="Y" type="integer" />
+            </traceFormat>
+        </context>
+        <msink:context rotatedBoundingBox="0 0 1 1" />
+    </definitions>
+    <annotationXML>
+        <table xmlns="http://www.w3.org/1999/xhtml"><tr><td>not a mapping</td></tr></table>
+    </annotationXML>
+    <trace xmlns="urn:other">1 2</trace>
+    <trace>3 4</trace>
+</ink>"##;
+        let result = parse_str(document).unwrap();
+        // only the InkML trace is read
+        let strokes = result.clone().formatted(&Default::default()).unwrap();
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].0.x(), [0.003]);
+
+        let extensions = result.extensions();
+        let msink = extensions
+            .iter()
+            .find(|extension| extension.name == "context")
+            .unwrap();
+        assert!(msink.is_microsoft());
+        assert_eq!(msink.parent, "definitions");
+        // the xhtml elements of the annotation are skipped
+        assert!(extensions.iter().all(|extension| extension.name != "table"));
+    }
+}
@@ -0,0 +1,22 @@
+// entry points used by the fuzz targets of `fuzz/` (not a stable api)
+
+use crate::context::ChannelType;
+use crate::trace_data::{ChannelData, TraceData};
+
+/// decodes the text of a trace with channels of the given types
+/// (each byte gives the type of one channel)
+pub fn decode_trace(channel_types: &[u8], text: &str) -> anyhow::Result<Vec<ChannelData>> {
+    let mut trace_data = TraceData::default();
+    trace_data.reset(
+        channel_types
+            .iter()
+            .map(|channel_type| match channel_type % 4 {
+                0 => ChannelType::Integer,
+                1 => ChannelType::Decimal,
+                2 => ChannelType::Double,
+                _ => ChannelType::Bool,
+            }),
+    );
+    trace_data.parse_raw_data(text)?;
+    Ok(trace_data.take_data())
+}
@@ -0,0 +1,338 @@
+// conversions to and from the stroke models of Rnote and Xournal++
+// and export to the pointer events of the MyScript iink SDK
+//
+// - Rnote : pen paths are a list of elements (position in px at 96 dpi + pressure)
+//   with an RGBA color (floats between 0 and 1) and a stroke width in px
+// - Xournal++ : `<stroke>` elements in a `.xopp` file, coordinates in pt (1/72 in)
+//   with a `#RRGGBBAA` color and a `width` attribute holding the base width
+//   followed (for pressure sensitive strokes) by the width of each segment
+// - MyScript iink : JSON pointer events, one `stroke` event per stroke with
+//   arrays of x, y (px at the resolution of the input), t (ms) and p (0 - 1)
+
+use crate::brushes::Brush;
+use crate::context::CM_PER_INCH;
+use crate::trace_data::FormattedStroke;
+use crate::xml_helpers::get_id;
+use anyhow::anyhow;
+use std::io::Read;
+use xml::reader::{EventReader, XmlEvent};
+
+/// resolution of the Rnote document coordinates
+const RNOTE_DPI: f64 = 96.0;
+/// resolution of the Xournal++ document coordinates (pt)
+const XOPP_DPI: f64 = 72.0;
+/// default resolution of the iink SDK input
+pub const IINK_DPI: f64 = 96.0;
+
+/// one point of a Rnote pen path
+#[derive(Debug, Clone, PartialEq)]
+pub struct RnoteElement {
+    /// position in px
+    pub pos: (f64, f64),
+    /// pressure, between 0 and 1
+    pub pressure: f64,
+}
+
+/// a Rnote brush stroke
+#[derive(Debug, Clone, PartialEq)]
+pub struct RnoteStroke {
+    pub elements: Vec<RnoteElement>,
+    /// RGBA color, each component between 0 and 1
+    pub color: [f64; 4],
+    /// width in px
+    pub stroke_width: f64,
+}
+
+/// Converts strokes to Rnote brush strokes
+/// Brushes ignoring pressure give a constant pressure of 1.0
+pub fn to_rnote_strokes(strokes: &[(FormattedStroke, Brush)]) -> Vec<RnoteStroke> {
+    let px_per_cm = RNOTE_DPI / CM_PER_INCH;
+    strokes
+        .iter()
+        .map(|(stroke, brush)| {
+            let style = brush.effective_style();
+            RnoteStroke {
+                elements: stroke
+                    .x
+                    .iter()
+                    .zip(&stroke.y)
+                    .zip(&stroke.f)
+                    .map(|((x, y), f)| RnoteElement {
+                        pos: (x * px_per_cm, y * px_per_cm),
+                        pressure: if brush.ignorepressure {
+                            1.0
+                        } else {
+                            f.clamp(0.0, 1.0)
+                        },
+                    })
+                    .collect(),
+                color: [
+                    f64::from(style.color.0) / 255.0,
+                    f64::from(style.color.1) / 255.0,
+                    f64::from(style.color.2) / 255.0,
+                    f64::from(style.alpha),
+                ],
+                stroke_width: style.width_cm * px_per_cm,
+            }
+        })
+        .collect()
+}
+
+/// a Xournal++ stroke
+#[derive(Debug, Clone, PartialEq)]
+pub struct XoppStroke {
+    /// `tool` attribute (pen, highlighter, ...)
+    pub tool: String,
+    /// RGBA color
+    pub color: (u8, u8, u8, u8),
+    /// base width in pt, followed by the width of each segment if the
+    /// stroke is pressure sensitive
+    pub widths: Vec<f64>,
+    /// coordinates in pt
+    pub coordinates: Vec<(f64, f64)>,
+}
+
+/// Converts Xournal++ strokes to formatted strokes and brushes
+/// The pressure is recovered from the ratio between the width of each
+/// segment and the base width
+pub fn from_xopp(strokes: &[XoppStroke]) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
+    let cm_per_pt = CM_PER_INCH / XOPP_DPI;
+    strokes
+        .iter()
+        .enumerate()
+        .map(|(index, stroke)| {
+            let base_width = *stroke
+                .widths
+                .first()
+                .ok_or(anyhow!("The xopp stroke {index} has no width"))?;
+            let segment_widths = &stroke.widths[1..];
+            let pressure_sensitive = !segment_widths.is_empty() && base_width > 0.0;
+
+            let f = (0..stroke.coordinates.len())
+                .map(|point_index| {
+                    if pressure_sensitive {
+                        // the last point has no segment starting from it
+                        let width = segment_widths
+                            .get(point_index)
+                            .or(segment_widths.last())
+                            .unwrap();
+                        (width / base_width).clamp(0.0, 1.0)
+                    } else {
+                        1.0
+                    }
+                })
+                .collect();
+
+            Ok((
+                FormattedStroke {
+                    x: stroke
+                        .coordinates
+                        .iter()
+                        .map(|(x, _)| x * cm_per_pt)
+                        .collect(),
+                    y: stroke
+                        .coordinates
+                        .iter()
+                        .map(|(_, y)| y * cm_per_pt)
+                        .collect(),
+                    f,
+                    ..Default::default()
+                },
+                Brush::init(
+                    format!("br{}", index + 1),
+                    stroke.color,
+                    !pressure_sensitive,
+                    0,
+                    base_width * cm_per_pt,
+                ),
+            ))
+        })
+        .collect()
+}
+
+/// Converts formatted strokes and brushes to Xournal++ strokes
+pub fn to_xopp(strokes: &[(FormattedStroke, Brush)]) -> Vec<XoppStroke> {
+    let pt_per_cm = XOPP_DPI / CM_PER_INCH;
+    strokes
+        .iter()
+        .map(|(stroke, brush)| {
+            let base_width = brush.stroke_width_cm() * pt_per_cm;
+            let mut widths = vec![base_width];
+            if !brush.ignorepressure && stroke.f.len() > 1 {
+                widths.extend(
+                    stroke.f[..stroke.f.len() - 1]
+                        .iter()
+                        .map(|f| base_width * f.clamp(0.0, 1.0)),
+                );
+            }
+            XoppStroke {
+                tool: String::from("pen"),
+                color: (brush.color.r, brush.color.g, brush.color.b, brush.color.a),
+                widths,
+                coordinates: stroke
+                    .x
+                    .iter()
+                    .zip(&stroke.y)
+                    .map(|(x, y)| (x * pt_per_cm, y * pt_per_cm))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Converts strokes to the JSON pointer events of the MyScript iink SDK
+/// (`{"events":[{"type":"stroke","pointerType":"PEN","x":[..],"y":[..],"t":[..],"p":[..]}]}`)
+/// with the positions in px at `dpi` (`IINK_DPI` for the default of the SDK)
+/// `t` is left out for strokes without timestamps. Brushes ignoring pressure
+/// give a constant pressure of 1.0
+pub fn to_iink_pointer_events(strokes: &[(FormattedStroke, Brush)], dpi: f64) -> String {
+    let px_per_cm = dpi / CM_PER_INCH;
+    let events: Vec<String> = strokes
+        .iter()
+        .map(|(stroke, brush)| {
+            let mut event = format!(
+                r#"{{"type":"stroke","pointerType":"PEN","x":{},"y":{}"#,
+                json_array(stroke.x.iter().map(|x| x * px_per_cm)),
+                json_array(stroke.y.iter().map(|y| y * px_per_cm)),
+            );
+            if let Some(t) = &stroke.t {
+                event.push_str(&format!(r#","t":{}"#, json_array(t.iter().copied())));
+            }
+            let pressures = stroke.f.iter().map(|f| {
+                if brush.ignorepressure {
+                    1.0
+                } else {
+                    f.clamp(0.0, 1.0)
+                }
+            });
+            event.push_str(&format!(r#","p":{}}}"#, json_array(pressures)));
+            event
+        })
+        .collect();
+    format!(r#"{{"events":[{}]}}"#, events.join(","))
+}
+
+/// JSON array of numbers (`null` for values that aren't finite)
+fn json_array(values: impl Iterator<Item = f64>) -> String {
+    let values: Vec<String> = values
+        .map(|value| {
+            if value.is_finite() {
+                value.to_string()
+            } else {
+                String::from("null")
+            }
+        })
+        .collect();
+    format!("[{}]", values.join(","))
+}
+
+/// Reads all `<stroke>` elements of an (uncompressed) `.xopp` document
+pub fn read_xopp_strokes<T: Read>(buf_file: T) -> anyhow::Result<Vec<XoppStroke>> {
+    let mut strokes = vec![];
+    let mut current_stroke: Option<XoppStroke> = None;
+    // coordinates of the current stroke
+    let mut stroke_text = String::new();
+
+    for xml_event in EventReader::new(buf_file) {
+        match xml_event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) if name.local_name == "stroke" => {
+                let attribute = |key: &str| get_id(&attributes, String::from(key));
+                let color =
+                    attribute("color").ok_or(anyhow!("No color was found for the xopp stroke"))?;
+                let widths = attribute("width")
+                    .ok_or(anyhow!("No width was found for the xopp stroke"))?
+                    .split_whitespace()
+                    .map(|value| {
+                        value
+                            .parse::<f64>()
+                            .map_err(|_| anyhow!("Could not parse {value} to f64"))
+                    })
+                    .collect::<anyhow::Result<Vec<f64>>>()?;
+                stroke_text.clear();
+                current_stroke = Some(XoppStroke {
+                    tool: attribute("tool").unwrap_or(String::from("pen")),
+                    color: parse_xopp_color(&color)?,
+                    widths,
+                    coordinates: vec![],
+                });
+            }
+            // the text can be split in several events (CDATA sections, ...)
+            Ok(XmlEvent::Characters(text) | XmlEvent::CData(text) | XmlEvent::Whitespace(text))
+                if current_stroke.is_some() =>
+            {
+                stroke_text.push_str(&text);
+            }
+            Ok(XmlEvent::EndElement { name }) if name.local_name == "stroke" => {
+                if let Some(mut stroke) = current_stroke.take() {
+                    let values = stroke_text
+                        .split_whitespace()
+                        .map(|value| {
+                            value
+                                .parse::<f64>()
+                                .map_err(|_| anyhow!("Could not parse {value} to f64"))
+                        })
+                        .collect::<anyhow::Result<Vec<f64>>>()?;
+                    if values.len() % 2 != 0 {
+                        return Err(anyhow!(
+                            "Expected pairs of coordinates in the xopp stroke, found {} values",
+                            values.len()
+                        ));
+                    }
+                    stroke.coordinates = values.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+                    strokes.push(stroke);
+                }
+            }
+            Err(e) => return Err(anyhow!("Failed to parse xml element : {e}")),
+            _ => {}
+        }
+    }
+    Ok(strokes)
+}
+
+/// parses a `#RRGGBBAA` color
+fn parse_xopp_color(color_string: &str) -> anyhow::Result<(u8, u8, u8, u8)> {
+    if color_string.len() != 9 || !color_string.is_ascii() || !color_string.starts_with('#') {
+        return Err(anyhow!(
+            "Unexpected xopp color {color_string}, expected the #RRGGBBAA format"
+        ));
+    }
+    let component = |start: usize| {
+        u8::from_str_radix(&color_string[start..start + 2], 16)
+            .map_err(|_| anyhow!("Failed to parse {color_string}"))
+    };
+    Ok((component(1)?, component(3)?, component(5)?, component(7)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xopp_coordinates_are_read_across_cdata_sections() {
+        let document = r##"<xournal><page><layer>
+            <stroke tool="pen" color="#0000ffff" width="1.41 1.2 1.3">10 20 <![CDATA[30 40]]>
+                <!-- comment --> 50 60</stroke>
+            <stroke tool="highlighter" color="#ffff007f" width="8"><![CDATA[1 2 3 4]]></stroke>
+        </layer></page></xournal>"##;
+        let strokes = read_xopp_strokes(document.as_bytes()).unwrap();
+        assert_eq!(strokes.len(), 2);
+        assert_eq!(
+            strokes[0].coordinates,
+            [(10.0, 20.0), (30.0, 40.0), (50.0, 60.0)]
+        );
+        assert_eq!(strokes[0].widths, [1.41, 1.2, 1.3]);
+        assert_eq!(strokes[1].tool, "highlighter");
+        assert_eq!(strokes[1].color, (255, 255, 0, 127));
+        assert_eq!(strokes[1].coordinates, [(1.0, 2.0), (3.0, 4.0)]);
+    }
+
+    #[test]
+    fn xopp_strokes_with_an_odd_number_of_values_are_rejected() {
+        let document = r##"<xournal><page><layer>
+            <stroke tool="pen" color="#000000ff" width="1">10 20 <![CDATA[30]]></stroke>
+        </layer></page></xournal>"##;
+        assert!(read_xopp_strokes(document.as_bytes()).is_err());
+    }
+}
@@ -1,15 +1,143 @@
+// The parser, the writer and everything else need std (default feature),
+// only the encoding of the traces (`codec`) and the writer without
+// allocation (`embedded`) are available without it
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 // modules
+#[cfg(feature = "async")]
+mod async_parser;
+#[cfg(feature = "std")]
+mod batch;
+#[cfg(feature = "std")]
 mod brushes;
+pub mod codec;
+#[cfg(feature = "std")]
+mod colors;
+#[cfg(feature = "container")]
+pub mod container;
+#[cfg(feature = "std")]
 mod context;
+#[cfg(feature = "std")]
+pub mod datasets;
+#[cfg(feature = "std")]
+mod diff;
+pub mod embedded;
+#[cfg(feature = "emma")]
+mod emma;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+mod extensions;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+#[cfg(feature = "std")]
+pub mod interop;
+#[cfg(feature = "std")]
+mod mapping;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "std")]
+mod normalize;
+#[cfg(feature = "std")]
+mod options;
+#[cfg(feature = "std")]
+mod palette;
+#[cfg(feature = "std")]
 mod parser;
+#[cfg(feature = "raster")]
+pub mod render;
+#[cfg(feature = "std")]
+pub mod rendering;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "std")]
+mod resolver;
+#[cfg(feature = "std")]
+mod spatial;
+#[cfg(feature = "std")]
+mod symbols;
+#[cfg(feature = "test-util")]
+pub mod testing;
+#[cfg(feature = "std")]
 mod trace_data;
+#[cfg(feature = "std")]
 mod traits;
+#[cfg(feature = "std")]
+mod tree;
+#[cfg(feature = "std")]
+mod validate;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "std")]
 mod writer;
+#[cfg(feature = "std")]
+mod xml_encoding;
+#[cfg(feature = "std")]
 mod xml_helpers;
+#[cfg(feature = "std")]
+mod xml_source;
 
 //re export
-pub use brushes::Brush;
-pub use parser::parse_formatted;
-pub use parser::parser;
-pub use trace_data::FormattedStroke;
-pub use writer::writer;
+#[cfg(feature = "async")]
+pub use async_parser::{parse_formatted_async, parser_async};
+#[cfg(feature = "std")]
+pub use batch::{convert_batch, BatchOptions, BatchOutput};
+#[cfg(feature = "std")]
+pub use brushes::{Brush, BrushBuilder, BrushTip, EffectiveStyle, RasterOp};
+#[cfg(feature = "std")]
+pub use colors::Color;
+#[cfg(feature = "std")]
+pub use context::{
+    Channel, ChannelKind, ChannelType, ChannelUnit, Context, ContextBuilder, Orientation,
+    Resolution, ResolutionUnits,
+};
+#[cfg(feature = "std")]
+pub use diff::{diff, IdChanges, InkDiff};
+#[cfg(feature = "emma")]
+pub use emma::EmmaInterpretation;
+#[cfg(feature = "std")]
+pub use error::{
+    Cancelled, InvalidStrokesError, Limit, LimitExceeded, MissingChannel, OutOfRangeValue,
+};
+#[cfg(feature = "std")]
+pub use extensions::Extension;
+#[cfg(feature = "std")]
+pub use mapping::{Interpolation, TableMapping};
+#[cfg(feature = "metrics")]
+pub use metrics::ParseMetrics;
+#[cfg(feature = "std")]
+pub use normalize::normalize;
+#[cfg(feature = "std")]
+pub use options::{
+    BlackTransparency, Canvas, DuplicatePolicy, FormatOptions, IdAttribute, ParsedSoFar,
+    ParserLimits, ParserOptions, Target, TraceEncoding, WriterConfig,
+};
+#[cfg(feature = "std")]
+pub use palette::palette;
+#[cfg(feature = "std")]
+pub use parser::{
+    parse_bytes, parse_file, parse_formatted, parse_formatted_bytes, parse_formatted_file,
+    parse_formatted_str, parse_formatted_tree, parse_formatted_with_options,
+    parse_formatted_with_sources, parse_str, parser, parser_with_options, ParserResult,
+};
+#[cfg(feature = "std")]
+pub use resolver::{FileSystemResolver, NoReferenceResolver, ReferenceResolver};
+#[cfg(feature = "std")]
+pub use spatial::SpatialIndex;
+#[cfg(feature = "std")]
+pub use trace_data::{
+    BoundingBox, Continuation, FormattedStroke, IntoStrokePoints, Origin, StrokePoint,
+    StrokePoints, StrokeSource, TraceType,
+};
+#[cfg(feature = "std")]
+pub use tree::{Annotation, InkNode, TraceGroup};
+#[cfg(feature = "std")]
+pub use validate::{validate, ValidationIssue, ValidationIssueKind};
+#[cfg(feature = "std")]
+pub use writer::{
+    append_strokes, validate_output, write_parser_result, write_to_file, writer, writer_tree,
+    writer_with_config, InkmlStreamWriter,
+};
@@ -1,20 +1,7 @@
-use brushes::Brush;
 use std::fs::File;
 use std::io::BufReader;
-use trace_data::FormattedStroke;
-mod brushes;
-mod context;
-mod parser;
-mod trace_data;
-mod traits;
-mod writer;
-mod xml_helpers;
-
-use parser::{parse_formatted, parser};
 use tracing::trace;
-#[cfg(feature = "tracer")]
-use tracing_subscriber;
-use writer::writer;
+use writer_inkml::{parse_formatted, parser, writer, Brush, FormattedStroke};
 
 fn main() {
     #[cfg(feature = "tracer")]
@@ -44,11 +31,7 @@ fn main() {
 
     // writer stage
     let data = vec![(
-        FormattedStroke {
-            x: vec![0.0, 1.0],
-            y: vec![0.0, 1.0],
-            f: vec![0.0, 1.0],
-        },
+        FormattedStroke::new(vec![0.0, 1.0], vec![0.0, 1.0], vec![0.0, 1.0]).unwrap(),
         Brush::init(String::from("hello"), (0, 1, 0), true, 150, 10.0),
     )];
     writer(data).unwrap();
@@ -0,0 +1,236 @@
+// `table` mappings of channels : lookup tables given by devices with
+// nonlinear sensors (pressure curves for the most part)
+//
+// `<channel name="F" ...><mapping type="table"><table interpolation="linear">0 0, 512 700, 1023 1023</table></mapping></channel>`
+// Each row of the table is a raw value of the channel followed by the value it
+// stands for. Raw values (and the `min` and `max` of the channel) are mapped
+// before the scaling of the channel
+
+use anyhow::anyhow;
+
+/// how values between two rows of a table are mapped
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    /// value of the row below
+    Floor,
+    /// value of the nearest row
+    Middle,
+    /// value of the row above
+    Ceiling,
+    /// linear interpolation between the two rows
+    /// (`cubic` tables are read as linear ones)
+    #[default]
+    Linear,
+}
+
+impl Interpolation {
+    pub(crate) fn parse(name: &Option<String>) -> anyhow::Result<Interpolation> {
+        match name.as_deref() {
+            None | Some("linear") | Some("cubic") => Ok(Interpolation::Linear),
+            Some("floor") => Ok(Interpolation::Floor),
+            Some("middle") => Ok(Interpolation::Middle),
+            Some("ceiling") => Ok(Interpolation::Ceiling),
+            Some(value) => Err(anyhow!("Unknown interpolation {value} for a table")),
+        }
+    }
+}
+
+impl From<Interpolation> for String {
+    fn from(value: Interpolation) -> Self {
+        match value {
+            Interpolation::Floor => "floor",
+            Interpolation::Middle => "middle",
+            Interpolation::Ceiling => "ceiling",
+            Interpolation::Linear => "linear",
+        }
+        .to_owned()
+    }
+}
+
+/// lookup table from the raw values of a channel to the values they stand for
+/// Values outside of the table take the value of the first or last row
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableMapping {
+    /// (raw value, mapped value), sorted by raw value without duplicates
+    rows: Vec<(f64, f64)>,
+    interpolation: Interpolation,
+}
+
+impl TableMapping {
+    /// table from its rows (raw value, mapped value), in any order
+    /// Fails without rows or with values that are not finite
+    pub fn new(
+        rows: impl IntoIterator<Item = (f64, f64)>,
+        interpolation: Interpolation,
+    ) -> anyhow::Result<TableMapping> {
+        let mut rows = rows.into_iter().collect::<Vec<(f64, f64)>>();
+        if rows.is_empty() {
+            return Err(anyhow!("A table mapping needs at least one row"));
+        }
+        if rows
+            .iter()
+            .any(|(raw, mapped)| !raw.is_finite() || !mapped.is_finite())
+        {
+            return Err(anyhow!("The rows of a table mapping must be finite"));
+        }
+        rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+        rows.dedup_by(|a, b| a.0 == b.0);
+        Ok(TableMapping {
+            rows,
+            interpolation,
+        })
+    }
+
+    /// reads the text of a `table` element : rows separated by commas, each
+    /// row being the raw value then the mapped one
+    pub(crate) fn parse(text: &str, interpolation: Interpolation) -> anyhow::Result<TableMapping> {
+        let rows = text
+            .split(',')
+            .filter(|row| !row.trim().is_empty())
+            .map(|row| {
+                let values = row
+                    .split_whitespace()
+                    .map(str::parse::<f64>)
+                    .collect::<Result<Vec<f64>, _>>()
+                    .map_err(|e| anyhow!("Could not read the table row \"{}\" : {e}", row.trim()))?;
+                match values[..] {
+                    [raw, mapped] => Ok((raw, mapped)),
+                    _ => Err(anyhow!(
+                        "The table row \"{}\" should have 2 values, only tables of one channel are supported",
+                        row.trim()
+                    )),
+                }
+            })
+            .collect::<anyhow::Result<Vec<(f64, f64)>>>()?;
+        TableMapping::new(rows, interpolation)
+    }
+
+    pub fn rows(&self) -> &[(f64, f64)] {
+        &self.rows
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    /// the value `raw` stands for
+    pub fn apply(&self, raw: f64) -> f64 {
+        interpolate(self.rows.iter().copied(), raw, self.interpolation)
+    }
+
+    /// raw value standing for `mapped`, for the writer
+    /// (exact for tables going in a single direction only)
+    pub(crate) fn unapply(&self, mapped: f64) -> f64 {
+        let mut rows = self
+            .rows
+            .iter()
+            .map(|&(raw, mapped)| (mapped, raw))
+            .collect::<Vec<(f64, f64)>>();
+        rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+        interpolate(rows, mapped, Interpolation::Linear)
+    }
+
+    /// content of the `table` element
+    pub(crate) fn text(&self) -> String {
+        self.rows
+            .iter()
+            .map(|(raw, mapped)| format!("{raw} {mapped}"))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+/// value at `x` in the `rows` (sorted by their first value)
+fn interpolate(
+    rows: impl IntoIterator<Item = (f64, f64)>,
+    x: f64,
+    interpolation: Interpolation,
+) -> f64 {
+    if x.is_nan() {
+        return x;
+    }
+    let mut below: Option<(f64, f64)> = None;
+    for (row_x, row_y) in rows {
+        if row_x == x {
+            return row_y;
+        }
+        if row_x > x {
+            let Some((below_x, below_y)) = below else {
+                return row_y;
+            };
+            return match interpolation {
+                Interpolation::Floor => below_y,
+                Interpolation::Ceiling => row_y,
+                Interpolation::Middle if x - below_x < row_x - x => below_y,
+                Interpolation::Middle => row_y,
+                Interpolation::Linear => {
+                    below_y + (row_y - below_y) * (x - below_x) / (row_x - below_x)
+                }
+            };
+        }
+        below = Some((row_x, row_y));
+    }
+    below.map_or(x, |(_, y)| y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(interpolation: Interpolation) -> TableMapping {
+        TableMapping::parse("0 0, 512 700, 1023 1023", interpolation).unwrap()
+    }
+
+    #[test]
+    fn values_between_rows_are_interpolated() {
+        assert_eq!(table(Interpolation::Linear).apply(256.0), 350.0);
+        assert_eq!(table(Interpolation::Floor).apply(500.0), 0.0);
+        assert_eq!(table(Interpolation::Ceiling).apply(10.0), 700.0);
+        assert_eq!(table(Interpolation::Middle).apply(200.0), 0.0);
+        assert_eq!(table(Interpolation::Middle).apply(300.0), 700.0);
+        // rows and values outside of the table
+        assert_eq!(table(Interpolation::Floor).apply(512.0), 700.0);
+        assert_eq!(table(Interpolation::Linear).apply(-5.0), 0.0);
+        assert_eq!(table(Interpolation::Linear).apply(2000.0), 1023.0);
+    }
+
+    #[test]
+    fn mapped_values_go_back_to_the_raw_ones() {
+        let table = table(Interpolation::Linear);
+        for raw in [0.0, 100.0, 512.0, 800.0, 1023.0] {
+            assert!((table.unapply(table.apply(raw)) - raw).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn tables_are_read_from_their_text() {
+        let table = TableMapping::parse("1023 1023,0 0, 512 700,", Interpolation::Linear).unwrap();
+        assert_eq!(table.rows(), [(0.0, 0.0), (512.0, 700.0), (1023.0, 1023.0)]);
+        assert_eq!(table.text(), "0 0, 512 700, 1023 1023");
+
+        assert!(TableMapping::parse("", Interpolation::Linear).is_err());
+        assert!(TableMapping::parse("0 0 0, 1 1 1", Interpolation::Linear).is_err());
+        assert!(TableMapping::parse("0 zero", Interpolation::Linear).is_err());
+        assert!(Interpolation::parse(&Some(String::from("spline"))).is_err());
+        assert_eq!(
+            Interpolation::parse(&Some(String::from("cubic"))).unwrap(),
+            Interpolation::Linear
+        );
+    }
+
+    #[test]
+    fn pressures_are_mapped_before_the_scaling() {
+        let document = r#"<ink xmlns="http://www.w3.org/2003/InkML">
+    <traceFormat>
+        <channel name="X" type="integer" />
+        <channel name="Y" type="integer" />
+        <channel name="F" type="integer" max="1023">
+            <mapping type="table"><table interpolation="linear">0 0, 512 700, 1023 1023</table></mapping>
+        </channel>
+    </traceFormat>
+    <trace>0 0 0, 1 1 256, 2 2 1023</trace>
+</ink>"#;
+        let strokes = crate::parser::parse_formatted_str(document).unwrap();
+        assert_eq!(strokes[0].0.f(), [0.0, 350.0 / 1023.0, 1.0]);
+    }
+}
@@ -0,0 +1,38 @@
+// measures of the parser (`metrics` feature), to compare the xml backends
+// and the options on a corpus
+//
+// Only the time spent in each phase and the size of the traces are recorded,
+// allocations aren't counted (this would need a global allocator)
+
+use std::time::{Duration, Instant};
+
+/// time spent in each phase of the parsing of a document, with its size
+/// See `ParserResult::metrics`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ParseMetrics {
+    /// reading the xml events from the source (the xml backend)
+    pub xml_events: Duration,
+    /// decoding the text of the traces into values
+    pub trace_decode: Duration,
+    /// converting the traces to strokes, only measured by
+    /// `ParserResult::formatted_with_metrics`
+    pub formatting: Duration,
+    /// number of xml events read
+    pub events: usize,
+    /// bytes of trace text buffered before decoding
+    pub trace_bytes: usize,
+    /// number of points decoded
+    pub points: usize,
+}
+
+impl ParseMetrics {
+    /// total time of the measured phases
+    pub fn total(&self) -> Duration {
+        self.xml_events + self.trace_decode + self.formatting
+    }
+
+    /// adds the time since `start` to `phase`
+    pub(crate) fn record(phase: &mut Duration, start: Instant) {
+        *phase += start.elapsed();
+    }
+}
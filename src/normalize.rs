@@ -0,0 +1,119 @@
+// canonical form of a parsed document, so that documents with the same
+// content serialise to the same bytes (round trip tests)
+
+use std::collections::HashMap;
+
+use crate::brushes::{Brush, QuantizedSize};
+use crate::context::Context;
+use crate::parser::ParserResult;
+use crate::trace_data::ChannelData;
+
+/// number of decimals kept for the decimal channel values
+const DECIMALS: i32 = 6;
+
+/// new ids of the definitions : `{prefix}0`, `{prefix}1`, ... in the order
+/// of their first use by a trace (then the unused ones by id), identical
+/// definitions being merged
+fn canonical_ids<'a, T, K: PartialEq>(
+    definitions: &HashMap<String, T>,
+    used: impl Iterator<Item = &'a str>,
+    key: impl Fn(&T) -> K,
+    prefix: &str,
+) -> HashMap<String, String> {
+    let mut order: Vec<&str> = vec![];
+    for id in used {
+        if definitions.contains_key(id) && !order.contains(&id) {
+            order.push(id);
+        }
+    }
+    let mut unused: Vec<&str> = definitions
+        .keys()
+        .map(String::as_str)
+        .filter(|id| !order.contains(id))
+        .collect();
+    unused.sort();
+    order.extend(unused);
+
+    let mut renamed = HashMap::new();
+    // one definition per new id
+    let mut kept: Vec<(K, String)> = vec![];
+    for id in order {
+        let key = key(&definitions[id]);
+        let new_id = match kept.iter().find(|(other, _)| *other == key) {
+            Some((_, new_id)) => new_id.clone(),
+            None => {
+                let new_id = format!("{prefix}{}", kept.len());
+                kept.push((key, new_id.clone()));
+                new_id
+            }
+        };
+        renamed.insert(id.to_owned(), new_id);
+    }
+    renamed
+}
+
+/// Rewrites the document in a canonical form :
+/// - contexts and brushes are renamed `ctx0`, `ctx1`, ... and `br0`, `br1`, ...
+///   in the order traces use them, identical ones being merged
+/// - decimal channel values are rounded to 6 decimals and the sizes of
+///   the brushes to the micrometre
+///
+/// so that `write_parser_result` gives the same output for documents with the
+/// same content (e.g. before and after a write and parse cycle)
+pub fn normalize(document: &mut ParserResult) {
+    let context_ids = canonical_ids(
+        &document.context_dict,
+        document
+            .context_brush_data_vec
+            .iter()
+            .map(|trace| &*trace.context_id),
+        |context: &Context| (context.channel_list.clone(), context.sample_rate),
+        "ctx",
+    );
+    let brush_ids = canonical_ids(
+        &document.context_brush,
+        document
+            .context_brush_data_vec
+            .iter()
+            .map(|trace| &*trace.brush_id),
+        Brush::duplicate_key,
+        "br",
+    );
+
+    let mut contexts = HashMap::new();
+    for (id, mut context) in document.context_dict.drain() {
+        let new_id = context_ids[&id].clone();
+        context.name = new_id.clone();
+        contexts.entry(new_id).or_insert(context);
+    }
+    document.context_dict = contexts;
+
+    let mut brushes = HashMap::new();
+    for (id, mut brush) in document.context_brush.drain() {
+        let new_id = brush_ids[&id].clone();
+        brush.width_cm = QuantizedSize::new(brush.width_cm).cm();
+        brush.height_cm = QuantizedSize::new(brush.height_cm).cm();
+        brushes
+            .entry(new_id.clone())
+            .or_insert_with(|| brush.with_name(new_id));
+    }
+    document.context_brush = brushes;
+
+    let scale = 10f64.powi(DECIMALS);
+    for trace in &mut document.context_brush_data_vec {
+        if let Some(new_id) = context_ids.get(&*trace.context_id) {
+            trace.context_id = document.symbols.intern(new_id);
+        }
+        if let Some(new_id) = brush_ids.get(&*trace.brush_id) {
+            trace.brush_id = document.symbols.intern(new_id);
+        }
+        for channel in &mut trace.data {
+            if let ChannelData::Double(values) = channel {
+                for value in values.iter_mut() {
+                    // (+ 0.0 turns -0 into 0)
+                    *value = (*value * scale).round() / scale + 0.0;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,774 @@
+// options given to the parser and the writer
+
+pub use crate::codec::TraceEncoding;
+use crate::colors::Color;
+use crate::context::{
+    Channel, ChannelKind, ChannelType, ChannelUnit, Context, Resolution, DEFAULT_PRESSURE_MAX,
+    DEFAULT_RESOLUTION,
+};
+use crate::error::{Cancelled, Limit, LimitExceeded};
+use crate::extensions::Extension;
+use crate::parser::ParserResult;
+use crate::resolver::{NoReferenceResolver, ReferenceResolver};
+use crate::trace_data::{BoundingBox, FormattedStroke, RawTrace};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+/// Options for `parser_with_options` and `parse_formatted_with_options`
+///
+/// The default options are the ones used by `parser` and `parse_formatted`
+pub struct ParserOptions {
+    /// used to load the documents external `contextRef`/`brushRef` point to
+    pub(crate) resolver: Box<dyn ReferenceResolver>,
+    /// what to do with contexts and brushes reusing an id
+    pub(crate) duplicates: DuplicatePolicy,
+    pub(crate) limits: ParserLimits,
+    pub(crate) format: FormatOptions,
+    /// channels kept (None : all of them)
+    pub(crate) channels: Option<Vec<ChannelKind>>,
+    /// traces kept : the ones with points inside of the region
+    pub(crate) region: Option<BoundingBox>,
+    /// called every `n` traces
+    pub(crate) progress: Option<(usize, ProgressCallback)>,
+    /// points missing their last channels take the previous values
+    pub(crate) fill_missing_channels: bool,
+    /// color of the brush used when the document doesn't define any
+    pub(crate) default_color: Color,
+    /// size (cm) of the brushes without width and height
+    pub(crate) default_width_cm: f64,
+    /// max of the F channels without one (None : the values are kept as is)
+    pub(crate) default_pressure_max: Option<f64>,
+    /// a document ending early gives the traces read before the failure
+    pub(crate) recover_partial: bool,
+}
+
+/// what the parser does with a context or a brush reusing the id of a previous one
+/// (some exports repeat their definitions), see `ParserOptions::duplicates`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// `DuplicateKeyError`
+    #[default]
+    Error,
+    /// the repeated definition is skipped if it's identical to the first one,
+    /// an error otherwise
+    IgnoreIdentical,
+    /// the repeated definition gets a free id (`ctx0_1`) with a warning,
+    /// the following references to the id use it
+    Rename,
+}
+
+/// callback given to `ParserOptions::progress`
+type ProgressCallback = Box<dyn Fn(ParsedSoFar) -> ControlFlow<()>>;
+
+/// what was read so far, given to the progress callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedSoFar {
+    /// number of traces read
+    pub traces: usize,
+    pub contexts: usize,
+    pub brushes: usize,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        ParserOptions {
+            resolver: Box::new(NoReferenceResolver),
+            duplicates: DuplicatePolicy::default(),
+            limits: ParserLimits::default(),
+            format: FormatOptions::default(),
+            channels: None,
+            region: None,
+            progress: None,
+            fill_missing_channels: false,
+            default_color: Color::rgba(255, 255, 255, 255),
+            default_width_cm: 0.1,
+            default_pressure_max: None,
+            recover_partial: false,
+        }
+    }
+}
+
+impl ParserOptions {
+    /// sets the resolver used for references to other documents
+    pub fn resolver<R: ReferenceResolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    /// strict (default) or lenient parsing
+    /// In lenient mode, a context or brush reusing an existing id is renamed
+    /// and a warning is emitted instead of failing (see `duplicates`)
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.duplicates = if strict {
+            DuplicatePolicy::Error
+        } else {
+            DuplicatePolicy::Rename
+        };
+        self
+    }
+
+    /// what to do with contexts and brushes reusing an id (an error by default)
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// limits on the size of the document (see `ParserLimits`)
+    pub fn limits(mut self, limits: ParserLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// options for the conversion of `parse_formatted_with_options`
+    pub fn format(mut self, format: FormatOptions) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// keeps only the channels of these kinds (e.g. X and Y for a preview)
+    /// The other channels are removed from the contexts and from the traces
+    /// as soon as they are read. X and Y are needed by `parse_formatted`
+    pub fn channels(mut self, channels: &[ChannelKind]) -> Self {
+        self.channels = Some(channels.to_vec());
+        self
+    }
+
+    /// keeps only the traces with a bounding box intersecting `region`
+    /// (in cm, in the coordinates of `parse_formatted`)
+    /// Traces without X and Y channels are kept
+    pub fn region(mut self, region: BoundingBox) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// calls `callback` every `every` traces (e.g. to update a progress bar)
+    /// Returning `ControlFlow::Break` stops the parsing with a `Cancelled` error
+    pub fn progress<F>(mut self, every: usize, callback: F) -> Self
+    where
+        F: Fn(ParsedSoFar) -> ControlFlow<()> + 'static,
+    {
+        self.progress = Some((every.max(1), Box::new(callback)));
+        self
+    }
+
+    /// accepts the points of a trace missing their last channels (`10 10 5, 11 12`),
+    /// the missing values being the ones of the previous point
+    /// Off by default : these points are errors
+    pub fn fill_missing_channels(mut self, fill_missing_channels: bool) -> Self {
+        self.fill_missing_channels = fill_missing_channels;
+        self
+    }
+
+    /// recovery of truncated documents (e.g. written by an application that crashed) :
+    /// when the xml ends early or can't be read anymore, the traces read before
+    /// are returned instead of an error and `ParserResult::truncated` is set
+    /// The trace being read when the document ends is lost
+    pub fn recover_partial(mut self, recover_partial: bool) -> Self {
+        self.recover_partial = recover_partial;
+        self
+    }
+
+    /// color of the brush given to the traces when the document doesn't
+    /// define any brush (white by default)
+    pub fn default_color(mut self, color: impl Into<Color>) -> Self {
+        self.default_color = color.into();
+        self
+    }
+
+    /// size in cm of the brushes defined without width or height, and of the
+    /// brush used when the document doesn't define any (0.1 cm by default)
+    pub fn default_width(mut self, width_cm: f64) -> Self {
+        self.default_width_cm = width_cm;
+        self
+    }
+
+    /// max of the F channels declared without one, so that their pressure is
+    /// normalised to 0 - 1 (e.g. 1023 or 8191 for most tablets)
+    /// By default the values of these channels are kept as is
+    pub fn default_pressure_max(mut self, max: f64) -> Self {
+        self.default_pressure_max = Some(max);
+        self
+    }
+
+    /// calls the progress callback if `parsed.traces` is a multiple of its interval
+    pub(crate) fn report_progress(&self, parsed: ParsedSoFar) -> Result<(), Cancelled> {
+        match &self.progress {
+            Some((every, callback)) if parsed.traces.is_multiple_of(*every) => {
+                match callback(parsed) {
+                    ControlFlow::Continue(()) => Ok(()),
+                    ControlFlow::Break(()) => Err(Cancelled {
+                        traces: parsed.traces,
+                    }),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// applies the region and channel filters to a trace read with `context`
+    pub(crate) fn select(&self, mut trace: RawTrace, context: &Context) -> Option<RawTrace> {
+        if let Some(region) = &self.region {
+            if let Some(bounding_box) = trace.bounding_box(context) {
+                if !bounding_box.intersects(region) {
+                    return None;
+                }
+            }
+        }
+        if let Some(channels) = &self.channels {
+            trace.retain_channels(context, channels);
+        }
+        Some(trace)
+    }
+
+    /// gives the default max to the F channels without one
+    pub(crate) fn apply_default_pressure_max(&self, contexts: &mut HashMap<String, Context>) {
+        if let Some(max) = self.default_pressure_max {
+            for channel in contexts
+                .values_mut()
+                .flat_map(|context| context.channel_list.iter_mut())
+                .filter(|channel| channel.kind == ChannelKind::F)
+            {
+                channel.default_max(max);
+            }
+        }
+    }
+
+    /// removes the channels that weren't selected from the contexts
+    /// (once all traces are read)
+    pub(crate) fn select_channels(&self, contexts: &mut HashMap<String, Context>) {
+        if let Some(channels) = &self.channels {
+            for context in contexts.values_mut() {
+                context
+                    .channel_list
+                    .retain(|channel| channels.contains(&channel.kind));
+            }
+        }
+    }
+}
+
+/// Attribute the writer puts the ids of the elements in
+/// (the parser reads both)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdAttribute {
+    /// `xml:id`, as in the spec
+    #[default]
+    XmlId,
+    /// `id`, for tools that don't know `xml:id`
+    Id,
+}
+
+impl IdAttribute {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            IdAttribute::XmlId => "xml:id",
+            IdAttribute::Id => "id",
+        }
+    }
+}
+
+/// Options for the conversion of the traces to `FormattedStroke`s
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FormatOptions {
+    /// keep the hover traces (`type="penUp"`)
+    pub(crate) include_pen_up: bool,
+    /// flip the Y axis (`None` : only for the contexts with Y increasing upward)
+    pub(crate) flip_y: Option<bool>,
+    /// keep the parts of continued traces as separate strokes
+    pub(crate) split_continuations: bool,
+    /// strokes are cut to the parts inside of this box
+    pub(crate) clip: Option<BoundingBox>,
+}
+
+impl FormatOptions {
+    /// keep the hover traces (`type="penUp"`), skipped by default
+    /// so that they aren't drawn as ink
+    pub fn include_pen_up(mut self, include_pen_up: bool) -> Self {
+        self.include_pen_up = include_pen_up;
+        self
+    }
+
+    /// keeps each part of a trace split with `continuation` attributes as a
+    /// stroke of its own. By default the parts are joined back into one stroke
+    pub fn split_continuations(mut self, split_continuations: bool) -> Self {
+        self.split_continuations = split_continuations;
+        self
+    }
+
+    /// overrides the orientation of the Y axis declared by the contexts
+    /// (`orientation="-ve"` on the Y channel, flipped back to the screen orientation)
+    /// `true` treats Y as increasing upward (the values are negated),
+    /// `false` keeps the values as written
+    pub fn flip_y(mut self, flip_y: bool) -> Self {
+        self.flip_y = Some(flip_y);
+        self
+    }
+
+    /// keeps only the parts of the strokes inside of `rect` (in cm, Y going down),
+    /// the strokes being cut where they cross its sides
+    /// (e.g. to extract a page from a large canvas)
+    pub fn clip(mut self, rect: BoundingBox) -> Self {
+        self.clip = Some(rect);
+        self
+    }
+}
+
+/// Limits on the size of the documents, to parse untrusted documents
+/// without exhausting the memory. A document going over one of them gives
+/// a `LimitExceeded` error
+///
+/// The default limits are far above what pen input produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserLimits {
+    max_traces: usize,
+    max_points_per_trace: usize,
+    max_contexts: usize,
+    max_depth: usize,
+    max_characters: usize,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        ParserLimits {
+            max_traces: 1_000_000,
+            max_points_per_trace: 1_000_000,
+            max_contexts: 10_000,
+            max_depth: 128,
+            max_characters: 1 << 30,
+        }
+    }
+}
+
+impl ParserLimits {
+    /// no limit
+    pub fn unlimited() -> ParserLimits {
+        ParserLimits {
+            max_traces: usize::MAX,
+            max_points_per_trace: usize::MAX,
+            max_contexts: usize::MAX,
+            max_depth: usize::MAX,
+            max_characters: usize::MAX,
+        }
+    }
+
+    /// maximum number of `trace` elements in the document, a `LimitExceeded`
+    /// error with `Limit::Traces` once the next one starts
+    pub fn max_traces(mut self, max_traces: usize) -> Self {
+        self.max_traces = max_traces;
+        self
+    }
+
+    /// maximum number of points of a trace (counted from the commas of its
+    /// text), a `LimitExceeded` error with `Limit::PointsPerTrace` otherwise
+    pub fn max_points_per_trace(mut self, max_points_per_trace: usize) -> Self {
+        self.max_points_per_trace = max_points_per_trace;
+        self
+    }
+
+    /// maximum number of contexts defined in the document, a `LimitExceeded`
+    /// error with `Limit::Contexts` otherwise
+    pub fn max_contexts(mut self, max_contexts: usize) -> Self {
+        self.max_contexts = max_contexts;
+        self
+    }
+
+    /// maximum number of nested xml elements
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// maximum number of characters of text in the whole document (in bytes)
+    pub fn max_characters(mut self, max_characters: usize) -> Self {
+        self.max_characters = max_characters;
+        self
+    }
+
+    /// errors if `value` is over `limit`
+    pub(crate) fn check(&self, limit: Limit, value: usize) -> anyhow::Result<()> {
+        let max = match limit {
+            Limit::Traces => self.max_traces,
+            Limit::PointsPerTrace => self.max_points_per_trace,
+            Limit::Contexts => self.max_contexts,
+            Limit::Depth => self.max_depth,
+            Limit::Characters => self.max_characters,
+        };
+        if value > max {
+            Err(LimitExceeded { limit, max }.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// application the documents are written for, see `WriterConfig::target`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    /// integer X, Y and F channels (1/1000 cm and 0 - 32767)
+    #[default]
+    Default,
+    /// Wacom Ink (WILL) based apps : decimal X and Y channels in cm, decimal
+    /// F from 0 to 1 and a decimal T channel in ms, written explicitly
+    Wacom,
+}
+
+/// how the writer keeps the transparency of black brushes, see
+/// `WriterConfig::black_transparency`
+/// (some applications show transparent `#000000` ink as fully transparent)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlackTransparency {
+    /// writes the color as `#010101`, which looks the same
+    #[default]
+    Nudge,
+    /// writes `#000000` with its `transparency` anyway
+    Emit,
+    /// writes the alpha in the color (`#000000AA`) instead of a `transparency`,
+    /// for applications reading it
+    AlphaInColor,
+}
+
+/// page written as the `canvas` of the context by the writer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Canvas {
+    /// the bounds of the strokes, grown by `margin` cm on each side
+    /// (not known in advance by the stream writer, which writes no canvas then)
+    FitStrokes { margin: f64 },
+    /// a page given in cm, e.g. `BoundingBox::new(0.0, 0.0, 21.0, 29.7)` for A4
+    Page(BoundingBox),
+}
+
+/// Options for `writer_with_config`
+///
+/// The default config is the one used by `writer`
+#[derive(Debug, Clone)]
+pub struct WriterConfig {
+    /// resolution of the X and Y channels, in points per cm
+    pub(crate) resolution: f64,
+    /// value of the F channel for a pressure of 1
+    pub(crate) pressure_max: i64,
+    pub(crate) encoding: TraceEncoding,
+    /// number of decimals of `decimal` X and Y channels
+    /// (None : integer channels)
+    pub(crate) decimal_precision: Option<usize>,
+    /// values out of the range of their channel are clamped instead of
+    /// failing with an `InvalidStrokesError`
+    pub(crate) clamp: bool,
+    /// strokes without points are written as empty traces instead of
+    /// being skipped
+    pub(crate) write_empty_strokes: bool,
+    /// context replacing the default one (resolution and decimal are then ignored)
+    pub(crate) custom_context: Option<Context>,
+    /// unknown elements written as is after the definitions
+    pub(crate) extensions: Vec<Extension>,
+    pub(crate) id_attribute: IdAttribute,
+    /// points closer than this distance (cm) to the previous one are dropped
+    pub(crate) dedup_epsilon: Option<f64>,
+    /// longer strokes are split into continuation traces
+    pub(crate) max_points_per_trace: Option<usize>,
+    /// brushes keep their name instead of being renamed `br1`, `br2`, ...
+    pub(crate) preserve_brush_ids: bool,
+    /// time (ms) the `timeOffset` of the traces are counted from
+    pub(crate) session_start: Option<f64>,
+    /// points per second declared in the default context, with a T channel
+    pub(crate) sample_rate: Option<f64>,
+    /// page written as the canvas of the context
+    pub(crate) canvas: Option<Canvas>,
+    pub(crate) black_transparency: BlackTransparency,
+    /// indented output with sorted attributes
+    pub(crate) pretty: bool,
+    /// unit of the X and Y values of the strokes given to the writer
+    pub(crate) input_unit: ChannelUnit,
+    /// maximum number of colors of the brushes
+    pub(crate) quantize_colors: Option<usize>,
+    /// `documentID` and `xml:lang` of the `ink` element
+    pub(crate) document_id: Option<String>,
+    pub(crate) lang: Option<String>,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        WriterConfig {
+            resolution: DEFAULT_RESOLUTION,
+            pressure_max: DEFAULT_PRESSURE_MAX,
+            encoding: TraceEncoding::Explicit,
+            decimal_precision: None,
+            clamp: false,
+            write_empty_strokes: false,
+            custom_context: None,
+            extensions: vec![],
+            id_attribute: IdAttribute::default(),
+            dedup_epsilon: None,
+            max_points_per_trace: None,
+            preserve_brush_ids: false,
+            session_start: None,
+            sample_rate: None,
+            canvas: None,
+            black_transparency: BlackTransparency::default(),
+            pretty: false,
+            input_unit: ChannelUnit::cm,
+            quantize_colors: None,
+            document_id: None,
+            lang: None,
+        }
+    }
+}
+
+impl WriterConfig {
+    /// resolution of the X and Y channels, in points per cm
+    /// (coordinates are rounded to 1/resolution cm)
+    pub fn resolution(mut self, resolution: f64) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// highest value of the F channel, for a pressure of 1
+    /// (e.g. 1023 for a device with 1024 pressure levels, 32767 by default)
+    pub fn pressure_max(mut self, pressure_max: i64) -> Self {
+        self.pressure_max = pressure_max;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: TraceEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// writes `decimal` X and Y channels with `precision` decimals instead
+    /// of integer ones (the values are still multiplied by the resolution,
+    /// use a resolution of 1.0 to write them in cm)
+    pub fn decimal(mut self, precision: usize) -> Self {
+        self.decimal_precision = Some(precision);
+        self
+    }
+
+    /// clamps the values out of the range of their channel (e.g. negative
+    /// pressure) instead of failing with an `InvalidStrokesError`
+    /// NaN and infinite values are errors in both cases
+    pub fn clamp(mut self, clamp: bool) -> Self {
+        self.clamp = clamp;
+        self
+    }
+
+    /// drops the points closer than `epsilon` cm to the previous point written
+    /// (0 : only exact repeats), see `FormattedStroke::dedup_points`
+    pub fn dedup_points(mut self, epsilon: f64) -> Self {
+        self.dedup_epsilon = Some(epsilon);
+        self
+    }
+
+    /// splits the strokes with more points into several traces (some
+    /// applications like OneNote reject long traces). The parts are chained with
+    /// the `continuation` and `priorRef` attributes so that readers can rejoin them
+    pub fn max_points_per_trace(mut self, max_points: usize) -> Self {
+        self.max_points_per_trace = Some(max_points.max(1));
+        self
+    }
+
+    /// writes strokes without points as empty traces
+    /// (by default they are skipped, with their brush if no other stroke uses it)
+    pub fn write_empty_strokes(mut self, write_empty_strokes: bool) -> Self {
+        self.write_empty_strokes = write_empty_strokes;
+        self
+    }
+
+    /// writes the strokes with a custom context (see `ContextBuilder`)
+    /// instead of the default X, Y, F one. It must have X, Y and F channels,
+    /// the other ones are written with their null value
+    /// The `resolution`, `pressure_max`, `sample_rate` and `decimal` options are then ignored
+    pub fn custom_context(mut self, context: Context) -> Self {
+        self.custom_context = Some(context);
+        self
+    }
+
+    /// unknown elements (e.g. from `ParserResult::extensions`) written back
+    /// as is, where they were read (in the definitions, in an `annotationXML`
+    /// or after the definitions)
+    pub fn extensions(mut self, extensions: Vec<Extension>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// also writes the Microsoft extensions of a parsed document (the
+    /// `msink:context` elements, with the annotations holding them) so that
+    /// ink pasted back into OneNote keeps the properties the crate doesn't model
+    pub fn msink_from(mut self, document: &ParserResult) -> Self {
+        self.extensions.extend(
+            document
+                .extensions()
+                .iter()
+                .filter(|extension| extension.has_microsoft())
+                .cloned(),
+        );
+        self
+    }
+
+    /// writes the brushes with their name (e.g. set with `Brush::init`) instead of
+    /// renaming them `br1`, `br2`, ... so that ids referred to from elsewhere stay valid
+    /// Brushes with the same name but different properties get a suffixed id
+    /// (`name_1`) with a warning, unnamed brushes are still numbered
+    pub fn preserve_brush_ids(mut self, preserve_brush_ids: bool) -> Self {
+        self.preserve_brush_ids = preserve_brush_ids;
+        self
+    }
+
+    /// writes the start of each stroke with timestamps as the `timeOffset` of its
+    /// trace, in ms from `session_start` (on the same clock as the timestamps,
+    /// e.g. the time the page was opened), so that readers can replay the ink
+    /// The T channel then holds the times from the start of the stroke
+    pub fn session_start(mut self, session_start: f64) -> Self {
+        self.session_start = Some(session_start);
+        self
+    }
+
+    /// declares the number of points per second of the device and adds a T channel
+    /// (in ms) to the default context : strokes without timestamps get uniform ones
+    /// spread with this rate
+    pub fn sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// sets the context, the encoding and the precision of the values for the
+    /// application the document is written for (replacing the ones set before)
+    pub fn target(mut self, target: Target) -> Self {
+        match target {
+            Target::Default => {
+                self.custom_context = None;
+                self.encoding = TraceEncoding::Explicit;
+                self.decimal_precision = None;
+            }
+            Target::Wacom => {
+                let channel =
+                    |kind, resolution| Channel::new(kind, ChannelType::Decimal, resolution);
+                self.custom_context = Some(Context {
+                    name: String::from("ctx0"),
+                    channel_list: vec![
+                        channel(ChannelKind::X, Resolution::per_cm(1.0)),
+                        channel(ChannelKind::Y, Resolution::per_cm(1.0)),
+                        channel(ChannelKind::F, Resolution::per_dev(1.0))
+                            .with_min(0.0)
+                            .with_max(1.0),
+                        channel(ChannelKind::T, Resolution::per_ms(1.0)),
+                    ],
+                    sample_rate: None,
+                });
+                self.encoding = TraceEncoding::Explicit;
+                // 1/10000 cm, under the resolution of the tablets
+                self.decimal_precision = Some(4);
+            }
+        }
+        self
+    }
+
+    /// writes a `canvas` (with its `canvasTransform`) in the context, giving the
+    /// size and the origin of the page so that readers honouring canvases place
+    /// the ink on it
+    pub fn canvas(mut self, canvas: Canvas) -> Self {
+        self.canvas = Some(canvas);
+        self
+    }
+
+    /// how the transparency of black brushes is written
+    /// (`BlackTransparency::Nudge` by default)
+    pub fn black_transparency(mut self, black_transparency: BlackTransparency) -> Self {
+        self.black_transparency = black_transparency;
+        self
+    }
+
+    /// indents the output, one element per line, and writes the attributes of
+    /// every element in a stable order : the id first, then `name`, then the
+    /// others in alphabetical order. For readable diffs of generated documents
+    /// The stream writer only indents, its attributes are always in the same order
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
+
+    /// unit of the X and Y values of the strokes given to the writer (cm by
+    /// default), e.g. `ChannelUnit::px` for strokes in CSS pixels (1/96 in) or
+    /// `ChannelUnit::mm`. They are converted to cm before being written
+    /// The other lengths (brush sizes, canvas, `dedup_points`) stay in cm and
+    /// units other than lengths make the writer fail
+    pub fn input_unit(mut self, unit: ChannelUnit) -> Self {
+        self.input_unit = unit;
+        self
+    }
+
+    /// merges the closest colors of the brushes until there are at most `n` of them
+    /// (e.g. 254,0,0 and 255,0,0 from different exports), so that more strokes
+    /// share a brush. The most used color of the merged ones is written
+    /// Not applied by the `InkmlStreamWriter`, the strokes aren't known in advance
+    pub fn quantize_colors(mut self, n: usize) -> Self {
+        self.quantize_colors = Some(n);
+        self
+    }
+
+    /// `documentID` of the `ink` element, a URI identifying the document
+    pub fn document_id(mut self, document_id: impl Into<String>) -> Self {
+        self.document_id = Some(document_id.into());
+        self
+    }
+
+    /// language of the document (`xml:lang` of the `ink` element, e.g. `fr-CA`),
+    /// used by recognisers to pick a model. The groups of `writer_tree` with
+    /// another language get their own `xml:lang`
+    pub fn lang(mut self, lang: impl Into<String>) -> Self {
+        self.lang = Some(lang.into());
+        self
+    }
+
+    /// attribute holding the ids of the contexts and brushes (`xml:id` by default)
+    pub fn id_attribute(mut self, id_attribute: IdAttribute) -> Self {
+        self.id_attribute = id_attribute;
+        self
+    }
+
+    /// the context strokes are written with
+    pub(crate) fn context(&self) -> Context {
+        if let Some(context) = &self.custom_context {
+            return context.clone();
+        }
+        let mut context = Context::default_with_pressure(self.resolution, self.pressure_max);
+        if let Some(sample_rate) = self.sample_rate {
+            context.sample_rate = Some(sample_rate);
+            context.channel_list.push(Channel::new(
+                ChannelKind::T,
+                ChannelType::Integer,
+                Resolution::per_ms(1.0),
+            ));
+        }
+        if self.decimal_precision.is_some() {
+            for channel in context.channel_list.iter_mut() {
+                if matches!(channel.kind, ChannelKind::X | ChannelKind::Y) {
+                    channel.types = ChannelType::Decimal;
+                }
+            }
+        }
+        context
+    }
+
+    /// page of the canvas for `strokes`, None without a canvas (or without
+    /// points to fit)
+    pub(crate) fn canvas_page<'a>(
+        &self,
+        strokes: impl Iterator<Item = &'a FormattedStroke>,
+    ) -> Option<BoundingBox> {
+        match self.canvas? {
+            Canvas::Page(page) => Some(page),
+            Canvas::FitStrokes { margin } => BoundingBox::from_points(
+                strokes
+                    .filter_map(FormattedStroke::bounding_box)
+                    .flat_map(|bounds| {
+                        [(bounds.min_x, bounds.min_y), (bounds.max_x, bounds.max_y)]
+                    }),
+            )
+            .map(|bounds| bounds.grown(margin)),
+        }
+    }
+
+    /// `timeOffset` of the trace of `stroke` : its first timestamp from the session start
+    pub(crate) fn time_offset(&self, stroke: &FormattedStroke) -> Option<f64> {
+        let start = stroke.t.as_ref()?.first()?;
+        self.session_start
+            .map(|session_start| start - session_start)
+    }
+}
@@ -0,0 +1,73 @@
+// colors used by the strokes, and quantisation of the near-identical ones
+// (e.g. 254,0,0 and 255,0,0 from different exports) so that their strokes
+// share a brush when written (see `WriterConfig::quantize_colors`)
+//
+// Quantisation merges the two closest colors until the palette is small
+// enough, the most used color of the two is kept : no new color is made up
+
+use crate::brushes::Brush;
+use crate::colors::Color;
+use crate::trace_data::FormattedStroke;
+use std::collections::HashMap;
+
+/// colors with their number of uses, the most used first
+/// (then in order of first use)
+fn counted(colors: impl Iterator<Item = Color>) -> Vec<(Color, usize)> {
+    let mut counts: Vec<(Color, usize)> = vec![];
+    for color in colors {
+        match counts.iter_mut().find(|(other, _)| *other == color) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((color, 1)),
+        }
+    }
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts
+}
+
+/// distinct colors of the brushes of the strokes, the most used first
+pub fn palette(strokes: &[(FormattedStroke, Brush)]) -> Vec<Color> {
+    counted(strokes.iter().map(|(_, brush)| brush.color))
+        .into_iter()
+        .map(|(color, _)| color)
+        .collect()
+}
+
+fn distance(a: Color, b: Color) -> u32 {
+    [(a.r, b.r), (a.g, b.g), (a.b, b.b), (a.a, b.a)]
+        .into_iter()
+        .map(|(x, y)| u32::from(x.abs_diff(y)).pow(2))
+        .sum()
+}
+
+/// the color of a palette of at most `n` colors (at least one) standing for
+/// each of the `colors`
+pub(crate) fn quantized(colors: impl Iterator<Item = Color>, n: usize) -> HashMap<Color, Color> {
+    // (kept color, its uses, the colors it stands for)
+    let mut clusters = counted(colors)
+        .into_iter()
+        .map(|(color, count)| (color, count, vec![color]))
+        .collect::<Vec<(Color, usize, Vec<Color>)>>();
+    while clusters.len() > n.max(1) {
+        let mut closest = (0, 1, u32::MAX);
+        for i in 0..clusters.len() {
+            for j in i + 1..clusters.len() {
+                let d = distance(clusters[i].0, clusters[j].0);
+                if d < closest.2 {
+                    closest = (i, j, d);
+                }
+            }
+        }
+        let (i, j, _) = closest;
+        let (color, count, members) = clusters.remove(j);
+        let kept = &mut clusters[i];
+        if count > kept.1 {
+            kept.0 = color;
+        }
+        kept.1 += count;
+        kept.2.extend(members);
+    }
+    clusters
+        .into_iter()
+        .flat_map(|(kept, _, members)| members.into_iter().map(move |color| (color, kept)))
+        .collect()
+}
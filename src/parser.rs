@@ -1,14 +1,38 @@
 use anyhow::anyhow;
 use std::collections::HashMap;
-use std::io::Read;
-use xml::reader::{EventReader, XmlEvent as rXmlEvent};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent as rXmlEvent;
 
-use crate::brushes::Brush;
+use crate::brushes::{Brush, BrushTip, RasterOp};
+use crate::colors::{parse_color, Color};
 use crate::context::{Channel, ChannelKind, ChannelType, ChannelUnit, Context, ResolutionUnits};
-use crate::trace_data::FormattedStroke;
-use crate::trace_data::{ChannelData, TraceData};
-use crate::xml_helpers::{get_id, get_ids, verify_channel_properties};
-use tracing::{debug,trace};
+#[cfg(feature = "emma")]
+use crate::emma::{interpretations, EmmaInterpretation};
+use crate::error::Limit;
+use crate::extensions::Extension;
+use crate::mapping::{Interpolation, TableMapping};
+#[cfg(feature = "metrics")]
+use crate::metrics::ParseMetrics;
+use crate::options::{DuplicatePolicy, FormatOptions, ParsedSoFar, ParserOptions};
+use crate::symbols::SymbolTable;
+#[cfg(not(feature = "parallel"))]
+use crate::trace_data::TraceData;
+#[cfg(feature = "parallel")]
+use crate::trace_data::{decode_traces_parallel, PendingTrace};
+use crate::trace_data::{ChannelData, FormattedStroke};
+use crate::trace_data::{Continuation, RawTrace, StrokeSource, TraceType};
+use crate::tree::{build_tree, Annotation, GroupInfo, GroupedStroke, InkNode};
+use crate::xml_helpers::{
+    get_element_id, get_id, get_ids, get_lang, parse_bool, split_reference,
+    verify_channel_properties,
+};
+use crate::xml_source::{xml_source, xml_source_bytes, XmlSource};
+use tracing::span::EnteredSpan;
+use tracing::{debug, debug_span, field, trace_span, warn};
 
 #[derive(Debug)]
 enum ContextStartElement {
@@ -20,6 +44,8 @@ enum ContextStartElement {
 struct ParserContext {
     /// keeps trace of whether we are inside of a trace element
     is_trace: bool,
+    /// text of the open trace, decoded once the trace ends
+    trace_text: String,
     /// stores the context(s) of the inkml file.
     /// Using a conditional to mark whether we encountered a context
     /// We suppose that there is always a context, even if this is only
@@ -28,78 +54,450 @@ struct ParserContext {
     current_context_id: Option<String>,
     start_context_element: Option<ContextStartElement>,
     current_brush_id: Option<String>,
+    current_trace_type: TraceType,
+    /// `timeOffset` and `duration` of the current trace, in ms
+    current_time_offset: Option<f64>,
+    current_duration: Option<f64>,
+    /// id of the current trace
+    current_trace_id: Option<String>,
+    /// `continuation` and `priorRef` of the current trace
+    current_continuation: Option<Continuation>,
+    current_prior_ref: Option<String>,
     brushes: HashMap<String, Brush>,
+    /// contexts and brushes renamed because their id was already taken
+    /// (`DuplicatePolicy::Rename`), maps the id from the file to the id the
+    /// last definition was stored with
+    context_aliases: HashMap<String, String>,
+    brush_aliases: HashMap<String, String>,
+    /// (id, id it's read with) of the open context or brush reusing an id,
+    /// compared to the first one once read (`DuplicatePolicy::IgnoreIdentical`)
+    duplicate_context: Option<(String, String)>,
+    duplicate_brush: Option<(String, String)>,
+    /// documents loaded to resolve external references, indexed by their uri
+    external_documents: HashMap<String, ParserResult>,
+    /// local names of the open InkML elements
+    open_elements: Vec<String>,
+    /// open unknown elements, the innermost one last
+    open_extensions: Vec<Extension>,
+    extensions: Vec<Extension>,
+    /// `traceGroup` elements (and `traceView` elements grouping other
+    /// views) read so far and the index of the open one
+    groups: Vec<GroupInfo>,
+    current_group: Option<usize>,
+    /// whether each open `traceView` was read as a group
+    open_views: Vec<bool>,
+    /// (`contextRef`, `brushRef`) of the open `traceGroup` elements, inherited
+    /// from the enclosing ones, for the traces without their own references
+    group_refs: Vec<(Option<String>, Option<String>)>,
+    /// annotation of the open group (or of the document) being read
+    current_annotation: Option<Annotation>,
+    /// annotations of the `ink` element
+    annotations: Vec<Annotation>,
+    /// interpolation and text of the open `table` mapping of a channel
+    current_table: Option<(Interpolation, String)>,
+    /// times of the `timestamp` elements read so far, by id
+    timestamps: HashMap<String, f64>,
+    #[cfg(feature = "emma")]
+    emma: Vec<EmmaInterpretation>,
+    /// ids of the contexts and brushes of the traces
+    symbols: SymbolTable,
+    /// `documentID` and `xml:lang` of the `ink` element
+    document_id: Option<String>,
+    lang: Option<String>,
 }
 
-#[derive(Debug)]
+impl ParserContext {
+    fn open_group(&mut self, attributes: &[OwnedAttribute]) {
+        // the language is inherited from the enclosing group or the document
+        let lang = get_lang(attributes).or_else(|| match self.current_group {
+            Some(index) => self.groups[index].lang.clone(),
+            None => self.lang.clone(),
+        });
+        self.groups.push(GroupInfo {
+            id: get_element_id(attributes),
+            lang,
+            parent: self.current_group,
+            ..Default::default()
+        });
+        self.current_group = Some(self.groups.len() - 1);
+    }
+
+    fn close_group(&mut self) {
+        self.current_group = self
+            .current_group
+            .and_then(|index| self.groups[index].parent);
+    }
+
+    fn close_view(&mut self) {
+        if self.open_views.pop() == Some(true) {
+            self.close_group();
+        }
+    }
+
+    fn in_annotation(&self) -> bool {
+        self.open_elements
+            .iter()
+            .any(|element| element == "annotation" || element == "annotationXML")
+    }
+
+    /// keeps the unknown elements and their content, returns true
+    /// if the event was part of one of them (the parser then skips it)
+    /// Elements are told apart by their namespace and local name, the unknown
+    /// elements of annotations (e.g. `emma` or xhtml ones) are skipped but only
+    /// kept if they hold Microsoft elements
+    fn capture_extension(&mut self, event: &anyhow::Result<rXmlEvent>) -> bool {
+        match event {
+            Ok(rXmlEvent::StartElement {
+                name, attributes, ..
+            }) => {
+                if self.open_extensions.is_empty() && !Extension::is_extension(name) {
+                    self.open_elements.push(name.local_name.clone());
+                    return false;
+                }
+                let parent = self
+                    .open_extensions
+                    .last()
+                    .map(|extension| &extension.name)
+                    .or(self.open_elements.last())
+                    .cloned()
+                    .unwrap_or_default();
+                self.open_extensions
+                    .push(Extension::new(name, attributes, parent));
+                true
+            }
+            Ok(rXmlEvent::EndElement { .. }) => match self.open_extensions.pop() {
+                Some(extension) => {
+                    let in_annotation = self.in_annotation();
+                    match self.open_extensions.last_mut() {
+                        Some(parent) => parent.children.push(extension),
+                        None if in_annotation => {
+                            #[cfg(feature = "emma")]
+                            {
+                                let group = self
+                                    .current_group
+                                    .and_then(|index| self.groups[index].id.as_deref());
+                                self.emma.extend(interpretations(&extension, group));
+                            }
+                            // the Microsoft properties (`msink:context`) are kept to be written back
+                            if extension.has_microsoft() {
+                                self.extensions.push(extension);
+                            }
+                        }
+
+                        None => self.extensions.push(extension),
+                    }
+                    true
+                }
+                None => {
+                    self.open_elements.pop();
+                    false
+                }
+            },
+            Ok(rXmlEvent::Characters(text) | rXmlEvent::CData(text)) => {
+                match self.open_extensions.last_mut() {
+                    Some(extension) => {
+                        extension.text.push_str(text);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Ok(rXmlEvent::Whitespace(_)) => !self.open_extensions.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// true if the open element is the `table` of a `mapping` of a channel
+    fn in_channel_mapping(&self) -> bool {
+        self.open_elements.ends_with(&[
+            String::from("channel"),
+            String::from("mapping"),
+            String::from("table"),
+        ])
+    }
+
+    /// true inside of a `canvas` element
+    fn in_canvas(&self) -> bool {
+        self.open_elements.iter().any(|element| element == "canvas")
+    }
+
+    /// returns the document loaded from `uri`, loading it on first use
+    fn external_document(
+        &mut self,
+        uri: &str,
+        options: &ParserOptions,
+    ) -> anyhow::Result<&ParserResult> {
+        if !self.external_documents.contains_key(uri) {
+            let document = options.resolver.resolve(uri)?;
+            self.external_documents.insert(uri.to_owned(), document);
+        }
+        Ok(&self.external_documents[uri])
+    }
+
+    /// gives the key of the context a `contextRef` refers to
+    /// Contexts from other documents are copied over, keyed by the full reference
+    fn context_reference(
+        &mut self,
+        reference: &str,
+        options: &ParserOptions,
+    ) -> anyhow::Result<String> {
+        match split_reference(reference) {
+            (None, id) => Ok(self
+                .context_aliases
+                .get(id)
+                .cloned()
+                .unwrap_or(id.to_owned())),
+            (Some(uri), id) => {
+                if !self.context.contains_key(reference) {
+                    let mut context = self
+                        .external_document(uri, options)?
+                        .context_dict
+                        .get(id)
+                        .cloned()
+                        .ok_or(anyhow!("The context {id} was not found in {uri}"))?;
+                    context.name = reference.to_owned();
+                    self.context.insert(reference.to_owned(), context);
+                }
+                Ok(reference.to_owned())
+            }
+        }
+    }
+
+    /// gives the key of the brush a `brushRef` refers to
+    /// Brushes from other documents are copied over, keyed by the full reference
+    fn brush_reference(
+        &mut self,
+        reference: &str,
+        options: &ParserOptions,
+    ) -> anyhow::Result<String> {
+        match split_reference(reference) {
+            (None, id) => Ok(self.brush_aliases.get(id).cloned().unwrap_or(id.to_owned())),
+            (Some(uri), id) => {
+                if !self.brushes.contains_key(reference) {
+                    let mut brush = self
+                        .external_document(uri, options)?
+                        .context_brush
+                        .get(id)
+                        .cloned()
+                        .ok_or(anyhow!("The brush {id} was not found in {uri}"))?;
+                    brush.name = reference.into();
+                    self.brushes.insert(reference.to_owned(), brush);
+                }
+                Ok(reference.to_owned())
+            }
+        }
+    }
+}
+
+/// The document as read by `parser` : the definitions (contexts, brushes,
+/// timestamps) and the traces with their raw values
+/// `formatted` converts the traces to strokes
+#[derive(Debug, Clone)]
 pub struct ParserResult {
-    /// Each element contains
-    /// - The name of the context
-    /// - The name of the brush
-    /// - The (raw) channel data associated with it.
-    ///   In particular this raw channel data
-    ///     - Keeps the same order as the one given in the trace
-    ///     - Keeps the same type (integer, boolean or double) as the
-    ///       one given in the trace definition
-    context_brush_data_vec: Vec<(String, String, Vec<ChannelData>)>,
-    context_dict: HashMap<String, Context>,
-    context_brush: HashMap<String, Brush>,
+    /// The traces, each with the name of its context and brush, its type
+    /// and its (raw) channel data
+    pub(crate) context_brush_data_vec: Vec<RawTrace>,
+    pub(crate) context_dict: HashMap<String, Context>,
+    pub(crate) context_brush: HashMap<String, Brush>,
+    /// unknown elements (vendor extensions), written back by `write_parser_result`
+    pub(crate) extensions: Vec<Extension>,
+    /// `traceGroup` elements, traces refer to them by their index
+    pub(crate) groups: Vec<GroupInfo>,
+    /// annotations of the `ink` element
+    pub(crate) annotations: Vec<Annotation>,
+    /// times of the `timestamp` elements by id, in ms since the epoch
+    /// (T channels are relative to them with `respectTo`)
+    pub(crate) timestamps: HashMap<String, f64>,
+    /// ids of the contexts and brushes the traces refer to
+    pub(crate) symbols: SymbolTable,
+    /// the document ended early (see `ParserOptions::recover_partial`)
+    pub(crate) truncated: bool,
+    /// `documentID` and `xml:lang` of the `ink` element
+    pub(crate) document_id: Option<String>,
+    pub(crate) lang: Option<String>,
+    /// recognition results of the `annotationXML` elements
+    #[cfg(feature = "emma")]
+    pub(crate) emma: Vec<EmmaInterpretation>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: ParseMetrics,
 }
 
 /// This function returns the raw data from the trace
 /// Hence all supported channels with their origin types are
 /// returned, with corresponding resolution, brush properties and so on
 pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
-    let parser = EventReader::new(buf_file);
+    parser_with_options(buf_file, &ParserOptions::default())
+}
+
+/// Same as `parser` with options
+pub fn parser_with_options<T: Read>(
+    buf_file: T,
+    options: &ParserOptions,
+) -> anyhow::Result<ParserResult> {
+    parse_source(xml_source(buf_file), options)
+}
+
+/// parses the events of `source`, whatever its backend
+fn parse_source(
+    mut source: impl XmlSource,
+    options: &ParserOptions,
+) -> anyhow::Result<ParserResult> {
     let mut parser_context = ParserContext::default();
 
-    let mut trace_collect: Vec<(String, String, Vec<ChannelData>)> = vec![];
+    #[cfg(not(feature = "parallel"))]
+    let mut trace_collect: Vec<RawTrace> = vec![];
+    // decoder for the text of the traces, reused from one trace to the next
+    #[cfg(not(feature = "parallel"))]
+    let mut trace_data = TraceData::new(options.fill_missing_channels);
+    // with the parallel feature, the text of the traces is decoded once the
+    // whole document is read
+    #[cfg(feature = "parallel")]
+    let mut pending_traces: Vec<PendingTrace> = vec![];
+
+    // sizes checked against the limits of the options
+    let limits = &options.limits;
+    let (mut depth, mut num_traces, mut num_characters) = (0usize, 0usize, 0usize);
+    // number of points of the open trace
+    let mut num_points = 0usize;
+
+    // spans of the document and of the open context, brush and trace elements
+    let document_span = debug_span!(
+        "inkml_document",
+        traces = field::Empty,
+        contexts = field::Empty,
+        brushes = field::Empty
+    );
+    let _document_entered = document_span.enter();
+    let mut context_span: Option<EnteredSpan> = None;
+    let mut brush_span: Option<EnteredSpan> = None;
+    let mut trace_span: Option<EnteredSpan> = None;
+    #[cfg(feature = "metrics")]
+    let mut metrics = ParseMetrics::default();
+    // the document ended before its root element was closed
+    let truncated;
+
+    loop {
+        #[cfg(feature = "metrics")]
+        let event_start = std::time::Instant::now();
+        let xml_event = source.next_event();
+        #[cfg(feature = "metrics")]
+        {
+            ParseMetrics::record(&mut metrics.xml_events, event_start);
+            metrics.events += 1;
+        }
+        // position of the event, used for error reporting
+        let position = source.position();
 
-    for xml_event in parser {
+        match &xml_event {
+            Ok(rXmlEvent::StartElement { name, .. }) => {
+                depth += 1;
+                limits.check(Limit::Depth, depth)?;
+                if name.local_name == "trace" {
+                    num_traces += 1;
+                    limits.check(Limit::Traces, num_traces)?;
+                    num_points = 1;
+                }
+            }
+            Ok(rXmlEvent::EndElement { .. }) => depth = depth.saturating_sub(1),
+            Ok(
+                rXmlEvent::Characters(text) | rXmlEvent::Whitespace(text) | rXmlEvent::CData(text),
+            ) => {
+                num_characters += text.len();
+                limits.check(Limit::Characters, num_characters)?;
+                if parser_context.is_trace {
+                    // points are separated by commas
+                    num_points += text.bytes().filter(|byte| *byte == b',').count();
+                    limits.check(Limit::PointsPerTrace, num_points)?;
+                }
+            }
+            _ => {}
+        }
+        if parser_context.capture_extension(&xml_event) {
+            continue;
+        }
+        // the trace format of a canvas describes the page, not the channels of the context
+        if parser_context.in_canvas() {
+            continue;
+        }
         match xml_event {
+            Ok(rXmlEvent::EndDocument) => {
+                // (the fast-xml backend doesn't fail on unclosed elements)
+                truncated = depth > 0;
+                break;
+            }
             Ok(rXmlEvent::StartElement {
                 name, attributes, ..
             }) => {
                 // we should dispatch on some local names
                 match name.local_name.as_str() {
                     "context" => {
-                        let id_context =
-                            get_id(&attributes, String::from("id")).unwrap_or(String::from("ctx0"));
-                        debug!("context id :{:?}", id_context);
+                        let mut id_context =
+                            get_element_id(&attributes).unwrap_or(String::from("ctx0"));
 
-                        // create the empty context
-                        if !parser_context.context.contains_key(&id_context) {
-                            parser_context.context.insert(
-                                id_context.clone(),
-                                Context::create_empty(id_context.clone()),
-                            );
-                            parser_context.current_context_id = Some(id_context);
-                            parser_context.start_context_element =
-                                Some(ContextStartElement::Context);
-                        } else {
-                            return Err(anyhow!("could not create the context"));
+                        if parser_context.context.contains_key(&id_context) {
+                            let renamed_id = (1..)
+                                .map(|index| format!("{id_context}_{index}"))
+                                .find(|candidate| !parser_context.context.contains_key(candidate))
+                                .unwrap();
+                            match options.duplicates {
+                                DuplicatePolicy::Error => {
+                                    return Err(anyhow!(
+                                        "DuplicateKeyError : the context {id_context} at {position} was already defined"
+                                    ))
+                                }
+                                // read aside, compared with the first one at its end
+                                DuplicatePolicy::IgnoreIdentical => {
+                                    parser_context.duplicate_context =
+                                        Some((id_context, renamed_id.clone()));
+                                }
+                                // following traces referring to this id will use this new context
+                                DuplicatePolicy::Rename => {
+                                    warn!(
+                                        "the context {id_context} at {position} was already defined, renamed to {renamed_id}"
+                                    );
+                                    parser_context
+                                        .context_aliases
+                                        .insert(id_context, renamed_id.clone());
+                                }
+                            }
+                            id_context = renamed_id;
                         }
+
+                        // create the empty context
+                        parser_context.context.insert(
+                            id_context.clone(),
+                            Context::create_empty(id_context.clone()),
+                        );
+                        context_span = Some(debug_span!("context", id = %id_context).entered());
+                        parser_context.current_context_id = Some(id_context);
+                        parser_context.start_context_element = Some(ContextStartElement::Context);
                     }
-                    "inkSource" => {
-                        let id_source = get_id(&attributes, String::from("id"));
-                        debug!("source id :{:?}", id_source);
-                        // useful to start/end the parsing of a source (full context !)
-                        // though there are cases where only the trace format can exist
-                    }
-                    "traceFormat" => {
-                        debug!("start of traceFormat");
-                        // if we have no inkSource, this should init our context as well with a default inkSource id here
-                        if parser_context.context.is_empty() {
-                            // create a new context with a default name
-                            parser_context.context.insert(
-                                String::from("ctx0"),
-                                Context::create_empty(String::from("ctx0")),
-                            );
-                            parser_context.current_context_id = Some(String::from("ctx0"));
-                            parser_context.start_context_element =
-                                Some(ContextStartElement::TraceFormat);
+                    "sampleRate" => {
+                        let sample_rate = get_id(&attributes, String::from("value"))
+                            .map(|value| {
+                                value.parse::<f64>().map_err(|_| {
+                                    anyhow!("Could not parse the sample rate {value} to f64")
+                                })
+                            })
+                            .transpose()?;
+                        if let Some(context) = parser_context
+                            .current_context_id
+                            .as_ref()
+                            .and_then(|id| parser_context.context.get_mut(id))
+                        {
+                            context.sample_rate = sample_rate;
                         }
-                        debug!("here is the current context: {:?}", parser_context.context);
+                    }
+                    // if we have no inkSource, this should init our context as well with a default inkSource id here
+                    "traceFormat" if parser_context.context.is_empty() => {
+                        // create a new context with a default name
+                        parser_context.context.insert(
+                            String::from("ctx0"),
+                            Context::create_empty(String::from("ctx0")),
+                        );
+                        parser_context.current_context_id = Some(String::from("ctx0"));
+                        parser_context.start_context_element =
+                            Some(ContextStartElement::TraceFormat);
                     }
                     "channel" => {
                         let ids = get_ids(
@@ -109,10 +507,18 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                                 String::from("type"),
                                 String::from("units"), // can be optional
                                 String::from("max"),
+                                String::from("min"),
+                                String::from("orientation"),
+                                String::from("respectTo"),
                             ],
                         );
                         // add the channels to the CURRENT context
-                        debug!("{:?}", ids);
+                        if parser_context.is_trace {
+                            // the context would no longer match the traces already read
+                            return Err(anyhow!(
+                                "A channel was defined inside of a trace at {position}"
+                            ));
+                        }
                         if let Some(ref current_context) = parser_context.current_context_id {
                             parser_context
                                 .context
@@ -122,8 +528,47 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                                 .push(Channel::initialise_channel_from_name(ids)?);
                         }
                     }
-                    "channelProperties" => {
-                        debug!("start of channel properties");
+                    "table" if parser_context.in_channel_mapping() => {
+                        let ids = get_ids(
+                            attributes,
+                            vec![String::from("apply"), String::from("interpolation")],
+                        );
+                        if ids[0].as_deref() == Some("relative") {
+                            // the values would depend on the previous ones
+                            warn!("relative table mapping at {position} ignored");
+                        } else {
+                            parser_context.current_table =
+                                Some((Interpolation::parse(&ids[1])?, String::new()));
+                        }
+                    }
+                    "timestamp" => {
+                        let id = get_element_id(&attributes);
+                        let ids = get_ids(
+                            attributes,
+                            vec![
+                                String::from("time"),
+                                String::from("timestampRef"),
+                                String::from("timeOffset"),
+                            ],
+                        );
+                        let time_offset = parse_time(&ids[2])?.unwrap_or(0.0);
+                        let time = match (parse_time(&ids[0])?, &ids[1]) {
+                            (Some(time), _) => Some(time),
+                            // relative to another timestamp read before
+                            (None, Some(reference)) => parser_context
+                                .timestamps
+                                .get(split_reference(reference).1)
+                                .map(|time| time + time_offset),
+                            (None, None) => None,
+                        };
+                        match (id, time) {
+                            (Some(id), Some(time)) => {
+                                parser_context.timestamps.insert(id, time);
+                            }
+                            (id, _) => {
+                                warn!(?id, "timestamp at {position} without a known time ignored")
+                            }
+                        }
                     }
                     "channelProperty" => {
                         // inside of a context, the channelProperty gives additional info on the scaling of elements
@@ -136,7 +581,6 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                                 String::from("units"),
                             ],
                         );
-                        debug!("{:?}", ids);
 
                         if verify_channel_properties(&ids)
                             && parser_context.current_context_id.is_some()
@@ -181,23 +625,58 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                     "brush" => {
                         // either the id exist or not
                         // if not fallback on a default value
-                        let brush_id =
-                            get_id(&attributes, String::from("id")).unwrap_or(String::from("br0"));
-                        debug!("brush id {:?}", brush_id);
+                        let mut brush_id =
+                            get_element_id(&attributes).unwrap_or(String::from("br0"));
+                        brush_span = Some(debug_span!("brush", id = %brush_id).entered());
 
-                        parser_context.current_brush_id = Some(brush_id.clone());
+                        // same policies as for the contexts
                         if parser_context.brushes.contains_key(&brush_id) {
-                            return Err(anyhow!(
-                                "DuplicateKeyError : We cannot have twice the same brush"
-                            ));
-                            // we cannot have twice the same brush id
-                        } else {
+                            let renamed_id = (1..)
+                                .map(|index| format!("{brush_id}_{index}"))
+                                .find(|candidate| !parser_context.brushes.contains_key(candidate))
+                                .unwrap();
+                            match options.duplicates {
+                                DuplicatePolicy::Error => {
+                                    return Err(anyhow!(
+                                        "DuplicateKeyError : the brush {brush_id} at {position} was already defined"
+                                    ))
+                                }
+                                DuplicatePolicy::IgnoreIdentical => {
+                                    parser_context.duplicate_brush =
+                                        Some((brush_id, renamed_id.clone()));
+                                }
+                                DuplicatePolicy::Rename => {
+                                    warn!(
+                                        "the brush {brush_id} at {position} was already defined, renamed to {renamed_id}"
+                                    );
+                                    parser_context
+                                        .brush_aliases
+                                        .insert(brush_id, renamed_id.clone());
+                                }
+                            }
+                            brush_id = renamed_id;
+                        }
+                        parser_context.current_brush_id = Some(brush_id.clone());
+                        let brush = match get_id(&attributes, String::from("brushRef")) {
+                            Some(reference) => {
+                                // copy the referenced brush, the properties
+                                // of this element then override its values
+                                let parent_id =
+                                    parser_context.brush_reference(&reference, options)?;
+                                let mut brush = parser_context
+                                    .brushes
+                                    .get(&parent_id)
+                                    .ok_or(anyhow!("The brush {brush_id} inherits from the brush {parent_id} but it was not found.
+                                                        The parser expects brushes to refer to brushes that are defined before them in the inkml file"))?
+                                    .clone();
+                                brush.name = brush_id.as_str().into();
+                                brush
+                            }
                             // we init the brush with default parameters
                             // this also allows the default parameter to serve as a fallback (except for the stroke width)
-                            parser_context
-                                .brushes
-                                .insert(brush_id.clone(), Brush::init_brush_with_id(&brush_id));
-                        }
+                            None => Brush::init_brush_with_id(&brush_id),
+                        };
+                        parser_context.brushes.insert(brush_id.clone(), brush);
                     }
                     "brushProperty" => {
                         // we first check what property we have
@@ -216,77 +695,30 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                             Some(property_name) => {
                                 match property_name.as_str() {
                                     "width" | "height" => {
-                                        // as we don't have support for rectangular brushes
-                                        // we increase the stroke width and take the max of both
-
-                                        // we convert everything to mm here
-                                        let in_unit = match get_id(
-                                            &attributes,
-                                            String::from("units"),
-                                        ) {
-                                            None => {
-                                                return Err(anyhow!(
-                                                    "No unit was found for the brush property {:?}",
-                                                    property_name.as_str()
-                                                ))
-                                            }
-                                            Some(unit_str) => {
-                                                match ChannelUnit::parse(&Some(unit_str.clone())) {
-                                                        Some(unit) => unit,
-                                                        None => return Err(anyhow!("Could not find a ChannelUnit matching {:?}", unit_str)),
-                                                    }
-                                            }
-                                        };
-                                        let value = match get_id(&attributes, String::from("value"))
-                                        {
-                                            None => {
-                                                return Err(anyhow!(
-                                                "No value was given to set the {:?} of the brush",
-                                                property_name
-                                            ))
+                                        // an unusable size keeps the previous one rather
+                                        // than failing the whole document
+                                        match brush_size_cm(&attributes) {
+                                            Ok(size) if property_name == "width" => {
+                                                current_brush.width_cm = size
                                             }
-                                            Some(value_str) => {
-                                                value_str.parse::<f64>().map_err(|_| {
-                                                    anyhow!("Could not parse {value_str} to f64")
-                                                })?
-                                            }
-                                        };
-                                        let stroke_width =
-                                            in_unit.convert_to(ChannelUnit::cm, value)?;
-                                        current_brush.stroke_width_cm =
-                                            current_brush.stroke_width_cm.max(stroke_width);
+                                            Ok(size) => current_brush.height_cm = size,
+                                            Err(e) => warn!(
+                                                "skipping the {property_name} of the brush at {position} : {e}"
+                                            ),
+                                        }
                                     }
                                     "color" => {
                                         match get_id(&attributes, String::from("value")) {
                                             Some(color_string) => {
-                                                // format : #{:02X}{:02X}{:02X} for RGB
-                                                if color_string.len() == 7 {
-                                                    debug!("Matching color {:?}", color_string);
-                                                    let r = u8::from_str_radix(
-                                                        &color_string[1..=2],
-                                                        16,
-                                                    )
-                                                    .map_err(|_| {
-                                                        anyhow!("Failed to parse {color_string}")
-                                                    })?;
-                                                    let g = u8::from_str_radix(
-                                                        &color_string[3..=4],
-                                                        16,
-                                                    )
-                                                    .map_err(|_| {
-                                                        anyhow!("Failed to parse {color_string}")
-                                                    })?;
-                                                    let b = u8::from_str_radix(
-                                                        &color_string[5..=6],
-                                                        16,
-                                                    )
-                                                    .map_err(|_| {
-                                                        anyhow!("Failed to parse {color_string}")
-                                                    })?;
-                                                    current_brush.color = (r, g, b);
-                                                } else {
-                                                    return Err(anyhow!("Unexpected length for the color string, expected 7, found {}",color_string.len()));
-                                                }
+                                                let ((r, g, b), alpha) =
+                                                    parse_color(&color_string)?;
+                                                // without alpha, a previous transparency is kept
+                                                current_brush.color = Color::rgba(
+                                                    r,
+                                                    g,
+                                                    b,
+                                                    alpha.unwrap_or(current_brush.color.a),
+                                                );
                                             }
                                             None => {
                                                 return Err(anyhow!(
@@ -302,32 +734,48 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                                                 // workaround to make it work with
                                                 // this https://devblogs.microsoft.com/microsoft365dev/onenote-ink-beta-apis/
                                                 // with transparency between 0 and 256 !!
-                                                current_brush.transparency = value_str
+                                                let transparency = value_str
                                                     .parse::<u16>()
                                                     .map_err(|_| anyhow!("Failed to parse {value_str} to an integer"))?
                                                     .clamp(0, u8::MAX.into())
                                                     as u8;
+                                                current_brush.color = current_brush.color.with_transparency(transparency);
                                             }
                                         }
                                     }
                                     "ignorePressure" => {
-                                        let value = get_id(&attributes, String::from("value"));
-                                        match value {
-                                            Some(bool_str) => match bool_str.as_str() {
-                                                "1" | "true" => {
-                                                    current_brush.ignorepressure = true;
-                                                }
-                                                "0" | "false" => {
-                                                    current_brush.ignorepressure = false;
-                                                }
-                                                _ => return Err(anyhow!("Unexpected value for the boolean, expected 1,0,true of false, found {bool_str}")),
-                                            },
-                                            None => return Err(anyhow!("No value was found to set the transparency")),
-                                        }
+                                        current_brush.ignorepressure = parse_bool(
+                                            &get_id(&attributes, String::from("value")),
+                                            "ignorePressure",
+                                        )?;
+                                    }
+                                    "fitToCurve" => {
+                                        current_brush.fit_to_curve = Some(parse_bool(
+                                            &get_id(&attributes, String::from("value")),
+                                            "fitToCurve",
+                                        )?);
+                                    }
+                                    "antiAliased" => {
+                                        current_brush.anti_aliased = Some(parse_bool(
+                                            &get_id(&attributes, String::from("value")),
+                                            "antiAliased",
+                                        )?);
+                                    }
+                                    "tip" => {
+                                        current_brush.tip = BrushTip::parse(&get_id(
+                                            &attributes,
+                                            String::from("value"),
+                                        ))?;
+                                    }
+                                    "rasterOp" => {
+                                        current_brush.raster_op = RasterOp::parse(&get_id(
+                                            &attributes,
+                                            String::from("value"),
+                                        ))?;
                                     }
                                     _ => {
                                         // ignore
-                                        debug!("brush property ignored: {:?}", property_name);
+                                        debug!(property = %property_name, "brush property ignored");
                                     }
                                 }
                             }
@@ -338,21 +786,94 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                             }
                         }
                     }
+                    "ink" => {
+                        parser_context.document_id =
+                            get_id(&attributes, String::from("documentID"));
+                        parser_context.lang = get_lang(&attributes);
+                    }
+                    "traceGroup" => {
+                        parser_context.open_group(&attributes);
+                        let (context_ref, brush_ref) = parser_context
+                            .group_refs
+                            .last()
+                            .cloned()
+                            .unwrap_or_default();
+                        parser_context.group_refs.push((
+                            get_id(&attributes, String::from("contextRef")).or(context_ref),
+                            get_id(&attributes, String::from("brushRef")).or(brush_ref),
+                        ));
+                    }
+                    "traceView" => {
+                        // a view referring to a trace (or a group) adds it to the
+                        // open group, the other ones group views (e.g. IAMonDo)
+                        match get_id(&attributes, String::from("traceDataRef")) {
+                            Some(reference) => {
+                                if let Some(index) = parser_context.current_group {
+                                    parser_context.groups[index]
+                                        .trace_refs
+                                        .push(reference.trim_start_matches('#').to_owned());
+                                }
+                                parser_context.open_views.push(false);
+                            }
+                            None => {
+                                parser_context.open_group(&attributes);
+                                parser_context.open_views.push(true);
+                            }
+                        }
+                    }
+                    "annotation" => {
+                        // only the annotations of the groups and of the document are kept
+                        let open_elements = &parser_context.open_elements;
+                        if open_elements.len() >= 2
+                            && matches!(
+                                open_elements[open_elements.len() - 2].as_str(),
+                                "traceGroup" | "traceView" | "ink"
+                            )
+                        {
+                            parser_context.current_annotation = Some(Annotation {
+                                kind: get_id(&attributes, String::from("type")),
+                                text: String::new(),
+                            });
+                        }
+                    }
                     "trace" => {
-                        trace!("start of trace");
                         parser_context.is_trace = true;
+                        parser_context.trace_text.clear();
+                        parser_context.current_trace_id = get_element_id(&attributes);
                         // need to assign a context and a brush
                         // this will give the information on the type (int or float) of each channel
                         // and their number
                         // this will allow to read the trace context that follows
                         // and then populate to a stroke with a color and a width (+ eventually transparency)
-                        let ids = get_ids(
+                        let mut ids = get_ids(
                             attributes,
-                            vec![String::from("contextRef"), String::from("brushRef")],
+                            vec![
+                                String::from("contextRef"),
+                                String::from("brushRef"),
+                                String::from("type"),
+                                String::from("timeOffset"),
+                                String::from("duration"),
+                                String::from("continuation"),
+                                String::from("priorRef"),
+                            ],
                         );
+                        // references of the enclosing groups otherwise
+                        if let Some((context_ref, brush_ref)) = parser_context.group_refs.last() {
+                            ids[0] = ids[0].take().or_else(|| context_ref.clone());
+                            ids[1] = ids[1].take().or_else(|| brush_ref.clone());
+                        }
+                        parser_context.current_trace_type = TraceType::parse(&ids[2])?;
+                        parser_context.current_time_offset = parse_time(&ids[3])?;
+                        parser_context.current_duration = parse_time(&ids[4])?;
+                        parser_context.current_continuation = Continuation::parse(&ids[5])?;
+                        parser_context.current_prior_ref = ids[6]
+                            .as_ref()
+                            .map(|prior_ref| prior_ref.trim_start_matches('#').to_owned());
 
                         parser_context.current_context_id = match &ids[0] {
-                            Some(candidate) => Some(candidate.replace("#", "")),
+                            Some(candidate) => {
+                                Some(parser_context.context_reference(candidate, options)?)
+                            }
                             None => Some(String::from("ctx0")),
                         };
                         // we will check inside the trace that the context exist or not
@@ -360,7 +881,8 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                         // we check the brush existence here
                         parser_context.current_brush_id = match &ids[1] {
                             Some(candidate_with_hash) => {
-                                let candidate = candidate_with_hash.clone().replace("#", "");
+                                let candidate =
+                                    parser_context.brush_reference(candidate_with_hash, options)?;
                                 if !parser_context.brushes.contains_key(&candidate) {
                                     return Err(anyhow!("The trace refers to the Brush {candidate} but it was not found.
                                                         The parser expects trace to refer to brushes that are defined before them in the inkml file"));
@@ -380,45 +902,200 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                                 }
                             }
                         };
+                        trace_span = Some(
+                            trace_span!(
+                                "trace",
+                                id = parser_context.current_trace_id.as_deref(),
+                                context = parser_context.current_context_id.as_deref(),
+                                brush = parser_context.current_brush_id.as_deref(),
+                                points = field::Empty
+                            )
+                            .entered(),
+                        );
                     }
                     _ => {}
                 }
             }
+            // the whole text of the trace is read
+            Ok(rXmlEvent::EndElement { name })
+                if name.local_name == "trace" && !parser_context.trace_text.trim().is_empty() =>
+            {
+                parser_context.is_trace = false;
+                let string_out = std::mem::take(&mut parser_context.trace_text);
+                // get the ChannelType from the current context
+                let channel_types = match parser_context.current_context_id {
+                    Some(ref key) => match parser_context.context.get(key) {
+                        Some(current_context) => current_context
+                            .channel_list
+                            .iter()
+                            .map(|x| x.types.clone())
+                            .collect::<Vec<ChannelType>>(),
+                        None => {
+                            return Err(anyhow!(
+                                "Trace data was started but couldn't find its associated context"
+                            ))
+                        }
+                    },
+                    None => {
+                        return Err(anyhow!(
+                            "Text data is only expected inside of a trace but no trace was opened"
+                        ))
+                    }
+                };
+
+                #[cfg(feature = "metrics")]
+                let decode_start = std::time::Instant::now();
+                #[cfg(not(feature = "parallel"))]
+                {
+                    trace_data.reset(channel_types);
+                    trace_data.parse_raw_data(&string_out)?;
+                }
+                #[cfg(feature = "metrics")]
+                {
+                    ParseMetrics::record(&mut metrics.trace_decode, decode_start);
+                    metrics.trace_bytes += string_out.len();
+                }
+
+                if (parser_context.current_brush_id.is_none())
+                    && (parser_context.brushes.is_empty()
+                        || parser_context.brushes.contains_key(&String::from("br0")))
+                {
+                    if parser_context.brushes.is_empty() {
+                        // no brush was defined. We add a default brush
+                        parser_context.brushes.insert(
+                            String::from("br0"),
+                            Brush::init(
+                                String::from("br0"),
+                                options.default_color,
+                                true,
+                                0,
+                                options.default_width_cm,
+                            ),
+                        );
+                    }
+                    parser_context.current_brush_id = Some(String::from("br0"));
+                }
+
+                // collect output
+                let trace = RawTrace {
+                    id: parser_context.current_trace_id.take(),
+                    context_id: parser_context
+                        .symbols
+                        .intern(&parser_context.current_context_id.unwrap()),
+                    brush_id: parser_context
+                        .symbols
+                        .intern(&parser_context.current_brush_id.unwrap()),
+                    trace_type: parser_context.current_trace_type,
+                    time_offset: parser_context.current_time_offset,
+                    duration: parser_context.current_duration,
+                    group: parser_context.current_group,
+                    continuation: parser_context.current_continuation.take(),
+                    prior_ref: parser_context.current_prior_ref.take(),
+                    data: vec![],
+                };
+                #[cfg(not(feature = "parallel"))]
+                {
+                    let trace = RawTrace {
+                        data: trace_data.take_data(),
+                        ..trace
+                    };
+                    #[cfg(feature = "trace-points")]
+                    tracing::trace!(data = ?trace.data, "values of the trace");
+                    #[cfg(feature = "metrics")]
+                    {
+                        metrics.points += trace.data.first().map_or(0, ChannelData::len);
+                    }
+                    let context = &parser_context.context[&*trace.context_id];
+                    trace_collect.extend(options.select(trace, context));
+                }
+                #[cfg(feature = "parallel")]
+                pending_traces.push((trace, channel_types, string_out));
+
+                parser_context.current_brush_id = None;
+                parser_context.current_context_id = None;
+
+                if let Some(span) = trace_span.take() {
+                    span.record("points", num_points);
+                }
+                options.report_progress(ParsedSoFar {
+                    traces: num_traces,
+                    contexts: parser_context.context.len(),
+                    brushes: parser_context.brushes.len(),
+                })?;
+            }
             Ok(rXmlEvent::EndElement { name }) => {
                 match name.local_name.as_str() {
-                    "definitions" => {
-                        debug!("\x1b[93mclosing definitions\x1b[0m");
+                    "traceGroup" => {
+                        parser_context.close_group();
+                        parser_context.group_refs.pop();
+                    }
+                    "traceView" => parser_context.close_view(),
+                    "annotation" => {
+                        if let Some(annotation) = parser_context.current_annotation.take() {
+                            match parser_context.current_group {
+                                Some(index) => {
+                                    parser_context.groups[index].annotations.push(annotation)
+                                }
+                                None => parser_context.annotations.push(annotation),
+                            }
+                        }
                     }
                     "context" => {
+                        if let Some((id, read_id)) = parser_context.duplicate_context.take() {
+                            let mut duplicate = parser_context.context.remove(&read_id);
+                            if let Some(duplicate) = duplicate.as_mut() {
+                                duplicate.name = id.clone();
+                            }
+                            if duplicate.as_ref() != parser_context.context.get(&id) {
+                                return Err(anyhow!(
+                                    "DuplicateKeyError : the context {id} at {position} was already defined with other values"
+                                ));
+                            }
+                            debug!(
+                                "the context {id} at {position} repeats an identical one, ignored"
+                            );
+                        }
                         parser_context.current_context_id = None;
                         parser_context.start_context_element = None;
-                        debug!("\x1b[93mclosing context\x1b[0m");
+                        drop(context_span.take());
                     }
-                    "inkSource" => {
-                        debug!("\x1b[93mclosing inkSource\x1b[0m");
+                    "table" => {
+                        if let Some((interpolation, text)) = parser_context.current_table.take() {
+                            let mapping =
+                                TableMapping::parse(&text, interpolation).map_err(|e| {
+                                    e.context(format!("Invalid table mapping at {position}"))
+                                })?;
+                            if let Some(channel) = parser_context
+                                .current_context_id
+                                .as_ref()
+                                .and_then(|id| parser_context.context.get_mut(id))
+                                .and_then(|context| context.channel_list.last_mut())
+                            {
+                                channel.set_mapping(mapping);
+                            }
+                        }
                     }
                     "traceFormat" => {
-                        if !matches!(
+                        // a standalone traceFormat (no context element) ends here
+                        // inside of a context, the channelProperties can still follow
+                        if matches!(
                             parser_context.start_context_element,
                             Some(ContextStartElement::TraceFormat)
                         ) {
                             parser_context.start_context_element = None;
                             parser_context.current_context_id = None;
                         }
-                        trace!("\x1b[93mclosing traceFormat\x1b[0m");
-                    }
-                    "channelProperties" => {
-                        debug!("\x1b[93mclosing channelProperties\x1b[0m");
-                        debug!("now the context is {:?}", parser_context.context);
                     }
                     "trace" => {
-                        trace!("\x1b[93mclosing trace\x1b[0m");
+                        if let Some(span) = trace_span.take() {
+                            span.record("points", 0);
+                        }
                         parser_context.is_trace = false;
                         parser_context.current_context_id = None;
                         parser_context.current_brush_id = None;
                     }
                     "brush" => {
-                        debug!("\x1b[93mclosing brush\x1b[0m");
+                        drop(brush_span.take());
 
                         // if no stroke width was given, give a min default value
                         match parser_context.current_brush_id {
@@ -428,152 +1105,921 @@ pub fn parser<T: Read>(buf_file: T) -> anyhow::Result<ParserResult> {
                                 Some(brush) => brush,
                                 None => return Err(anyhow!("Cannot find the brush with its (supposedly) key in the dictionnary")),
                             };
-                            if current_brush.stroke_width_cm == 0.0 {
-                                current_brush.stroke_width_cm = 0.1;
+                            // a missing width or height takes the value of the other one
+                            match (current_brush.width_cm == 0.0, current_brush.height_cm == 0.0) {
+                                (true, true) => {
+                                    current_brush.width_cm = options.default_width_cm;
+                                    current_brush.height_cm = options.default_width_cm;
+                                }
+                                (true, false) => current_brush.width_cm = current_brush.height_cm,
+                                (false, true) => current_brush.height_cm = current_brush.width_cm,
+                                (false, false) => {}
                             }
                         }
                     }
+                        if let Some((id, read_id)) = parser_context.duplicate_brush.take() {
+                            let duplicate = parser_context.brushes.remove(&read_id);
+                            if duplicate.map(|brush| brush.duplicate_key())
+                                != parser_context.brushes.get(&id).map(Brush::duplicate_key)
+                            {
+                                return Err(anyhow!(
+                                    "DuplicateKeyError : the brush {id} at {position} was already defined with other values"
+                                ));
+                            }
+                            debug!(
+                                "the brush {id} at {position} repeats an identical one, ignored"
+                            );
+                        }
 
                         parser_context.current_brush_id = None;
                     }
                     _ => {}
                 }
             }
-            Ok(rXmlEvent::Characters(string_out)) => {
-                // we have to verify we are inside a trace
-                if parser_context.is_trace {
-                    // get the ChannelType from the current context
-                    let ch_type_vec = match parser_context.current_context_id {
-                        Some(ref key) => match parser_context.context.get(&key.clone()) {
-                            Some(current_context) => current_context
-                                .channel_list
-                                .iter()
-                                .map(|x| x.types.clone())
-                                .collect::<Vec<ChannelType>>(),
-                            None => {
-                                return Err(anyhow!(
-                                "Trace data was started but couldn't find its associated context"
-                            ))
-                            }
-                        },
-                        None => {
-                            return Err(anyhow!(
-                            "Text data is only expected inside of a trace but no trace was opened"
-                        ))
-                        }
-                    };
-
-                    trace!("start of trace char");
-
-                    // init the trace data parser
-                    let mut trace_data = TraceData::from_channel_types(ch_type_vec);
-                    trace_data.parse_raw_data(string_out)?;
-
-                    if (parser_context.current_brush_id.is_none())
-                        && (parser_context.brushes.is_empty()
-                            || parser_context.brushes.contains_key(&String::from("br0")))
-                    {
-                        if parser_context.brushes.is_empty() {
-                            // no brush was defined. We add a default brush
-                            parser_context.brushes.insert(
-                                String::from("br0"),
-                                Brush::init(String::from("br0"), (255, 255, 255), true, 0, 0.1),
-                            );
-                        }
-                        parser_context.current_brush_id = Some(String::from("br0"));
-                    }
-
-                    // collect output
-                    trace_collect.push((
-                        parser_context.current_context_id.unwrap(),
-                        parser_context.current_brush_id.unwrap(),
-                        trace_data.data(),
-                    ));
-
-                    parser_context.current_brush_id = None;
-                    parser_context.current_context_id = None;
+            Ok(rXmlEvent::Characters(text) | rXmlEvent::CData(text))
+                if parser_context.current_annotation.is_some() =>
+            {
+                if let Some(annotation) = parser_context.current_annotation.as_mut() {
+                    annotation.text.push_str(&text);
                 }
             }
+            Ok(rXmlEvent::Characters(text) | rXmlEvent::CData(text))
+                if parser_context.current_table.is_some() =>
+            {
+                if let Some((_, table)) = parser_context.current_table.as_mut() {
+                    table.push_str(&text);
+                }
+            }
+            // the reader can split the text in several events
+            Ok(
+                rXmlEvent::Characters(text) | rXmlEvent::CData(text) | rXmlEvent::Whitespace(text),
+            ) if parser_context.is_trace => {
+                parser_context.trace_text.push_str(&text);
+            }
+            Err(e) if options.recover_partial => {
+                warn!("the document ends early at {position}, only the traces read before are kept : {e}");
+                truncated = true;
+                break;
+            }
             Err(e) => return Err(anyhow!("Failed to parse xml element : {e}")),
             _ => {}
         }
+        limits.check(Limit::Contexts, parser_context.context.len())?;
     }
 
+    #[cfg(all(feature = "parallel", feature = "metrics"))]
+    let decode_start = std::time::Instant::now();
+    #[cfg(feature = "parallel")]
+    let decoded = decode_traces_parallel(pending_traces, options.fill_missing_channels)?;
+    #[cfg(all(feature = "parallel", feature = "metrics"))]
+    ParseMetrics::record(&mut metrics.trace_decode, decode_start);
+    #[cfg(feature = "parallel")]
+    let trace_collect = decoded
+        .into_iter()
+        .filter_map(|trace| {
+            #[cfg(feature = "trace-points")]
+            tracing::trace!(id = ?trace.id, data = ?trace.data, "values of the trace");
+            #[cfg(feature = "metrics")]
+            {
+                metrics.points += trace.data.first().map_or(0, ChannelData::len);
+            }
+            let context = &parser_context.context[&*trace.context_id];
+            options.select(trace, context)
+        })
+        .collect();
+    options.apply_default_pressure_max(&mut parser_context.context);
+    options.select_channels(&mut parser_context.context);
+    document_span.record("traces", num_traces);
+    document_span.record("contexts", parser_context.context.len());
+    document_span.record("brushes", parser_context.brushes.len());
+
     Ok(ParserResult {
         context_brush_data_vec: trace_collect,
         context_dict: parser_context.context,
         context_brush: parser_context.brushes,
+        extensions: parser_context.extensions,
+        groups: parser_context.groups,
+        annotations: parser_context.annotations,
+        timestamps: parser_context.timestamps,
+        symbols: parser_context.symbols,
+        truncated,
+        document_id: parser_context.document_id,
+        lang: parser_context.lang,
+        #[cfg(feature = "emma")]
+        emma: parser_context.emma,
+        #[cfg(feature = "metrics")]
+        metrics,
     })
 }
 
+/// timestamps of the points of a trace in ms : from the T channel or spread
+/// from the `timeOffset` with the sample rate of the context (or the `duration`)
+/// A T channel relative to a `timestamp` (`respectTo`) gives absolute times
+fn timestamps(
+    context: &Context,
+    timestamps_dict: &HashMap<String, f64>,
+    stroke: &[ChannelData],
+    x_idx: usize,
+    time_offset: Option<f64>,
+    duration: Option<f64>,
+) -> Option<Vec<f64>> {
+    let offset = time_offset.unwrap_or(0.0);
+    if let Some(t_idx) = context.channel_exists(ChannelKind::T) {
+        let channel = &context.channel_list[t_idx];
+        let origin = channel
+            .respect_to()
+            .and_then(|id| timestamps_dict.get(id))
+            .copied()
+            .unwrap_or(offset);
+        return Some(
+            channel
+                .formatted_values(&stroke[t_idx])
+                .map(|t| origin + t)
+                .collect(),
+        );
+    }
+    let len = stroke[x_idx].len();
+    let interval = match (context.sample_rate, duration) {
+        (Some(sample_rate), _) if sample_rate > 0.0 => 1000.0 / sample_rate,
+        (_, Some(duration)) if len > 1 => duration / (len - 1) as f64,
+        (_, Some(_)) => 0.0,
+        _ => return None,
+    };
+    Some(
+        (0..len)
+            .map(|index| offset + index as f64 * interval)
+            .collect(),
+    )
+}
+
+/// size of a `width` or `height` brush property, converted to cm
+/// (`units` can be any length unit : `cm`, `mm`, `in`, `pt`, `px`, ...)
+fn brush_size_cm(attributes: &[OwnedAttribute]) -> anyhow::Result<f64> {
+    let unit_str = get_id(attributes, String::from("units"))
+        .ok_or_else(|| anyhow!("No unit was found for the size"))?;
+    let in_unit = ChannelUnit::parse(&Some(unit_str.trim().to_owned()))
+        .ok_or_else(|| anyhow!("Could not find a ChannelUnit matching {unit_str:?}"))?;
+    let value_str = get_id(attributes, String::from("value"))
+        .ok_or_else(|| anyhow!("No value was given for the size"))?;
+    let value = value_str
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| anyhow!("Could not parse {value_str} to f64"))?;
+    in_unit.convert_to(ChannelUnit::cm, value)
+}
+
+/// parses a time attribute of a trace (`timeOffset`, `duration`)
+fn parse_time(value: &Option<String>) -> anyhow::Result<Option<f64>> {
+    value
+        .as_ref()
+        .map(|value| {
+            value
+                .parse::<f64>()
+                .map_err(|_| anyhow!("Could not parse the time {value} to f64"))
+        })
+        .transpose()
+}
+
+/// Same as `parser` for a document already in memory
+/// (with the `fast-xml` feature, the bytes are read in place when the
+/// document is in UTF-8, no `BufReader` is needed)
+pub fn parse_bytes(bytes: &[u8]) -> anyhow::Result<ParserResult> {
+    parse_source(xml_source_bytes(bytes)?, &ParserOptions::default())
+}
+
+/// Same as `parser` for a document in a string
+pub fn parse_str(document: &str) -> anyhow::Result<ParserResult> {
+    parser(document.as_bytes())
+}
+
+/// Parses the InkML file at `path`
+pub fn parse_file<P: AsRef<Path>>(path: P) -> anyhow::Result<ParserResult> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| anyhow!("Could not open {} : {e}", path.display()))?;
+    parser(BufReader::new(file))
+}
+
 /// This function formats the output of the parser
 /// for an easier use.
 /// We return an iterator over strokes where the X,Y and F
 /// channels are returned as floats with X and Y being in cm unit
 /// and F between 0 and 1 (and 1.0 if F is missing), with the associated brush
+/// (see `Brush::effective_style` for the color, opacity and width to render it with)
+/// Hover traces (`type="penUp"`) are skipped (see `FormatOptions::include_pen_up`)
 pub fn parse_formatted<T: Read>(buf_file: T) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
-    let mut formatted_result: Vec<(FormattedStroke, Brush)> = vec![];
-    let ParserResult {
-        context_brush_data_vec: strokes,
-        context_dict,
-        context_brush: brushes_dict,
-    } = parser(buf_file)?;
-
-    // iterate over results
-    for (context_str, brush_str, stroke) in strokes {
-        let context = context_dict
-            .get(&context_str)
-            .ok_or_else(|| anyhow!("Could not find the context"))?;
-        let brush = brushes_dict
-            .get(&brush_str)
-            .ok_or_else(|| anyhow!("Could not find the brush"))?
-            .clone();
-
-        // verify X, Y exist
-        let (x_idx, y_idx) = (
-            context.channel_exists(ChannelKind::X),
-            context.channel_exists(ChannelKind::Y),
-        );
-        let f_idx = context.channel_exists(ChannelKind::F);
-
-        if x_idx.is_some() && y_idx.is_some() {
-            // calculate scalings
-            let x_ratio = context
-                .channel_list
-                .get(x_idx.unwrap())
-                .unwrap()
-                .get_scaling();
-            let y_ratio = context
-                .channel_list
-                .get(x_idx.unwrap())
-                .unwrap()
-                .get_scaling();
-
-            formatted_result.push((
-                FormattedStroke {
-                    x: stroke.get(x_idx.unwrap()).unwrap().cast_to_float(x_ratio),
-                    y: stroke.get(y_idx.unwrap()).unwrap().cast_to_float(y_ratio),
-                    f: if f_idx.is_some() {
-                        let f_ratio = context
-                            .channel_list
-                            .get(f_idx.unwrap())
-                            .unwrap()
-                            .get_scaling();
-                        stroke.get(f_idx.unwrap()).unwrap().cast_to_float(f_ratio)
+    parse_formatted_with_options(buf_file, &ParserOptions::default())
+}
+
+/// Same as `parse_formatted` for a document already in memory
+pub fn parse_formatted_bytes(bytes: &[u8]) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
+    parse_bytes(bytes)?.formatted(&FormatOptions::default())
+}
+
+/// Same as `parse_formatted` for a document in a string
+pub fn parse_formatted_str(document: &str) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
+    parse_formatted(document.as_bytes())
+}
+
+/// Same as `parse_formatted` for the InkML file at `path`
+pub fn parse_formatted_file<P: AsRef<Path>>(
+    path: P,
+) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
+    parse_file(path)?.formatted(&FormatOptions::default())
+}
+
+/// Same as `parse_formatted` with options
+pub fn parse_formatted_with_options<T: Read>(
+    buf_file: T,
+    options: &ParserOptions,
+) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
+    parser_with_options(buf_file, options)?.formatted(&options.format)
+}
+
+/// Same as `parse_formatted`, with the trace and the context each stroke comes from
+/// (see `ParserResult::formatted_with_sources`)
+pub fn parse_formatted_with_sources<T: Read>(
+    buf_file: T,
+) -> anyhow::Result<Vec<(FormattedStroke, Brush, StrokeSource)>> {
+    parser(buf_file)?.formatted_with_sources(&FormatOptions::default())
+}
+
+/// Same as `parse_formatted`, with the strokes grouped by the `traceGroup`
+/// elements they are in (see `ParserResult::formatted_tree`)
+pub fn parse_formatted_tree<T: Read>(buf_file: T) -> anyhow::Result<Vec<InkNode>> {
+    parser(buf_file)?.formatted_tree(&FormatOptions::default())
+}
+
+// two parses of the same document are equal : the metrics (times of the
+// parse) and the symbol table (ids of the traces) are left out
+impl PartialEq for ParserResult {
+    fn eq(&self, other: &Self) -> bool {
+        let equal = self.context_brush_data_vec == other.context_brush_data_vec
+            && self.context_dict == other.context_dict
+            && self.context_brush == other.context_brush
+            && self.extensions == other.extensions
+            && self.groups == other.groups
+            && self.annotations == other.annotations
+            && self.timestamps == other.timestamps
+            && self.truncated == other.truncated
+            && self.document_id == other.document_id
+            && self.lang == other.lang;
+        #[cfg(feature = "emma")]
+        let equal = equal && self.emma == other.emma;
+        equal
+    }
+}
+
+impl ParserResult {
+    /// contexts of the document by id (as traces refer to them)
+    pub fn contexts(&self) -> &HashMap<String, Context> {
+        &self.context_dict
+    }
+
+    /// the context with the id `id`
+    pub fn context(&self, id: &str) -> Option<&Context> {
+        self.context_dict.get(id)
+    }
+
+    /// brushes of the document by id
+    pub fn brushes(&self) -> &HashMap<String, Brush> {
+        &self.context_brush
+    }
+
+    /// times of the `timestamp` elements by id, in ms since the epoch
+    pub fn timestamps(&self) -> &HashMap<String, f64> {
+        &self.timestamps
+    }
+
+    /// number of traces read (pen up traces included)
+    pub fn trace_count(&self) -> usize {
+        self.context_brush_data_vec.len()
+    }
+
+    /// the elements of the document that aren't InkML elements
+    /// (e.g. `<msink:context>`), in document order
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    /// `documentID` attribute of the `ink` element (a URI identifying the document)
+    pub fn document_id(&self) -> Option<&str> {
+        self.document_id.as_deref()
+    }
+
+    /// language of the document (`xml:lang` of the `ink` element), the groups
+    /// without one inherit it (`TraceGroup::lang`)
+    pub fn lang(&self) -> Option<&str> {
+        self.lang.as_deref()
+    }
+
+    /// annotations of the document (the `annotation` children of `ink`),
+    /// e.g. the `truth` or `UI` labels of handwriting datasets
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// recognition results given as EMMA in the `annotationXML` elements,
+    /// in document order (alternatives of a `one-of` share their `parent`)
+    #[cfg(feature = "emma")]
+    pub fn emma(&self) -> &[EmmaInterpretation] {
+        &self.emma
+    }
+
+    /// true if the document ended before its root element was closed, only
+    /// the traces read until then are there (see `ParserOptions::recover_partial`)
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// time spent in each phase of the parsing and size of the document
+    /// (the formatting isn't measured here, see `formatted_with_metrics`)
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> ParseMetrics {
+        self.metrics
+    }
+
+    /// Same as `formatted`, with the metrics of the parsing and of the formatting
+    #[cfg(feature = "metrics")]
+    pub fn formatted_with_metrics(
+        self,
+        options: &FormatOptions,
+    ) -> anyhow::Result<(Vec<(FormattedStroke, Brush)>, ParseMetrics)> {
+        let mut metrics = self.metrics;
+        let start = std::time::Instant::now();
+        let strokes = self.formatted(options)?;
+        ParseMetrics::record(&mut metrics.formatting, start);
+        Ok((strokes, metrics))
+    }
+
+    /// Converts the traces to strokes, as `parse_formatted` does
+    pub fn formatted(
+        self,
+        options: &FormatOptions,
+    ) -> anyhow::Result<Vec<(FormattedStroke, Brush)>> {
+        Ok(self
+            .formatted_with_groups(options)?
+            .1
+            .into_iter()
+            .map(|(_, _, stroke, brush)| (stroke, brush))
+            .collect())
+    }
+
+    /// Same as `formatted`, with the trace and the context each stroke comes from
+    pub fn formatted_with_sources(
+        self,
+        options: &FormatOptions,
+    ) -> anyhow::Result<Vec<(FormattedStroke, Brush, StrokeSource)>> {
+        Ok(self
+            .formatted_with_groups(options)?
+            .1
+            .into_iter()
+            .map(|(_, source, stroke, brush)| (stroke, brush, source))
+            .collect())
+    }
+
+    /// Converts the traces to strokes grouped by the `traceGroup` elements
+    /// they are in, with the annotations of the groups
+    pub fn formatted_tree(self, options: &FormatOptions) -> anyhow::Result<Vec<InkNode>> {
+        let (groups, strokes) = self.formatted_with_groups(options)?;
+        Ok(build_tree(groups, strokes))
+    }
+
+    /// strokes with the index of their group and the id of their trace
+    pub(crate) fn formatted_with_groups(
+        self,
+        options: &FormatOptions,
+    ) -> anyhow::Result<(Vec<GroupInfo>, Vec<GroupedStroke>)> {
+        let ParserResult {
+            context_brush_data_vec: strokes,
+            context_dict,
+            context_brush: brushes_dict,
+            groups,
+            timestamps: timestamps_dict,
+            ..
+        } = self;
+        let mut formatted_result: Vec<GroupedStroke> = Vec::with_capacity(strokes.len());
+        // shared by the sources of the strokes
+        let context_dict: HashMap<String, Arc<Context>> = context_dict
+            .into_iter()
+            .map(|(id, context)| (id, Arc::new(context)))
+            .collect();
+
+        // iterate over results
+        // (the raw data of each trace is dropped once it's converted)
+        for RawTrace {
+            id,
+            context_id: context_str,
+            brush_id: brush_str,
+            trace_type,
+            time_offset,
+            duration,
+            group,
+            continuation,
+            prior_ref,
+            data: stroke,
+        } in strokes
+        {
+            if trace_type == TraceType::PenUp && !options.include_pen_up {
+                continue;
+            }
+            let context = context_dict
+                .get(&*context_str)
+                .ok_or_else(|| anyhow!("Could not find the context"))?;
+            // cheap clone : the name of the brush is shared
+            let brush = brushes_dict
+                .get(&*brush_str)
+                .ok_or_else(|| anyhow!("Could not find the brush"))?
+                .clone();
+
+            // verify X, Y exist
+            let (x_idx, y_idx) = (
+                context.channel_exists(ChannelKind::X),
+                context.channel_exists(ChannelKind::Y),
+            );
+            let f_idx = context.channel_exists(ChannelKind::F);
+
+            if stroke.len() != context.channel_list.len() {
+                return Err(anyhow!(
+                    "The trace has {} channels but its context {context_str} has {}",
+                    stroke.len(),
+                    context.channel_list.len()
+                ));
+            }
+
+            if let (Some(x_idx), Some(y_idx)) = (x_idx, y_idx) {
+                // scaled to cm for X and Y and to 0 - 1 for F
+                // Y is in screen orientation unless overridden
+                let channels = &context.channel_list;
+                let y_upward = context.y_upward();
+                let flip = if options.flip_y.unwrap_or(y_upward) != y_upward {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let formatted = FormattedStroke {
+                    x: channels[x_idx].formatted_values(&stroke[x_idx]).collect(),
+                    y: channels[y_idx]
+                        .formatted_values(&stroke[y_idx])
+                        .map(|y| flip * y)
+                        .collect(),
+                    f: if let Some(f_idx) = f_idx {
+                        channels[f_idx].formatted_values(&stroke[f_idx]).collect()
                     } else {
-                        stroke
-                            .get(x_idx.unwrap())
-                            .unwrap()
-                            .cast_to_float(1.0)
-                            .into_iter()
-                            .map(|_| 1.0)
-                            .collect()
+                        vec![1.0; stroke[x_idx].len()]
                     },
-                },
-                brush,
-            ));
+                    trace_type,
+                    t: timestamps(
+                        context,
+                        &timestamps_dict,
+                        &stroke,
+                        x_idx,
+                        time_offset,
+                        duration,
+                    ),
+                };
+
+                // the next parts of a continued trace are added to the first one
+                let previous_part = match (continuation, &prior_ref) {
+                    (Some(Continuation::Middle | Continuation::End), Some(prior_ref))
+                        if !options.split_continuations =>
+                    {
+                        formatted_result
+                            .iter_mut()
+                            .rev()
+                            .find(|(_, source, _, _)| source.trace_id.as_ref() == Some(prior_ref))
+                    }
+                    _ => None,
+                };
+                match previous_part {
+                    Some((_, previous_source, previous, _)) => {
+                        previous.append(formatted);
+                        // the following part refers to this one
+                        previous_source.trace_id = id;
+                    }
+                    None => {
+                        let source = StrokeSource {
+                            trace_id: id,
+                            context: context.clone(),
+                        };
+                        formatted_result.push((group, source, formatted, brush))
+                    }
+                }
+            }
+        }
+
+        // clipped once the continued traces are joined
+        if let Some(rect) = &options.clip {
+            formatted_result = formatted_result
+                .into_iter()
+                .flat_map(|(group, source, formatted, brush)| {
+                    formatted
+                        .clip(rect)
+                        .into_iter()
+                        .map(move |part| (group, source.clone(), part, brush.clone()))
+                })
+                .collect();
+        }
+
+        Ok((groups, formatted_result))
+    }
+
+    /// Splits the traces where one of their state channels (tip switch `S`,
+    /// buttons `B1`, `B2`, ...) changes, for devices giving a single long trace.
+    /// The parts where the tip switch is off become pen up traces (skipped by
+    /// `formatted` unless `FormatOptions::include_pen_up` is set)
+    pub fn split_by_state_channel(self) -> ParserResult {
+        let context_dict = self.context_dict;
+        let context_brush_data_vec = self
+            .context_brush_data_vec
+            .into_iter()
+            .flat_map(|trace| match context_dict.get(&*trace.context_id) {
+                Some(context) => trace.split_by_state(context),
+                None => vec![trace],
+            })
+            .collect();
+        ParserResult {
+            context_brush_data_vec,
+            context_dict,
+            context_brush: self.context_brush,
+            extensions: self.extensions,
+            groups: self.groups,
+            annotations: self.annotations,
+            timestamps: self.timestamps,
+            symbols: self.symbols,
+            truncated: self.truncated,
+            document_id: self.document_id,
+            lang: self.lang,
+            #[cfg(feature = "emma")]
+            emma: self.emma,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "fast-xml")]
+    #[test]
+    fn both_backends_give_the_same_result() {
+        use super::*;
+        use crate::xml_encoding::Utf8Reader;
+        use crate::xml_source::{QuickXmlSource, XmlRsSource};
+
+        let options = ParserOptions::default();
+        for entry in std::fs::read_dir("test_files").unwrap() {
+            let path = entry.unwrap().path();
+            let document = std::fs::read(&path).unwrap();
+            let xml_rs = parse_source(
+                XmlRsSource::new(Utf8Reader::new(document.as_slice())),
+                &options,
+            );
+            let quick_xml = parse_source(
+                QuickXmlSource::new(BufReader::new(Utf8Reader::new(document.as_slice()))),
+                &options,
+            );
+            match (xml_rs, quick_xml) {
+                (Ok(xml_rs), Ok(quick_xml)) => {
+                    assert!(
+                        xml_rs == quick_xml,
+                        "{} : different results",
+                        path.display()
+                    )
+                }
+                // (the files the parser rejects are rejected by both)
+                (Err(_), Err(_)) => {}
+                (xml_rs, quick_xml) => panic!(
+                    "{} : {:?} with xml-rs, {:?} with quick-xml",
+                    path.display(),
+                    xml_rs.err(),
+                    quick_xml.err()
+                ),
+            }
+        }
+    }
+
+    #[cfg(feature = "fast-xml")]
+    #[test]
+    fn bytes_read_in_place_give_the_same_result() {
+        use super::*;
+
+        let document = std::fs::read("test_files/correct.xml").unwrap();
+        let expected = parser(BufReader::new(document.as_slice())).unwrap();
+        assert!(parse_bytes(&document).unwrap() == expected);
+
+        // with a BOM
+        let with_bom = [&[0xEF, 0xBB, 0xBF], document.as_slice()].concat();
+        assert!(parse_bytes(&with_bom).unwrap() == expected);
+
+        // converted to UTF-8 first
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(
+                String::from_utf8(document)
+                    .unwrap()
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes),
+            )
+            .collect();
+        assert!(parse_bytes(&utf16).unwrap() == expected);
+    }
+
+    #[test]
+    fn traces_use_the_references_of_their_groups() {
+        use super::*;
+
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <context xml:id="ctx0">
+            <traceFormat>
+                <channel name="X" type="integer" />
+                <channel name="Y" type="integer" />
+            </traceFormat>
+        </context>
+        <context xml:id="ctx1">
+            <traceFormat>
+                <channel name="Y" type="integer" />
+                <channel name="X" type="integer" />
+            </traceFormat>
+        </context>
+        <brush xml:id="br0" />
+        <brush xml:id="br1" />
+    </definitions>
+    <traceGroup contextRef="#ctx1" brushRef="#br1">
+        <trace>1 2</trace>
+        <traceGroup brushRef="#br0">
+            <trace>3 4</trace>
+            <trace contextRef="#ctx0" brushRef="#br1">5 6</trace>
+        </traceGroup>
+    </traceGroup>
+    <trace brushRef="#br0">7 8</trace>
+</ink>"##;
+        let sources = parse_str(document)
+            .unwrap()
+            .formatted_with_sources(&FormatOptions::default())
+            .unwrap();
+        let references: Vec<(&str, &str)> = sources
+            .iter()
+            .map(|(_, brush, source)| (source.context_id(), brush.name()))
+            .collect();
+        assert_eq!(
+            references,
+            [
+                ("ctx1", "br1"),
+                ("ctx1", "br0"),
+                ("ctx0", "br1"),
+                ("ctx0", "br0")
+            ]
+        );
+    }
+
+    #[test]
+    fn brushes_inherit_from_their_brush_ref() {
+        use super::*;
+
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <brush xml:id="base">
+            <brushProperty name="width" value="0.1" units="cm" />
+            <brushProperty name="color" value="#FF0000" />
+            <brushProperty name="ignorePressure" value="true" />
+        </brush>
+        <brush xml:id="blue" brushRef="#base">
+            <brushProperty name="color" value="#0000FF" />
+        </brush>
+        <brush xml:id="thin" brushRef="#blue">
+            <brushProperty name="width" value="0.05" units="cm" />
+        </brush>
+    </definitions>
+</ink>"##;
+        let result = parse_str(document).unwrap();
+        let brushes = result.brushes();
+
+        let blue = &brushes["blue"];
+        assert_eq!(blue.name(), "blue");
+        assert_eq!(blue.color.to_rgb(), (0, 0, 255));
+        assert_eq!(blue.stroke_width_cm(), 0.1);
+        assert!(blue.ignorepressure);
+
+        // the width replaces the inherited one, even when it's smaller
+        let thin = &brushes["thin"];
+        assert_eq!(thin.color.to_rgb(), (0, 0, 255));
+        assert_eq!(thin.width_cm, 0.05);
+        // the base brush is unchanged
+        assert_eq!(brushes["base"].color.to_rgb(), (255, 0, 0));
+    }
+
+    #[test]
+    fn brushes_inheriting_from_a_missing_brush_are_rejected() {
+        use super::*;
+
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <brush xml:id="br0" brushRef="#missing" />
+    </definitions>
+</ink>"##;
+        assert!(parse_str(document).is_err());
+    }
+
+    /// two contexts with the id `ctx0`, the trace refers to the second one
+    const DUPLICATE_CONTEXTS: &str = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <context xml:id="ctx0">
+            <traceFormat>
+                <channel name="X" type="integer" />
+                <channel name="Y" type="integer" />
+            </traceFormat>
+        </context>
+        <context xml:id="ctx0">
+            <traceFormat>
+                <channel name="Y" type="integer" />
+                <channel name="X" type="integer" />
+            </traceFormat>
+        </context>
+    </definitions>
+    <trace contextRef="#ctx0">1 2</trace>
+</ink>"##;
+
+    #[test]
+    fn duplicate_contexts_are_reported_with_their_id() {
+        use super::*;
+
+        let error = parse_str(DUPLICATE_CONTEXTS).unwrap_err().to_string();
+        assert!(error.contains("DuplicateKeyError"), "{error}");
+        assert!(error.contains("the context ctx0 at"), "{error}");
+    }
+
+    #[test]
+    fn duplicate_contexts_are_renamed_in_lenient_mode() {
+        use super::*;
+
+        let options = ParserOptions::default().strict(false);
+        let result = parser_with_options(DUPLICATE_CONTEXTS.as_bytes(), &options).unwrap();
+        assert_eq!(result.contexts().len(), 2);
+        assert!(result.context("ctx0_1").is_some());
+
+        // the reference following the renamed context goes to it (X and Y swapped)
+        let strokes = result
+            .formatted_with_sources(&FormatOptions::default())
+            .unwrap();
+        assert_eq!(strokes[0].2.context_id(), "ctx0_1");
+        assert_eq!(
+            (strokes[0].0.x(), strokes[0].0.y()),
+            (&[0.002][..], &[0.001][..])
+        );
+    }
+
+    #[test]
+    fn trace_text_split_in_several_events_is_decoded_at_once() {
+        use super::*;
+
+        // the differences go on across the CDATA section, the comment and the
+        // character reference
+        let document = r#"<ink xmlns="http://www.w3.org/2003/InkML">
+    <traceFormat>
+        <channel name="X" type="integer" />
+        <channel name="Y" type="integer" />
+    </traceFormat>
+    <trace>10 10,'1'2<![CDATA[,'1'2]]><!-- comment -->,"0"0,&#34;0"1</trace>
+</ink>"#;
+        let result = parse_str(document).unwrap();
+        assert_eq!(
+            result.context_brush_data_vec[0].data,
+            [
+                ChannelData::Integer(vec![10, 11, 12, 13, 14]),
+                ChannelData::Integer(vec![10, 12, 14, 16, 19])
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_in_cdata_sections_are_read() {
+        use super::*;
+
+        let document = r#"<ink xmlns="http://www.w3.org/2003/InkML">
+    <traceFormat>
+        <channel name="X" type="integer" />
+        <channel name="Y" type="integer" />
+    </traceFormat>
+    <trace><![CDATA[10 10, 20 30]]></trace>
+</ink>"#;
+        let strokes = parse_formatted_str(document).unwrap();
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].0.x(), [0.01, 0.02]);
+        assert_eq!(strokes[0].0.y(), [0.01, 0.03]);
+    }
+
+    #[test]
+    fn times_are_relative_to_the_timestamp_of_the_channel() {
+        use super::*;
+
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <timestamp xml:id="ts0" time="1700000000000" />
+        <timestamp xml:id="ts1" timestampRef="#ts0" timeOffset="500" />
+        <timestamp xml:id="unknown" timestampRef="#missing" />
+        <context xml:id="ctx0">
+            <traceFormat>
+                <channel name="X" type="integer" />
+                <channel name="Y" type="integer" />
+                <channel name="T" type="integer" units="ms" respectTo="#ts1" />
+            </traceFormat>
+        </context>
+    </definitions>
+    <trace>0 0 0, 1 1 10, 2 2 25</trace>
+</ink>"##;
+        let result = parse_str(document).unwrap();
+        let timestamps = result.timestamps().clone();
+        assert_eq!(timestamps.len(), 2);
+        assert_eq!(timestamps["ts1"], 1700000000500.0);
+        assert_eq!(
+            result.context("ctx0").unwrap().channel_list[2].respect_to(),
+            Some("ts1")
+        );
+
+        let strokes = result.clone().formatted(&FormatOptions::default()).unwrap();
+        assert_eq!(
+            strokes[0].0.t().unwrap(),
+            [1700000000500.0, 1700000000510.0, 1700000000525.0]
+        );
+
+        // written back with the timestamps and the reference
+        let reparsed = parse_bytes(&crate::writer::write_parser_result(&result).unwrap()).unwrap();
+        assert_eq!(reparsed.timestamps(), &timestamps);
+        assert_eq!(
+            reparsed.context("ctx0").unwrap().channel_list[2].respect_to(),
+            Some("ts1")
+        );
+    }
+
+    /// two brushes `br0`, the second one `color` (identical with `#0000FF`),
+    /// and a trace using `br0` after them
+    fn duplicate_brushes(color: &str) -> String {
+        format!(
+            r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <context xml:id="ctx0">
+            <traceFormat>
+                <channel name="X" type="integer" />
+                <channel name="Y" type="integer" />
+            </traceFormat>
+        </context>
+        <brush xml:id="br0"><brushProperty name="color" value="#0000FF" /></brush>
+        <brush xml:id="br0"><brushProperty name="color" value="{color}" /></brush>
+    </definitions>
+    <trace brushRef="#br0">1 2</trace>
+</ink>"##
+        )
+    }
 
-    Ok(formatted_result)
+    #[test]
+    fn duplicate_brushes_follow_the_policy() {
+        use super::*;
+
+        let parse = |color: &str, policy: DuplicatePolicy| {
+            let options = ParserOptions::default().duplicates(policy);
+            parser_with_options(duplicate_brushes(color).as_bytes(), &options)
+        };
+        let trace_brush = |result: ParserResult| {
+            let strokes = result.formatted(&FormatOptions::default()).unwrap();
+            (String::from(strokes[0].1.name()), strokes[0].1.color)
+        };
+
+        let error = parse("#0000FF", DuplicatePolicy::Error).unwrap_err();
+        assert!(error.to_string().contains("the brush br0 at"), "{error}");
+
+        // identical definitions are read once
+        let result = parse("#0000FF", DuplicatePolicy::IgnoreIdentical).unwrap();
+        assert_eq!(result.brushes().len(), 1);
+        assert_eq!(
+            trace_brush(result),
+            (String::from("br0"), Color::rgb(0, 0, 255))
+        );
+        assert!(parse("#FF0000", DuplicatePolicy::IgnoreIdentical).is_err());
+
+        // the trace refers to the last definition
+        let result = parse("#FF0000", DuplicatePolicy::Rename).unwrap();
+        assert_eq!(result.brushes().len(), 2);
+        assert_eq!(
+            trace_brush(result),
+            (String::from("br0_1"), Color::rgb(255, 0, 0))
+        );
+    }
+
+    #[test]
+    fn identical_contexts_are_read_once() {
+        use super::*;
+
+        let options = ParserOptions::default().duplicates(DuplicatePolicy::IgnoreIdentical);
+        let identical = DUPLICATE_CONTEXTS.replacen(
+            r#"<channel name="Y" type="integer" />
+                <channel name="X" type="integer" />"#,
+            r#"<channel name="X" type="integer" />
+                <channel name="Y" type="integer" />"#,
+            1,
+        );
+        let result = parser_with_options(identical.as_bytes(), &options).unwrap();
+        assert_eq!(result.contexts().len(), 1);
+        assert!(parser_with_options(DUPLICATE_CONTEXTS.as_bytes(), &options).is_err());
+    }
 }
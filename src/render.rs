@@ -0,0 +1,145 @@
+// rasterisation of strokes, for thumbnails and previews
+
+use crate::brushes::{Brush, RasterOp};
+use crate::context::CM_PER_INCH;
+use crate::rendering::{variable_width_polyline, PressureCurve};
+use crate::trace_data::FormattedStroke;
+use anyhow::anyhow;
+use image::RgbaImage;
+use tiny_skia::{
+    BlendMode, FillRule, LineCap, Paint, PathBuilder, Pixmap, PixmapPaint, Stroke, Transform,
+};
+
+/// Draws the strokes to an image with a resolution of `dpi` pixels per inch
+///
+/// The image covers the bounding box of the strokes (including their width).
+/// The stroke width follows the pressure unless the brush ignores it, and the
+/// brush opacity is applied (see `Brush::effective_style`)
+pub fn to_image(strokes: &[(FormattedStroke, Brush)], dpi: f64) -> anyhow::Result<RgbaImage> {
+    if !(dpi.is_finite() && dpi > 0.0) {
+        return Err(anyhow!("The dpi should be a positive number, found {dpi}"));
+    }
+    let px_per_cm = dpi / CM_PER_INCH;
+
+    // bounding box in cm, including half of the stroke width on each side
+    let (min_x, min_y, max_x, max_y) = strokes.iter().fold(
+        (
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ),
+        |acc, (stroke, brush)| {
+            let half_width = brush.effective_style().width_cm / 2.0;
+            stroke.x.iter().zip(&stroke.y).fold(acc, |acc, (x, y)| {
+                (
+                    acc.0.min(x - half_width),
+                    acc.1.min(y - half_width),
+                    acc.2.max(x + half_width),
+                    acc.3.max(y + half_width),
+                )
+            })
+        },
+    );
+    let (width, height) = if min_x <= max_x && min_y <= max_y {
+        (
+            ((max_x - min_x) * px_per_cm).ceil().max(1.0) as u32,
+            ((max_y - min_y) * px_per_cm).ceil().max(1.0) as u32,
+        )
+    } else {
+        // no points to draw
+        (1, 1)
+    };
+
+    let mut pixmap = Pixmap::new(width, height).ok_or(anyhow!(
+        "Could not create an image of {width}x{height} pixels"
+    ))?;
+    let transform = Transform::from_scale(px_per_cm as f32, px_per_cm as f32)
+        .pre_translate(-min_x as f32, -min_y as f32);
+
+    for (stroke, brush) in strokes {
+        let style = brush.effective_style();
+        if style.alpha >= 1.0 {
+            draw_stroke(&mut pixmap, stroke, brush, transform);
+        } else {
+            // draw the stroke opaque on its own layer then blend the layer
+            // so that overlapping segments don't accumulate transparency
+            let mut layer = Pixmap::new(width, height).ok_or(anyhow!(
+                "Could not create an image of {width}x{height} pixels"
+            ))?;
+            draw_stroke(&mut layer, stroke, brush, transform);
+            pixmap.draw_pixmap(
+                0,
+                0,
+                layer.as_ref(),
+                &PixmapPaint {
+                    opacity: style.alpha,
+                    blend_mode: blend_mode(brush.raster_op),
+                    ..PixmapPaint::default()
+                },
+                Transform::identity(),
+                None,
+            );
+        }
+    }
+
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect::<Vec<u8>>();
+    RgbaImage::from_raw(width, height, pixels)
+        .ok_or(anyhow!("Could not convert the rendered pixels to an image"))
+}
+
+/// draws the stroke (opaque) segment per segment, each segment having
+/// the mean width of its two points
+fn draw_stroke(pixmap: &mut Pixmap, stroke: &FormattedStroke, brush: &Brush, transform: Transform) {
+    let style = brush.effective_style();
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(style.color.0, style.color.1, style.color.2, u8::MAX);
+    paint.anti_alias = true;
+    paint.blend_mode = blend_mode(brush.raster_op);
+
+    let points = variable_width_polyline(stroke, brush, PressureCurve::Linear)
+        .into_iter()
+        .map(|point| (point.x as f32, point.y as f32, point.width as f32))
+        .collect::<Vec<(f32, f32, f32)>>();
+
+    if let [(x, y, width)] = points[..] {
+        // single point : draw a dot
+        let mut path = PathBuilder::new();
+        path.push_circle(x, y, width / 2.0);
+        if let Some(path) = path.finish() {
+            pixmap.fill_path(&path, &paint, FillRule::Winding, transform, None);
+        }
+        return;
+    }
+
+    for segment in points.windows(2) {
+        let ((x0, y0, width0), (x1, y1, width1)) = (segment[0], segment[1]);
+        let mut path = PathBuilder::new();
+        path.move_to(x0, y0);
+        path.line_to(x1, y1);
+        if let Some(path) = path.finish() {
+            let stroke_style = Stroke {
+                width: (width0 + width1) / 2.0,
+                line_cap: LineCap::Round,
+                ..Stroke::default()
+            };
+            pixmap.stroke_path(&path, &paint, &stroke_style, transform, None);
+        }
+    }
+}
+
+/// closest blend mode to the raster operation of the brush
+/// (highlighters use `MaskPen`, which darkens what's below)
+fn blend_mode(raster_op: RasterOp) -> BlendMode {
+    match raster_op {
+        RasterOp::MaskPen => BlendMode::Multiply,
+        _ => BlendMode::SourceOver,
+    }
+}
@@ -0,0 +1,76 @@
+// pressure to width mapping, to draw strokes as variable width polylines
+// (the `render` module uses it for its images)
+
+use crate::brushes::Brush;
+use crate::trace_data::FormattedStroke;
+
+/// response of the width of the stroke to the pressure
+#[derive(Debug, Clone, Copy)]
+pub enum PressureCurve {
+    /// width proportional to the pressure
+    Linear,
+    /// light pressures give thin lines (width proportional to the squared pressure)
+    EaseIn,
+    /// close to what OneNote draws : the width goes from a quarter of the brush
+    /// width (no pressure) to the full width
+    OneNote,
+    /// width proportional to `pressure ^ gamma`
+    Gamma(f64),
+    /// any mapping from the pressure (0 - 1) to a factor of the brush width
+    Custom(fn(f64) -> f64),
+}
+
+impl PressureCurve {
+    /// factor applied to the width of the brush for a pressure between 0 and 1
+    /// (values outside of this range are clamped)
+    pub fn factor(&self, pressure: f64) -> f64 {
+        let pressure = pressure.clamp(0.0, 1.0);
+        match self {
+            PressureCurve::Linear => pressure,
+            PressureCurve::EaseIn => pressure * pressure,
+            PressureCurve::OneNote => 0.25 + 0.75 * pressure,
+            PressureCurve::Gamma(gamma) => pressure.powf(*gamma),
+            PressureCurve::Custom(curve) => curve(pressure),
+        }
+    }
+
+    /// width in cm of a point drawn with `brush` at this pressure
+    /// (the width of the brush if it ignores the pressure)
+    pub fn width_cm(&self, brush: &Brush, pressure: f64) -> f64 {
+        let width = brush.effective_style().width_cm;
+        if brush.ignorepressure {
+            width
+        } else {
+            width * self.factor(pressure)
+        }
+    }
+}
+
+/// a point of a variable width polyline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WidthPoint {
+    /// position in cm
+    pub x: f64,
+    pub y: f64,
+    /// width of the line at this point, in cm
+    pub width: f64,
+}
+
+/// Converts a stroke to a polyline with a width per point, following `curve`
+pub fn variable_width_polyline(
+    stroke: &FormattedStroke,
+    brush: &Brush,
+    curve: PressureCurve,
+) -> Vec<WidthPoint> {
+    stroke
+        .x
+        .iter()
+        .zip(&stroke.y)
+        .zip(&stroke.f)
+        .map(|((x, y), f)| WidthPoint {
+            x: *x,
+            y: *y,
+            width: curve.width_cm(brush, *f),
+        })
+        .collect()
+}
@@ -0,0 +1,160 @@
+// replay of the ink in the order it was drawn, to animate handwriting
+//
+// The timeline is built from the timestamps of the strokes (T channel,
+// `timeOffset`, sample rate) counted from the earliest one. Strokes without
+// timestamps get synthesised ones : they start after the end of the previous
+// strokes, their points being drawn at a fixed interval
+
+use crate::brushes::Brush;
+use crate::trace_data::{FormattedStroke, StrokePoint};
+
+/// timing of the replay
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// time between two points of a stroke without timestamps, in ms
+    pub(crate) point_interval: f64,
+    /// pause before a stroke without timestamps, in ms
+    pub(crate) stroke_gap: f64,
+    /// playback speed (2.0 : twice faster)
+    pub(crate) speed: f64,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            point_interval: 10.0,
+            stroke_gap: 200.0,
+            speed: 1.0,
+        }
+    }
+}
+
+impl ReplayOptions {
+    /// time between two points of strokes without timestamps, in ms (10 by default)
+    pub fn point_interval(mut self, point_interval: f64) -> Self {
+        self.point_interval = point_interval;
+        self
+    }
+
+    /// pause before strokes without timestamps, in ms (200 by default)
+    pub fn stroke_gap(mut self, stroke_gap: f64) -> Self {
+        self.stroke_gap = stroke_gap;
+        self
+    }
+
+    /// playback speed, 2.0 to replay twice faster (1.0 by default)
+    /// Speeds that are not positive are ignored
+    pub fn speed(mut self, speed: f64) -> Self {
+        if speed > 0.0 {
+            self.speed = speed;
+        }
+        self
+    }
+}
+
+/// a point to draw during the replay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayEvent {
+    /// index of the stroke in the input of the player
+    pub stroke_index: usize,
+    /// index of the point in its stroke (0 : the stroke begins)
+    pub point_index: usize,
+    pub point: StrokePoint,
+    /// time of the point from the start of the replay, in ms
+    pub time: f64,
+    /// time to wait since the previous event, in ms
+    pub delay: f64,
+}
+
+/// Gives the points of the strokes in time order, see `new`
+/// Used as an iterator, or with `advance_to` from an animation loop
+#[derive(Debug, Clone)]
+pub struct Player {
+    /// events in time order
+    events: Vec<ReplayEvent>,
+    /// index of the next event
+    position: usize,
+}
+
+impl Player {
+    /// timeline of the strokes (by their index in `strokes`)
+    pub fn new(strokes: &[(FormattedStroke, Brush)], options: &ReplayOptions) -> Player {
+        let origin = strokes
+            .iter()
+            .filter_map(|(stroke, _)| stroke.t().and_then(|t| t.first().copied()))
+            .reduce(f64::min)
+            .unwrap_or(0.0);
+
+        let mut events = vec![];
+        // end of the strokes placed so far
+        let mut end: Option<f64> = None;
+        for (stroke_index, (stroke, _)) in strokes.iter().enumerate() {
+            let start = end.map_or(0.0, |end| end + options.stroke_gap);
+            for (point_index, point) in stroke.iter().enumerate() {
+                let time = match point.t {
+                    Some(t) => t - origin,
+                    None => start + point_index as f64 * options.point_interval,
+                };
+                end = Some(end.map_or(time, |end| end.max(time)));
+                events.push(ReplayEvent {
+                    stroke_index,
+                    point_index,
+                    point,
+                    time: time / options.speed,
+                    delay: 0.0,
+                });
+            }
+        }
+        // stable : points at the same time keep the order of the strokes
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let mut previous = 0.0;
+        for event in &mut events {
+            event.delay = event.time - previous;
+            previous = event.time;
+        }
+        Player {
+            events,
+            position: 0,
+        }
+    }
+
+    /// length of the replay in ms
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0.0, |event| event.time)
+    }
+
+    /// time of the next event, None at the end of the replay
+    pub fn next_time(&self) -> Option<f64> {
+        self.events.get(self.position).map(|event| event.time)
+    }
+
+    /// events up to `time` (in ms from the start) not given yet, e.g. for each
+    /// frame of an animation
+    pub fn advance_to(&mut self, time: f64) -> &[ReplayEvent] {
+        let start = self.position;
+        self.position += self.events[start..].partition_point(|event| event.time <= time);
+        &self.events[start..self.position]
+    }
+
+    /// goes back to the start of the replay
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+}
+
+impl Iterator for Player {
+    type Item = ReplayEvent;
+
+    fn next(&mut self) -> Option<ReplayEvent> {
+        let event = self.events.get(self.position).copied();
+        self.position += usize::from(event.is_some());
+        event
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.events.len() - self.position;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Player {}
@@ -0,0 +1,68 @@
+// resolution of references pointing to another document
+// InkML allows `contextRef="defs.inkml#ctx1"` or `brushRef="defs.inkml#br1"`
+// where the definitions are stored in a separate file
+
+use crate::parser::{parser, ParserResult};
+use anyhow::anyhow;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+
+/// Loads the document an external reference points to
+///
+/// For a reference `defs.inkml#ctx1`, `resolve` is called with `defs.inkml`
+/// and the context `ctx1` is then taken from the returned result
+pub trait ReferenceResolver {
+    fn resolve(&self, uri: &str) -> anyhow::Result<ParserResult>;
+}
+
+/// Default resolver : any external reference is an error
+#[derive(Debug, Default, Clone)]
+pub struct NoReferenceResolver;
+
+impl ReferenceResolver for NoReferenceResolver {
+    fn resolve(&self, uri: &str) -> anyhow::Result<ParserResult> {
+        Err(anyhow!(
+            "The document refers to the external document {uri} but no ReferenceResolver was given to load it"
+        ))
+    }
+}
+
+/// Resolver loading the referenced documents from the filesystem
+/// Uris are taken relative to `base_dir`, the ones leading outside of it
+/// (absolute paths, `..`) are rejected
+///
+/// Remark : the loaded documents are parsed without a resolver, so
+/// they can't themselves refer to other documents
+#[derive(Debug, Clone)]
+pub struct FileSystemResolver {
+    base_dir: PathBuf,
+}
+
+impl FileSystemResolver {
+    pub fn new(base_dir: impl Into<PathBuf>) -> FileSystemResolver {
+        FileSystemResolver {
+            base_dir: base_dir.into(),
+        }
+    }
+}
+
+impl ReferenceResolver for FileSystemResolver {
+    fn resolve(&self, uri: &str) -> anyhow::Result<ParserResult> {
+        // a document can't read files outside of the base directory
+        if Path::new(uri).components().any(|component| {
+            matches!(
+                component,
+                Component::RootDir | Component::Prefix(_) | Component::ParentDir
+            )
+        }) {
+            return Err(anyhow!(
+                "The referenced document {uri} is outside of the base directory of the resolver"
+            ));
+        }
+        let path = self.base_dir.join(uri);
+        let file = File::open(&path)
+            .map_err(|e| anyhow!("{e} : Could not open the referenced document {:?}", path))?;
+        parser(BufReader::new(file))
+    }
+}
@@ -0,0 +1,142 @@
+// index of the strokes by position, so that eraser or lasso tools only
+// test the strokes near the pointer instead of all of them
+//
+// Uniform grid : each stroke is put in the cells its bounding box (grown by
+// half of the width of its brush) overlaps. Strokes covering too many cells
+// are kept aside and tested by every query
+
+use std::collections::HashMap;
+
+use crate::brushes::Brush;
+use crate::trace_data::{BoundingBox, FormattedStroke};
+
+/// strokes covering more cells are kept in a list tested by every query
+const MAX_CELLS_PER_STROKE: u64 = 64;
+
+/// index of strokes by position, see `build`
+#[derive(Debug, Clone)]
+pub struct SpatialIndex {
+    /// size of the cells, in cm
+    cell_size: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    /// strokes covering too many cells
+    large: Vec<usize>,
+    /// bounding box of each stroke grown by half of its width (None : no point)
+    boxes: Vec<Option<BoundingBox>>,
+}
+
+/// number of cells between the two cells (included), saturated
+fn cell_count((min_col, min_row): (i64, i64), (max_col, max_row): (i64, i64)) -> u64 {
+    let columns = max_col.abs_diff(min_col).saturating_add(1);
+    let rows = max_row.abs_diff(min_row).saturating_add(1);
+    columns.saturating_mul(rows)
+}
+
+impl SpatialIndex {
+    /// Indexes the strokes (by their index in `strokes`), the cells being about
+    /// the size of an average stroke
+    pub fn build(strokes: &[(FormattedStroke, Brush)]) -> SpatialIndex {
+        let boxes: Vec<Option<BoundingBox>> = strokes
+            .iter()
+            .map(|(stroke, brush)| {
+                stroke
+                    .bounding_box()
+                    .map(|bounding_box| bounding_box.grown(brush.stroke_width_cm() / 2.0))
+            })
+            .collect();
+        let (total_size, count) =
+            boxes
+                .iter()
+                .flatten()
+                .fold((0.0, 0), |(total_size, count), bounding_box| {
+                    let size = (bounding_box.max_x - bounding_box.min_x)
+                        .max(bounding_box.max_y - bounding_box.min_y);
+                    (total_size + size, count + 1)
+                });
+        let cell_size = match total_size / f64::from(count.max(1)) {
+            size if size.is_finite() && size > 0.0 => size,
+            _ => 1.0,
+        };
+
+        let mut index = SpatialIndex {
+            cell_size,
+            cells: HashMap::new(),
+            large: vec![],
+            boxes,
+        };
+        for (stroke_index, bounding_box) in index.boxes.iter().enumerate() {
+            let Some(bounding_box) = bounding_box else {
+                continue;
+            };
+            let (min, max) = index.cell_range(bounding_box);
+            if cell_count(min, max) > MAX_CELLS_PER_STROKE {
+                index.large.push(stroke_index);
+                continue;
+            }
+            for col in min.0..=max.0 {
+                for row in min.1..=max.1 {
+                    index
+                        .cells
+                        .entry((col, row))
+                        .or_default()
+                        .push(stroke_index);
+                }
+            }
+        }
+        index
+    }
+
+    /// cells of the corners of the box
+    fn cell_range(&self, bounding_box: &BoundingBox) -> ((i64, i64), (i64, i64)) {
+        // (casts saturate, NaN gives 0)
+        let cell = |x: f64, y: f64| {
+            (
+                (x / self.cell_size).floor() as i64,
+                (y / self.cell_size).floor() as i64,
+            )
+        };
+        (
+            cell(bounding_box.min_x, bounding_box.min_y),
+            cell(bounding_box.max_x, bounding_box.max_y),
+        )
+    }
+
+    /// Strokes (their index, sorted) with their bounding box grown by half of
+    /// the width of their brush overlapping `rect`
+    pub fn query_rect(&self, rect: &BoundingBox) -> Vec<usize> {
+        let (min, max) = self.cell_range(rect);
+        let mut found: Vec<usize> = if cell_count(min, max) > self.cells.len() as u64 {
+            // faster to test all of them
+            (0..self.boxes.len()).collect()
+        } else {
+            let mut found = self.large.clone();
+            for col in min.0..=max.0 {
+                for row in min.1..=max.1 {
+                    if let Some(strokes) = self.cells.get(&(col, row)) {
+                        found.extend(strokes);
+                    }
+                }
+            }
+            found.sort_unstable();
+            found.dedup();
+            found
+        };
+        found.retain(|index| {
+            self.boxes[*index].is_some_and(|bounding_box| bounding_box.intersects(rect))
+        });
+        found
+    }
+
+    /// Strokes (their index, sorted) with their bounding box grown by half of
+    /// the width of their brush at most `radius` cm from the point
+    /// This is a test on the boxes : the strokes can be further away
+    /// (see `FormattedStroke::hit_test` to test them)
+    pub fn query_point(&self, x: f64, y: f64, radius: f64) -> Vec<usize> {
+        let rect = BoundingBox::new(x - radius, y - radius, x + radius, y + radius);
+        let mut found = self.query_rect(&rect);
+        found.retain(|index| {
+            self.boxes[*index].is_some_and(|bounding_box| bounding_box.distance_to(x, y) <= radius)
+        });
+        found
+    }
+}
@@ -0,0 +1,28 @@
+// interning of the ids of contexts and brushes : each trace refers to them,
+// a table shared by the `ParserResult` gives one allocation per id instead
+// of one per trace (and clones of the ids are then cheap)
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// an interned id
+pub(crate) type Symbol = Arc<str>;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SymbolTable {
+    symbols: HashSet<Symbol>,
+}
+
+impl SymbolTable {
+    /// the symbol of `id`, added to the table if it's a new one
+    pub(crate) fn intern(&mut self, id: &str) -> Symbol {
+        match self.symbols.get(id) {
+            Some(symbol) => symbol.clone(),
+            None => {
+                let symbol: Symbol = Arc::from(id);
+                self.symbols.insert(symbol.clone());
+                symbol
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// round trip checks for the applications embedding the crate : a document
+// is parsed, written, parsed again and the strokes are compared
+//
+// Both writers are checked : `write_parser_result` (keeping the contexts
+// and brushes of the document) and `writer` (from the strokes, with a T
+// channel when they have timestamps)
+
+use anyhow::anyhow;
+use std::path::Path;
+
+use crate::brushes::{Brush, QuantizedSize};
+use crate::context::{Channel, ChannelKind, ChannelType, Context, Resolution};
+use crate::options::{FormatOptions, WriterConfig};
+use crate::parser::{parse_formatted, parser, ParserResult};
+use crate::trace_data::FormattedStroke;
+use crate::writer::{write_parser_result, writer_with_config};
+
+/// largest differences accepted between the strokes before and after the round trip
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerances {
+    /// positions, in cm
+    pub position_cm: f64,
+    /// pressures (between 0 and 1)
+    pub pressure: f64,
+    /// timestamps, in ms
+    pub time_ms: f64,
+}
+
+impl Default for Tolerances {
+    /// half of the resolution of the default context of the writer
+    /// (1/1000 cm) with some margin
+    fn default() -> Self {
+        Tolerances {
+            position_cm: 0.001,
+            pressure: 0.001,
+            time_ms: 1.0,
+        }
+    }
+}
+
+/// first difference beyond the tolerances between two lists of values
+fn compare_values(name: &str, before: &[f64], after: &[f64], tolerance: f64) -> anyhow::Result<()> {
+    if before.len() != after.len() {
+        return Err(anyhow!(
+            "{name} : {} values before, {} after",
+            before.len(),
+            after.len()
+        ));
+    }
+    match before
+        .iter()
+        .zip(after)
+        .enumerate()
+        .find(|(_, (before, after))| (*before - *after).abs() > tolerance)
+    {
+        Some((index, (before, after))) => Err(anyhow!(
+            "{name} of point {index} : {before} before, {after} after"
+        )),
+        None => Ok(()),
+    }
+}
+
+/// compares the properties of the brushes written to the file
+fn compare_brushes(before: &Brush, after: &Brush) -> anyhow::Result<()> {
+    let size = |brush: &Brush| {
+        (
+            QuantizedSize::new(brush.width_cm),
+            QuantizedSize::new(brush.height_cm),
+        )
+    };
+    if before.color != after.color
+        || size(before) != size(after)
+        || before.ignorepressure != after.ignorepressure
+        || before.tip != after.tip
+        || before.raster_op != after.raster_op
+        || before.fit_to_curve != after.fit_to_curve
+        || before.anti_aliased != after.anti_aliased
+    {
+        return Err(anyhow!("brush {before:?} became {after:?}"));
+    }
+    Ok(())
+}
+
+/// compares the contexts of the document with the ones written by `write_parser_result`
+/// (channels, units, bounds and sample rate)
+fn compare_contexts(before: &ParserResult, after: &ParserResult) -> anyhow::Result<()> {
+    for (name, context) in &before.context_dict {
+        let other = after
+            .context_dict
+            .get(name)
+            .ok_or_else(|| anyhow!("context {name} lost"))?;
+        let channels = |context: &Context| {
+            context
+                .channel_list
+                .iter()
+                .map(|channel| {
+                    (
+                        channel.kind.clone(),
+                        channel.types.clone(),
+                        channel.unit_channel.clone(),
+                        channel.min_value.clone(),
+                        channel.max_value.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        if channels(context) != channels(other) || context.sample_rate != other.sample_rate {
+            return Err(anyhow!("context {context:?} became {other:?}"));
+        }
+    }
+    Ok(())
+}
+
+/// compares the strokes before and after a round trip
+fn compare_strokes(
+    before: &[(FormattedStroke, Brush)],
+    after: &[(FormattedStroke, Brush)],
+    tolerances: &Tolerances,
+) -> anyhow::Result<()> {
+    if before.len() != after.len() {
+        return Err(anyhow!(
+            "{} strokes before, {} after",
+            before.len(),
+            after.len()
+        ));
+    }
+    for (index, ((stroke, brush), (other_stroke, other_brush))) in
+        before.iter().zip(after).enumerate()
+    {
+        let check = || -> anyhow::Result<()> {
+            compare_values("x", &stroke.x, &other_stroke.x, tolerances.position_cm)?;
+            compare_values("y", &stroke.y, &other_stroke.y, tolerances.position_cm)?;
+            compare_values("f", &stroke.f, &other_stroke.f, tolerances.pressure)?;
+            match (&stroke.t, &other_stroke.t) {
+                (Some(t), Some(other_t)) => compare_values("t", t, other_t, tolerances.time_ms)?,
+                (None, None) => {}
+                (Some(_), None) => return Err(anyhow!("timestamps lost")),
+                (None, Some(_)) => return Err(anyhow!("timestamps added")),
+            }
+            if stroke.trace_type != other_stroke.trace_type {
+                return Err(anyhow!("trace type changed"));
+            }
+            compare_brushes(brush, other_brush)
+        };
+        check().map_err(|e| anyhow!("stroke {index} : {e}"))?;
+    }
+    Ok(())
+}
+
+/// Parses the document, writes it with both writers, parses the output and
+/// compares the strokes with the original ones
+pub fn check_roundtrip(document: &[u8], tolerances: &Tolerances) -> anyhow::Result<()> {
+    let original = parser(document)?;
+    let strokes = original.clone().formatted(&FormatOptions::default())?;
+
+    let written = write_parser_result(&original)?;
+    let reparsed_document = parser(written.as_slice())
+        .map_err(|e| anyhow!("write_parser_result gave an invalid document : {e}"))?;
+    let check = || -> anyhow::Result<()> {
+        compare_contexts(&original, &reparsed_document)?;
+        let reparsed = reparsed_document
+            .clone()
+            .formatted(&FormatOptions::default())?;
+        compare_strokes(&strokes, &reparsed, tolerances)
+    };
+    check().map_err(|e| anyhow!("write_parser_result round trip : {e}"))?;
+
+    let mut config = WriterConfig::default();
+    if strokes.iter().any(|(stroke, _)| stroke.t.is_some()) {
+        let mut context = config.context();
+        context.channel_list.push(Channel::new(
+            ChannelKind::T,
+            ChannelType::Integer,
+            Resolution::per_ms(1.0),
+        ));
+        config = config.custom_context(context);
+    }
+    let written = writer_with_config(strokes.clone(), &config)?;
+    let reparsed = parse_formatted(written.as_slice())
+        .map_err(|e| anyhow!("writer gave an invalid document : {e}"))?;
+    compare_strokes(&strokes, &reparsed, tolerances).map_err(|e| anyhow!("writer round trip : {e}"))
+}
+
+/// Panics if the strokes of the file at `path` don't survive a round trip
+/// (see `check_roundtrip`), with the default tolerances
+pub fn assert_roundtrip<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    let document =
+        std::fs::read(path).unwrap_or_else(|e| panic!("could not read {} : {e}", path.display()));
+    if let Err(e) = check_roundtrip(&document, &Tolerances::default()) {
+        panic!("{} : {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_lost_or_added_are_differences() {
+        let brush = Brush::init(String::from("br0"), (0, 0, 0), false, 0, 0.1);
+        let stroke = FormattedStroke::new(vec![0.0, 1.0], vec![0.0, 1.0], vec![0.5, 0.5]).unwrap();
+        let timed = stroke.clone().with_timestamps(vec![0.0, 10.0]).unwrap();
+        let tolerances = Tolerances::default();
+
+        let with_t = [(timed, brush.clone())];
+        let without_t = [(stroke, brush)];
+        assert!(compare_strokes(&with_t, &with_t, &tolerances).is_ok());
+        assert!(compare_strokes(&without_t, &without_t, &tolerances).is_ok());
+        assert!(compare_strokes(&with_t, &without_t, &tolerances).is_err());
+        assert!(compare_strokes(&without_t, &with_t, &tolerances).is_err());
+    }
+
+    #[test]
+    fn every_brush_property_is_compared() {
+        let brush = Brush::init(String::from("br0"), (0, 0, 0), false, 0, 0.1);
+        assert!(compare_brushes(&brush, &brush.clone()).is_ok());
+
+        let fitted = Brush {
+            fit_to_curve: Some(true),
+            ..brush.clone()
+        };
+        assert!(compare_brushes(&brush, &fitted).is_err());
+        let anti_aliased = Brush {
+            anti_aliased: Some(false),
+            ..brush.clone()
+        };
+        assert!(compare_brushes(&brush, &anti_aliased).is_err());
+    }
+
+    #[test]
+    fn contexts_are_compared() {
+        let document = |max: &str, sample_rate: &str| {
+            format!(
+                r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <context xml:id="ctx0">
+            <inkSource xml:id="src0">
+                <traceFormat>
+                    <channel name="X" type="integer" units="cm" />
+                    <channel name="Y" type="integer" units="cm" />
+                    <channel name="F" type="integer" max="{max}" />
+                </traceFormat>
+                <sampleRate value="{sample_rate}" />
+            </inkSource>
+        </context>
+    </definitions>
+    <trace contextRef="#ctx0">0 0 512, 10 10 512</trace>
+</ink>"##
+            )
+        };
+        let parse = |document: String| parser(document.as_bytes()).unwrap();
+        let original = parse(document("1024", "100"));
+        assert!(compare_contexts(&original, &parse(document("1024", "100"))).is_ok());
+        assert!(compare_contexts(&original, &parse(document("2048", "100"))).is_err());
+        assert!(compare_contexts(&original, &parse(document("1024", "200"))).is_err());
+    }
+}
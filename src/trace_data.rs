@@ -4,40 +4,414 @@
 // even if these are default values
 // From the context we can define what the format of the data is
 
-use crate::{context::ChannelType, traits::Writable};
+use crate::brushes::Brush;
+use crate::codec::ChannelDataEl;
+pub(crate) use crate::codec::{format_trace, ChannelData, TraceData};
+use crate::context::{Channel, ChannelKind, ChannelType, Context, CM_PER_INCH};
+use crate::error::{MissingChannel, OutOfRangeValue};
+use crate::symbols::Symbol;
 use anyhow::anyhow;
-use tracing::trace;
-use xml::writer::XmlEvent;
+use std::ops::Range;
+use std::sync::Arc;
 
-/// polymorphic enum to hold the data from a trace before a resolution conversion
-#[derive(Debug, Clone)]
-pub enum ChannelData {
-    Integer(Vec<i64>),
-    Bool(Vec<bool>),
-    Double(Vec<f64>),
+/// `type` of a trace : whether the pen was touching the surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceType {
+    /// ink (default)
+    #[default]
+    PenDown,
+    /// hover data, the pen was above the surface
+    PenUp,
+    /// the device couldn't tell
+    Indeterminate,
 }
 
-impl ChannelData {
-    pub(crate) fn cast_to_float(&self, scaling: f64) -> Vec<f64> {
+impl TraceType {
+    pub(crate) fn parse(name: &Option<String>) -> anyhow::Result<TraceType> {
+        match name.as_deref() {
+            None | Some("penDown") => Ok(TraceType::PenDown),
+            Some("penUp") => Ok(TraceType::PenUp),
+            Some("indeterminate") => Ok(TraceType::Indeterminate),
+            Some(value) => Err(anyhow!("the trace type {value} was not found")),
+        }
+    }
+}
+
+impl From<TraceType> for String {
+    fn from(value: TraceType) -> Self {
+        match value {
+            TraceType::PenDown => String::from("penDown"),
+            TraceType::PenUp => String::from("penUp"),
+            TraceType::Indeterminate => String::from("indeterminate"),
+        }
+    }
+}
+
+/// `continuation` of a trace : part of a trace split in several elements
+/// (the parts after the first one refer to the previous part with `priorRef`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Continuation {
+    Begin,
+    Middle,
+    End,
+}
+
+impl Continuation {
+    pub(crate) fn parse(name: &Option<String>) -> anyhow::Result<Option<Continuation>> {
+        match name.as_deref() {
+            None => Ok(None),
+            Some("begin") => Ok(Some(Continuation::Begin)),
+            Some("middle") => Ok(Some(Continuation::Middle)),
+            Some("end") => Ok(Some(Continuation::End)),
+            Some(value) => Err(anyhow!("the continuation {value} was not found")),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
         match self {
-            ChannelData::Integer(int_vec) => int_vec.iter().map(|x| *x as f64 * scaling).collect(),
-            ChannelData::Bool(bool_vec) => bool_vec
-                .iter()
-                .map(|x| (if *x { 1.0 } else { 0.0 }) * scaling)
-                .collect(),
-            ChannelData::Double(double_vec) => double_vec.iter().map(|x| x * scaling).collect(),
+            Continuation::Begin => "begin",
+            Continuation::Middle => "middle",
+            Continuation::End => "end",
         }
     }
 }
 
-/// polymorhpic enum to hold the data from a point of the trace
-/// Only used for holding the last element or difference (in order to calculate
-/// 'x or "y)
-#[derive(Debug, Clone)]
-pub enum ChannelDataEl {
-    Integer(i64),
-    Double(f64),
-    Bool,
+/// a trace as read by the parser
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RawTrace {
+    /// id of the `trace` element
+    pub(crate) id: Option<String>,
+    /// ids interned in the `SymbolTable` of the `ParserResult`
+    pub(crate) context_id: Symbol,
+    pub(crate) brush_id: Symbol,
+    pub(crate) trace_type: TraceType,
+    /// `timeOffset` and `duration` attributes, in ms
+    pub(crate) time_offset: Option<f64>,
+    pub(crate) duration: Option<f64>,
+    /// index of the `traceGroup` of the trace in the `ParserResult`
+    pub(crate) group: Option<usize>,
+    /// `continuation` and `priorRef` (without the `#`) attributes
+    pub(crate) continuation: Option<Continuation>,
+    pub(crate) prior_ref: Option<String>,
+    /// The (raw) channel data, which
+    /// - Keeps the same order as the one given in the trace
+    /// - Keeps the same type (integer, boolean or double) as the
+    ///   one given in the trace definition
+    pub(crate) data: Vec<ChannelData>,
+}
+
+impl RawTrace {
+    /// bounding box of the trace in the coordinates of `parse_formatted`
+    /// (None without X and Y channels or without points)
+    pub(crate) fn bounding_box(&self, context: &Context) -> Option<BoundingBox> {
+        let x_idx = context.channel_exists(ChannelKind::X)?;
+        let y_idx = context.channel_exists(ChannelKind::Y)?;
+        let (x, y) = (self.data.get(x_idx)?, self.data.get(y_idx)?);
+        BoundingBox::from_points(
+            context.channel_list[x_idx]
+                .formatted_values(x)
+                .zip(context.channel_list[y_idx].formatted_values(y)),
+        )
+    }
+
+    /// keeps the data of the channels of `context` with a kind in `kinds` only
+    pub(crate) fn retain_channels(&mut self, context: &Context, kinds: &[ChannelKind]) {
+        let mut channels = context.channel_list.iter();
+        self.data.retain(|_| {
+            channels
+                .next()
+                .is_some_and(|channel| kinds.contains(&channel.kind))
+        });
+    }
+
+    /// splits the trace where one of the state channels of `context`
+    /// (tip switch `S`, buttons `B1`, `B2`, ...) changes
+    /// The parts where the tip switch is off become pen up traces
+    pub(crate) fn split_by_state(self, context: &Context) -> Vec<RawTrace> {
+        let len = self.data.iter().map(ChannelData::len).min().unwrap_or(0);
+        // values of each state channel, with whether it's the tip switch
+        let states = context
+            .channel_list
+            .iter()
+            .zip(&self.data)
+            .filter(|(channel, _)| channel.kind.is_state())
+            .map(|(channel, data)| {
+                (
+                    channel.kind == ChannelKind::S,
+                    data.cast_to_float(1.0)
+                        .map(|value| value != 0.0)
+                        .collect::<Vec<bool>>(),
+                )
+            })
+            .collect::<Vec<(bool, Vec<bool>)>>();
+        if states.is_empty() || len == 0 {
+            return vec![self];
+        }
+        let tip_down = states
+            .iter()
+            .find(|(is_tip, _)| *is_tip)
+            .map(|(_, values)| values);
+
+        let mut traces = vec![];
+        let mut start = 0;
+        for index in 1..=len {
+            if index < len
+                && states
+                    .iter()
+                    .all(|(_, values)| values[index] == values[index - 1])
+            {
+                continue;
+            }
+            // the parts start later than the trace (known with the sample rate)
+            let (time_offset, duration) = match context.sample_rate {
+                _ if start == 0 && index == len => (self.time_offset, self.duration),
+                Some(sample_rate) if sample_rate > 0.0 => (
+                    Some(self.time_offset.unwrap_or(0.0) + start as f64 * 1000.0 / sample_rate),
+                    None,
+                ),
+                _ => (self.time_offset, None),
+            };
+            let trace_type = match tip_down {
+                Some(tip_down) if !tip_down[start] => TraceType::PenUp,
+                _ => self.trace_type,
+            };
+            // ids of the parts derived from the trace one so that they stay unique,
+            // the last part keeps it : the next trace of a continuation refers to it
+            let id = match &self.id {
+                Some(id) if index < len => Some(format!("{id}_{}", traces.len())),
+                id => id.clone(),
+            };
+            // only the first part continues the previous trace
+            let (continuation, prior_ref) = match start {
+                0 => (self.continuation, self.prior_ref.clone()),
+                _ => (None, None),
+            };
+            traces.push(RawTrace {
+                id,
+                context_id: self.context_id.clone(),
+                brush_id: self.brush_id.clone(),
+                trace_type,
+                time_offset,
+                duration,
+                group: self.group,
+                continuation,
+                prior_ref,
+                data: self
+                    .data
+                    .iter()
+                    .map(|channel| channel.slice(start..index))
+                    .collect(),
+            });
+            start = index;
+        }
+        traces
+    }
+}
+
+/// axis aligned box (in cm for strokes, with Y going down)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl BoundingBox {
+    pub fn new(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> BoundingBox {
+        BoundingBox {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }
+    }
+
+    /// smallest box holding all the points, None if there is no point
+    pub(crate) fn from_points(points: impl Iterator<Item = (f64, f64)>) -> Option<BoundingBox> {
+        points.fold(None, |bounding_box, (x, y)| {
+            Some(match bounding_box {
+                None => BoundingBox::new(x, y, x, y),
+                Some(bounding_box) => BoundingBox::new(
+                    bounding_box.min_x.min(x),
+                    bounding_box.min_y.min(y),
+                    bounding_box.max_x.max(x),
+                    bounding_box.max_y.max(y),
+                ),
+            })
+        })
+    }
+
+    /// true if the boxes overlap (touching counts)
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min_x <= other.max_x
+            && other.min_x <= self.max_x
+            && self.min_y <= other.max_y
+            && other.min_y <= self.max_y
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_y..=self.max_y).contains(&y)
+    }
+
+    /// the box grown by `margin` on each side
+    pub fn grown(&self, margin: f64) -> BoundingBox {
+        BoundingBox::new(
+            self.min_x - margin,
+            self.min_y - margin,
+            self.max_x + margin,
+            self.max_y + margin,
+        )
+    }
+
+    /// distance from the point to the box (0 inside of it)
+    pub fn distance_to(&self, x: f64, y: f64) -> f64 {
+        let dx = (self.min_x - x).max(x - self.max_x).max(0.0);
+        let dy = (self.min_y - y).max(y - self.max_y).max(0.0);
+        dx.hypot(dy)
+    }
+}
+
+/// distance from the point to the segment from `a` to `b`
+fn segment_distance((x, y): (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    // position of the closest point on the segment
+    let t = if length_squared > 0.0 {
+        (((x - a.0) * dx + (y - a.1) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (x - (a.0 + t * dx)).hypot(y - (a.1 + t * dy))
+}
+
+/// where a segment crosses the border of the area kept of a stroke (as a
+/// fraction of the segment), see `FormattedStroke::kept_parts`
+enum Crossing {
+    Enter(f64),
+    Leave(f64),
+}
+
+/// fractions of the segment from `a` to `b` where it enters and leaves the
+/// circle, None if it doesn't go inside of it
+fn circle_crossing(
+    a: (f64, f64),
+    b: (f64, f64),
+    center: (f64, f64),
+    radius: f64,
+) -> Option<(f64, f64)> {
+    // |a + t (b - a) - center|² = radius²
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (ex, ey) = (a.0 - center.0, a.1 - center.1);
+    let quadratic = dx * dx + dy * dy;
+    let linear = 2.0 * (ex * dx + ey * dy);
+    let constant = ex * ex + ey * ey - radius * radius;
+    let discriminant = linear * linear - 4.0 * quadratic * constant;
+    if quadratic == 0.0 || discriminant.is_nan() || discriminant <= 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (enter, exit) = (
+        (-linear - root) / (2.0 * quadratic),
+        (-linear + root) / (2.0 * quadratic),
+    );
+    (exit > 0.0 && enter < 1.0).then_some((enter, exit))
+}
+
+/// true if the segment from `a` to `b` crosses the box
+fn segment_crosses(a: (f64, f64), b: (f64, f64), rect: &BoundingBox) -> bool {
+    rect_crossing(a, b, rect).is_some()
+}
+
+/// fractions of the segment from `a` to `b` where its part inside of the box
+/// starts and ends, None if it doesn't go inside of it (Liang-Barsky clipping)
+fn rect_crossing(a: (f64, f64), b: (f64, f64), rect: &BoundingBox) -> Option<(f64, f64)> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut start, mut end) = (0.0f64, 1.0f64);
+    for (direction, distance) in [
+        (-dx, a.0 - rect.min_x),
+        (dx, rect.max_x - a.0),
+        (-dy, a.1 - rect.min_y),
+        (dy, rect.max_y - a.1),
+    ] {
+        if direction == 0.0 {
+            // parallel to this side, outside of it
+            if distance < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let crossing = distance / direction;
+        if direction < 0.0 {
+            start = start.max(crossing);
+        } else {
+            end = end.min(crossing);
+        }
+        if start > end {
+            return None;
+        }
+    }
+    Some((start, end))
+}
+
+/// distance from the segment from `a` to `b` to the box (0 if they overlap)
+fn segment_rect_distance(a: (f64, f64), b: (f64, f64), rect: &BoundingBox) -> f64 {
+    if segment_crosses(a, b, rect) {
+        return 0.0;
+    }
+    // otherwise the closest points are an end of the segment or a corner of the box
+    [
+        (rect.min_x, rect.min_y),
+        (rect.max_x, rect.min_y),
+        (rect.min_x, rect.max_y),
+        (rect.max_x, rect.max_y),
+    ]
+    .into_iter()
+    .map(|corner| segment_distance(corner, a, b))
+    .fold(
+        rect.distance_to(a.0, a.1).min(rect.distance_to(b.0, b.1)),
+        f64::min,
+    )
+}
+
+impl ChannelData {
+    /// the values as floats multiplied by `scaling` (booleans are 0 or 1)
+    pub(crate) fn cast_to_float(&self, scaling: f64) -> impl Iterator<Item = f64> + '_ {
+        (0..self.len()).map(move |index| {
+            let value = match self {
+                ChannelData::Integer(int_vec) => int_vec[index] as f64,
+                ChannelData::Bool(bool_vec) => {
+                    if bool_vec[index] {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                ChannelData::Double(double_vec) => double_vec[index],
+            };
+            value * scaling
+        })
+    }
+}
+
+impl ChannelData {
+    /// `len` times `value` in type `types`
+    pub(crate) fn filled(types: &ChannelType, value: f64, len: usize) -> ChannelData {
+        match types {
+            ChannelType::Integer => ChannelData::Integer(vec![value as i64; len]),
+            ChannelType::Decimal | ChannelType::Double => ChannelData::Double(vec![value; len]),
+            ChannelType::Bool => ChannelData::Bool(vec![value != 0.0; len]),
+        }
+    }
+
+    /// copy of the values in `range`
+    pub(crate) fn slice(&self, range: Range<usize>) -> ChannelData {
+        match self {
+            ChannelData::Integer(int_vec) => ChannelData::Integer(int_vec[range].to_vec()),
+            ChannelData::Bool(bool_vec) => ChannelData::Bool(bool_vec[range].to_vec()),
+            ChannelData::Double(double_vec) => ChannelData::Double(double_vec[range].to_vec()),
+        }
+    }
 }
 
 impl ChannelDataEl {
@@ -50,392 +424,970 @@ impl ChannelDataEl {
     }
 }
 
-impl From<ChannelDataEl> for String {
-    fn from(value: ChannelDataEl) -> Self {
-        match value {
-            ChannelDataEl::Bool => String::from("1"),
-            ChannelDataEl::Double(value) => format!("{value}"),
-            ChannelDataEl::Integer(value) => format!("{value}"),
+/// where the pixel (0, 0) is for `FormattedStroke::to_pixels`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Origin {
+    /// at (0, 0) cm, Y going down
+    #[default]
+    Zero,
+    /// at the top left corner of an area in cm (e.g. the page or the bounds
+    /// of the strokes), Y going down as for GUI toolkits and web canvases
+    TopLeft(BoundingBox),
+    /// at the bottom left corner of an area in cm, Y going up (PDF, OpenGL)
+    BottomLeft(BoundingBox),
+}
+
+impl Origin {
+    /// pixels per cm, position of the pixel (0, 0) in cm and direction of Y
+    fn pixel_space(self, dpi: f32) -> (f64, (f64, f64), f64) {
+        let px_per_cm = f64::from(dpi) / CM_PER_INCH;
+        match self {
+            Origin::Zero => (px_per_cm, (0.0, 0.0), 1.0),
+            Origin::TopLeft(area) => (px_per_cm, (area.min_x, area.min_y), 1.0),
+            Origin::BottomLeft(area) => (px_per_cm, (area.min_x, area.max_y), -1.0),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 /// Type to hold a formatted stroke data
 /// - X as a float channel in cm unit
 /// - Y as a float channel in cm unit
 /// - F as a float channel in dev unit (from 0.0 to 1.0)
+/// - the type of the trace (pen down for ink, pen up for hover data)
+/// - T, the time of each point in ms (if known)
+///
+/// Built with `new`, which checks that each point has a value in each channel
 pub struct FormattedStroke {
-    pub x: Vec<f64>,
-    pub y: Vec<f64>,
-    pub f: Vec<f64>,
+    pub(crate) x: Vec<f64>,
+    pub(crate) y: Vec<f64>,
+    pub(crate) f: Vec<f64>,
+    pub(crate) trace_type: TraceType,
+    /// time of each point in ms, if known (T channel, or `timeOffset`
+    /// with the sample rate of the device or the `duration` of the trace)
+    pub(crate) t: Option<Vec<f64>>,
 }
 
-impl Writable for FormattedStroke {
-    fn write<W: std::io::Write>(
-        &self,
-        writer: &mut xml::EventWriter<W>,
-    ) -> Result<(), xml::writer::Error> {
-        // rem : we suppose that the context is the default with pressure one
-        // So resolution of 1000 of 1/cm in integer and
-        // F in dev unit between 0 and 32767
-
-        let string_out =
-            self.x
-                .iter()
-                .zip(&self.y)
-                .zip(&self.f)
-                .fold(String::from(""), |acc, ((x, y), f)| {
-                    let x_int = (x * 1000.0) as i64;
-                    let y_int = (y * 1000.0) as i64;
-                    let f_int = (f * 32767.0) as u64;
-
-                    acc + &format!("{x_int} {y_int} {f_int},")
-                });
-
-        writer.write(XmlEvent::characters(&string_out[0..string_out.len() - 1]))?;
+/// where a stroke comes from : its trace and the context of the trace
+/// (e.g. to tell the strokes of a pen from the ones of a touch screen)
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrokeSource {
+    /// id of the trace (of its last part for strokes joined from continuation traces)
+    pub trace_id: Option<String>,
+    /// context of the trace, shared by the strokes using it
+    pub context: Arc<Context>,
+}
 
-        writer.write(XmlEvent::end_element())?;
+impl StrokeSource {
+    /// id of the context of the trace
+    pub fn context_id(&self) -> &str {
+        &self.context.name
+    }
 
-        Ok(())
+    /// channels of the trace as written in the document (resolution, units, ...)
+    pub fn channels(&self) -> &[Channel] {
+        &self.context.channel_list
     }
 }
 
-/// Type of modifier
-/// Used as a token before the corresponding value is parsed
-#[derive(Debug, Clone, Copy)]
-enum ValueModifier {
-    Explicit,
-    SingleDifference,
-    DoubleDifference,
-}
+impl FormattedStroke {
+    /// pen down stroke from its coordinates (in cm) and pressures (from 0 to 1)
+    /// Fails if the channels don't have the same length or if a value is NaN or infinite
+    pub fn new(x: Vec<f64>, y: Vec<f64>, f: Vec<f64>) -> anyhow::Result<FormattedStroke> {
+        let stroke = FormattedStroke {
+            x,
+            y,
+            f,
+            ..Default::default()
+        };
+        stroke.check_aligned()?;
+        stroke.check_finite()?;
+        Ok(stroke)
+    }
 
-impl ChannelData {
-    fn map_from_channel_type(ch_type: ChannelType) -> ChannelData {
-        match ch_type {
-            ChannelType::Integer => ChannelData::Integer(vec![]),
-            ChannelType::Decimal => ChannelData::Double(vec![]),
-            ChannelType::Bool => ChannelData::Bool(vec![]),
-            ChannelType::Double => ChannelData::Double(vec![]),
-        }
-    }
-}
-
-pub struct TraceData {
-    data: Vec<ChannelData>,
-    last_value_modifiers: Vec<ValueModifier>,
-    last_value_difference: Vec<ChannelDataEl>,
-    /// the index of the channel we are currently parsing the data for
-    index_channel: usize,
-    /// accumulator for the value of the channel
-    value_str: String,
-    /// set to true on the first character of the num data of the channel
-    is_value_found: bool,
-    /// to switch to the new modifier if it's found before the numerical value
-    /// Hence we are yet to have the value info to create the nextr LastValueModifier
-    new_modifier: ValueModifier,
-}
-
-impl TraceData {
-    pub fn data(&self) -> Vec<ChannelData> {
-        self.data.clone()
-    }
-
-    pub fn from_channel_types(types: Vec<ChannelType>) -> TraceData {
-        let num_channels = types.len();
-        TraceData {
-            data: types
-                .clone()
-                .into_iter()
-                .map(ChannelData::map_from_channel_type)
-                .collect(),
-            last_value_difference: types.into_iter().map(|x| x.get_null_value()).collect(),
-            last_value_modifiers: (1..=num_channels)
-                .map(|_| ValueModifier::Explicit)
-                .collect(),
-            index_channel: 0,
-            value_str: String::from(""),
-            new_modifier: ValueModifier::Explicit,
-            is_value_found: false,
-        }
-    }
-
-    pub fn parse_raw_data(&mut self, line_str: String) -> anyhow::Result<()> {
-        //line_str : ex '37'-40'1680'0'0
-        // one element from the trace string after
-        // splitting per ,
-        for line in line_str.split(",") {
-            // reset the variables
-            self.index_channel = 0;
-            self.is_value_found = false;
-
-            let mut iterator = line.char_indices();
-
-            // will store the modifier : updated if needed
-            self.new_modifier = *self
-                .last_value_modifiers
-                .get(self.index_channel)
-                .ok_or(anyhow!(""))?;
-            while self.index_channel < self.last_value_modifiers.len() {
-                match iterator.next() {
-                    Some((_, next_char)) => {
-                        match next_char {
-                            ' ' | '\r' | '\n' | '\t' => {
-                                if self.is_value_found {
-                                    self.push_found_value()?;
-                                }
-                            }
-                            '!' => {
-                                self.new_modifier = ValueModifier::Explicit;
-                                if self.is_value_found {
-                                    self.push_found_value()?;
-                                }
-                            }
-                            '\'' => {
-                                self.new_modifier = ValueModifier::SingleDifference;
-                                if self.is_value_found {
-                                    self.push_found_value()?;
-                                }
-                            }
-                            '\"' => {
-                                self.new_modifier = ValueModifier::DoubleDifference;
-                                if self.is_value_found {
-                                    self.push_found_value()?;
-                                }
-                            }
-                            '0'..='9' | '.' => {
-                                self.is_value_found = true;
-                                self.value_str.push(next_char);
-                            }
-                            '-' => {
-                                // 0-12 is valid syntax !!
-                                if self.is_value_found {
-                                    // if two values are concatenated with no space in between
-                                    // parse the value up till now
-                                    self.push_found_value()?;
-                                    self.is_value_found = true;
-
-                                    // then restart
-                                    self.new_modifier = *self
-                                        .last_value_modifiers
-                                        .get(self.index_channel)
-                                        .ok_or(anyhow!("Could not find the last value modified for the current channel"))?;
-                                    // we should verify the index here
-                                    self.value_str.push(next_char);
-                                } else {
-                                    self.is_value_found = true;
-                                    self.value_str.push(next_char);
-                                }
-                            }
-                            'T' | 'F' => {
-                                // for boolean traces
-                                self.is_value_found = true;
-                                self.value_str.push(next_char);
-                                self.push_found_value()?;
-                            }
-                            _ => return Err(anyhow!("Unexpected char {next_char} found")),
-                        }
-                    }
-                    None => {
-                        // we expect to have situation like 0,
-                        // hence we have None but we have parsed correctly
-                        if self.is_value_found {
-                            self.push_found_value()?;
-                        } else {
-                            return Err(anyhow!("Unexpected end. Expected more data before the end of the current trace"));
-                            // we have exhausted the whole line before
-                            // parsing all channel data ...
-                            // Remark : needed so that we never loop forever
-                        }
-                    }
+    /// sets the time of each point in ms
+    /// Fails if there isn't one finite timestamp per point
+    pub fn with_timestamps(mut self, t: Vec<f64>) -> anyhow::Result<FormattedStroke> {
+        self.t = Some(t);
+        self.check_aligned()?;
+        self.check_finite()?;
+        Ok(self)
+    }
+
+    /// sets the type of the trace
+    pub fn with_trace_type(mut self, trace_type: TraceType) -> FormattedStroke {
+        self.trace_type = trace_type;
+        self
+    }
+
+    /// X coordinates in cm
+    pub fn x(&self) -> &[f64] {
+        &self.x
+    }
+
+    /// Y coordinates in cm (going down)
+    pub fn y(&self) -> &[f64] {
+        &self.y
+    }
+
+    /// pressures, from 0 to 1
+    pub fn f(&self) -> &[f64] {
+        &self.f
+    }
+
+    /// time of each point in ms, if known
+    pub fn t(&self) -> Option<&[f64]> {
+        self.t.as_deref()
+    }
+
+    pub fn trace_type(&self) -> TraceType {
+        self.trace_type
+    }
+
+    /// number of complete points (with a value for each channel)
+    pub fn len(&self) -> usize {
+        self.x.len().min(self.y.len()).min(self.f.len())
+    }
+
+    /// true if the stroke has no complete point
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// smallest box holding the points of the stroke, None if it has no point
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        BoundingBox::from_points(self.x.iter().copied().zip(self.y.iter().copied()))
+    }
+
+    /// segments between the successive points (a single point gives
+    /// a segment of length 0)
+    fn segments(&self) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+        let point = |index: usize| (self.x[index], self.y[index]);
+        (usize::from(self.len() > 1)..self.len())
+            .map(move |index| (point(index.saturating_sub(1)), point(index)))
+    }
+
+    /// true if the point is on the stroke drawn with `brush`, or at most
+    /// `tolerance_cm` away from it (e.g. for a selection or an eraser tool)
+    /// The full width of the brush is used, whatever the pressure
+    pub fn hit_test(&self, x: f64, y: f64, brush: &Brush, tolerance_cm: f64) -> bool {
+        let reach = brush.stroke_width_cm() / 2.0 + tolerance_cm;
+        self.bounding_box()
+            .is_some_and(|bounding_box| bounding_box.distance_to(x, y) <= reach)
+            && self
+                .segments()
+                .any(|(a, b)| segment_distance((x, y), a, b) <= reach)
+    }
+
+    /// true if the stroke drawn with `brush` overlaps `rect` (e.g. for a
+    /// rectangle selection). The full width of the brush is used, whatever the pressure
+    pub fn intersects_rect(&self, rect: &BoundingBox, brush: &Brush) -> bool {
+        let reach = brush.stroke_width_cm() / 2.0;
+        self.bounding_box()
+            .is_some_and(|bounding_box| bounding_box.grown(reach).intersects(rect))
+            && self
+                .segments()
+                .any(|(a, b)| segment_rect_distance(a, b, rect) <= reach)
+    }
+
+    /// Erases the parts of the stroke inside of the circle (as a pixel eraser) :
+    /// the segments crossing the circle are cut where they cross it, with the
+    /// pressure and the time at the cut interpolated
+    /// Gives the parts left (the stroke itself if the circle doesn't touch it)
+    pub fn erase_circle(&self, x: f64, y: f64, radius: f64) -> Vec<FormattedStroke> {
+        self.kept_parts(
+            |index| (self.x[index] - x).hypot(self.y[index] - y) >= radius,
+            |index| {
+                circle_crossing(
+                    (self.x[index], self.y[index]),
+                    (self.x[index + 1], self.y[index + 1]),
+                    (x, y),
+                    radius,
+                )
+                .map_or([None, None], |(enter, exit)| {
+                    [
+                        Some(Crossing::Leave(enter)),
+                        (exit < 1.0).then_some(Crossing::Enter(exit)),
+                    ]
+                })
+            },
+        )
+    }
+
+    /// Parts of the stroke inside of `rect`, cut where it crosses the sides of
+    /// the box with the pressure and the time at the cut interpolated
+    /// (e.g. to extract a page from a large canvas)
+    pub fn clip(&self, rect: &BoundingBox) -> Vec<FormattedStroke> {
+        self.kept_parts(
+            |index| {
+                (rect.min_x..=rect.max_x).contains(&self.x[index])
+                    && (rect.min_y..=rect.max_y).contains(&self.y[index])
+            },
+            |index| {
+                rect_crossing(
+                    (self.x[index], self.y[index]),
+                    (self.x[index + 1], self.y[index + 1]),
+                    rect,
+                )
+                .map_or([None, None], |(enter, exit)| {
+                    [
+                        // (a segment only touching a corner doesn't enter)
+                        (enter > 0.0 && enter < exit).then_some(Crossing::Enter(enter)),
+                        (exit < 1.0).then_some(Crossing::Leave(exit)),
+                    ]
+                })
+            },
+        )
+    }
+
+    /// Parts of the stroke made of its points that are `kept` and of the points
+    /// where its segments cross the border of the area kept (`crossings` of the
+    /// segment from a point to the next one, in order), with the pressure and
+    /// the time at these points interpolated
+    fn kept_parts(
+        &self,
+        kept: impl Fn(usize) -> bool,
+        crossings: impl Fn(usize) -> [Option<Crossing>; 2],
+    ) -> Vec<FormattedStroke> {
+        let len = self.len();
+        let has_t = self.t.as_ref().is_some_and(|t| t.len() >= len);
+        let new_part = || FormattedStroke {
+            trace_type: self.trace_type,
+            t: has_t.then(Vec::new),
+            ..Default::default()
+        };
+        // the point at `fraction` of the segment from `index` to the next point
+        let push = |part: &mut FormattedStroke, index: usize, fraction: f64| {
+            let lerp = |values: &[f64]| {
+                if fraction == 0.0 {
+                    values[index]
+                } else {
+                    values[index] + fraction * (values[index + 1] - values[index])
                 }
+            };
+            part.x.push(lerp(&self.x));
+            part.y.push(lerp(&self.y));
+            part.f.push(lerp(&self.f));
+            if let (Some(t), Some(part_t)) = (&self.t, &mut part.t) {
+                part_t.push(lerp(t));
             }
+        };
 
-            trace!("verifying what's left is only spaces");
-
-            // verify that the end of the line is all spaces
-            // check that we have not more ignored data further down
-            for (_, next_char) in iterator {
-                match next_char {
-                    ' ' | '\r' | '\n' | '\t' => {}
-                    _ => {
-                        return Err(anyhow!(
-                            "char not expected {:?}, we only expected space-like elements",
-                            next_char
-                        )); //there was something left ...
+        let mut parts = vec![];
+        let mut part = new_part();
+        for index in 0..len {
+            if kept(index) {
+                push(&mut part, index, 0.0);
+            }
+            if index + 1 == len {
+                break;
+            }
+            for crossing in crossings(index).into_iter().flatten() {
+                match crossing {
+                    Crossing::Enter(fraction) => push(&mut part, index, fraction),
+                    Crossing::Leave(fraction) if !part.is_empty() => {
+                        // (the point itself is already in the part)
+                        if fraction > 0.0 {
+                            push(&mut part, index, fraction);
+                        }
+                        parts.push(std::mem::replace(&mut part, new_part()));
                     }
+                    Crossing::Leave(_) => {}
                 }
             }
-            trace!("ok, this was only spaces");
         }
+        if !part.is_empty() {
+            parts.push(part);
+        }
+        parts
+    }
 
-        for i in 0..self.data.len() {
-            trace!("{:?}", self.data[i]);
+    /// checks that all the channels have a value for each point
+    /// (same number of x, y, f and t values)
+    pub(crate) fn check_aligned(&self) -> anyhow::Result<()> {
+        let len = self.x.len();
+        if self.y.len() != len
+            || self.f.len() != len
+            || self.t.as_ref().is_some_and(|t| t.len() != len)
+        {
+            return Err(anyhow!(
+                "The channels of the stroke have different lengths : {} x, {} y, {} f and {:?} t values",
+                len,
+                self.y.len(),
+                self.f.len(),
+                self.t.as_ref().map(Vec::len)
+            ));
         }
         Ok(())
     }
 
-    fn push_found_value(&mut self) -> anyhow::Result<()> {
-        // parse the value
-        trace!(
-            "End val, Value up till now {:?}, modifier {:?}, index : {:?}",
-            self.value_str, self.new_modifier, self.index_channel
-        );
+    /// checks that no value is NaN or infinite
+    fn check_finite(&self) -> anyhow::Result<()> {
+        let channels = [("x", &self.x), ("y", &self.y), ("f", &self.f)];
+        let all = channels
+            .into_iter()
+            .chain(self.t.as_ref().map(|t| ("t", t)));
+        for (name, values) in all {
+            if let Some(index) = values.iter().position(|value| !value.is_finite()) {
+                return Err(anyhow!(
+                    "The {name} value of the point {index} of the stroke is {}",
+                    values[index]
+                ));
+            }
+        }
+        Ok(())
+    }
 
-        // push to the corresponding channel
-        match &mut self
-            .data
-            .get_mut(self.index_channel)
-            .ok_or(anyhow!("Could not find the current channel"))?
-        {
-            ChannelData::Integer(current) => {
-                let parsed_value = self.value_str.parse::<i64>();
-                trace!(
-                    "parsed value : {:?} value str {:?}",
-                    parsed_value, self.value_str
-                );
-                match parsed_value {
-                    Ok(value) => match self.new_modifier {
-                        ValueModifier::Explicit => {
-                            current.push(value);
-                        }
-                        ValueModifier::SingleDifference => {
-                            let previous = current.last().ok_or(anyhow!("could not find the previous value for the channel. 
-                                                                                    This is unexpected as we found a single difference modifier, 
-                                                                                    so the value is the previous one + the current values"))?;
-                            let last_difference_container =
-                                self.last_value_difference[self.index_channel].clone();
-                            match last_difference_container {
-                                ChannelDataEl::Integer(last_difference) => {
-                                    self.last_value_difference[self.index_channel] =
-                                        ChannelDataEl::Integer(last_difference + value);
-                                    current.push(value + previous);
-                                }
-                                _ => {
-                                    return Err(anyhow!(
-                                        "The saved previous element for the channel is incorrect."
-                                    ))
-                                }
-                            }
-                        }
-                        ValueModifier::DoubleDifference => {
-                            let previous = current.last().ok_or(anyhow!("Could not find the previous value for the channel.
-                                                                            This is unexpected as we found a double difference modifier
-                                                                            so the value is calculated relative to the previous one"))?;
-                            let last_difference_container =
-                                self.last_value_difference[self.index_channel].clone();
-                            match last_difference_container {
-                                ChannelDataEl::Integer(last_difference) => {
-                                    self.last_value_difference[self.index_channel] =
-                                        ChannelDataEl::Integer(last_difference + value);
-                                    current.push(value + previous + last_difference);
-                                }
-                                _ => {
-                                    return Err(anyhow!(
-                                        "The saved previous element for the channel is incorrect"
-                                    ))
-                                }
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        return Err(anyhow!("{e} : Could not parse the value as int"));
-                    }
+    /// Splits the stroke in two : the points before `index` and the other ones
+    /// Fails if the channels don't have the same length or if `index` is past the end
+    pub fn split_at(&self, index: usize) -> anyhow::Result<(FormattedStroke, FormattedStroke)> {
+        self.check_aligned()?;
+        if index > self.len() {
+            return Err(anyhow!(
+                "Could not split the stroke at {index}, it has {} points",
+                self.len()
+            ));
+        }
+        let part = |range: Range<usize>| FormattedStroke {
+            x: self.x[range.clone()].to_vec(),
+            y: self.y[range.clone()].to_vec(),
+            f: self.f[range.clone()].to_vec(),
+            trace_type: self.trace_type,
+            t: self.t.as_ref().map(|t| t[range].to_vec()),
+        };
+        Ok((part(0..index), part(index..self.len())))
+    }
+
+    /// Splits the stroke in two : the points before the time `t` (in ms)
+    /// and the other ones (the timestamps being increasing)
+    /// Fails if the stroke has no timestamps
+    pub fn split_at_time(&self, t: f64) -> anyhow::Result<(FormattedStroke, FormattedStroke)> {
+        let times = self.timestamps()?;
+        self.split_at(times.partition_point(|time| *time < t))
+    }
+
+    /// timestamps of the points, a `MissingChannel` error if the stroke has none
+    fn timestamps(&self) -> anyhow::Result<&[f64]> {
+        self.t.as_deref().ok_or_else(|| {
+            anyhow::Error::new(MissingChannel {
+                channel: String::from("T"),
+            })
+        })
+    }
+
+    /// length of the path of the stroke in cm
+    pub fn arc_length_cm(&self) -> f64 {
+        self.segments()
+            .map(|((x0, y0), (x1, y1))| (x1 - x0).hypot(y1 - y0))
+            .sum()
+    }
+
+    /// speed along each segment of the stroke in cm/s (one value less than the points)
+    /// A segment without elapsed time (same timestamp on both ends) gets the
+    /// speed of the previous one (0 for the first one)
+    /// Fails with a `MissingChannel` error if the stroke has no timestamps
+    pub fn speeds(&self) -> anyhow::Result<Vec<f64>> {
+        let times = self.timestamps()?;
+        let len = self.len().min(times.len());
+        let mut speed = 0.0;
+        Ok((1..len)
+            .map(|index| {
+                let elapsed = times[index] - times[index - 1];
+                if elapsed > 0.0 {
+                    let distance = (self.x[index] - self.x[index - 1])
+                        .hypot(self.y[index] - self.y[index - 1]);
+                    speed = distance / elapsed * 1000.0;
+                }
+                speed
+            })
+            .collect())
+    }
+
+    /// curvature at each inner point of the stroke in 1/cm (inverse of the radius
+    /// of the circle through the point and its neighbours, one value per point
+    /// except the first and last ones)
+    /// Positive when the stroke turns clockwise on the page (Y going down),
+    /// 0 if two of the points are the same
+    pub fn curvatures(&self) -> Vec<f64> {
+        (2..self.len())
+            .map(|index| {
+                let (ax, ay) = (self.x[index - 2], self.y[index - 2]);
+                let (bx, by) = (self.x[index - 1], self.y[index - 1]);
+                let (cx, cy) = (self.x[index], self.y[index]);
+                let cross = (bx - ax) * (cy - by) - (by - ay) * (cx - bx);
+                let lengths =
+                    (bx - ax).hypot(by - ay) * (cx - bx).hypot(cy - by) * (cx - ax).hypot(cy - ay);
+                if lengths > 0.0 {
+                    2.0 * cross / lengths
+                } else {
+                    0.0
                 }
+            })
+            .collect()
+    }
+
+    /// the stroke with X and Y in pixels at `dpi` from `origin`
+    /// (pressure and timestamps are kept), for GUI toolkits drawing in pixels
+    /// On screens with a device pixel ratio, multiply the dpi by the ratio
+    /// (e.g. 96 * 2) to get physical pixels
+    pub fn to_pixels(&self, dpi: f32, origin: Origin) -> FormattedStroke {
+        let (px_per_cm, (x0, y0), direction) = origin.pixel_space(dpi);
+        FormattedStroke {
+            x: self.x.iter().map(|x| (x - x0) * px_per_cm).collect(),
+            y: self
+                .y
+                .iter()
+                .map(|y| direction * (y - y0) * px_per_cm)
+                .collect(),
+            f: self.f.clone(),
+            trace_type: self.trace_type,
+            t: self.t.clone(),
+        }
+    }
+
+    /// the stroke in cm from a stroke with X and Y in pixels,
+    /// the inverse of `to_pixels`
+    pub fn from_pixels(&self, dpi: f32, origin: Origin) -> FormattedStroke {
+        let (px_per_cm, (x0, y0), direction) = origin.pixel_space(dpi);
+        FormattedStroke {
+            x: self.x.iter().map(|x| x / px_per_cm + x0).collect(),
+            y: self
+                .y
+                .iter()
+                .map(|y| direction * y / px_per_cm + y0)
+                .collect(),
+            f: self.f.clone(),
+            trace_type: self.trace_type,
+            t: self.t.clone(),
+        }
+    }
+
+    /// Joins two strokes, the points of `other` following the ones of the stroke
+    /// The timestamps are kept if both strokes have them
+    /// Fails if the channels of a stroke don't have the same length or if
+    /// the types of the traces differ
+    pub fn concat(mut self, other: FormattedStroke) -> anyhow::Result<FormattedStroke> {
+        self.check_aligned()?;
+        other.check_aligned()?;
+        if self.trace_type != other.trace_type {
+            return Err(anyhow!(
+                "Could not join a {:?} stroke with a {:?} one",
+                self.trace_type,
+                other.trace_type
+            ));
+        }
+        self.append(other);
+        Ok(self)
+    }
+
+    /// adds the points of `other` at the end of the stroke
+    /// (the timestamps are dropped if one of them has none)
+    pub(crate) fn append(&mut self, other: FormattedStroke) {
+        let len = self.len();
+        self.t = match (self.t.take(), other.t) {
+            (Some(mut t), Some(other_t)) if t.len() >= len => {
+                t.truncate(len);
+                t.extend(other_t);
+                Some(t)
+            }
+            _ => None,
+        };
+        self.x.truncate(len);
+        self.y.truncate(len);
+        self.f.truncate(len);
+        self.x.extend(other.x);
+        self.y.extend(other.y);
+        self.f.extend(other.f);
+    }
+
+    /// Stable 64 bit hash of the geometry of the stroke : positions rounded to
+    /// the micrometre and pressures to 1/1000, to find the same stroke in
+    /// several documents (e.g. duplicates when merging them)
+    /// The value doesn't depend on the platform or on the version of Rust
+    pub fn fingerprint(&self) -> u64 {
+        // FNV-1a
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |value: i64| {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+            }
+        };
+        for (values, steps) in [(&self.x, 1e4), (&self.y, 1e4), (&self.f, 1e3)] {
+            write(values.len() as i64);
+            for value in values {
+                // non finite values are all mapped to 0
+                write(if value.is_finite() {
+                    (value * steps).round() as i64
+                } else {
+                    0
+                });
             }
-            ChannelData::Double(current) => {
-                let parsed_value: Result<f64, std::num::ParseFloatError> =
-                    self.value_str.parse::<f64>();
-                trace!(
-                 "parsed value : {:?} value str {:?}",
-                    parsed_value, self.value_str
+        }
+        hash
+    }
+
+    /// copy of the stroke without the points closer than `epsilon` cm to the
+    /// previous point kept (repeated samples, zero length segments)
+    /// An `epsilon` of 0 only removes the points identical to the previous one
+    /// The first sample of each run is kept
+    pub fn dedup_points(&self, epsilon: f64) -> FormattedStroke {
+        let mut kept = vec![];
+        for index in 0..self.len() {
+            let duplicate = kept.last().is_some_and(|&previous: &usize| {
+                let (dx, dy) = (
+                    self.x[index] - self.x[previous],
+                    self.y[index] - self.y[previous],
                 );
-                match parsed_value {
-                    Ok(value) => match self.new_modifier {
-                        ValueModifier::Explicit => {
-                            current.push(value);
-                        }
-                        ValueModifier::SingleDifference => {
-                            let previous = current.last().ok_or(anyhow!(
-                                "could not find the previous value for the channel. 
-                            This is unexpected as we found a single difference modifier, 
-                            so the value is the previous one + the current values"
-                            ))?;
-                            let last_difference_container =
-                                self.last_value_difference[self.index_channel].clone();
-                            match last_difference_container {
-                                ChannelDataEl::Double(last_difference) => {
-                                    self.last_value_difference[self.index_channel] =
-                                        ChannelDataEl::Double(last_difference + value);
-                                    current.push(value + previous);
-                                }
-                                _ => {
-                                    return Err(anyhow!(
-                                        "The saved previous element for the channel is incorrect"
-                                    ))
-                                }
-                            }
-                        }
-                        ValueModifier::DoubleDifference => {
-                            let previous = current.last().ok_or(anyhow!(
-                                "could not find the previous value for the channel. 
-                            This is unexpected as we found a single difference modifier, 
-                            so the value is the previous one + the current values"
-                            ))?;
-                            let last_difference_container =
-                                self.last_value_difference[self.index_channel].clone();
-                            match last_difference_container {
-                                ChannelDataEl::Double(last_difference) => {
-                                    self.last_value_difference[self.index_channel] =
-                                        ChannelDataEl::Double(last_difference + value);
-                                    current.push(value + previous + last_difference);
-                                }
-                                _ => {
-                                    return Err(anyhow!(
-                                        "The saved previous element for the channel is incorrect"
-                                    ))
-                                }
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        return Err(anyhow!("{e} : Could not parse to float"));
-                    }
+                if epsilon == 0.0 {
+                    dx == 0.0 && dy == 0.0
+                } else {
+                    dx.hypot(dy) < epsilon
                 }
+            });
+            if !duplicate {
+                kept.push(index);
             }
-            ChannelData::Bool(current) => {
-                trace!("value : {:?}", self.value_str);
-                let parsed_value = match self.value_str.as_str() {
-                    "T" => Ok(true),
-                    "F" => Ok(false),
-                    _ => Err(()),
-                };
-                trace!(
-                    "parsed value : {:?} value str {:?}",
-                    parsed_value, self.value_str
-                );
+        }
+        let select = |values: &Vec<f64>| kept.iter().map(|index| values[*index]).collect();
+        FormattedStroke {
+            x: select(&self.x),
+            y: select(&self.y),
+            f: select(&self.f),
+            trace_type: self.trace_type,
+            t: self
+                .t
+                .as_ref()
+                .filter(|t| t.len() >= self.len())
+                .map(select),
+        }
+    }
 
-                // boolean : will be true or false, not changing anything there
-                // so effectively the corresponding index in the last_value_difference
-                // element is unused
-                match parsed_value {
-                    Ok(bool_value) => {
-                        current.push(bool_value);
-                    }
-                    Err(_) => {
-                        return Err(anyhow!(
-                            "Could not parse to bool the value {:?}",
-                            self.value_str
-                        ))
-                    }
+    /// values of the stroke of kind `kind`
+    fn channel_values(&self, kind: &ChannelKind) -> Option<&Vec<f64>> {
+        match kind {
+            ChannelKind::X => Some(&self.x),
+            ChannelKind::Y => Some(&self.y),
+            ChannelKind::F => Some(&self.f),
+            _ => None,
+        }
+    }
+
+    /// values that can't be written in the channels of `context` : non finite
+    /// ones and the ones outside of the range of their channel
+    /// (unless `clamp` is set, where only the non finite ones are reported)
+    pub(crate) fn invalid_values(
+        &self,
+        context: &Context,
+        stroke_index: usize,
+        clamp: bool,
+    ) -> Vec<OutOfRangeValue> {
+        let mut invalid = vec![];
+        for channel in &context.channel_list {
+            let Some(values) = self.channel_values(&channel.kind) else {
+                continue;
+            };
+            let (min, max) = channel.value_range();
+            for (point_index, value) in values.iter().enumerate() {
+                if !value.is_finite() || (!clamp && (*value < min || *value > max)) {
+                    invalid.push(OutOfRangeValue {
+                        stroke_index,
+                        point_index,
+                        channel: String::from(channel.kind.clone()),
+                        value: *value,
+                    });
                 }
             }
         }
+        invalid
+    }
 
-        self.last_value_modifiers[self.index_channel] = self.new_modifier;
-        self.value_str.clear();
-        self.index_channel += 1;
-        self.is_value_found = false;
-        Ok(())
+    /// copy of the stroke with X and Y multiplied by `factor`
+    pub(crate) fn scaled(&self, factor: f64) -> FormattedStroke {
+        let mut scaled = self.clone();
+        for value in scaled.x.iter_mut().chain(scaled.y.iter_mut()) {
+            *value *= factor;
+        }
+        scaled
+    }
+
+    /// copy of the stroke with its values clamped to the range of the
+    /// channels of `context`
+    pub(crate) fn clamped(&self, context: &Context) -> FormattedStroke {
+        let mut clamped = self.clone();
+        for channel in &context.channel_list {
+            let (min, max) = channel.value_range();
+            let values = match channel.kind {
+                ChannelKind::X => &mut clamped.x,
+                ChannelKind::Y => &mut clamped.y,
+                ChannelKind::F => &mut clamped.f,
+                _ => continue,
+            };
+            for value in values.iter_mut() {
+                *value = value.max(min).min(max);
+            }
+        }
+        clamped
+    }
+
+    /// converts the stroke to the raw channel data of `context`
+    /// (the inverse of what `parse_formatted` does)
+    ///
+    /// Only contexts with exactly the X, Y and F channels (integer or decimal)
+    /// can hold a formatted stroke, `None` is returned otherwise
+    ///
+    /// The T channel gets the timestamps (from the first one if `relative_times`
+    /// is set, for traces with a `timeOffset`), or times spread with the sample
+    /// rate of the context for strokes without timestamps
+    pub(crate) fn to_channel_data(
+        &self,
+        context: &Context,
+        relative_times: bool,
+    ) -> Option<Vec<ChannelData>> {
+        // X, Y and F are needed, the other channels (tilt, time, ...)
+        // get their null value, except for the tip switch (strokes are drawn)
+        if [ChannelKind::X, ChannelKind::Y, ChannelKind::F]
+            .into_iter()
+            .any(|kind| context.channel_exists(kind).is_none())
+        {
+            return None;
+        }
+        let offset = match (&self.t, relative_times) {
+            (Some(t), true) => t.first().copied().unwrap_or(0.0),
+            _ => 0.0,
+        };
+        let times = match (&self.t, context.sample_rate) {
+            (Some(t), _) => Some(t.iter().map(|time| time - offset).collect::<Vec<f64>>()),
+            (None, Some(sample_rate)) if sample_rate > 0.0 => Some(
+                (0..self.len())
+                    .map(|index| index as f64 * 1000.0 / sample_rate)
+                    .collect(),
+            ),
+            _ => None,
+        };
+        context
+            .channel_list
+            .iter()
+            .map(|channel| {
+                let values = match channel.kind {
+                    ChannelKind::T => times.as_ref(),
+                    ref kind => self.channel_values(kind),
+                };
+                let Some(values) = values else {
+                    let value = if channel.kind == ChannelKind::S {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    return Some(ChannelData::filled(&channel.types, value, self.len()));
+                };
+                match channel.types {
+                    ChannelType::Integer => Some(ChannelData::Integer(
+                        values
+                            .iter()
+                            .map(|&x| channel.raw_value(x).round() as i64)
+                            .collect(),
+                    )),
+                    ChannelType::Decimal | ChannelType::Double => Some(ChannelData::Double(
+                        values.iter().map(|&x| channel.raw_value(x)).collect(),
+                    )),
+                    ChannelType::Bool => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// a point of a stroke, as given by `FormattedStroke::iter`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StrokePoint {
+    /// coordinates in cm
+    pub x: f64,
+    pub y: f64,
+    /// pressure, from 0 to 1
+    pub f: f64,
+    /// time in ms, if known
+    pub t: Option<f64>,
+}
+
+impl FormattedStroke {
+    /// the point at `index` (lower than `len`)
+    fn point(&self, index: usize) -> StrokePoint {
+        StrokePoint {
+            x: self.x[index],
+            y: self.y[index],
+            f: self.f[index],
+            t: self.t.as_ref().and_then(|t| t.get(index).copied()),
+        }
+    }
+
+    /// iterates over the complete points of the stroke
+    pub fn iter(&self) -> StrokePoints<'_> {
+        StrokePoints {
+            stroke: self,
+            range: 0..self.len(),
+        }
+    }
+}
+
+/// iterator over the points of a stroke
+#[derive(Debug, Clone)]
+pub struct StrokePoints<'a> {
+    stroke: &'a FormattedStroke,
+    range: Range<usize>,
+}
+
+impl Iterator for StrokePoints<'_> {
+    type Item = StrokePoint;
+
+    fn next(&mut self) -> Option<StrokePoint> {
+        self.range.next().map(|index| self.stroke.point(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for StrokePoints<'_> {
+    fn next_back(&mut self) -> Option<StrokePoint> {
+        self.range.next_back().map(|index| self.stroke.point(index))
+    }
+}
+
+impl ExactSizeIterator for StrokePoints<'_> {}
+
+impl<'a> IntoIterator for &'a FormattedStroke {
+    type Item = StrokePoint;
+    type IntoIter = StrokePoints<'a>;
+
+    fn into_iter(self) -> StrokePoints<'a> {
+        self.iter()
+    }
+}
+
+/// iterator over the points of a stroke, consuming it
+#[derive(Debug, Clone)]
+pub struct IntoStrokePoints {
+    stroke: FormattedStroke,
+    range: Range<usize>,
+}
+
+impl Iterator for IntoStrokePoints {
+    type Item = StrokePoint;
+
+    fn next(&mut self) -> Option<StrokePoint> {
+        self.range.next().map(|index| self.stroke.point(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for IntoStrokePoints {
+    fn next_back(&mut self) -> Option<StrokePoint> {
+        self.range.next_back().map(|index| self.stroke.point(index))
+    }
+}
+
+impl ExactSizeIterator for IntoStrokePoints {}
+
+impl IntoIterator for FormattedStroke {
+    type Item = StrokePoint;
+    type IntoIter = IntoStrokePoints;
+
+    fn into_iter(self) -> IntoStrokePoints {
+        let range = 0..self.len();
+        IntoStrokePoints {
+            stroke: self,
+            range,
+        }
+    }
+}
+
+/// pen down stroke from its points
+/// The timestamps are kept only if all the points have one
+impl FromIterator<StrokePoint> for FormattedStroke {
+    fn from_iter<I: IntoIterator<Item = StrokePoint>>(points: I) -> FormattedStroke {
+        let mut stroke = FormattedStroke::default();
+        let mut t = Some(vec![]);
+        for point in points {
+            stroke.x.push(point.x);
+            stroke.y.push(point.y);
+            stroke.f.push(point.f);
+            t = t.zip(point.t).map(|(mut t, time)| {
+                t.push(time);
+                t
+            });
+        }
+        // no timestamps for a stroke without points
+        stroke.t = t.filter(|t| !t.is_empty());
+        stroke
+    }
+}
+
+/// a trace waiting to be decoded : context id, brush id, types of the channels
+/// and text of the trace
+#[cfg(feature = "parallel")]
+pub(crate) type PendingTrace = (RawTrace, Vec<ChannelType>, String);
+
+/// decodes the text of the traces on the rayon thread pool, each thread
+/// reusing its own decoder
+/// If several traces are malformed, the error of any of them is returned
+#[cfg(feature = "parallel")]
+pub(crate) fn decode_traces_parallel(
+    traces: Vec<PendingTrace>,
+    fill_missing_channels: bool,
+) -> anyhow::Result<Vec<RawTrace>> {
+    use rayon::prelude::*;
+
+    traces
+        .into_par_iter()
+        .map_init(
+            || TraceData::new(fill_missing_channels),
+            |trace_data, (mut trace, channel_types, text)| {
+                trace_data.reset(channel_types);
+                trace_data.parse_raw_data(&text)?;
+                trace.data = trace_data.take_data();
+                Ok(trace)
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{ContextBuilder, Resolution};
+
+    fn trace_with_tip_switch(id: Option<&str>, tip: Vec<i64>) -> (RawTrace, Context) {
+        let context = ContextBuilder::new()
+            .with_channel(
+                ChannelKind::X,
+                ChannelType::Integer,
+                Resolution::per_cm(1000.0),
+            )
+            .with_channel(
+                ChannelKind::Y,
+                ChannelType::Integer,
+                Resolution::per_cm(1000.0),
+            )
+            .with_channel(
+                ChannelKind::S,
+                ChannelType::Integer,
+                Resolution::per_dev(1.0),
+            )
+            .build()
+            .unwrap();
+        let positions = (0..tip.len() as i64).collect::<Vec<i64>>();
+        let trace = RawTrace {
+            id: id.map(String::from),
+            context_id: Symbol::from("ctx0"),
+            brush_id: Symbol::from("br0"),
+            trace_type: TraceType::PenDown,
+            time_offset: None,
+            duration: None,
+            group: None,
+            continuation: None,
+            prior_ref: None,
+            data: vec![
+                ChannelData::Integer(positions.clone()),
+                ChannelData::Integer(positions),
+                ChannelData::Integer(tip),
+            ],
+        };
+        (trace, context)
+    }
+
+    fn line(x: Vec<f64>) -> FormattedStroke {
+        let len = x.len();
+        FormattedStroke::new(
+            x,
+            vec![0.0; len],
+            (0..len).map(|i| i as f64 / 4.0).collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn erasing_cuts_the_stroke_at_the_circle() {
+        let parts = line(vec![0.0, 2.0, 4.0]).erase_circle(2.0, 0.0, 1.0);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].x(), [0.0, 1.0]);
+        assert_eq!(parts[0].f(), [0.0, 0.125]);
+        assert_eq!(parts[1].x(), [3.0, 4.0]);
+        assert_eq!(parts[1].f(), [0.375, 0.5]);
+    }
+
+    #[test]
+    fn clipping_keeps_the_parts_inside_of_the_box() {
+        let rect = BoundingBox::new(1.0, -1.0, 3.0, 1.0);
+        let parts = line(vec![0.0, 2.0, 4.0, 2.0]).clip(&rect);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].x(), [1.0, 2.0, 3.0]);
+        assert_eq!(parts[0].f(), [0.125, 0.25, 0.375]);
+        assert_eq!(parts[1].x(), [3.0, 2.0]);
+        // a point on the side isn't repeated when the stroke leaves from it
+        let parts = line(vec![1.0, 0.0]).clip(&rect);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].x(), [1.0]);
+    }
+
+    #[test]
+    fn split_parts_have_unique_ids() {
+        let (trace, context) = trace_with_tip_switch(Some("t1"), vec![1, 1, 0, 0, 1]);
+        let parts = trace.split_by_state(&context);
+        let ids = parts
+            .iter()
+            .map(|part| part.id.as_deref())
+            .collect::<Vec<Option<&str>>>();
+        assert_eq!(ids, [Some("t1_0"), Some("t1_1"), Some("t1")]);
+        assert_eq!(parts[1].trace_type, TraceType::PenUp);
+    }
+
+    #[test]
+    fn unsplit_trace_keeps_its_id() {
+        let (trace, context) = trace_with_tip_switch(Some("t1"), vec![1, 1, 1]);
+        let parts = trace.split_by_state(&context);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].id.as_deref(), Some("t1"));
+
+        let (trace, context) = trace_with_tip_switch(None, vec![1, 0]);
+        assert!(trace
+            .split_by_state(&context)
+            .iter()
+            .all(|part| part.id.is_none()));
+    }
+
+    #[test]
+    fn split_continuation_is_only_joined_by_its_first_part() {
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+            <definitions>
+                <context xml:id="ctx0">
+                    <inkSource xml:id="src0">
+                        <traceFormat>
+                            <channel name="X" type="integer" />
+                            <channel name="Y" type="integer" />
+                            <channel name="S" type="boolean" />
+                        </traceFormat>
+                    </inkSource>
+                </context>
+                <brush xml:id="br0" />
+            </definitions>
+            <trace xml:id="t0" continuation="begin">0 0 T</trace>
+            <trace xml:id="t1" continuation="middle" priorRef="#t0">1000 0 T, 2000 0 T, 3000 0 F, 4000 0 T, 5000 0 T</trace>
+            <trace xml:id="t2" continuation="end" priorRef="#t1">6000 0 T</trace>
+        </ink>"##;
+        let strokes = crate::parser::parse_str(document)
+            .unwrap()
+            .split_by_state_channel()
+            .formatted(&crate::options::FormatOptions::default())
+            .unwrap();
+        let x = strokes
+            .iter()
+            .map(|(stroke, _)| stroke.x().to_vec())
+            .collect::<Vec<Vec<f64>>>();
+        // the pen up part in the middle isn't bridged
+        assert_eq!(x, [vec![0.0, 1.0, 2.0], vec![4.0, 5.0, 6.0]]);
     }
 }
@@ -1,6 +1,12 @@
+use crate::options::IdAttribute;
 use std::io::Write;
 use xml::writer::{Error, EventWriter};
 
 pub(crate) trait Writable {
-    fn write<W: Write>(&self, writer: &mut EventWriter<W>) -> Result<(), Error>;
+    /// `id_attribute` : attribute the id of the element is written in
+    fn write<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        id_attribute: IdAttribute,
+    ) -> Result<(), Error>;
 }
@@ -0,0 +1,107 @@
+// strokes organised by the `traceGroup` elements they are in (layers, selections)
+
+use crate::brushes::Brush;
+use crate::trace_data::{FormattedStroke, StrokeSource};
+
+/// an `annotation` element
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// `type` attribute
+    pub kind: Option<String>,
+    pub text: String,
+}
+
+/// a `traceGroup` element
+#[derive(Debug, Clone, Default)]
+pub struct TraceGroup {
+    pub id: Option<String>,
+    /// language of the group (`xml:lang`), inherited from the enclosing
+    /// groups and the document if it has none
+    pub lang: Option<String>,
+    /// the annotations of the group itself
+    pub annotations: Vec<Annotation>,
+    pub children: Vec<InkNode>,
+}
+
+/// a node of the tree given by `ParserResult::formatted_tree`
+#[derive(Debug, Clone)]
+pub enum InkNode {
+    Group(TraceGroup),
+    Stroke(FormattedStroke, Brush),
+}
+
+/// group read by the parser : its attributes and the index of its parent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct GroupInfo {
+    pub(crate) id: Option<String>,
+    /// `xml:lang` of the group or the inherited one
+    pub(crate) lang: Option<String>,
+    pub(crate) annotations: Vec<Annotation>,
+    pub(crate) parent: Option<usize>,
+    /// `traceDataRef` of the `traceView` elements of the group, without the `#`
+    pub(crate) trace_refs: Vec<String>,
+}
+
+/// a stroke with the index of its group and its trace
+pub(crate) type GroupedStroke = (Option<usize>, StrokeSource, FormattedStroke, Brush);
+
+/// builds the tree of the strokes (in document order) from the index of
+/// the group of each of them
+/// Groups without any stroke are left out
+pub(crate) fn build_tree(groups: Vec<GroupInfo>, strokes: Vec<GroupedStroke>) -> Vec<InkNode> {
+    let mut roots = vec![];
+    // open groups, from the outermost one
+    let mut open: Vec<(usize, TraceGroup)> = vec![];
+
+    // closes the innermost open group
+    fn close(open: &mut Vec<(usize, TraceGroup)>, roots: &mut Vec<InkNode>) {
+        if let Some((_, group)) = open.pop() {
+            match open.last_mut() {
+                Some((_, parent)) => parent.children.push(InkNode::Group(group)),
+                None => roots.push(InkNode::Group(group)),
+            }
+        }
+    }
+
+    for (group, _, stroke, brush) in strokes {
+        // groups containing the stroke, from the outermost one
+        let mut path = vec![];
+        let mut current = group;
+        while let Some(index) = current {
+            path.push(index);
+            current = groups[index].parent;
+        }
+        path.reverse();
+
+        let common = open
+            .iter()
+            .zip(&path)
+            .take_while(|((open_index, _), index)| open_index == *index)
+            .count();
+        while open.len() > common {
+            close(&mut open, &mut roots);
+        }
+        for index in &path[common..] {
+            let info = &groups[*index];
+            open.push((
+                *index,
+                TraceGroup {
+                    id: info.id.clone(),
+                    lang: info.lang.clone(),
+                    annotations: info.annotations.clone(),
+                    children: vec![],
+                },
+            ));
+        }
+
+        let node = InkNode::Stroke(stroke, brush);
+        match open.last_mut() {
+            Some((_, parent)) => parent.children.push(node),
+            None => roots.push(node),
+        }
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut roots);
+    }
+    roots
+}
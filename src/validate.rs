@@ -0,0 +1,298 @@
+// validation of documents against the structure given by the InkML spec
+// (https://www.w3.org/TR/InkML/), as a lint for the files we read and write
+//
+// This isn't a validation against the schema of the spec but a hand written
+// subset of its rules, checked :
+// - nesting : each element is inside of an element that can contain it
+//   (from the content models of the spec)
+// - required attributes
+// - references : `#id` references (`contextRef`, `brushRef`, `traceDataRef`,
+//   `priorRef`, ...) point to an existing element of the right kind and ids are unique
+// The content of `annotationXML` and of the mapping elements isn't checked,
+// nor are the order of the elements and the values of the attributes
+
+use crate::xml_helpers::{get_element_id, get_id, split_reference};
+use crate::xml_source::{xml_source, XmlSource};
+use std::collections::HashMap;
+use std::io::Read;
+use xml::reader::XmlEvent;
+
+/// kind of a `ValidationIssue`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationIssueKind {
+    /// the xml itself is malformed, validation stops there
+    MalformedXml,
+    /// unknown element or element inside of an element that can't contain it
+    UnexpectedElement,
+    MissingAttribute,
+    /// reference to an element that doesn't exist or isn't of the right kind
+    UnknownReference,
+    DuplicateId,
+    /// the strict parser can't read the document (see `validate_output`)
+    ParserError,
+}
+
+/// a problem found by `validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub kind: ValidationIssueKind,
+    /// position of the element in the document (empty if unknown)
+    pub position: String,
+    pub message: String,
+}
+
+/// elements whose content isn't checked
+const OPAQUE_ELEMENTS: [&str; 4] = ["annotationXML", "mapping", "canvasTransform", "table"];
+
+/// elements that can contain the element `name` (`None` : the root)
+fn allowed_parents(name: &str) -> Option<&'static [Option<&'static str>]> {
+    Some(match name {
+        "ink" => &[None],
+        "definitions" => &[Some("ink")],
+        "context" => &[Some("ink"), Some("definitions")],
+        "inkSource" => &[Some("context"), Some("definitions")],
+        "traceFormat" => &[
+            Some("definitions"),
+            Some("context"),
+            Some("inkSource"),
+            Some("canvas"),
+        ],
+        "channel" => &[Some("traceFormat"), Some("intermittentChannels")],
+        "intermittentChannels" => &[Some("traceFormat")],
+        "channelProperties" => &[Some("inkSource"), Some("context")],
+        "channelProperty" => &[Some("channelProperties")],
+        "sampleRate" | "latency" | "activeArea" | "srcProperty" => &[Some("inkSource")],
+        "brush" => &[Some("definitions"), Some("context")],
+        "brushProperty" => &[Some("brush"), Some("brushProperty")],
+        "timestamp" => &[Some("definitions"), Some("context")],
+        "canvas" => &[Some("definitions"), Some("context")],
+        "canvasTransform" => &[Some("definitions"), Some("context")],
+        "mapping" | "table" => &[
+            Some("definitions"),
+            Some("channel"),
+            Some("canvasTransform"),
+            Some("mapping"),
+        ],
+        "trace" | "traceGroup" => &[Some("ink"), Some("definitions"), Some("traceGroup")],
+        // (views refer to the traces, they only contain other views)
+        "traceView" => &[Some("ink"), Some("definitions"), Some("traceView")],
+        "annotation" | "annotationXML" => return Some(&[]),
+        _ => return None,
+    })
+}
+
+/// true if `name` is the local name of an InkML element
+pub(crate) fn is_inkml_element(name: &str) -> bool {
+    allowed_parents(name).is_some()
+}
+
+/// attributes the element `name` must have
+fn required_attributes(name: &str) -> &'static [&'static str] {
+    match name {
+        "channel" => &["name"],
+        "channelProperty" => &["channel", "name", "value"],
+        "brushProperty" => &["name", "value"],
+        "inkSource" => &["id"],
+        "sampleRate" | "latency" => &["value"],
+        _ => &[],
+    }
+}
+
+/// attributes holding a reference, with the kinds of element they can refer to
+const REFERENCES: [(&str, &[&str]); 9] = [
+    ("contextRef", &["context"]),
+    ("brushRef", &["brush"]),
+    ("traceFormatRef", &["traceFormat"]),
+    ("inkSourceRef", &["inkSource"]),
+    ("canvasRef", &["canvas"]),
+    ("canvasTransformRef", &["canvasTransform"]),
+    ("timestampRef", &["timestamp"]),
+    ("traceDataRef", &["trace", "traceGroup", "traceView"]),
+    ("priorRef", &["trace"]),
+];
+
+/// Checks a document against a hand written subset of the rules of the InkML
+/// spec (nesting of the elements, required attributes and references), not
+/// against its whole schema
+/// An empty list means no problem was found
+pub fn validate<R: Read>(reader: R) -> Vec<ValidationIssue> {
+    let mut source = xml_source(reader);
+    let mut issues = vec![];
+    let issue = |kind, position: String, message: String| ValidationIssue {
+        kind,
+        position,
+        message,
+    };
+
+    // open elements
+    let mut stack: Vec<String> = vec![];
+    // number of open elements whose content isn't checked
+    let mut opaque_depth = 0usize;
+    // element name for each id
+    let mut ids: HashMap<String, String> = HashMap::new();
+    // (position, attribute, id, expected elements) of each local reference
+    let mut references: Vec<(String, &str, String, &[&str])> = vec![];
+
+    loop {
+        let event = source.next_event();
+        let position = source.position().to_string();
+        match event {
+            Ok(XmlEvent::EndDocument) => break,
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => {
+                let name = name.local_name;
+                if opaque_depth > 0 {
+                    opaque_depth += 1;
+                    stack.push(name);
+                    continue;
+                }
+                let parent = stack.last().map(String::as_str);
+                match allowed_parents(&name) {
+                    None => issues.push(issue(
+                        ValidationIssueKind::UnexpectedElement,
+                        position.clone(),
+                        format!("Unknown element {name}"),
+                    )),
+                    // annotations can be anywhere
+                    Some([]) => {}
+                    Some(parents) if !parents.contains(&parent) => issues.push(issue(
+                        ValidationIssueKind::UnexpectedElement,
+                        position.clone(),
+                        format!(
+                            "The element {name} can't be inside of {}",
+                            parent.unwrap_or("the document root")
+                        ),
+                    )),
+                    _ => {}
+                }
+                for attribute in required_attributes(&name) {
+                    if get_id(&attributes, String::from(*attribute)).is_none() {
+                        issues.push(issue(
+                            ValidationIssueKind::MissingAttribute,
+                            position.clone(),
+                            format!("The element {name} has no {attribute} attribute"),
+                        ));
+                    }
+                }
+                if let Some(id) = get_element_id(&attributes) {
+                    if let Some(previous) = ids.insert(id.clone(), name.clone()) {
+                        issues.push(issue(
+                            ValidationIssueKind::DuplicateId,
+                            position.clone(),
+                            format!("The id {id} of the {name} element was already used by a {previous} element"),
+                        ));
+                    }
+                }
+                for (attribute, target) in REFERENCES {
+                    if let Some(reference) = get_id(&attributes, String::from(attribute)) {
+                        // references to other documents aren't checked
+                        if let (None, id) = split_reference(&reference) {
+                            references.push((position.clone(), attribute, id.to_owned(), target));
+                        }
+                    }
+                }
+                if OPAQUE_ELEMENTS.contains(&name.as_str()) {
+                    opaque_depth = 1;
+                }
+                stack.push(name);
+            }
+            Ok(XmlEvent::EndElement { .. }) => {
+                stack.pop();
+                opaque_depth = opaque_depth.saturating_sub(1);
+            }
+            Err(e) => {
+                issues.push(issue(
+                    ValidationIssueKind::MalformedXml,
+                    position,
+                    format!("{e}"),
+                ));
+                return issues;
+            }
+            _ => {}
+        }
+    }
+
+    for (position, attribute, id, targets) in references {
+        match ids.get(&id) {
+            Some(element) if targets.contains(&element.as_str()) => {}
+            Some(element) => issues.push(issue(
+                ValidationIssueKind::UnknownReference,
+                position,
+                format!(
+                    "The {attribute} #{id} refers to a {element} element instead of a {}",
+                    targets.join(" or ")
+                ),
+            )),
+            None => issues.push(issue(
+                ValidationIssueKind::UnknownReference,
+                position,
+                format!("The {attribute} #{id} refers to no element"),
+            )),
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(document: &str) -> Vec<ValidationIssueKind> {
+        validate(document.as_bytes())
+            .into_iter()
+            .map(|issue| issue.kind)
+            .collect()
+    }
+
+    #[test]
+    fn definitions_are_only_allowed_in_their_elements() {
+        for element in ["brush", "timestamp", "traceFormat"] {
+            assert_eq!(
+                kinds(&format!(
+                    r#"<ink xmlns="http://www.w3.org/2003/InkML"><{element} /></ink>"#
+                )),
+                [ValidationIssueKind::UnexpectedElement],
+                "{element}"
+            );
+            assert_eq!(
+                kinds(&format!(
+                    r#"<ink xmlns="http://www.w3.org/2003/InkML"><definitions><{element} /></definitions></ink>"#
+                )),
+                [],
+                "{element}"
+            );
+        }
+    }
+
+    #[test]
+    fn views_hold_views_and_no_trace() {
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+            <trace xml:id="t0">0 0</trace>
+            <traceView><traceView traceDataRef="#t0" /></traceView>
+        </ink>"##;
+        assert_eq!(kinds(document), []);
+        let document = r#"<ink xmlns="http://www.w3.org/2003/InkML">
+            <traceView><trace>0 0</trace></traceView>
+        </ink>"#;
+        assert_eq!(kinds(document), [ValidationIssueKind::UnexpectedElement]);
+    }
+
+    #[test]
+    fn trace_references_are_resolved() {
+        let document = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+            <definitions><brush xml:id="br0" /></definitions>
+            <trace xml:id="t0" continuation="begin">0 0</trace>
+            <trace continuation="end" priorRef="#t0">1 1</trace>
+            <trace continuation="end" priorRef="#br0">1 1</trace>
+            <traceView traceDataRef="#missing" />
+        </ink>"##;
+        assert_eq!(
+            kinds(document),
+            [
+                ValidationIssueKind::UnknownReference,
+                ValidationIssueKind::UnknownReference
+            ]
+        );
+    }
+}
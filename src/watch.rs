@@ -0,0 +1,146 @@
+// reparses an InkML file each time it's saved and reports the strokes
+// added and removed since the previous version (for live previews)
+//
+// The directory of the file is watched rather than the file itself, as
+// many applications save by writing a new file and renaming it
+
+use anyhow::anyhow;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::ffi::OsString;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::brushes::Brush;
+use crate::diff::{diff_strokes, StrokeRef};
+use crate::options::FormatOptions;
+use crate::parser::parser;
+use crate::trace_data::FormattedStroke;
+
+/// strokes added and removed between two versions of the file
+#[derive(Debug, Clone, Default)]
+pub struct StrokeChanges {
+    pub added: Vec<(FormattedStroke, Brush)>,
+    pub removed: Vec<(FormattedStroke, Brush)>,
+}
+
+impl StrokeChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// watches an InkML file, stops when dropped
+pub struct InkmlWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// last version of the file read
+struct WatchedFile {
+    path: PathBuf,
+    /// hash of the content of the file, to skip the events that don't change it
+    content_hash: u64,
+    strokes: Vec<(FormattedStroke, Brush)>,
+}
+
+/// strokes of `new` missing from `old` and strokes of `old` missing from `new`
+fn stroke_changes(
+    old: &[(FormattedStroke, Brush)],
+    new: &[(FormattedStroke, Brush)],
+) -> StrokeChanges {
+    fn refs(strokes: &[(FormattedStroke, Brush)]) -> Vec<StrokeRef<'_>> {
+        strokes
+            .iter()
+            .map(|(stroke, brush)| (None, stroke, brush))
+            .collect()
+    }
+    let diff = diff_strokes(&refs(old), &refs(new));
+    StrokeChanges {
+        added: diff.added.iter().map(|index| new[*index].clone()).collect(),
+        removed: diff
+            .removed
+            .iter()
+            .map(|index| old[*index].clone())
+            .collect(),
+    }
+}
+
+impl WatchedFile {
+    /// reads the file again, None if its strokes didn't change
+    /// (or if it doesn't exist or is empty, e.g. during a save)
+    fn reload(&mut self) -> Option<anyhow::Result<StrokeChanges>> {
+        let content = std::fs::read(&self.path).ok()?;
+        if content.is_empty() {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let content_hash = hasher.finish();
+        if content_hash == self.content_hash {
+            return None;
+        }
+        self.content_hash = content_hash;
+
+        let strokes = match parser(content.as_slice())
+            .and_then(|result| result.formatted(&FormatOptions::default()))
+        {
+            Ok(strokes) => strokes,
+            Err(e) => return Some(Err(e)),
+        };
+        let changes = stroke_changes(&self.strokes, &strokes);
+        self.strokes = strokes;
+        (!changes.is_empty()).then_some(Ok(changes))
+    }
+}
+
+impl InkmlWatcher {
+    /// Watches the file at `path`, calling `callback` with the strokes added
+    /// and removed each time its content changes
+    /// The callback is first called with the strokes of the file as added strokes,
+    /// then from the thread of the watcher. A file that fails to parse (e.g. saved
+    /// halfway) gives an error and the next version is compared to the last one parsed
+    pub fn new<P, F>(path: P, mut callback: F) -> anyhow::Result<InkmlWatcher>
+    where
+        P: AsRef<Path>,
+        F: FnMut(anyhow::Result<StrokeChanges>) + Send + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        let file_name: OsString = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{} is not a file path", path.display()))?
+            .to_owned();
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let mut file = WatchedFile {
+            path,
+            content_hash: 0,
+            strokes: vec![],
+        };
+        if let Some(changes) = file.reload() {
+            callback(changes);
+        }
+
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => return callback(Err(anyhow!("Failed to watch the file : {e}"))),
+                };
+                let concerns_file = event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == Some(file_name.as_os_str()));
+                if concerns_file
+                    && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                {
+                    if let Some(changes) = file.reload() {
+                        callback(changes);
+                    }
+                }
+            })?;
+        watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+        Ok(InkmlWatcher { _watcher: watcher })
+    }
+}
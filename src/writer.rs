@@ -1,17 +1,65 @@
 use crate::brushes::BrushCollection;
-use crate::context::Context;
+use crate::colors::Color;
+use crate::context::{ChannelKind, ChannelUnit, Context, DEFAULT_PRESSURE_MAX, DEFAULT_RESOLUTION};
+use crate::error::InvalidStrokesError;
+use crate::extensions::Extension;
+use crate::options::{FormatOptions, IdAttribute, ParserOptions, TraceEncoding, WriterConfig};
+use crate::palette::quantized;
+use crate::parser::{parser_with_options, ParserResult};
+use crate::trace_data::{
+    format_trace, BoundingBox, ChannelData, Continuation, RawTrace, TraceType,
+};
 use crate::traits::Writable;
+use crate::tree::{Annotation, GroupInfo, InkNode};
+use crate::validate::{validate, ValidationIssue, ValidationIssueKind};
 use crate::{brushes::Brush, trace_data::FormattedStroke};
+use anyhow::anyhow;
 #[cfg(feature = "clipboard")]
 use clipboard_rs::{Clipboard, ClipboardContent, ClipboardContext};
-use xml::writer::{EmitterConfig, XmlEvent};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use xml::reader::{EventReader, XmlEvent as rXmlEvent};
+use xml::writer::events::StartElementBuilder;
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
 
 pub fn writer(stroke_data: Vec<(FormattedStroke, Brush)>) -> anyhow::Result<Vec<u8>> {
+    writer_with_config(stroke_data, &WriterConfig::default())
+}
+
+/// Same as `writer` with a config
+pub fn writer_with_config(
+    stroke_data: Vec<(FormattedStroke, Brush)>,
+    config: &WriterConfig,
+) -> anyhow::Result<Vec<u8>> {
+    // empty strokes are skipped unless asked otherwise
+    // (an empty input still gives a valid document with only the definitions)
+    // the index of each stroke in the input is kept for error reporting
+    let stroke_data = stroke_data
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (stroke, _))| config.write_empty_strokes || !stroke.is_empty())
+        .collect::<Vec<(usize, (FormattedStroke, Brush))>>();
+
     // create brushes
-    let mut brush_collection = BrushCollection::default();
-    for (_, brush) in &stroke_data {
-        brush_collection.add_brush(brush);
-    }
+    // and memorize the brush id given for each stroke
+    let mut brush_collection = BrushCollection::new(config.preserve_brush_ids);
+    let colors = quantized_colors(stroke_data.iter().map(|(_, (_, brush))| brush), config);
+    let mapping = stroke_data
+        .iter()
+        .map(|(_, (_, brush))| brush_collection.add_brush(&recolored(brush, &colors)))
+        .collect::<Vec<String>>();
+
+    // check the values against the context before writing anything
+    let context = config.context();
+    let strokes = checked_strokes(
+        stroke_data
+            .iter()
+            .map(|(stroke_index, (stroke, _))| (*stroke_index, stroke)),
+        &context,
+        config,
+    )?;
 
     let mut out_v: Vec<u8> = vec![];
     let mut writer = EmitterConfig::new()
@@ -19,8 +67,95 @@ pub fn writer(stroke_data: Vec<(FormattedStroke, Brush)>) -> anyhow::Result<Vec<
         .write_document_declaration(false)
         .create_writer(&mut out_v);
 
+    let page = config.canvas_page(strokes.iter().map(|stroke| &**stroke));
+    write_header(&mut writer, &context, page, &brush_collection, config)?;
+
+    // iterate over strokes
+    //add trace element with some contextRef and brushRef
+    // we also need to iterate on positions + convert with the correct
+    // value (depending on resolution and units for source and end !)
+
+    for (stroke_index, (formatted_stroke, brush_id)) in strokes.iter().zip(mapping).enumerate() {
+        // we are using the NEW brush id here
+        write_stroke(
+            &mut writer,
+            formatted_stroke,
+            &context,
+            &brush_id,
+            stroke_index,
+            config,
+        )?;
+    }
+
+    writer.write(XmlEvent::end_element())?; // end ink
+    if config.pretty {
+        out_v = pretty_printed(&out_v)?;
+    }
+
+    // copy to clipboard (for testing purposes only)
+    #[cfg(feature = "clipboard")]
+    {
+        let mimetype_windows = String::from("InkML Format");
+        let content: Vec<ClipboardContent> =
+            vec![ClipboardContent::Other(mimetype_windows, out_v.to_owned())];
+        let ctx = ClipboardContext::new().unwrap();
+        let _ = ctx.set(content);
+    }
+    Ok(out_v)
+}
+
+/// Same as `writer`, the document being written to the file at `path`
+pub fn write_to_file<P: AsRef<Path>>(
+    path: P,
+    stroke_data: Vec<(FormattedStroke, Brush)>,
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    std::fs::write(path, writer(stroke_data)?)
+        .map_err(|e| anyhow!("Could not write {} : {e}", path.display()))
+}
+
+/// the `ink` element with its `documentID` and `xml:lang`
+fn ink_element<'a>(document_id: Option<&'a str>, lang: Option<&'a str>) -> StartElementBuilder<'a> {
+    let mut start = XmlEvent::start_element("ink").default_ns("http://www.w3.org/2003/InkML");
+    if let Some(document_id) = document_id {
+        start = start.attr("documentID", document_id);
+    }
+    if let Some(lang) = lang {
+        start = start.attr("xml:lang", lang);
+    }
+    start
+}
+
+/// `annotation` elements, with their `type`
+fn write_annotations<W: Write>(
+    writer: &mut EventWriter<W>,
+    annotations: &[Annotation],
+) -> anyhow::Result<()> {
+    for annotation in annotations {
+        let mut start = XmlEvent::start_element("annotation");
+        if let Some(kind) = &annotation.kind {
+            start = start.attr("type", kind);
+        }
+        writer.write(start)?;
+        writer.write(XmlEvent::characters(&annotation.text))?;
+        writer.write(XmlEvent::end_element())?;
+    }
+    Ok(())
+}
+
+/// opens the ink element and writes the definitions and the extensions of the config
+fn write_header<W: Write>(
+    writer: &mut EventWriter<W>,
+    context: &Context,
+    page: Option<BoundingBox>,
+    brush_collection: &BrushCollection,
+    config: &WriterConfig,
+) -> anyhow::Result<()> {
     // xmls : InkML
-    writer.write(XmlEvent::start_element("ink").default_ns("http://www.w3.org/2003/InkML"))?;
+    writer.write(ink_element(
+        config.document_id.as_deref(),
+        config.lang.as_deref(),
+    ))?;
 
     // definitions block
     // contains :
@@ -32,42 +167,1100 @@ pub fn writer(stroke_data: Vec<(FormattedStroke, Brush)>) -> anyhow::Result<Vec<
     // - width, height, color, ignorePressure
     writer.write(XmlEvent::start_element("definitions"))?;
 
-    let context = Context::default_with_pressure();
-    context.write(&mut writer)?;
+    context.write_with_canvas(writer, config.id_attribute, page.as_ref())?;
 
     // write all brushes
-    for (_, brush) in brush_collection.brushes() {
-        brush.write(&mut writer)?;
+    for brush in brush_collection.brushes() {
+        brush.write_with_transparency(writer, config.id_attribute, config.black_transparency)?;
     }
+    write_extensions(writer, &config.extensions, true, config.id_attribute)?;
     writer.write(XmlEvent::end_element())?; // end definitions
 
-    // iterate over strokes
-    //add trace element with some contextRef and brushRef
-    // we also need to iterate on positions + convert with the correct
-    // value (depending on resolution and units for source and end !)
+    write_extensions(writer, &config.extensions, false, config.id_attribute)?;
+    Ok(())
+}
 
-    for ((formatted_stroke, _), brush_id) in stroke_data.into_iter().zip(brush_collection.mapping())
-    {
-        // we are using the NEW brush id here
+/// writes the extensions read inside of the definitions (`in_definitions`)
+/// or the other ones
+fn write_extensions<W: Write>(
+    writer: &mut EventWriter<W>,
+    extensions: &[Extension],
+    in_definitions: bool,
+    id_attribute: IdAttribute,
+) -> Result<(), xml::writer::Error> {
+    for extension in extensions {
+        if extension.in_definitions() == in_definitions {
+            extension.write_in_place(writer, id_attribute)?;
+        }
+    }
+    Ok(())
+}
+
+/// Same as `writer_with_config` for strokes organised in groups (e.g. layers) :
+/// each group is written as a `traceGroup` with its id and annotations
+pub fn writer_tree(nodes: &[InkNode], config: &WriterConfig) -> anyhow::Result<Vec<u8>> {
+    // strokes in document order
+    fn flatten<'a>(nodes: &'a [InkNode], strokes: &mut Vec<(&'a FormattedStroke, &'a Brush)>) {
+        for node in nodes {
+            match node {
+                InkNode::Group(group) => flatten(&group.children, strokes),
+                InkNode::Stroke(stroke, brush) => strokes.push((stroke, brush)),
+            }
+        }
+    }
+    let mut all_strokes = vec![];
+    flatten(nodes, &mut all_strokes);
+    let kept = |stroke: &FormattedStroke| config.write_empty_strokes || !stroke.is_empty();
+
+    let mut brush_collection = BrushCollection::new(config.preserve_brush_ids);
+    let colors = quantized_colors(
+        all_strokes
+            .iter()
+            .filter(|(stroke, _)| kept(stroke))
+            .map(|(_, brush)| *brush),
+        config,
+    );
+    let mapping = all_strokes
+        .iter()
+        .filter(|(stroke, _)| kept(stroke))
+        .map(|(_, brush)| brush_collection.add_brush(&recolored(brush, &colors)))
+        .collect::<Vec<String>>();
+    let context = config.context();
+    let strokes = checked_strokes(
+        all_strokes
+            .iter()
+            .enumerate()
+            .filter(|(_, (stroke, _))| kept(stroke))
+            .map(|(stroke_index, (stroke, _))| (stroke_index, *stroke)),
+        &context,
+        config,
+    )?;
+    // the checked stroke and its brush id for each stroke (None : skipped)
+    let mut checked = strokes.iter().zip(&mapping);
+    let mut written = all_strokes
+        .iter()
+        .enumerate()
+        .map(|(stroke_index, (stroke, _))| {
+            if kept(stroke) {
+                checked
+                    .next()
+                    .map(|(stroke, brush_id)| (stroke_index, stroke, brush_id))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    // (`lang` : language of the enclosing element, only a different one is written)
+    fn write_nodes<W: Write>(
+        writer: &mut EventWriter<W>,
+        nodes: &[InkNode],
+        written: &mut dyn Iterator<Item = Option<(usize, &Cow<FormattedStroke>, &String)>>,
+        context: &Context,
+        config: &WriterConfig,
+        lang: Option<&str>,
+    ) -> anyhow::Result<()> {
+        for node in nodes {
+            match node {
+                InkNode::Group(group) => {
+                    let mut start = XmlEvent::start_element("traceGroup");
+                    if let Some(id) = &group.id {
+                        start = start.attr(config.id_attribute.name(), id);
+                    }
+                    let group_lang = group.lang.as_deref().or(lang);
+                    if let Some(group_lang) =
+                        group_lang.filter(|group_lang| Some(*group_lang) != lang)
+                    {
+                        start = start.attr("xml:lang", group_lang);
+                    }
+                    writer.write(start)?;
+                    write_annotations(writer, &group.annotations)?;
+                    write_nodes(
+                        writer,
+                        &group.children,
+                        written,
+                        context,
+                        config,
+                        group_lang,
+                    )?;
+                    writer.write(XmlEvent::end_element())?; // end traceGroup
+                }
+                InkNode::Stroke(..) => {
+                    if let Some(Some((stroke_index, stroke, brush_id))) = written.next() {
+                        write_stroke(writer, stroke, context, brush_id, stroke_index, config)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut out_v: Vec<u8> = vec![];
+    let mut writer = EmitterConfig::new()
+        .perform_indent(false)
+        .write_document_declaration(false)
+        .create_writer(&mut out_v);
+    let page = config.canvas_page(strokes.iter().map(|stroke| &**stroke));
+    write_header(&mut writer, &context, page, &brush_collection, config)?;
+    write_nodes(
+        &mut writer,
+        nodes,
+        &mut written,
+        &context,
+        config,
+        config.lang.as_deref(),
+    )?;
+    writer.write(XmlEvent::end_element())?; // end ink
+    if config.pretty {
+        return pretty_printed(&out_v);
+    }
+    Ok(out_v)
+}
+
+/// the document indented, the attributes of each element being sorted
+/// (id, `name` then the others alphabetically), see `WriterConfig::pretty`
+fn pretty_printed(document: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out_v: Vec<u8> = vec![];
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .write_document_declaration(false)
+        .create_writer(&mut out_v);
+    for event in EventReader::new(document) {
+        match event? {
+            rXmlEvent::StartDocument { .. } | rXmlEvent::EndDocument => {}
+            rXmlEvent::StartElement {
+                name,
+                mut attributes,
+                namespace,
+            } => {
+                attributes.sort_by_cached_key(|attribute| {
+                    let rank = match attribute.name.local_name.as_str() {
+                        "id" => 0,
+                        "name" => 1,
+                        _ => 2,
+                    };
+                    (rank, attribute.name.to_string())
+                });
+                writer.write(XmlEvent::StartElement {
+                    name: name.borrow(),
+                    attributes: attributes
+                        .iter()
+                        .map(|attribute| attribute.borrow())
+                        .collect(),
+                    namespace: Cow::Borrowed(&namespace),
+                })?;
+            }
+            event => {
+                if let Some(event) = event.as_writer_event() {
+                    writer.write(event)?;
+                }
+            }
+        }
+    }
+    Ok(out_v)
+}
+
+/// the color standing for each color of the brushes, if the config quantises them
+fn quantized_colors<'a>(
+    brushes: impl Iterator<Item = &'a Brush>,
+    config: &WriterConfig,
+) -> HashMap<Color, Color> {
+    match config.quantize_colors {
+        Some(n) => quantized(brushes.map(|brush| brush.color), n),
+        None => HashMap::new(),
+    }
+}
+
+fn recolored<'a>(brush: &'a Brush, colors: &HashMap<Color, Color>) -> Cow<'a, Brush> {
+    match colors.get(&brush.color) {
+        Some(color) if *color != brush.color => Cow::Owned(Brush {
+            color: *color,
+            ..brush.clone()
+        }),
+        _ => Cow::Borrowed(brush),
+    }
+}
+
+/// checks that the strokes can be written in the channels of `context`,
+/// clamping them if the config says so, and removes their duplicate points
+/// if asked
+/// All the invalid values are reported in an `InvalidStrokesError`
+fn checked_strokes<'a>(
+    strokes: impl Iterator<Item = (usize, &'a FormattedStroke)>,
+    context: &Context,
+    config: &WriterConfig,
+) -> anyhow::Result<Vec<Cow<'a, FormattedStroke>>> {
+    // strokes given in another unit are converted to cm first
+    let cm_per_unit = config
+        .input_unit
+        .convert_to(ChannelUnit::cm, 1.0)
+        .map_err(|e| anyhow!("Could not use the input unit of the config : {e}"))?;
+    let mut invalid_values = vec![];
+    let checked = strokes
+        .map(|(stroke_index, stroke)| {
+            // points missing a value would be dropped silently
+            stroke
+                .check_aligned()
+                .map_err(|e| anyhow!("Could not write the stroke {stroke_index} : {e}"))?;
+            let stroke = if cm_per_unit == 1.0 {
+                Cow::Borrowed(stroke)
+            } else {
+                Cow::Owned(stroke.scaled(cm_per_unit))
+            };
+            // checked before the deduplication so that point indices match the input
+            invalid_values.extend(stroke.invalid_values(context, stroke_index, config.clamp));
+            let stroke = match config.dedup_epsilon {
+                Some(epsilon) => Cow::Owned(stroke.dedup_points(epsilon)),
+                None => stroke,
+            };
+            Ok(if config.clamp {
+                Cow::Owned(stroke.clamped(context))
+            } else {
+                stroke
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if invalid_values.is_empty() {
+        Ok(checked)
+    } else {
+        Err(InvalidStrokesError {
+            values: invalid_values,
+        }
+        .into())
+    }
+}
+
+/// writes a trace element with its raw channel data
+/// (`attributes` : other attributes of the trace, e.g. for continuations)
+#[allow(clippy::too_many_arguments)]
+fn write_trace<W: Write>(
+    writer: &mut EventWriter<W>,
+    context_id: &str,
+    brush_id: &str,
+    trace_type: TraceType,
+    data: &[ChannelData],
+    encoding: TraceEncoding,
+    precision: Option<usize>,
+    attributes: &[(&str, &str)],
+) -> anyhow::Result<()> {
+    let context_ref = format!("#{}", context_id);
+    let brush_ref = format!("#{}", brush_id);
+    let mut start = XmlEvent::start_element("trace");
+    for (name, value) in attributes {
+        start = start.attr(*name, value);
+    }
+    start = start
+        .attr("contextRef", context_ref.as_str())
+        .attr("brushRef", brush_ref.as_str());
+    // pen down is the default
+    let type_name = String::from(trace_type);
+    if trace_type != TraceType::PenDown {
+        start = start.attr("type", type_name.as_str());
+    }
+    writer.write(start)?;
+    writer.write(XmlEvent::characters(&format_trace(
+        data, encoding, precision,
+    )))?;
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+/// writes a formatted stroke as a trace, converting it to the channels of `context`
+/// Strokes longer than the `max_points_per_trace` of the config are split into
+/// continuation traces (`continuation` and `priorRef` attributes) with ids
+/// derived from `stroke_index`
+fn write_stroke<W: Write>(
+    writer: &mut EventWriter<W>,
+    stroke: &FormattedStroke,
+    context: &Context,
+    brush_id: &str,
+    stroke_index: usize,
+    config: &WriterConfig,
+) -> anyhow::Result<()> {
+    let time_offset = config.time_offset(stroke);
+    let data = stroke
+        .to_channel_data(context, time_offset.is_some())
+        .ok_or(anyhow!(
+            "Could not convert the stroke to the channels of the context {}",
+            context.name
+        ))?;
+    let len = stroke.len();
+    let max_points = match config.max_points_per_trace {
+        Some(max_points) if len > max_points => max_points,
+        _ => {
+            let time_offset = time_offset.map(|time_offset| time_offset.to_string());
+            return write_trace(
+                writer,
+                &context.name,
+                brush_id,
+                stroke.trace_type,
+                &data,
+                config.encoding,
+                config.decimal_precision,
+                &time_offset
+                    .iter()
+                    .map(|time_offset| ("timeOffset", time_offset.as_str()))
+                    .collect::<Vec<_>>(),
+            );
+        }
+    };
+    let has_t_channel = context.channel_exists(ChannelKind::T).is_some();
+
+    let number_parts = len.div_ceil(max_points);
+    for part in 0..number_parts {
+        let range = part * max_points..((part + 1) * max_points).min(len);
+        let id = format!("stroke{stroke_index}_{part}");
+        let prior_ref = format!("#stroke{stroke_index}_{}", part.saturating_sub(1));
+        let continuation = match part {
+            0 => Continuation::Begin,
+            part if part + 1 == number_parts => Continuation::End,
+            _ => Continuation::Middle,
+        };
+        let mut attributes = vec![
+            (config.id_attribute.name(), id.as_str()),
+            ("continuation", continuation.name()),
+        ];
+        if part > 0 {
+            attributes.push(("priorRef", prior_ref.as_str()));
+        }
+        // the T channel is relative to the start of the stroke, the times
+        // of a part without it start with the part
+        let part_offset = match (time_offset, &stroke.t) {
+            (Some(time_offset), Some(t)) if !has_t_channel => {
+                Some((time_offset + t[range.start] - t[0]).to_string())
+            }
+            (time_offset, _) => time_offset.map(|time_offset| time_offset.to_string()),
+        };
+        if let Some(part_offset) = &part_offset {
+            attributes.push(("timeOffset", part_offset.as_str()));
+        }
+        write_trace(
+            writer,
+            &context.name,
+            brush_id,
+            stroke.trace_type,
+            &data
+                .iter()
+                .map(|channel| channel.slice(range.clone()))
+                .collect::<Vec<ChannelData>>(),
+            config.encoding,
+            config.decimal_precision,
+            &attributes,
+        )?;
+    }
+    Ok(())
+}
+
+/// `traceGroup` elements of a parsed document written around its traces :
+/// the groups are opened in the order they were read (the order of their
+/// indices) and each trace is written inside of its group
+/// The groups only holding references to traces are written as `traceView`
+/// elements (the spec doesn't allow references in a `traceGroup`)
+struct GroupNesting<'a> {
+    groups: &'a [GroupInfo],
+    /// language of the document, inherited by the groups
+    lang: Option<&'a str>,
+    /// groups written as a `traceView`, by index
+    views: Vec<bool>,
+    /// open groups, from the outermost one
+    open: Vec<usize>,
+    /// index of the next group to open
+    next: usize,
+}
+
+impl<'a> GroupNesting<'a> {
+    fn new(document: &'a ParserResult) -> GroupNesting<'a> {
+        let groups = &document.groups;
+        let mut has_traces = vec![false; groups.len()];
+        for index in document
+            .context_brush_data_vec
+            .iter()
+            .filter_map(|trace| trace.group)
+        {
+            has_traces[index] = true;
+        }
+        // views hold references or other views, and no trace
+        // (the groups inside of a group come after it)
+        let mut views = vec![false; groups.len()];
+        let mut has_children = vec![false; groups.len()];
+        let mut only_views = vec![true; groups.len()];
+        for index in (0..groups.len()).rev() {
+            views[index] = !has_traces[index]
+                && only_views[index]
+                && (!groups[index].trace_refs.is_empty() || has_children[index]);
+            if let Some(parent) = groups[index].parent {
+                has_children[parent] = true;
+                only_views[parent] &= views[index];
+            }
+        }
+        GroupNesting {
+            groups,
+            lang: document.lang.as_deref(),
+            views,
+            open: vec![],
+            next: 0,
+        }
+    }
+
+    /// true if the group `index` is `ancestor` or is inside of it
+    fn is_in(&self, index: usize, ancestor: usize) -> bool {
+        let mut current = Some(index);
+        while let Some(index) = current {
+            if index == ancestor {
+                return true;
+            }
+            current = self.groups[index].parent;
+        }
+        false
+    }
+
+    /// opens the groups up to the group of the next trace (None : outside of all groups)
+    fn enter<W: Write>(
+        &mut self,
+        writer: &mut EventWriter<W>,
+        group: Option<usize>,
+    ) -> anyhow::Result<()> {
+        if let Some(index) = group {
+            while self.next <= index {
+                self.open_next(writer)?;
+            }
+        }
+        while !self.open.is_empty() && self.open.last().copied() != group {
+            self.close(writer)?;
+        }
+        Ok(())
+    }
+
+    /// writes the groups left and closes all of them
+    fn finish<W: Write>(&mut self, writer: &mut EventWriter<W>) -> anyhow::Result<()> {
+        while self.next < self.groups.len() {
+            self.open_next(writer)?;
+        }
+        while !self.open.is_empty() {
+            self.close(writer)?;
+        }
+        Ok(())
+    }
+
+    fn open_next<W: Write>(&mut self, writer: &mut EventWriter<W>) -> anyhow::Result<()> {
+        let index = self.next;
+        let group = &self.groups[index];
+        while !self.open.is_empty() && self.open.last().copied() != group.parent {
+            self.close(writer)?;
+        }
+        let mut start = XmlEvent::start_element(if self.views[index] {
+            "traceView"
+        } else {
+            "traceGroup"
+        });
+        if let Some(id) = &group.id {
+            start = start.attr(IdAttribute::default().name(), id);
+        }
+        // (only a language different from the inherited one is written)
+        let inherited = match group.parent {
+            Some(parent) => self.groups[parent].lang.as_deref(),
+            None => self.lang,
+        };
+        if let Some(lang) = group
+            .lang
+            .as_deref()
+            .filter(|lang| Some(*lang) != inherited)
+        {
+            start = start.attr("xml:lang", lang);
+        }
+        writer.write(start)?;
+        write_annotations(writer, &group.annotations)?;
+        for trace_ref in &group.trace_refs {
+            writer.write(
+                XmlEvent::start_element("traceView").attr("traceDataRef", &format!("#{trace_ref}")),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        self.open.push(index);
+        self.next = index + 1;
+        Ok(())
+    }
+
+    /// closes the innermost open group, after writing the groups read inside of it
+    fn close<W: Write>(&mut self, writer: &mut EventWriter<W>) -> anyhow::Result<()> {
+        let Some(&innermost) = self.open.last() else {
+            return Ok(());
+        };
+        while self.next < self.groups.len() && self.is_in(self.next, innermost) {
+            self.open_next(writer)?;
+        }
+        while self.open.last() != Some(&innermost) {
+            self.close(writer)?;
+        }
+        self.open.pop();
+        writer.write(XmlEvent::end_element())?; // end traceGroup or traceView
+        Ok(())
+    }
+}
+
+/// Writes a parsed document back to inkml : all of its timestamps, contexts and brushes
+/// (sorted by id), its annotations and its traces with their attributes, raw channel
+/// data and groups. Parsing the document written gives the same `ParserResult`
+pub fn write_parser_result(document: &ParserResult) -> anyhow::Result<Vec<u8>> {
+    let mut out_v: Vec<u8> = vec![];
+    let mut writer = EmitterConfig::new()
+        .perform_indent(false)
+        .write_document_declaration(false)
+        .create_writer(&mut out_v);
+
+    writer.write(ink_element(
+        document.document_id.as_deref(),
+        document.lang.as_deref(),
+    ))?;
+    write_annotations(&mut writer, &document.annotations)?;
+    writer.write(XmlEvent::start_element("definitions"))?;
+
+    // before the contexts, their channels can refer to them
+    let mut timestamp_ids = document.timestamps.keys().collect::<Vec<&String>>();
+    timestamp_ids.sort();
+    for timestamp_id in timestamp_ids {
         writer.write(
-            XmlEvent::start_element("trace")
-                .attr("contextRef", format!("#{}", context.name).as_str())
-                .attr("brushRef", format!("#{}", brush_id).as_str()),
+            XmlEvent::start_element("timestamp")
+                .attr(IdAttribute::default().name(), timestamp_id)
+                .attr("time", &format!("{}", document.timestamps[timestamp_id])),
         )?;
+        writer.write(XmlEvent::end_element())?;
+    }
+
+    let mut context_ids = document.context_dict.keys().collect::<Vec<&String>>();
+    context_ids.sort();
+    for context_id in context_ids {
+        let mut context = document.context_dict[context_id].clone();
+        // the context is written with the id traces refer to it with
+        context.name = context_id.clone();
+        context.write(&mut writer, IdAttribute::default())?;
+    }
 
-        formatted_stroke.write(&mut writer)?;
+    let mut brush_ids = document.context_brush.keys().collect::<Vec<&String>>();
+    brush_ids.sort();
+    for brush_id in brush_ids {
+        document.context_brush[brush_id]
+            .clone()
+            .with_name(brush_id.clone())
+            .write(&mut writer, IdAttribute::default())?;
     }
+    write_extensions(
+        &mut writer,
+        &document.extensions,
+        true,
+        IdAttribute::default(),
+    )?;
+    writer.write(XmlEvent::end_element())?; // end definitions
+
+    write_extensions(
+        &mut writer,
+        &document.extensions,
+        false,
+        IdAttribute::default(),
+    )?;
+
+    let mut nesting = GroupNesting::new(document);
+    for trace in &document.context_brush_data_vec {
+        nesting.enter(&mut writer, trace.group)?;
+        let time_offset = trace.time_offset.map(|time_offset| time_offset.to_string());
+        let duration = trace.duration.map(|duration| duration.to_string());
+        let prior_ref = trace
+            .prior_ref
+            .as_ref()
+            .map(|prior_ref| format!("#{prior_ref}"));
+        let attributes = [
+            (IdAttribute::default().name(), trace.id.as_deref()),
+            ("timeOffset", time_offset.as_deref()),
+            ("duration", duration.as_deref()),
+            (
+                "continuation",
+                trace.continuation.map(|continuation| continuation.name()),
+            ),
+            ("priorRef", prior_ref.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value)))
+        .collect::<Vec<(&str, &str)>>();
+        write_trace(
+            &mut writer,
+            &trace.context_id,
+            &trace.brush_id,
+            trace.trace_type,
+            &trace.data,
+            TraceEncoding::Explicit,
+            None,
+            &attributes,
+        )?;
+    }
+    nesting.finish(&mut writer)?;
 
     writer.write(XmlEvent::end_element())?; // end ink
+    Ok(out_v)
+}
 
-    // copy to clipboard (for testing purposes only)
-    #[cfg(feature = "clipboard")]
+/// Checks a document produced by the writers (or by another pipeline) : its
+/// structure against the InkML spec (see `validate`) then that the strict parser
+/// reads it and converts its traces to strokes
+/// An empty list means the document is conformant, e.g. for the tests of
+/// applications writing ink
+pub fn validate_output(document: &[u8]) -> Vec<ValidationIssue> {
+    let mut issues = validate(document);
+    if issues
+        .iter()
+        .any(|issue| issue.kind == ValidationIssueKind::MalformedXml)
     {
-        let mimetype_windows = String::from("InkML Format");
-        let content: Vec<ClipboardContent> =
-            vec![ClipboardContent::Other(mimetype_windows, out_v.to_owned())];
-        let ctx = ClipboardContext::new().unwrap();
-        let _ = ctx.set(content);
+        return issues;
+    }
+    let parsed = parser_with_options(document, &ParserOptions::default().strict(true))
+        .and_then(|result| result.formatted(&FormatOptions::default()));
+    if let Err(e) = parsed {
+        issues.push(ValidationIssue {
+            kind: ValidationIssueKind::ParserError,
+            position: String::new(),
+            message: format!("{e:#}"),
+        });
+    }
+    issues
+}
+
+/// Adds strokes to a parsed document then serialises it (see `write_parser_result`)
+///
+/// Existing brushes with the same properties and existing contexts able to hold
+/// the strokes (X, Y and F channels) are reused, so that saving repeatedly
+/// doesn't duplicate definitions. Otherwise new ones are added to the document
+///
+/// Strokes without points are skipped.
+/// If some strokes have values out of the range of their context, an
+/// `InvalidStrokesError` is returned and the document is left untouched
+pub fn append_strokes(
+    existing: &mut ParserResult,
+    new: Vec<(FormattedStroke, Brush)>,
+) -> anyhow::Result<Vec<u8>> {
+    // work on a copy so that nothing is added on error
+    let mut document = existing.clone();
+    let mut invalid_values = vec![];
+    for (stroke_index, (stroke, brush)) in new.into_iter().enumerate() {
+        if stroke.is_empty() {
+            continue;
+        }
+        // find a context, taking the first compatible one by id for determinism
+        let mut context_ids = document
+            .context_dict
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>();
+        context_ids.sort();
+        // (only the ones with X, Y and F alone, the others would get made up values)
+        let compatible = context_ids.into_iter().find_map(|context_id| {
+            let context = &document.context_dict[&context_id];
+            if context.channel_list.len() != 3 {
+                return None;
+            }
+            stroke
+                .to_channel_data(context, false)
+                .map(|data| (context_id, data))
+        });
+        let (context_id, data) = match compatible {
+            Some(found) => found,
+            None => {
+                let context_id = (0..)
+                    .map(|index| format!("ctx{index}"))
+                    .find(|candidate| !document.context_dict.contains_key(candidate))
+                    .unwrap();
+                let mut context =
+                    Context::default_with_pressure(DEFAULT_RESOLUTION, DEFAULT_PRESSURE_MAX);
+                context.name = context_id.clone();
+                let data = stroke.to_channel_data(&context, false).ok_or(anyhow!(
+                    "Could not convert the stroke to the default context"
+                ))?;
+                document.context_dict.insert(context_id.clone(), context);
+                (context_id, data)
+            }
+        };
+
+        let invalid =
+            stroke.invalid_values(&document.context_dict[&context_id], stroke_index, false);
+        if !invalid.is_empty() {
+            invalid_values.extend(invalid);
+            continue;
+        }
+
+        // find a brush with the same properties
+        let mut brush_ids = document
+            .context_brush
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>();
+        brush_ids.sort();
+        let brush_key = brush.duplicate_key();
+        let brush_id = match brush_ids
+            .into_iter()
+            .find(|brush_id| document.context_brush[brush_id].duplicate_key() == brush_key)
+        {
+            Some(brush_id) => brush_id,
+            None => {
+                let brush_id = (1..)
+                    .map(|index| format!("br{index}"))
+                    .find(|candidate| !document.context_brush.contains_key(candidate))
+                    .unwrap();
+                document
+                    .context_brush
+                    .insert(brush_id.clone(), brush.with_name(brush_id.clone()));
+                brush_id
+            }
+        };
+
+        document.context_brush_data_vec.push(RawTrace {
+            id: None,
+            context_id: document.symbols.intern(&context_id),
+            brush_id: document.symbols.intern(&brush_id),
+            trace_type: stroke.trace_type,
+            time_offset: None,
+            duration: None,
+            group: None,
+            continuation: None,
+            prior_ref: None,
+            data,
+        });
+    }
+    if !invalid_values.is_empty() {
+        return Err(InvalidStrokesError {
+            values: invalid_values,
+        }
+        .into());
+    }
+    *existing = document;
+    write_parser_result(existing)
+}
+
+/// Writer sending each stroke to the output as soon as it's pushed
+/// (to record live pen input without keeping the whole session in memory)
+///
+/// The context is written up front in the definitions. As brushes are only
+/// known when strokes arrive, each new brush is written just before the first
+/// trace using it, in its own `definitions` element (the `ink` element can
+/// hold several of them, but no brush directly)
+pub struct InkmlStreamWriter<W: Write> {
+    writer: EventWriter<W>,
+    context: Context,
+    brush_collection: BrushCollection,
+    config: WriterConfig,
+    /// number of strokes pushed so far
+    number_strokes: usize,
+    /// id of the last part of the stroke pushed in several parts, if it isn't ended
+    open_part: Option<String>,
+}
+
+impl<W: Write> InkmlStreamWriter<W> {
+    /// writes the header and definitions to `output`
+    pub fn new(output: W) -> anyhow::Result<InkmlStreamWriter<W>> {
+        InkmlStreamWriter::with_config(output, &WriterConfig::default())
+    }
+
+    /// Same as `new` with a config
+    pub fn with_config(output: W, config: &WriterConfig) -> anyhow::Result<InkmlStreamWriter<W>> {
+        let mut writer = EmitterConfig::new()
+            .perform_indent(config.pretty)
+            .write_document_declaration(false)
+            .create_writer(output);
+
+        writer.write(ink_element(
+            config.document_id.as_deref(),
+            config.lang.as_deref(),
+        ))?;
+        writer.write(XmlEvent::start_element("definitions"))?;
+        let context = config.context();
+        // the strokes to fit aren't known yet
+        let page = config.canvas_page(std::iter::empty());
+        context.write_with_canvas(&mut writer, config.id_attribute, page.as_ref())?;
+        write_extensions(&mut writer, &config.extensions, true, config.id_attribute)?;
+        writer.write(XmlEvent::end_element())?; // end definitions
+        write_extensions(&mut writer, &config.extensions, false, config.id_attribute)?;
+        writer.inner_mut().flush()?;
+
+        Ok(InkmlStreamWriter {
+            writer,
+            context,
+            brush_collection: BrushCollection::new(config.preserve_brush_ids),
+            config: config.clone(),
+            number_strokes: 0,
+            open_part: None,
+        })
+    }
+
+    /// writes the stroke (and its brush if it's a new one) then flushes the output
+    /// A stroke without points is skipped (unless the config says otherwise)
+    /// and a stroke with values out of the range of the context is not written
+    /// (`InvalidStrokesError`, with the index of the stroke among the pushed ones)
+    pub fn push_stroke(&mut self, stroke: &FormattedStroke, brush: &Brush) -> anyhow::Result<()> {
+        self.push(stroke, brush, None)
+    }
+
+    /// writes a part of a stroke still being drawn, as a trace with a `continuation`
+    /// attribute : the parts after the first one refer to the previous one with
+    /// `priorRef` so that readers join them back into one stroke
+    /// Parts are written as given, even empty or longer than `max_points_per_trace`
+    pub fn push_stroke_part(
+        &mut self,
+        stroke: &FormattedStroke,
+        brush: &Brush,
+        continuation: Continuation,
+    ) -> anyhow::Result<()> {
+        match (continuation, &self.open_part) {
+            (Continuation::Begin, Some(_)) => Err(anyhow!(
+                "A new stroke was begun before the end of the previous one"
+            )),
+            (Continuation::Middle | Continuation::End, None) => {
+                Err(anyhow!("A part of a stroke was pushed without a beginning"))
+            }
+            _ => self.push(stroke, brush, Some(continuation)),
+        }
+    }
+
+    fn push(
+        &mut self,
+        stroke: &FormattedStroke,
+        brush: &Brush,
+        continuation: Option<Continuation>,
+    ) -> anyhow::Result<()> {
+        let stroke_index = self.number_strokes;
+        self.number_strokes += 1;
+        if stroke.is_empty() && !self.config.write_empty_strokes && continuation.is_none() {
+            return Ok(());
+        }
+        let stroke = checked_strokes(
+            std::iter::once((stroke_index, stroke)),
+            &self.context,
+            &self.config,
+        )?
+        .remove(0);
+
+        let number_brushes = self.brush_collection.brushes().len();
+        let brush_id = self.brush_collection.add_brush(brush);
+        if self.brush_collection.brushes().len() > number_brushes {
+            // new brush
+            self.writer.write(XmlEvent::start_element("definitions"))?;
+            self.brush_collection.brushes()[number_brushes].write_with_transparency(
+                &mut self.writer,
+                self.config.id_attribute,
+                self.config.black_transparency,
+            )?;
+            self.writer.write(XmlEvent::end_element())?; // end definitions
+        }
+
+        match continuation {
+            None => write_stroke(
+                &mut self.writer,
+                &stroke,
+                &self.context,
+                &brush_id,
+                stroke_index,
+                &self.config,
+            )?,
+            Some(continuation) => {
+                let time_offset = self.config.time_offset(&stroke);
+                let data = stroke
+                    .to_channel_data(&self.context, time_offset.is_some())
+                    .ok_or(anyhow!(
+                        "Could not convert the stroke to the channels of the context {}",
+                        self.context.name
+                    ))?;
+                let time_offset = time_offset.map(|time_offset| time_offset.to_string());
+                let id = format!("stroke{stroke_index}");
+                let prior_ref = self.open_part.as_ref().map(|prior| format!("#{prior}"));
+                let mut attributes = vec![
+                    (self.config.id_attribute.name(), id.as_str()),
+                    ("continuation", continuation.name()),
+                ];
+                if let Some(prior_ref) = &prior_ref {
+                    attributes.push(("priorRef", prior_ref.as_str()));
+                }
+                if let Some(time_offset) = &time_offset {
+                    attributes.push(("timeOffset", time_offset.as_str()));
+                }
+                write_trace(
+                    &mut self.writer,
+                    &self.context.name,
+                    &brush_id,
+                    stroke.trace_type,
+                    &data,
+                    self.config.encoding,
+                    self.config.decimal_precision,
+                    &attributes,
+                )?;
+                self.open_part = match continuation {
+                    Continuation::End => None,
+                    _ => Some(id),
+                };
+            }
+        }
+        self.writer.inner_mut().flush()?;
+        Ok(())
+    }
+
+    /// closes the document and gives back the output
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        self.writer.write(XmlEvent::end_element())?; // end ink
+        self.writer.inner_mut().flush()?;
+        Ok(self.writer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ChannelType;
+    use crate::parser::{parse_bytes, parse_formatted_bytes};
+    use crate::tree::TraceGroup;
+
+    fn strokes() -> Vec<(FormattedStroke, Brush)> {
+        let stroke = FormattedStroke::new(
+            vec![1.0, 1.0123, 1.0251, 1.04],
+            vec![2.0, 2.5, 3.0, 3.25],
+            vec![0.25, 0.5, 0.75, 1.0],
+        )
+        .unwrap();
+        vec![(
+            stroke,
+            Brush::init(String::from("br0"), (0, 0, 255), false, 0, 0.1),
+        )]
+    }
+
+    #[test]
+    fn positions_are_rounded_to_the_resolution() {
+        let config = WriterConfig::default().resolution(100.0);
+        let written = writer_with_config(strokes(), &config).unwrap();
+        let read = parse_formatted_bytes(&written).unwrap();
+        assert_eq!(read[0].0.x(), [1.0, 1.01, 1.03, 1.04]);
+
+        let context = parse_bytes(&written)
+            .unwrap()
+            .context("ctx0")
+            .unwrap()
+            .clone();
+        assert_eq!(context.channel_list[0].resolution_value, 100.0);
+    }
+
+    #[test]
+    fn encodings_give_the_same_strokes() {
+        let read = |written: &[u8]| {
+            parse_formatted_bytes(written)
+                .unwrap()
+                .into_iter()
+                .map(|(stroke, _)| {
+                    (
+                        stroke.x().to_vec(),
+                        stroke.y().to_vec(),
+                        stroke.f().to_vec(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let explicit = writer_with_config(strokes(), &WriterConfig::default()).unwrap();
+        let expected = read(&explicit);
+        for encoding in [
+            TraceEncoding::SingleDifference,
+            TraceEncoding::DoubleDifference,
+        ] {
+            let config = WriterConfig::default().encoding(encoding);
+            let written = writer_with_config(strokes(), &config).unwrap();
+            assert!(written.len() < explicit.len());
+            assert_eq!(read(&written), expected);
+        }
+    }
+
+    #[test]
+    fn decimal_channels_keep_their_precision() {
+        let config = WriterConfig::default().resolution(1.0).decimal(3);
+        let written = writer_with_config(strokes(), &config).unwrap();
+        let context = parse_bytes(&written)
+            .unwrap()
+            .context("ctx0")
+            .unwrap()
+            .clone();
+        assert_eq!(context.channel_list[0].types, ChannelType::Decimal);
+        assert_eq!(context.channel_list[1].types, ChannelType::Decimal);
+
+        let text = String::from_utf8(written.clone()).unwrap();
+        assert!(text.contains("1.012 2.5"), "{text}");
+        let read = parse_formatted_bytes(&written).unwrap();
+        assert_eq!(read[0].0.x(), [1.0, 1.012, 1.025, 1.04]);
+    }
+
+    /// the valid strokes with a second one having `f` as its pressures
+    fn with_pressures(f: Vec<f64>) -> Vec<(FormattedStroke, Brush)> {
+        let mut strokes = strokes();
+        let stroke = FormattedStroke::new(vec![0.0; f.len()], vec![0.0; f.len()], f).unwrap();
+        strokes.push((stroke, strokes[0].1.clone()));
+        strokes
+    }
+
+    #[test]
+    fn values_out_of_their_channel_are_reported() {
+        let error = writer(with_pressures(vec![0.5, -0.25, 1.5])).unwrap_err();
+        let error = error.downcast::<InvalidStrokesError>().unwrap();
+        assert_eq!(error.stroke_indices(), [1]);
+        let values = error
+            .values
+            .iter()
+            .map(|value| (value.point_index, value.channel.as_str(), value.value))
+            .collect::<Vec<_>>();
+        assert_eq!(values, [(1, "F", -0.25), (2, "F", 1.5)]);
+    }
+
+    #[test]
+    fn values_out_of_their_channel_are_clamped() {
+        let config = WriterConfig::default().clamp(true);
+        let written = writer_with_config(with_pressures(vec![1.0, -0.25, 1.5]), &config).unwrap();
+        let read = parse_formatted_bytes(&written).unwrap();
+        assert_eq!(read[1].0.f(), [1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn document_id_and_languages_are_written() {
+        let config = WriterConfig::default()
+            .document_id("urn:doc:1")
+            .lang("fr-CA");
+        let written = writer_with_config(strokes(), &config).unwrap();
+        let read = parse_bytes(&written).unwrap();
+        assert_eq!(read.document_id(), Some("urn:doc:1"));
+        assert_eq!(read.lang(), Some("fr-CA"));
+
+        // groups only get a language differing from the enclosing one
+        let (stroke, brush) = strokes().remove(0);
+        let group = |lang: &str, children: Vec<InkNode>| {
+            InkNode::Group(TraceGroup {
+                id: None,
+                lang: Some(String::from(lang)),
+                annotations: vec![],
+                children,
+            })
+        };
+        let nodes = [group(
+            "fr-CA",
+            vec![group("en", vec![InkNode::Stroke(stroke, brush)])],
+        )];
+        let written = String::from_utf8(writer_tree(&nodes, &config).unwrap()).unwrap();
+        assert_eq!(
+            written.matches("xml:lang=\"fr-CA\"").count(),
+            1,
+            "{written}"
+        );
+        assert_eq!(written.matches("xml:lang=\"en\"").count(), 1, "{written}");
+
+        let tree = parse_bytes(written.as_bytes())
+            .unwrap()
+            .formatted_tree(&FormatOptions::default())
+            .unwrap();
+        let InkNode::Group(outer) = &tree[0] else {
+            panic!("{tree:?}");
+        };
+        let InkNode::Group(inner) = &outer.children[0] else {
+            panic!("{tree:?}");
+        };
+        // inherited from the document
+        assert_eq!(outer.lang.as_deref(), Some("fr-CA"));
+        assert_eq!(inner.lang.as_deref(), Some("en"));
     }
-    Ok(out_v)
 }
@@ -0,0 +1,351 @@
+// decoding of the documents to UTF-8 before the xml reader, so that both
+// backends read documents saved in UTF-16 (e.g. by OneNote) or Latin-1
+//
+// The encoding is found from the byte order mark, from the first bytes of
+// the document (`<?` in UTF-16 without a BOM) and from the `encoding` of the
+// xml declaration. The declaration is rewritten to `UTF-8` when the document
+// is converted. UTF-8 documents are given as is (without their BOM)
+
+#[cfg(feature = "fast-xml")]
+use std::borrow::Cow;
+use std::io::{self, Read};
+
+/// the declaration is looked for in the first bytes only
+const MAX_DECLARATION_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    /// encoding given by the byte order mark or the first characters,
+    /// with the length of the BOM
+    fn sniff(start: &[u8]) -> (Encoding, usize) {
+        match start {
+            [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+            [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+            [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+            // `<?` without a BOM
+            [b'<', 0, b'?', 0, ..] => (Encoding::Utf16Le, 0),
+            [0, b'<', 0, b'?', ..] => (Encoding::Utf16Be, 0),
+            _ => (Encoding::Utf8, 0),
+        }
+    }
+
+    /// encoding of an `encoding` declaration, None for the ones
+    /// left to the reader
+    fn from_label(label: &str) -> Option<Encoding> {
+        match label.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" => Some(Encoding::Utf16Le),
+            "utf-16be" => Some(Encoding::Utf16Be),
+            "iso-8859-1" | "latin1" | "latin-1" | "us-ascii" | "ascii" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+}
+
+/// reader giving the document in UTF-8, see the top of the module
+pub(crate) struct Utf8Reader<R: Read> {
+    inner: R,
+    /// None until the start of the document is read
+    encoding: Option<Encoding>,
+    /// bytes converted but not given yet
+    decoded: Vec<u8>,
+    decoded_start: usize,
+    /// bytes read but not converted yet (the end of a character)
+    undecoded: Vec<u8>,
+}
+
+impl<R: Read> Utf8Reader<R> {
+    pub(crate) fn new(inner: R) -> Utf8Reader<R> {
+        Utf8Reader {
+            inner,
+            encoding: None,
+            decoded: vec![],
+            decoded_start: 0,
+            undecoded: vec![],
+        }
+    }
+
+    /// reads up to `len` more bytes in `undecoded`, false at the end of the input
+    fn fill(&mut self, len: usize) -> io::Result<bool> {
+        let start = self.undecoded.len();
+        self.undecoded.resize(start + len, 0);
+        let read = loop {
+            match self.inner.read(&mut self.undecoded[start..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.undecoded
+            .truncate(start + read.as_ref().copied().unwrap_or(0));
+        Ok(read? > 0)
+    }
+
+    /// finds the encoding and converts the xml declaration
+    fn read_start(&mut self) -> io::Result<()> {
+        while self.undecoded.len() < 4 && self.fill(4 - self.undecoded.len())? {}
+        let (mut encoding, bom) = Encoding::sniff(&self.undecoded);
+        self.undecoded.drain(..bom);
+
+        // the declaration is in ASCII, it's read before knowing the
+        // encoding it declares
+        let mut start = ascii_start(encoding, &self.undecoded);
+        while (start.starts_with("<?xml") || "<?xml".starts_with(start.as_str()))
+            && !start.contains("?>")
+            && start.len() < MAX_DECLARATION_LEN
+            && self.fill(64)?
+        {
+            start = ascii_start(encoding, &self.undecoded);
+        }
+        let Some(end) = start
+            .starts_with("<?xml")
+            .then(|| start.find("?>"))
+            .flatten()
+        else {
+            self.encoding = Some(encoding);
+            return Ok(());
+        };
+        let mut declaration = start[..end + 2].to_owned();
+        let unit_len = match encoding {
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+            Encoding::Utf8 | Encoding::Latin1 => 1,
+        };
+        self.undecoded.drain(..declaration.len() * unit_len);
+        if let Some((label_start, label_end)) = declared_encoding(&declaration) {
+            let declared = Encoding::from_label(&declaration[label_start..label_end]);
+            // the byte order mark wins over the declaration
+            if encoding == Encoding::Utf8 && declared == Some(Encoding::Latin1) {
+                encoding = Encoding::Latin1;
+            }
+            if encoding != Encoding::Utf8 {
+                declaration.replace_range(label_start..label_end, "UTF-8");
+            }
+        }
+        self.decoded = declaration.into_bytes();
+        self.encoding = Some(encoding);
+        Ok(())
+    }
+}
+
+/// the document in UTF-8, borrowed when it already is (without its BOM)
+#[cfg(feature = "fast-xml")]
+pub(crate) fn utf8_document(bytes: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    let mut reader = Utf8Reader::new(bytes);
+    reader.read_start()?;
+    if reader.encoding == Some(Encoding::Utf8) {
+        let (_, bom) = Encoding::sniff(bytes);
+        return Ok(Cow::Borrowed(&bytes[bom..]));
+    }
+    let mut document = vec![];
+    reader.read_to_end(&mut document)?;
+    Ok(Cow::Owned(document))
+}
+
+/// the characters at the start of `bytes` up to the first one that isn't ASCII
+fn ascii_start(encoding: Encoding, bytes: &[u8]) -> String {
+    let units: Box<dyn Iterator<Item = u16>> = match encoding {
+        Encoding::Utf16Le => Box::new(
+            bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]])),
+        ),
+        Encoding::Utf16Be => Box::new(
+            bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]])),
+        ),
+        Encoding::Utf8 | Encoding::Latin1 => Box::new(bytes.iter().map(|&byte| u16::from(byte))),
+    };
+    units
+        .take_while(|unit| *unit < 0x80)
+        .map(|unit| char::from(unit as u8))
+        .collect()
+}
+
+impl<R: Read> Read for Utf8Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => {
+                self.read_start()?;
+                self.encoding.unwrap_or(Encoding::Utf8)
+            }
+        };
+        loop {
+            if self.decoded_start < self.decoded.len() {
+                let len = buf.len().min(self.decoded.len() - self.decoded_start);
+                buf[..len]
+                    .copy_from_slice(&self.decoded[self.decoded_start..self.decoded_start + len]);
+                self.decoded_start += len;
+                return Ok(len);
+            }
+            self.decoded.clear();
+            self.decoded_start = 0;
+            if encoding == Encoding::Utf8 {
+                // bytes read with the declaration first
+                if !self.undecoded.is_empty() {
+                    self.decoded = std::mem::take(&mut self.undecoded);
+                    continue;
+                }
+                return self.inner.read(buf);
+            }
+            let at_end = !self.fill(buf.len().max(64))?;
+            self.decoded = decode(encoding, &mut self.undecoded, at_end)?.into_bytes();
+            if self.decoded.is_empty() && at_end {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// position of the value of the `encoding` in the declaration
+fn declared_encoding(declaration: &str) -> Option<(usize, usize)> {
+    let after_name = declaration.find("encoding")? + "encoding".len();
+    let rest = &declaration[after_name..];
+    let quote_index = rest.find(['"', '\''])?;
+    if rest[..quote_index].trim() != "=" {
+        return None;
+    }
+    let quote = rest.as_bytes()[quote_index] as char;
+    let label_start = after_name + quote_index + 1;
+    let label_len = declaration[label_start..].find(quote)?;
+    Some((label_start, label_start + label_len))
+}
+
+/// converts the complete characters of `bytes`, the end of a character cut
+/// by the end of the buffer is left in it (unless the input is `at_end`)
+fn decode(encoding: Encoding, bytes: &mut Vec<u8>, at_end: bool) -> io::Result<String> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+    let (text, used) = match encoding {
+        // (not used : UTF-8 documents are given as is by the reader)
+        Encoding::Utf8 => (String::from_utf8_lossy(bytes).into_owned(), bytes.len()),
+        Encoding::Latin1 => (
+            bytes.iter().map(|&byte| char::from(byte)).collect(),
+            bytes.len(),
+        ),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let mut units = bytes
+                .chunks_exact(2)
+                .map(|pair| match encoding {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                })
+                .collect::<Vec<u16>>();
+            // a high surrogate waits for the rest of its pair
+            if !at_end
+                && units
+                    .last()
+                    .is_some_and(|unit| (0xD800..0xDC00).contains(unit))
+            {
+                units.pop();
+            }
+            if at_end && bytes.len() % 2 == 1 {
+                return Err(invalid("The UTF-16 document has an odd number of bytes"));
+            }
+            let text = char::decode_utf16(units.iter().copied())
+                .collect::<Result<String, _>>()
+                .map_err(|_| invalid("The document is not valid UTF-16"))?;
+            (text, units.len() * 2)
+        }
+    };
+    bytes.drain(..used);
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// reader giving one byte at a time, to cut the characters and the declaration
+    struct ByteByByte<'a>(&'a [u8]);
+
+    impl Read for ByteByByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((byte, rest)), Some(first)) => {
+                    *first = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn converted(bytes: &[u8]) -> io::Result<String> {
+        let mut text = String::new();
+        Utf8Reader::new(ByteByByte(bytes)).read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    fn utf16(text: &str, little_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| match little_endian {
+                true => unit.to_le_bytes(),
+                false => unit.to_be_bytes(),
+            })
+            .collect()
+    }
+
+    const DOCUMENT: &str = "<ink>é 𝄞</ink>";
+
+    #[test]
+    fn utf8_documents_lose_their_bom_only() {
+        let with_bom = [&[0xEF, 0xBB, 0xBF], DOCUMENT.as_bytes()].concat();
+        assert_eq!(converted(&with_bom).unwrap(), DOCUMENT);
+        let declared = r#"<?xml version="1.0" encoding="utf-8"?><ink>é</ink>"#;
+        assert_eq!(converted(declared.as_bytes()).unwrap(), declared);
+    }
+
+    #[test]
+    fn utf16_documents_are_converted() {
+        let declared = r#"<?xml version="1.0" encoding="UTF-16"?>"#;
+        let expected = format!(r#"<?xml version="1.0" encoding="UTF-8"?>{DOCUMENT}"#);
+        let text = format!("{declared}{DOCUMENT}");
+
+        let little_endian = [&[0xFF, 0xFE][..], &utf16(&text, true)].concat();
+        assert_eq!(converted(&little_endian).unwrap(), expected);
+        let big_endian = [&[0xFE, 0xFF][..], &utf16(&text, false)].concat();
+        assert_eq!(converted(&big_endian).unwrap(), expected);
+        // without a BOM, from the `<?` of the declaration
+        assert_eq!(converted(&utf16(&text, true)).unwrap(), expected);
+        assert_eq!(converted(&utf16(&text, false)).unwrap(), expected);
+    }
+
+    #[test]
+    fn latin1_documents_are_converted() {
+        let mut document = br#"<?xml version="1.0" encoding="ISO-8859-1"?><ink>"#.to_vec();
+        document.extend([0xE9, b'<', b'/']);
+        document.extend(b"ink>");
+        assert_eq!(
+            converted(&document).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><ink>é</ink>"#
+        );
+    }
+
+    #[test]
+    fn invalid_utf16_is_an_error() {
+        let odd = [&[0xFF, 0xFE][..], &utf16(DOCUMENT, true), b"<"].concat();
+        assert!(converted(&odd).is_err());
+        // lone low surrogate
+        let lone = [&[0xFF, 0xFE][..], &utf16("<ink>", true), &[0x00, 0xDC]].concat();
+        assert!(converted(&lone).is_err());
+    }
+
+    #[test]
+    fn utf16_documents_are_parsed() {
+        let document = r#"<ink xmlns="http://www.w3.org/2003/InkML">
+            <traceFormat><channel name="X" type="integer" /><channel name="Y" type="integer" /></traceFormat>
+            <trace>10 20</trace>
+        </ink>"#;
+        let bytes = [&[0xFF, 0xFE][..], &utf16(document, true)].concat();
+        let strokes = crate::parser::parse_formatted_bytes(&bytes).unwrap();
+        assert_eq!(strokes[0].0.x(), [0.01]);
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::anyhow;
 use xml::attribute::OwnedAttribute;
 
 pub(crate) fn get_id(attributes: &[OwnedAttribute], match_string: String) -> Option<String> {
@@ -8,6 +9,30 @@ pub(crate) fn get_id(attributes: &[OwnedAttribute], match_string: String) -> Opt
         .next()
 }
 
+/// id of an element : its `xml:id`, else its `id`, else an id with another prefix
+pub(crate) fn get_element_id(attributes: &[OwnedAttribute]) -> Option<String> {
+    let ids = || {
+        attributes
+            .iter()
+            .filter(|attribute| attribute.name.local_name == "id")
+    };
+    ids()
+        .find(|attribute| attribute.name.prefix.as_deref() == Some("xml"))
+        .or_else(|| ids().find(|attribute| attribute.name.prefix.is_none()))
+        .or_else(|| ids().next())
+        .map(|attribute| attribute.value.clone())
+}
+
+/// `xml:lang` attribute of an element
+pub(crate) fn get_lang(attributes: &[OwnedAttribute]) -> Option<String> {
+    attributes
+        .iter()
+        .find(|attribute| {
+            attribute.name.local_name == "lang" && attribute.name.prefix.as_deref() == Some("xml")
+        })
+        .map(|attribute| attribute.value.clone())
+}
+
 /// gets the attributes we asked for in that order
 pub(crate) fn get_ids(
     attributes: Vec<OwnedAttribute>,
@@ -26,6 +51,21 @@ pub(crate) fn get_ids(
         .collect()
 }
 
+/// parses a boolean property value (`1`, `0`, `true` or `false`)
+/// `property` is the name of the property, for error messages
+pub(crate) fn parse_bool(value: &Option<String>, property: &str) -> anyhow::Result<bool> {
+    match value {
+        Some(bool_str) => match bool_str.as_str() {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            _ => Err(anyhow!(
+                "Unexpected value for the boolean, expected 1,0,true of false, found {bool_str}"
+            )),
+        },
+        None => Err(anyhow!("No value was found to set {property}")),
+    }
+}
+
 pub(crate) fn verify_channel_properties(ids: &[Option<String>]) -> bool {
     if ids.iter().all(|new| new.is_some()) {
         // we have verified all of the information is there
@@ -41,3 +81,15 @@ pub(crate) fn verify_channel_properties(ids: &[Option<String>]) -> bool {
     }
     false
 }
+
+/// splits a reference (`contextRef`, `brushRef`) into the document it
+/// points to (if it's not the current one) and the id
+/// - `#ctx0` or `ctx0` gives `(None, "ctx0")`
+/// - `defs.inkml#ctx1` gives `(Some("defs.inkml"), "ctx1")`
+pub(crate) fn split_reference(reference: &str) -> (Option<&str>, &str) {
+    match reference.split_once('#') {
+        Some(("", id)) => (None, id),
+        Some((uri, id)) => (Some(uri), id),
+        None => (None, reference),
+    }
+}
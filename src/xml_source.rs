@@ -0,0 +1,266 @@
+// source of the xml events read by the parser
+// - xml-rs (default)
+// - quick-xml with the `fast-xml` feature (faster on big documents)
+// Both give the events in the xml-rs format so that the parser
+// doesn't depend on the backend
+// The documents are converted to UTF-8 before the backend (see `xml_encoding`)
+
+use anyhow::anyhow;
+use std::fmt;
+#[cfg(feature = "fast-xml")]
+use std::io::BufReader;
+use std::io::Read;
+use xml::common::{Position, TextPosition};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::xml_encoding::Utf8Reader;
+
+/// reader of xml events
+pub(crate) trait XmlSource {
+    /// the next event (`EndDocument` once the whole document is read)
+    fn next_event(&mut self) -> anyhow::Result<XmlEvent>;
+    /// position of the last event read, for error messages
+    fn position(&self) -> XmlPosition;
+}
+
+/// position in the document
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum XmlPosition {
+    /// line and column
+    Text(TextPosition),
+    /// offset in bytes
+    #[cfg_attr(not(feature = "fast-xml"), allow(unused))]
+    Offset(u64),
+}
+
+impl fmt::Display for XmlPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlPosition::Text(position) => write!(f, "{position}"),
+            XmlPosition::Offset(offset) => write!(f, "byte {offset}"),
+        }
+    }
+}
+
+/// source using the backend selected by the features
+#[cfg(not(feature = "fast-xml"))]
+pub(crate) fn xml_source<R: Read>(source: R) -> impl XmlSource {
+    XmlRsSource::new(Utf8Reader::new(source))
+}
+
+/// source using the backend selected by the features
+#[cfg(feature = "fast-xml")]
+pub(crate) fn xml_source<R: Read>(source: R) -> impl XmlSource {
+    QuickXmlSource::new(BufReader::new(Utf8Reader::new(source)))
+}
+
+/// source for a document already in memory
+#[cfg(not(feature = "fast-xml"))]
+pub(crate) fn xml_source_bytes(bytes: &[u8]) -> anyhow::Result<impl XmlSource + '_> {
+    Ok(xml_source(bytes))
+}
+
+/// source for a document already in memory, read in place by quick-xml
+/// (copied only when it has to be converted to UTF-8)
+#[cfg(feature = "fast-xml")]
+pub(crate) fn xml_source_bytes(bytes: &[u8]) -> anyhow::Result<impl XmlSource + '_> {
+    let document = crate::xml_encoding::utf8_document(bytes)?;
+    Ok(QuickXmlSource::new(std::io::Cursor::new(document)))
+}
+
+/// xml-rs backend
+#[cfg_attr(feature = "fast-xml", allow(dead_code))]
+pub(crate) struct XmlRsSource<R: Read> {
+    reader: EventReader<R>,
+}
+
+#[cfg_attr(feature = "fast-xml", allow(dead_code))]
+impl<R: Read> XmlRsSource<R> {
+    pub(crate) fn new(source: R) -> XmlRsSource<R> {
+        XmlRsSource {
+            reader: EventReader::new(source),
+        }
+    }
+}
+
+impl<R: Read> XmlSource for XmlRsSource<R> {
+    fn next_event(&mut self) -> anyhow::Result<XmlEvent> {
+        self.reader.next().map_err(|e| anyhow!(e))
+    }
+
+    fn position(&self) -> XmlPosition {
+        XmlPosition::Text(self.reader.position())
+    }
+}
+
+#[cfg(feature = "fast-xml")]
+pub(crate) use fast::QuickXmlSource;
+
+#[cfg(feature = "fast-xml")]
+mod fast {
+    use super::{XmlPosition, XmlSource};
+    use anyhow::anyhow;
+    use quick_xml::events::{BytesStart, Event};
+    use quick_xml::name::{QName, ResolveResult};
+    use quick_xml::NsReader;
+    use std::collections::VecDeque;
+    use std::io::BufRead;
+    use xml::attribute::OwnedAttribute;
+    use xml::name::OwnedName;
+    use xml::namespace::Namespace;
+    use xml::reader::XmlEvent;
+
+    /// quick-xml backend
+    pub(crate) struct QuickXmlSource<R: BufRead> {
+        reader: NsReader<R>,
+        buffer: Vec<u8>,
+        /// events converted but not given yet (an element following some text)
+        pending: VecDeque<XmlEvent>,
+        /// start of the last event
+        position: u64,
+    }
+
+    impl<R: BufRead> QuickXmlSource<R> {
+        pub(crate) fn new(source: R) -> QuickXmlSource<R> {
+            let mut reader = NsReader::from_reader(source);
+            // `<a/>` gives a start and an end event, as with xml-rs
+            reader.config_mut().expand_empty_elements = true;
+            QuickXmlSource {
+                reader,
+                buffer: vec![],
+                pending: VecDeque::new(),
+                position: 0,
+            }
+        }
+    }
+
+    impl<R: BufRead> XmlSource for QuickXmlSource<R> {
+        fn next_event(&mut self) -> anyhow::Result<XmlEvent> {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            // text is split by quick-xml around references (`&amp;`, `&#32;`)
+            // while xml-rs gives it as one event : accumulate it
+            let mut text = String::new();
+            let mut text_position = None;
+            loop {
+                let position = self.reader.buffer_position();
+                self.buffer.clear();
+                let (namespace, event) = self.reader.read_resolved_event_into(&mut self.buffer)?;
+                let namespace = match namespace {
+                    ResolveResult::Bound(namespace) => {
+                        Some(String::from(std::str::from_utf8(namespace.into_inner())?))
+                    }
+                    _ => None,
+                };
+                match event {
+                    Event::Text(content) => {
+                        text_position.get_or_insert(position);
+                        text.push_str(&content.xml_content()?);
+                        continue;
+                    }
+                    Event::GeneralRef(reference) => {
+                        text_position.get_or_insert(position);
+                        match reference.resolve_char_ref()? {
+                            Some(character) => text.push(character),
+                            None => text.push(predefined_entity(&reference.decode()?)?),
+                        }
+                        continue;
+                    }
+                    event => {
+                        if let Some(converted) = convert_event(event, namespace)? {
+                            self.pending.push_back(converted);
+                        }
+                    }
+                }
+                if let Some(text_position) = text_position {
+                    self.position = text_position;
+                    return Ok(if text.chars().all(char::is_whitespace) {
+                        XmlEvent::Whitespace(text)
+                    } else {
+                        XmlEvent::Characters(text)
+                    });
+                }
+                if let Some(event) = self.pending.pop_front() {
+                    self.position = position;
+                    return Ok(event);
+                }
+                // ignored event (comment, declaration, ...)
+            }
+        }
+
+        fn position(&self) -> XmlPosition {
+            XmlPosition::Offset(self.position)
+        }
+    }
+
+    /// converts an event other than text, `None` for the events the parser ignores
+    /// (`namespace` : the namespace of the element, if any)
+    fn convert_event(event: Event, namespace: Option<String>) -> anyhow::Result<Option<XmlEvent>> {
+        Ok(match event {
+            Event::Start(start) => Some(start_element(&start, namespace)?),
+            Event::End(end) => Some(XmlEvent::EndElement {
+                name: OwnedName {
+                    namespace,
+                    ..owned_name(end.name())?
+                },
+            }),
+            Event::CData(content) => Some(XmlEvent::CData(String::from(content.decode()?))),
+            Event::Eof => Some(XmlEvent::EndDocument),
+            _ => None,
+        })
+    }
+
+    fn start_element(start: &BytesStart, namespace: Option<String>) -> anyhow::Result<XmlEvent> {
+        let attributes = start
+            .attributes()
+            .map(|attribute| {
+                let attribute = attribute?;
+                Ok(OwnedAttribute {
+                    name: owned_name(attribute.key)?,
+                    value: String::from(attribute.unescape_value()?),
+                })
+            })
+            .collect::<anyhow::Result<Vec<OwnedAttribute>>>()?
+            .into_iter()
+            // namespace declarations aren't attributes for xml-rs
+            .filter(|attribute| {
+                attribute.name.prefix.as_deref() != Some("xmlns")
+                    && !(attribute.name.prefix.is_none() && attribute.name.local_name == "xmlns")
+            })
+            .collect();
+        Ok(XmlEvent::StartElement {
+            name: OwnedName {
+                namespace,
+                ..owned_name(start.name())?
+            },
+            attributes,
+            namespace: Namespace::empty(),
+        })
+    }
+
+    fn owned_name(name: QName) -> anyhow::Result<OwnedName> {
+        let local_name = std::str::from_utf8(name.local_name().into_inner())?;
+        let prefix = name
+            .prefix()
+            .map(|prefix| std::str::from_utf8(prefix.into_inner()).map(String::from))
+            .transpose()?;
+        Ok(OwnedName {
+            local_name: String::from(local_name),
+            namespace: None,
+            prefix,
+        })
+    }
+
+    /// the entities predefined by xml
+    fn predefined_entity(name: &str) -> anyhow::Result<char> {
+        match name {
+            "amp" => Ok('&'),
+            "lt" => Ok('<'),
+            "gt" => Ok('>'),
+            "apos" => Ok('\''),
+            "quot" => Ok('"'),
+            _ => Err(anyhow!("Unknown entity &{name};")),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+#![cfg(feature = "std")]
+
+use writer_inkml::{parse_bytes, writer, Brush, FormatOptions, FormattedStroke};
+
+fn stroke() -> FormattedStroke {
+    FormattedStroke::new(vec![0.0, 1.0], vec![0.0, 1.0], vec![0.5, 0.5]).unwrap()
+}
+
+/// widths differing by float noise give one brush, and keep giving one
+/// brush when the document is read and written again
+#[test]
+fn brush_count_is_stable_through_round_trips() {
+    let widths = [
+        0.1,
+        0.1 + 1e-12,
+        0.1 - 1e-12,
+        0.35 / 3.0,
+        0.35 / 3.0 + 1e-12,
+    ];
+    let strokes = widths
+        .iter()
+        .map(|&width| {
+            (
+                stroke(),
+                Brush::init(String::from("br"), (0, 0, 0), false, 0, width),
+            )
+        })
+        .collect::<Vec<(FormattedStroke, Brush)>>();
+
+    let first = parse_bytes(&writer(strokes).unwrap()).unwrap();
+    assert_eq!(first.brushes().len(), 2);
+
+    let strokes = first.formatted(&FormatOptions::default()).unwrap();
+    let second = parse_bytes(&writer(strokes).unwrap()).unwrap();
+    assert_eq!(second.brushes().len(), 2);
+}
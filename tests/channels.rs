@@ -0,0 +1,36 @@
+#![cfg(feature = "std")]
+
+use writer_inkml::parse_formatted_str;
+
+/// document with a F channel declared with `min` and `max`
+fn with_pressure_bounds(min: &str, max: &str) -> String {
+    format!(
+        r##"<ink xmlns="http://www.w3.org/2003/InkML">
+    <definitions>
+        <context xml:id="ctx0">
+            <inkSource xml:id="src0">
+                <traceFormat>
+                    <channel name="X" type="integer" />
+                    <channel name="Y" type="integer" />
+                    <channel name="F" type="integer" min="{min}" max="{max}" />
+                </traceFormat>
+            </inkSource>
+        </context>
+        <brush xml:id="br0" />
+    </definitions>
+    <trace contextRef="#ctx0" brushRef="#br0">0 0 512, 10 10 512, 20 20 512</trace>
+</ink>"##
+    )
+}
+
+#[test]
+fn pressure_is_normalised_between_min_and_max() {
+    let strokes = parse_formatted_str(&with_pressure_bounds("0", "1024")).unwrap();
+    assert_eq!(strokes[0].0.f(), [0.5, 0.5, 0.5]);
+}
+
+#[test]
+fn pressure_without_range_is_constant() {
+    let strokes = parse_formatted_str(&with_pressure_bounds("512", "512")).unwrap();
+    assert_eq!(strokes[0].0.f(), [1.0, 1.0, 1.0]);
+}
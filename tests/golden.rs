@@ -0,0 +1,100 @@
+// writer output compared byte for byte with the documents of `tests/golden`
+// (after checking that they are valid with `validate_output`)
+
+#![cfg(feature = "std")]
+
+use writer_inkml::{
+    validate_output, writer, writer_tree, writer_with_config, Brush, BrushBuilder, Continuation,
+    FormattedStroke, InkNode, InkmlStreamWriter, TraceEncoding, TraceGroup, WriterConfig,
+};
+
+fn strokes() -> Vec<(FormattedStroke, Brush)> {
+    vec![
+        (
+            FormattedStroke::new(
+                vec![1.0, 1.5, 2.25, 3.0],
+                vec![2.0, 2.1, 2.4, 3.0],
+                vec![0.25, 0.5, 0.75, 0.5],
+            )
+            .unwrap(),
+            Brush::init(String::from("pen"), (0, 0, 255), false, 0, 0.05),
+        ),
+        (
+            FormattedStroke::new(vec![4.0, 5.0], vec![1.0, 1.0], vec![1.0, 1.0])
+                .unwrap()
+                .with_timestamps(vec![1000.0, 1016.0])
+                .unwrap(),
+            BrushBuilder::new().color((255, 255, 0)).build(),
+        ),
+    ]
+}
+
+fn check(document: &[u8], golden: &str) {
+    assert_eq!(validate_output(document), vec![]);
+    assert_eq!(String::from_utf8_lossy(document), golden);
+}
+
+#[test]
+fn default_writer() {
+    check(
+        &writer(strokes()).unwrap(),
+        include_str!("golden/default.inkml"),
+    );
+}
+
+#[test]
+fn configured_writer() {
+    let config = WriterConfig::default()
+        .document_id("urn:test:1")
+        .lang("fr")
+        .sample_rate(125.0)
+        .max_points_per_trace(3)
+        .encoding(TraceEncoding::DoubleDifference)
+        .pretty(true);
+    check(
+        &writer_with_config(strokes(), &config).unwrap(),
+        include_str!("golden/configured.inkml"),
+    );
+}
+
+#[test]
+fn tree_writer() {
+    let mut strokes = strokes().into_iter();
+    let (first, first_brush) = strokes.next().unwrap();
+    let (second, second_brush) = strokes.next().unwrap();
+    let nodes = vec![
+        InkNode::Group(TraceGroup {
+            id: Some(String::from("layer1")),
+            lang: Some(String::from("de")),
+            children: vec![InkNode::Stroke(first, first_brush)],
+            ..Default::default()
+        }),
+        InkNode::Stroke(second, second_brush),
+    ];
+    check(
+        &writer_tree(&nodes, &WriterConfig::default().lang("en")).unwrap(),
+        include_str!("golden/tree.inkml"),
+    );
+}
+
+#[test]
+fn stream_writer() {
+    let mut stream = InkmlStreamWriter::new(vec![]).unwrap();
+    let strokes = strokes();
+    for (stroke, brush) in &strokes {
+        stream.push_stroke(stroke, brush).unwrap();
+    }
+    // a stroke pushed in parts, with a brush already written
+    let (stroke, brush) = &strokes[0];
+    let (first, second) = stroke.split_at(2).unwrap();
+    stream
+        .push_stroke_part(&first, brush, Continuation::Begin)
+        .unwrap();
+    stream
+        .push_stroke_part(&second, brush, Continuation::End)
+        .unwrap();
+    check(
+        &stream.finish().unwrap(),
+        include_str!("golden/stream.inkml"),
+    );
+}
@@ -0,0 +1,21 @@
+#![cfg(feature = "std")]
+
+use writer_inkml::{FileSystemResolver, ReferenceResolver};
+
+#[test]
+fn resolves_documents_of_the_base_dir() {
+    let resolver = FileSystemResolver::new("test_files");
+    assert!(resolver.resolve("correct.xml").is_ok());
+}
+
+#[test]
+fn rejects_paths_outside_of_the_base_dir() {
+    let resolver = FileSystemResolver::new("test_files");
+    for uri in ["../Cargo.toml", "/etc/hostname", "sub/../../Cargo.toml"] {
+        let error = resolver.resolve(uri).unwrap_err().to_string();
+        assert!(
+            error.contains("outside of the base directory"),
+            "{uri} : {error}"
+        );
+    }
+}
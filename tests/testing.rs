@@ -0,0 +1,32 @@
+#![cfg(feature = "test-util")]
+
+use writer_inkml::testing::assert_roundtrip;
+use writer_inkml::{parse_file, FormatOptions};
+
+#[test]
+fn corpus_files_survive_a_round_trip() {
+    for file in [
+        "10065.inkml",
+        "correct.xml",
+        "highlighter_onenote.xml",
+        "journal_output.xml",
+        "onenote_multiple_contexts.xml",
+        "onenote_web.xml",
+        "word_output.xml",
+    ] {
+        assert_roundtrip(format!("test_files/{file}"));
+    }
+}
+
+#[test]
+fn continuations_survive_a_round_trip() {
+    let strokes = parse_file("test_files/continuation.xml")
+        .unwrap()
+        .formatted(&FormatOptions::default())
+        .unwrap();
+    // the parts of the first trace are joined, with their timestamps
+    assert_eq!(strokes.len(), 2);
+    assert_eq!(strokes[0].0.len(), 7);
+    assert!(strokes[0].0.t().is_some());
+    assert_roundtrip("test_files/continuation.xml");
+}
@@ -0,0 +1,77 @@
+#![cfg(feature = "std")]
+
+use writer_inkml::{parse_bytes, parse_file, parse_str, validate_output, write_parser_result};
+
+/// corpus files the parser rejects
+const REJECTED: [&str; 1] = ["bool_trace.xml"];
+
+/// every other corpus file is the same once written back
+#[test]
+fn corpus_files_are_written_back_unchanged() {
+    for entry in std::fs::read_dir("test_files").unwrap() {
+        let path = entry.unwrap().path();
+        let parsed = parse_file(&path);
+        if REJECTED.contains(&path.file_name().unwrap().to_str().unwrap()) {
+            assert!(parsed.is_err(), "{} is read now", path.display());
+            continue;
+        }
+        let document = parsed.unwrap_or_else(|e| panic!("{} : {e}", path.display()));
+        let written = write_parser_result(&document).unwrap();
+        let reparsed = parse_bytes(&written)
+            .unwrap_or_else(|e| panic!("{} written back : {e}", path.display()));
+        assert!(
+            document == reparsed,
+            "{} written back differs",
+            path.display()
+        );
+    }
+}
+
+/// document with ids, times, continuations, nested groups, languages and annotations
+const ANNOTATED: &str = r##"<ink xmlns="http://www.w3.org/2003/InkML" documentID="urn:doc:1" xml:lang="en">
+    <annotation type="author">someone</annotation>
+    <definitions>
+        <timestamp xml:id="ts0" time="1700000000000" />
+        <context xml:id="ctx0">
+            <inkSource xml:id="src0">
+                <traceFormat>
+                    <channel name="X" type="integer" />
+                    <channel name="Y" type="integer" />
+                    <channel name="T" type="integer" respectTo="#ts0" />
+                </traceFormat>
+            </inkSource>
+        </context>
+        <brush xml:id="br0" />
+    </definitions>
+    <trace xml:id="t0" contextRef="#ctx0" brushRef="#br0">0 0 0, 1 1 10</trace>
+    <traceGroup xml:id="page" xml:lang="fr">
+        <annotation type="title">première page</annotation>
+        <traceGroup xml:id="word">
+            <annotation>mot</annotation>
+            <trace xml:id="t1" contextRef="#ctx0" brushRef="#br0" timeOffset="20" duration="15" continuation="begin">2 2 20, 3 3 30</trace>
+            <trace xml:id="t2" contextRef="#ctx0" brushRef="#br0" continuation="end" priorRef="#t1">4 4 40</trace>
+        </traceGroup>
+        <traceGroup xml:id="empty" xml:lang="de" />
+        <trace xml:id="t3" contextRef="#ctx0" brushRef="#br0" type="penUp">5 5 50</trace>
+    </traceGroup>
+    <traceView xml:id="views">
+        <traceView traceDataRef="#t0" />
+        <traceView traceDataRef="#page" />
+    </traceView>
+    <trace contextRef="#ctx0" brushRef="#br0">6 6 60</trace>
+</ink>"##;
+
+#[test]
+fn attributes_groups_and_annotations_are_written_back() {
+    let document = parse_str(ANNOTATED).unwrap();
+    let written = write_parser_result(&document).unwrap();
+    let reparsed = parse_bytes(&written).unwrap();
+    assert!(
+        document == reparsed,
+        "{}\n{document:?}\n{reparsed:?}",
+        String::from_utf8_lossy(&written)
+    );
+    assert_eq!(validate_output(&written), vec![]);
+    assert_eq!(reparsed.document_id(), Some("urn:doc:1"));
+    assert_eq!(reparsed.lang(), Some("en"));
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "std")]
+
+use writer_inkml::{
+    parse_bytes, validate_output, writer, writer_with_config, Brush, FormattedStroke,
+    InvalidStrokesError, WriterConfig,
+};
+
+fn brush(name: &str, color: (u8, u8, u8)) -> Brush {
+    Brush::init(String::from(name), color, false, 0, 0.1)
+}
+
+fn stroke(len: usize) -> FormattedStroke {
+    let values = (0..len).map(|i| i as f64).collect::<Vec<f64>>();
+    FormattedStroke::new(values.clone(), values, vec![0.5; len]).unwrap()
+}
+
+#[test]
+fn no_strokes_give_a_minimal_valid_document() {
+    let document = writer(vec![]).unwrap();
+    assert_eq!(validate_output(&document), vec![]);
+    let parsed = parse_bytes(&document).unwrap();
+    assert_eq!(parsed.trace_count(), 0);
+    assert!(parsed.brushes().is_empty());
+}
+
+#[test]
+fn empty_strokes_are_skipped_by_default() {
+    let strokes = vec![
+        (stroke(2), brush("black", (0, 0, 0))),
+        (stroke(0), brush("red", (255, 0, 0))),
+        (stroke(3), brush("black", (0, 0, 0))),
+    ];
+    let document = writer(strokes).unwrap();
+    assert_eq!(validate_output(&document), vec![]);
+    let parsed = parse_bytes(&document).unwrap();
+    assert_eq!(parsed.trace_count(), 2);
+    // (with the brush only they used)
+    assert_eq!(parsed.brushes().len(), 1);
+}
+
+#[test]
+fn empty_strokes_are_written_if_configured() {
+    let strokes = vec![
+        (stroke(2), brush("black", (0, 0, 0))),
+        (stroke(0), brush("red", (255, 0, 0))),
+    ];
+    let config = WriterConfig::default().write_empty_strokes(true);
+    let document = String::from_utf8(writer_with_config(strokes, &config).unwrap()).unwrap();
+    assert_eq!(document.matches("<trace ").count(), 2);
+    assert_eq!(document.matches("<brush ").count(), 2);
+    // (the parser skips the empty traces)
+    assert_eq!(parse_bytes(document.as_bytes()).unwrap().trace_count(), 1);
+}
+
+#[test]
+fn skipped_strokes_keep_their_index_in_errors() {
+    let out_of_range = FormattedStroke::new(vec![0.0], vec![0.0], vec![2.0]).unwrap();
+    let strokes = vec![
+        (stroke(0), brush("black", (0, 0, 0))),
+        (out_of_range, brush("black", (0, 0, 0))),
+    ];
+    let error = writer(strokes).unwrap_err();
+    let error = error.downcast_ref::<InvalidStrokesError>().unwrap();
+    assert_eq!(error.stroke_indices(), [1]);
+}